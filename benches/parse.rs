@@ -0,0 +1,78 @@
+//! Parsing, serializing and CRC checking of pngs shaped to stress different parts of the
+//! parser: a typical small file, one with very many chunks and one with a single huge chunk.
+//! Fixtures are generated here rather than committed.
+//!
+//! Run with `cargo bench`, and compare against a baseline with `--save-baseline` and
+//! `--baseline` before and after a change.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use pngme::prelude::*;
+use std::hint::black_box;
+use std::str::FromStr;
+
+fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+}
+
+/// A header, a few text chunks, some image data and IEND, about the size of an icon.
+fn small() -> Vec<u8> {
+    let mut chunks = vec![chunk("IHDR", vec![0; 13])];
+    for i in 0..4 {
+        chunks.push(chunk("tEXt", format!("Comment\0note {}", i).into_bytes()));
+    }
+    chunks.push(chunk("IDAT", (0..4096).map(|i| (i % 251) as u8).collect()));
+    chunks.push(chunk("IEND", vec![]));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+/// 5,000 small chunks, where per-chunk overhead dominates.
+fn many_chunks() -> Vec<u8> {
+    let mut chunks = vec![chunk("IHDR", vec![0; 13])];
+    chunks.extend((0..5_000u32).map(|i| chunk("IDAT", i.to_be_bytes().repeat(4))));
+    chunks.push(chunk("IEND", vec![]));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+/// A single 64 MiB chunk, where copying and checksumming the data dominates.
+fn large_chunk() -> Vec<u8> {
+    let data = (0..64 << 20).map(|i| (i % 251) as u8).collect();
+    Png::from_chunks(vec![
+        chunk("IHDR", vec![0; 13]),
+        chunk("IDAT", data),
+        chunk("IEND", vec![]),
+    ])
+    .as_bytes()
+}
+
+fn benchmarks(c: &mut Criterion) {
+    for (name, bytes) in [
+        ("small", small()),
+        ("5000 chunks", many_chunks()),
+        ("64 MiB chunk", large_chunk()),
+    ] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        if bytes.len() > 1 << 20 {
+            group.sample_size(10);
+        }
+        let png = Png::try_from(&bytes[..]).unwrap();
+        group.bench_function("parse", |b| {
+            b.iter(|| Png::try_from(black_box(&bytes[..])).unwrap())
+        });
+        group.bench_function("serialize", |b| b.iter(|| black_box(&png).as_bytes()));
+        group.bench_function("verify crcs", |b| {
+            b.iter(|| assert!(black_box(&png).verify_crcs().is_empty()))
+        });
+        group.bench_function("roundtrip", |b| {
+            b.iter_batched(
+                || bytes.clone(),
+                |bytes| Png::try_from(&bytes[..]).unwrap().as_bytes(),
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,272 @@
+//! Frames of animated pngs. An APNG announces itself with acTL; each frame then starts with an
+//! fcTL giving its region of the canvas, followed by its image data in fdAT chunks, or in the
+//! IDAT chunks when the first frame is the default image itself.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// The frame chunks of an APNG don't have the layout or order the spec gives them.
+#[derive(Debug)]
+pub struct ApngError {
+    reason: String,
+}
+impl ApngError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for ApngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad animation chunk: {}", self.reason)
+    }
+}
+impl Error for ApngError {}
+
+/// Chunks not copied into extracted frames: they describe the animation or the full image.
+const ANIMATION_CHUNKS: [&[u8; 4]; 4] = [b"IHDR", b"acTL", b"fcTL", b"fdAT"];
+
+/// The region and timing of a frame, from its fcTL chunk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FrameControl {
+    pub sequence: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// The frame is shown for `delay_num / delay_den` seconds, where a `delay_den` of 0 means 100
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: u8,
+    pub blend_op: u8,
+}
+
+impl FrameControl {
+    /// Parses the 26 bytes of an fcTL chunk.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let data: &[u8; 26] = data
+            .try_into()
+            .map_err(|_| ApngError::boxed(format!("fcTL is {} bytes instead of 26", data.len())))?;
+        let u32_at =
+            |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let control = Self {
+            sequence: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16::from_be_bytes([data[20], data[21]]),
+            delay_den: u16::from_be_bytes([data[22], data[23]]),
+            dispose_op: data[24],
+            blend_op: data[25],
+        };
+        if control.width == 0 || control.height == 0 {
+            return Err(ApngError::boxed(format!(
+                "fcTL {} gives an empty {}x{} frame",
+                control.sequence, control.width, control.height
+            )));
+        }
+        Ok(control)
+    }
+}
+
+/// One frame of an animation: its fcTL and the compressed image data of its region.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub control: FrameControl,
+    /// The data of each IDAT or fdAT chunk in turn, without fdAT's sequence number
+    pub data: Vec<Vec<u8>>,
+    /// Whether the frame is the default image, stored in IDAT
+    pub default_image: bool,
+}
+
+impl Frame {
+    /// The frame as a png of its own: `source`'s IHDR resized to the frame, the chunks `source`
+    /// has before its image data (PLTE, tRNS, gAMA and the like), the frame's data as IDAT,
+    /// and IEND. The region is taken as stored, without applying the dispose and blend
+    /// operations of the frames before it.
+    pub fn to_png(&self, source: &Png) -> crate::Result<Png> {
+        let ihdr = source
+            .chunk_by_type("IHDR")
+            .ok_or_else(|| ApngError::boxed("there is no IHDR to copy".to_string()))?
+            .data();
+        if ihdr.len() != 13 {
+            return Err(ApngError::boxed(format!(
+                "IHDR is {} bytes instead of 13",
+                ihdr.len()
+            )));
+        }
+        let header = [
+            &self.control.width.to_be_bytes()[..],
+            &self.control.height.to_be_bytes(),
+            &ihdr[8..],
+        ]
+        .concat();
+        let mut chunks = vec![Chunk::new(
+            ChunkType::from_bytes_unchecked(*b"IHDR"),
+            header,
+        )];
+        chunks.extend(
+            source
+                .chunks()
+                .iter()
+                .take_while(|c| c.chunk_type().bytes() != *b"IDAT")
+                .filter(|c| !ANIMATION_CHUNKS.contains(&&c.chunk_type().bytes()))
+                .cloned(),
+        );
+        let idat = ChunkType::from_bytes_unchecked(*b"IDAT");
+        chunks.extend(self.data.iter().map(|data| Chunk::new(idat, &data[..])));
+        chunks.push(Chunk::new(ChunkType::from_bytes_unchecked(*b"IEND"), []));
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+/// The frames of `png` in order. IDAT not preceded by an fcTL is a default image shown only
+/// by viewers without APNG support, and is not a frame.
+pub fn frames(png: &Png) -> crate::Result<Vec<Frame>> {
+    let mut frames: Vec<Frame> = vec![];
+    for chunk in png.chunks() {
+        match &chunk.chunk_type().bytes() {
+            b"fcTL" => frames.push(Frame {
+                control: FrameControl::parse(chunk.data())?,
+                data: vec![],
+                default_image: false,
+            }),
+            b"IDAT" => {
+                if let Some(frame) = frames.last_mut() {
+                    frame.default_image = true;
+                    frame.data.push(chunk.data().to_vec());
+                }
+            }
+            b"fdAT" => {
+                let frame = frames
+                    .last_mut()
+                    .ok_or_else(|| ApngError::boxed("fdAT appears before any fcTL".to_string()))?;
+                let data = chunk.data().get(4..).ok_or_else(|| {
+                    ApngError::boxed(format!(
+                        "fdAT is {} bytes, too short for its sequence number",
+                        chunk.data().len()
+                    ))
+                })?;
+                frame.data.push(data.to_vec());
+            }
+            _ => {}
+        }
+    }
+    if let Some(frame) = frames.iter().find(|f| f.data.is_empty()) {
+        return Err(ApngError::boxed(format!(
+            "fcTL {} has no image data after it",
+            frame.control.sequence
+        )));
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::lsb::tests::generated_png;
+
+    /// fcTL data for a `width` x `height` frame at (`x`, `y`).
+    pub fn fctl(sequence: u32, width: u32, height: u32, x: u32, y: u32) -> Vec<u8> {
+        let mut data = [sequence, width, height, x, y]
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<u8>>();
+        data.extend_from_slice(&[0, 1, 0, 10, 0, 0]);
+        data
+    }
+
+    /// A 4x4 truecolor APNG of three frames: the default image, then a 2x2 and a 3x1 region
+    /// whose data is that of real images of those sizes.
+    pub fn animation() -> Png {
+        let chunk = |t: &[u8; 4], d: Vec<u8>| Chunk::new(ChunkType::from_bytes_unchecked(*t), d);
+        let data = |width, height| -> Vec<u8> {
+            generated_png(width, height, 2, 3)
+                .chunks()
+                .iter()
+                .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+                .flat_map(|c| c.data().to_vec())
+                .collect()
+        };
+        let fdat = |sequence: u32, data: &[u8]| [&sequence.to_be_bytes()[..], data].concat();
+        let default = generated_png(4, 4, 2, 3);
+        let mut chunks = vec![default.chunks()[0].clone()];
+        chunks.push(chunk(
+            b"acTL",
+            [3u32, 0].iter().flat_map(|n| n.to_be_bytes()).collect(),
+        ));
+        chunks.push(chunk(b"gAMA", 45455u32.to_be_bytes().to_vec()));
+        chunks.push(chunk(b"fcTL", fctl(0, 4, 4, 0, 0)));
+        chunks.extend(default.chunks()[2..4].iter().cloned());
+        chunks.push(chunk(b"fcTL", fctl(1, 2, 2, 1, 1)));
+        chunks.push(chunk(b"fdAT", fdat(2, &data(2, 2))));
+        chunks.push(chunk(b"fcTL", fctl(3, 3, 1, 0, 3)));
+        let third = data(3, 1);
+        chunks.push(chunk(b"fdAT", fdat(4, &third[..5])));
+        chunks.push(chunk(b"fdAT", fdat(5, &third[5..])));
+        chunks.push(chunk(b"IEND", vec![]));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_frames() {
+        let png = animation();
+        let frames = frames(&png).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].default_image);
+        assert_eq!(frames[0].data.len(), 2);
+        assert_eq!(
+            (frames[1].control.width, frames[1].control.x_offset),
+            (2, 1)
+        );
+        assert!(!frames[2].default_image);
+        assert_eq!(frames[2].data[0].len(), 5);
+
+        let extracted = frames[1].to_png(&png).unwrap();
+        let types: Vec<String> = extracted
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(extracted.chunks()[0].data()[..8], [0, 0, 0, 2, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_default_image_outside_the_animation() {
+        let png = animation();
+        let mut chunks = png.chunks().to_vec();
+        // Drop the fcTL in front of IDAT, leaving the default image out of the animation
+        chunks.remove(3);
+        let frames = frames(&Png::from_chunks(chunks)).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|f| !f.default_image));
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        let chunk = |t: &[u8; 4], d: Vec<u8>| Chunk::new(ChunkType::from_bytes_unchecked(*t), d);
+        let cases = [
+            (vec![chunk(b"fdAT", vec![0; 8])], "before any fcTL"),
+            (vec![chunk(b"fcTL", vec![0; 25])], "25 bytes instead of 26"),
+            (vec![chunk(b"fcTL", fctl(0, 0, 4, 0, 0))], "empty 0x4 frame"),
+            (vec![chunk(b"fcTL", fctl(0, 4, 4, 0, 0))], "no image data"),
+            (
+                vec![
+                    chunk(b"fcTL", fctl(0, 4, 4, 0, 0)),
+                    chunk(b"fdAT", vec![0; 3]),
+                ],
+                "too short for its sequence number",
+            ),
+        ];
+        for (chunks, expected) in cases {
+            let error = frames(&Png::from_chunks(chunks)).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Marks a payload packed from a directory with `encode --payload-dir`.
+const MAGIC: &[u8; 4] = b"pgAR";
+const VERSION: u8 = 1;
+
+/// A payload can't be packed or unpacked as an archive.
+#[derive(Debug)]
+pub struct ArchiveError {
+    reason: String,
+}
+impl ArchiveError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Archive error: {}", self.reason)
+    }
+}
+impl Error for ArchiveError {}
+
+/// A file in an archive, named by its path relative to the packed directory with `/`
+/// between components.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub mode: u32,
+    pub data: Vec<u8>,
+}
+
+/// Whether a payload is an archive packed with `pack`.
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) && data.get(MAGIC.len()) == Some(&VERSION)
+}
+
+/// Packs every file under `dir`, subdirectories included, in name order. Empty directories
+/// aren't recorded.
+pub fn pack(dir: &Path) -> crate::Result<Vec<u8>> {
+    let mut entries = vec![];
+    collect(dir, "", &mut entries)?;
+    if entries.is_empty() {
+        return Err(ArchiveError::boxed(format!(
+            "{} holds no files to pack",
+            dir.display()
+        )));
+    }
+    Ok(as_bytes(&entries))
+}
+
+fn collect(dir: &Path, prefix: &str, entries: &mut Vec<Entry>) -> crate::Result<()> {
+    let mut children: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let name = child
+            .file_name()
+            .into_string()
+            .map_err(|name| ArchiveError::boxed(format!("{:?} is not a UTF-8 file name", name)))?;
+        let name = format!("{}{}", prefix, name);
+        let metadata = child.metadata()?;
+        if metadata.is_dir() {
+            collect(&child.path(), &format!("{}/", name), entries)?;
+        } else if metadata.is_file() {
+            entries.push(Entry {
+                mode: mode_of(&metadata),
+                data: fs::read(child.path())?,
+                name,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+/// Without permission bits to record, files are recreated readable by everyone.
+#[cfg(not(unix))]
+fn mode_of(_metadata: &fs::Metadata) -> u32 {
+    0o644
+}
+
+/// The archive as the magic, a version byte and each entry as a big endian u16 name length,
+/// the name, a big endian u32 mode, a big endian u64 size and the file's bytes.
+pub fn as_bytes(entries: &[Entry]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    for entry in entries {
+        out.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(&entry.mode.to_be_bytes());
+        out.extend_from_slice(&(entry.data.len() as u64).to_be_bytes());
+        out.extend_from_slice(&entry.data);
+    }
+    out
+}
+
+/// The entries of an archive, the table of contents `decode --list` shows.
+pub fn entries(data: &[u8]) -> crate::Result<Vec<Entry>> {
+    if !is_archive(data) {
+        return Err(ArchiveError::boxed(
+            "the message is not an archive packed with --payload-dir".to_string(),
+        ));
+    }
+    let truncated = || ArchiveError::boxed("the archive is truncated".to_string());
+    let mut rest = &data[MAGIC.len() + 1..];
+    let mut entries = vec![];
+    while !rest.is_empty() {
+        let (len, after) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let len = u16::from_be_bytes(*len) as usize;
+        let name = after.get(..len).ok_or_else(truncated)?;
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| ArchiveError::boxed("a file name is not valid UTF-8".to_string()))?;
+        let (mode, after) = after[len..]
+            .split_first_chunk::<4>()
+            .ok_or_else(truncated)?;
+        let (size, after) = after.split_first_chunk::<8>().ok_or_else(truncated)?;
+        let size = usize::try_from(u64::from_be_bytes(*size)).map_err(|_| truncated())?;
+        let bytes = after.get(..size).ok_or_else(truncated)?;
+        entries.push(Entry {
+            name,
+            mode: u32::from_be_bytes(*mode),
+            data: bytes.to_vec(),
+        });
+        rest = &after[size..];
+    }
+    Ok(entries)
+}
+
+/// Where `name` goes under `out_dir`, refusing names that are absolute or climb out of it,
+/// so a crafted archive can't write anywhere else.
+fn destination(out_dir: &Path, name: &str) -> crate::Result<PathBuf> {
+    let relative = Path::new(name);
+    let safe = !name.is_empty()
+        && !name.contains('\\')
+        && relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    match safe {
+        true => Ok(out_dir.join(relative)),
+        false => Err(ArchiveError::boxed(format!(
+            "refusing to extract {:?}, which points outside the output directory",
+            name
+        ))),
+    }
+}
+
+/// Recreates the files of the archive under `out_dir`. Every name is checked before anything
+/// is written, so an archive with one unsafe name extracts nothing.
+pub fn extract(entries: &[Entry], out_dir: &Path) -> crate::Result<()> {
+    let destinations: Vec<PathBuf> = entries
+        .iter()
+        .map(|entry| destination(out_dir, &entry.name))
+        .collect::<crate::Result<_>>()?;
+    for (entry, path) in entries.iter().zip(destinations) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &entry.data)?;
+        set_mode(&path, entry.mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> crate::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::set_permissions(
+        path,
+        fs::Permissions::from_mode(mode & 0o777),
+    )?)
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> crate::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sigs")).unwrap();
+        fs::write(src.join("manifest.json"), b"{\"version\": 1}").unwrap();
+        fs::write(src.join("sigs/manifest.sig"), [0, 1, 2, 255]).unwrap();
+        fs::write(src.join("empty"), b"").unwrap();
+
+        let packed = pack(&src).unwrap();
+        assert!(is_archive(&packed));
+        let listed = entries(&packed).unwrap();
+        let names: Vec<&str> = listed.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["empty", "manifest.json", "sigs/manifest.sig"]);
+
+        let out = dir.path().join("out");
+        extract(&listed, &out).unwrap();
+        for name in names {
+            assert_eq!(
+                fs::read(out.join(name)).unwrap(),
+                fs::read(src.join(name)).unwrap()
+            );
+        }
+        assert!(entries(b"plain message").is_err());
+        assert!(entries(&packed[..packed.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_traversal() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        for name in ["../evil", "/etc/evil", "a/../../evil", "a\\..\\evil", ""] {
+            let archive = as_bytes(&[
+                Entry {
+                    name: "fine".to_string(),
+                    mode: 0o644,
+                    data: b"ok".to_vec(),
+                },
+                Entry {
+                    name: name.to_string(),
+                    mode: 0o644,
+                    data: b"pwned".to_vec(),
+                },
+            ]);
+            let err = extract(&entries(&archive).unwrap(), &out).unwrap_err();
+            assert!(err.to_string().contains("outside the output directory"));
+        }
+        assert!(!out.exists());
+        assert!(!dir.path().join("evil").exists());
+    }
+}
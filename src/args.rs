@@ -1,63 +1,1542 @@
 use clap::Args;
 use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
 
-#[derive(Args, Debug)]
-pub struct EncodeArgs {
-    /// Path to the input png file into which a message is to be encoded
+use crate::chunk_type::ChunkType;
+use crate::compress::Algorithm;
+use crate::digest::HashAlgorithm;
+use crate::expiry::{self, Expiry};
+use crate::newline::Newline;
+use crate::output::Format;
+use crate::png::Placement;
+use crate::remote;
+use crate::secret::SecretBytes;
+use crate::template::Template;
+
+/// Where in the png a message is hidden.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub enum Mode {
+    /// In a chunk of its own
+    #[default]
+    Chunk,
+    /// In the least significant bits of the pixel data, leaving the chunk list untouched
+    Lsb,
+}
+
+/// The order print lists chunks in.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub enum ChunkOrder {
+    /// As they appear in the file
+    #[default]
+    Offset,
+    /// By chunk type, byte by byte
+    Type,
+    /// By data length, smallest first
+    Size,
+}
+
+/// Where the key for encrypting or decrypting a message comes from.
+#[derive(Args, Debug, Clone, Default)]
+pub struct KeyArgs {
+    /// Passphrase to derive the encryption key from. Without this, --key-file or
+    /// --use-keyring, the PNGME_PASSPHRASE and then PNGME_KEY_HEX (64 hex digits of raw key)
+    /// environment variables are used, and failing those the passphrase is asked for on the
+    /// terminal.
+    #[arg(long, conflicts_with = "key_file")]
+    pub passphrase: Option<SecretBytes>,
+    /// File holding a raw 32 byte key, as written by `pngme keygen --symmetric`
+    #[arg(long)]
+    pub key_file: Option<String>,
+    /// Take the passphrase from the platform keyring (needs the `keyring` feature). If it can't
+    /// be read it is asked for instead, and stored for next time when encoding.
+    #[arg(
+        long,
+        requires = "keyring_id",
+        conflicts_with_all = ["passphrase", "key_file"]
+    )]
+    pub use_keyring: bool,
+    /// Name the passphrase is stored under in the keyring, as given to `pngme keyring set`
+    #[arg(long, requires = "use_keyring")]
+    pub keyring_id: Option<String>,
+}
+
+/// How to handle another pngme process writing the same file.
+#[derive(Args, Debug, Clone, Default)]
+pub struct LockArgs {
+    /// If another pngme process is writing the file, wait up to this many seconds for it to
+    /// finish instead of failing straight away
+    #[arg(long, value_name = "SECS")]
+    pub wait_lock: Option<u64>,
+}
+
+/// Recording a change in the audit trail kept in the file, for `pngme history`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct AuditArgs {
+    /// Record the change in the audit trail kept in the file: when, by which pngme version,
+    /// which command and which chunk types, but never the message itself
+    #[arg(long)]
+    pub audit: bool,
+    /// Text to record with the change in the audit trail, such as a ticket number
+    #[arg(long, requires = "audit")]
+    pub audit_note: Option<String>,
+}
+
+/// More files for a command to work through in one run, each handled on its own.
+#[derive(Args, Debug, Clone, Default)]
+pub struct BatchArgs {
+    /// More files, directories or glob patterns such as 'shots/*.png' to process the same way
+    /// as --file-path. Directories are searched for .png files.
+    #[arg(value_name = "PATH")]
+    pub paths: Vec<String>,
+    /// Also search the subdirectories of the directories given
     #[arg(short, long)]
+    pub recursive: bool,
+}
+
+/// Keeping the messages that are replaced or removed, for `pngme undo`.
+#[derive(Args, Debug, Clone)]
+pub struct HistoryArgs {
+    /// Keep the message being replaced or removed in the file, so `pngme undo` can restore it
+    #[arg(long)]
+    pub keep_previous: bool,
+    /// How many previous messages to keep for each chunk type or label with --keep-previous,
+    /// dropping the oldest beyond that
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    pub history_depth: u8,
+}
+
+impl Default for HistoryArgs {
+    fn default() -> Self {
+        Self {
+            keep_previous: false,
+            history_depth: 1,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct EncodeArgs {
+    /// Path to the input png file into which a message is to be encoded, or "-" for stdin
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["carriers", "paths"],
+        default_value = ""
+    )]
     pub file_path: String,
+    /// Split the message across the files matching this pattern, such as 'assets/*.png', in
+    /// place of --file-path. Only as many files as --max-per-file requires are used.
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        requires = "max_per_file",
+        conflicts_with_all = ["file_path", "paths", "mode", "redundancy", "replace", "out_path", "image_index"]
+    )]
+    pub carriers: Option<String>,
+    /// Most bytes to add to each file with --carriers, such as 512KiB or 2MB
+    #[arg(long, value_name = "SIZE", requires = "carriers", value_parser = parse_size)]
+    pub max_per_file: Option<usize>,
     #[arg(short, long)]
     /// 4 character string to use as png chunk type. Invalid if the third character is lowercase.
-    pub chunk_type: String,
-    #[arg(short, long)]
+    /// Required unless --label or --mode lsb is used.
+    pub chunk_type: Option<String>,
+    /// Name to store the message under instead of picking a chunk type, which is then derived
+    /// from the name
+    #[arg(long, conflicts_with = "chunk_type", value_parser = parse_label)]
+    pub label: Option<String>,
+    /// Where to hide the message
+    #[arg(long, value_enum, default_value_t)]
+    pub mode: Mode,
+    #[arg(short, long, required_unless_present_any = ["message_file", "payload_dir"])]
     /// Message to encode into the file
-    pub message: String,
+    pub message: Option<String>,
+    /// Read the message to encode from this file, or from stdin if it is "-". Any file can be
+    /// embedded, text or not, and its bytes are stored as they are.
+    #[arg(
+        long,
+        visible_alias = "input-file",
+        alias = "payload-file",
+        conflicts_with = "message"
+    )]
+    pub message_file: Option<String>,
+    /// When the message stops being valid: a UTC time like 2030-01-31T12:00:00Z, or a
+    /// duration from now like 12h, 30d or 2w. decode warns about expired messages.
+    #[arg(long, value_name = "TIME", value_parser = parse_expires)]
+    pub expires: Option<Expiry>,
+    /// Pack every file under this directory, subdirectories included, into the message, to be
+    /// unpacked with decode --extract-to
+    #[arg(long, conflicts_with_all = ["message", "message_file"])]
+    pub payload_dir: Option<String>,
+    /// How to treat line endings in a message read from --message-file
+    #[arg(long, value_enum, default_value_t)]
+    pub newline: Newline,
+    /// Where to put the chunks holding the message
+    #[arg(long, value_enum, default_value_t)]
+    pub placement: Placement,
+    /// Store this many copies of the message in separate chunks so decode can outvote damaged ones
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    pub redundancy: u8,
+    /// Split the message over as many chunks as it takes for each to be at most this big, such
+    /// as 64KiB, so no single chunk stands out. decode puts the pieces back together.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with_all = ["redundancy", "carriers", "mode"]
+    )]
+    pub max_chunk_size: Option<usize>,
+    /// Add this many Reed-Solomon parity bytes per block of up to 255 bytes, letting decode
+    /// correct up to half as many corrupted bytes per block
+    #[arg(long, value_parser = clap::value_parser!(u8).range(2..=254))]
+    pub ecc: Option<u8>,
+    /// Encrypt the message with XChaCha20-Poly1305 before storing it
+    #[arg(long)]
+    pub encrypt: bool,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    /// Sign the stored message with the Ed25519 key in this file, as written by `pngme keygen
+    /// --signing`, so decode --verify can tell it wasn't changed
+    #[arg(long, value_name = "KEYFILE")]
+    pub sign: Option<String>,
+    /// Store an HMAC-SHA256 tag of the stored message made with the raw 32 byte key in this
+    /// file, as written by `pngme keygen --symmetric`, so decode --mac-key can tell it was
+    /// written by someone holding the key and wasn't changed since
+    #[arg(long, value_name = "KEYFILE")]
+    pub mac_key: Option<String>,
+    #[command(flatten)]
+    pub lock: LockArgs,
+    /// Compress the message before padding and encrypting it, with deflate unless another
+    /// algorithm is given as in --compress=zstd, storing it as is when that doesn't make it
+    /// smaller. The algorithm is recorded with the message, so decode decompresses it without
+    /// being told
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "deflate"
+    )]
+    pub compress: Option<Algorithm>,
+    /// With --compress, store the message uncompressed unless compression saves at least this
+    /// percentage of its size
+    #[arg(
+        long,
+        default_value_t = 0,
+        requires = "compress",
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    pub min_compression_gain: u8,
+    /// Pad the message with random bytes to exactly this many bytes, counting a 4 byte length
+    /// header, so the stored size doesn't give away the message length
+    #[arg(long, conflicts_with = "pad_block", value_parser = clap::value_parser!(u32).range(4..))]
+    pub pad_to: Option<u32>,
+    /// Pad the message with random bytes, counting a 4 byte length header, up to a multiple of
+    /// this many bytes
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub pad_block: Option<u32>,
+    /// Write the png with the message here instead of over --file-path, or to stdout if it is
+    /// "-"
     #[arg(short, long)]
-    /// Output path to write new png file to
-    pub out_path: Option<Option<String>>,
+    pub out_path: Option<String>,
+    /// Keep the file as it was before encoding as <file>.bak
+    #[arg(long, conflicts_with = "out_path")]
+    pub backup: bool,
+    /// Decode the resulting image before writing it, refusing to write if that fails
+    #[arg(long)]
+    pub verify_image: bool,
+    /// Which of several png images concatenated in the file to use, counting from 0
+    #[arg(long)]
+    pub image_index: Option<usize>,
+    /// Print the digest of the written file as `<hex>  <path>`, the way sha256sum does, or
+    /// add it to the --json summary
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        default_missing_value = "sha256"
+    )]
+    pub print_hash: Option<HashAlgorithm>,
+    /// Replace the message already stored in the chunk type or under the label, instead of
+    /// adding another
+    #[arg(long)]
+    pub replace: bool,
+    #[command(flatten)]
+    pub history: HistoryArgs,
+    #[command(flatten)]
+    pub audit: AuditArgs,
+    /// Make the written file depend only on the inputs, so encoding the same message into the
+    /// same file again gives identical bytes. Nonces, salts and padding are derived from
+    /// --deterministic-seed and the message. Options that record the current time are refused,
+    /// and so is encryption without a seed.
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Secret to derive nonces, salts and padding from with --deterministic
+    #[arg(long, value_name = "SEED", requires = "deterministic")]
+    pub deterministic_seed: Option<String>,
+    #[command(flatten)]
+    pub batch: BatchArgs,
 }
 
 #[derive(Args, Debug)]
+pub struct UndoArgs {
+    /// Path to the png file to restore a message in
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Chunk type of the message to restore
+    #[arg(short, long, required_unless_present = "label")]
+    pub chunk_type: Option<String>,
+    /// Restore the message stored under this name with encode --label
+    #[arg(long, conflicts_with = "chunk_type", value_parser = parse_label)]
+    pub label: Option<String>,
+    #[command(flatten)]
+    pub lock: LockArgs,
+}
+
+#[derive(Args, Debug, Clone)]
 pub struct DecodeArgs {
     /// Path to the input png file from which a message is to be decoded
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["carriers", "paths"],
+        default_value = ""
+    )]
+    pub file_path: String,
+    /// Put together a message split with encode --carriers from the shards in the files
+    /// matching this pattern, in place of --file-path
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        conflicts_with_all = ["file_path", "paths", "all", "mode", "offset", "image_index", "chunk_type_hex"]
+    )]
+    pub carriers: Option<String>,
+    /// 4 character string to use as png chunk type. Invalid if the third character is lowercase.
+    /// Without this or --label, the file is searched for chunks holding a pngme envelope, which
+    /// messages stored without any envelope options don't have.
     #[arg(short, long)]
+    pub chunk_type: Option<String>,
+    /// Find the message by the name it was stored under with encode --label
+    #[arg(long, conflicts_with = "chunk_type", value_parser = parse_label)]
+    pub label: Option<String>,
+    /// Chunk type as 8 hex digits, such as 00495244, for damaged files whose chunk types
+    /// aren't made of letters
+    #[arg(
+        long,
+        value_name = "HEX",
+        conflicts_with_all = ["chunk_type", "label"],
+        value_parser = parse_chunk_type_hex
+    )]
+    pub chunk_type_hex: Option<ChunkType>,
+    /// Decode the messages in every chunk type holding a pngme envelope, or with --chunk-type
+    /// each chunk of that type on its own, printed with its index among them
+    #[arg(long, conflicts_with_all = ["label", "chunk_type_hex", "expect"])]
+    pub all: bool,
+    /// Where the message is hidden
+    #[arg(long, value_enum, default_value_t)]
+    pub mode: Mode,
+    /// Byte offset of a png embedded in a larger file. Parsing stops at its IEND.
+    #[arg(long)]
+    pub offset: Option<usize>,
+    /// Which of several png images concatenated in the file to use, counting from 0
+    #[arg(long)]
+    pub image_index: Option<usize>,
+    /// Decrypt a message stored with encode --encrypt
+    #[arg(long)]
+    pub decrypt: bool,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    /// Fail unless the message is signed with the key whose public half is in this file, as
+    /// written next to the key by `pngme keygen --signing`
+    #[arg(long, value_name = "PUBKEY")]
+    pub verify: Option<String>,
+    /// Fail unless the message carries an HMAC tag made with the raw 32 byte key in this file,
+    /// as stored by encode --mac-key
+    #[arg(long, value_name = "KEYFILE")]
+    pub mac_key: Option<String>,
+    /// Fail unless the decoded message equals this text
+    #[arg(long)]
+    pub expect: Option<String>,
+    /// How to treat line endings in both the decoded message and --expect when comparing them
+    #[arg(long, value_enum, default_value_t)]
+    pub newline: Newline,
+    /// Fail instead of only warning when the message is past the expiry it was stored with
+    #[arg(long)]
+    pub strict_expiry: bool,
+    /// List the files in a message stored with encode --payload-dir instead of printing it
+    #[arg(long, conflicts_with_all = ["all", "expect"])]
+    pub list: bool,
+    /// Unpack the files in a message stored with encode --payload-dir into this directory. Any
+    /// other message is written to it as payload.<ext>, the extension guessed from its contents.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["all", "expect"])]
+    pub extract_to: Option<String>,
+    /// Write the message to this file instead of printing it
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["all", "list", "extract_to"])]
+    pub output: Option<String>,
+    #[command(flatten)]
+    pub batch: BatchArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RemoveArgs {
+    /// Path to the input png file from which an encoded message is to be removed
+    #[arg(short, long, required_unless_present = "paths", default_value = "")]
     pub file_path: String,
     /// 4 character string to use as png chunk type. Invalid if the third character is lowercase.
+    #[arg(short, long, required_unless_present_any = ["label", "chunk_type_hex"])]
+    pub chunk_type: Option<String>,
+    /// Remove the message stored under this name with encode --label
+    #[arg(long, conflicts_with = "chunk_type", value_parser = parse_label)]
+    pub label: Option<String>,
+    /// Chunk type as 8 hex digits, such as 00495244, for damaged files whose chunk types
+    /// aren't made of letters
+    #[arg(
+        long,
+        value_name = "HEX",
+        conflicts_with_all = ["chunk_type", "label"],
+        value_parser = parse_chunk_type_hex
+    )]
+    pub chunk_type_hex: Option<ChunkType>,
+    /// Remove chunks given with --chunk-type-hex even if their type isn't made of letters
+    #[arg(long, requires = "chunk_type_hex")]
+    pub force: bool,
+    /// Remove every chunk of the type, whatever it holds, instead of the first message and
+    /// its redundant copies
+    #[arg(long, conflicts_with = "label")]
+    pub all: bool,
+    /// Remove only the chunk of the type at this position among chunks of that type, counting
+    /// from 0
+    #[arg(long, value_name = "N", conflicts_with_all = ["label", "all"])]
+    pub index: Option<usize>,
+    /// Decode the resulting image before writing it, refusing to write if that fails
+    #[arg(long)]
+    pub verify_image: bool,
+    /// Which of several png images concatenated in the file to use, counting from 0
+    #[arg(long)]
+    pub image_index: Option<usize>,
+    #[command(flatten)]
+    pub lock: LockArgs,
+    /// Print the digest of the written file as `<hex>  <path>`, the way sha256sum does, or
+    /// add it to the --json summary
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        default_missing_value = "sha256"
+    )]
+    pub print_hash: Option<HashAlgorithm>,
+    #[command(flatten)]
+    pub history: HistoryArgs,
+    #[command(flatten)]
+    pub audit: AuditArgs,
+    #[command(flatten)]
+    pub batch: BatchArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AuditTrailArgs {
+    /// Path to the png file whose audit trail is listed
+    #[arg(short, long)]
+    pub file_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct PrintArgs {
+    /// Path to the input png file from which an encoded message is to be printed to stdout
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Byte offset of a png embedded in a larger file. Parsing stops at its IEND.
+    #[arg(long)]
+    pub offset: Option<usize>,
+    /// Which of several png images concatenated in the file to use, counting from 0
+    #[arg(long)]
+    pub image_index: Option<usize>,
+    /// Only list the chunks of this type, given as 8 hex digits such as 00495244
+    #[arg(long, value_name = "HEX", value_parser = parse_chunk_type_hex)]
+    pub chunk_type_hex: Option<ChunkType>,
+    /// Order to list the chunks in. Chunks that compare equal stay in file order, and the
+    /// index shown is always the chunk's position in the file.
+    #[arg(long, value_enum, default_value_t)]
+    pub sort: ChunkOrder,
+    /// List the chunks in the opposite order
+    #[arg(long)]
+    pub reverse: bool,
+    /// Only list the N chunks with the most data
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+    /// Print a line per chunk from this template instead of the table, such as
+    /// "{index}\t{type}\t{length}". The fields are index, type, length, crc, crc_ok, offset,
+    /// category, flags, data_preview and data_base64. \t, \n, \\, {{ and }} are escapes.
+    #[arg(long, value_parser = parse_print_template)]
+    pub template: Option<Template>,
+    /// Exit with the integrity exit code if any chunk has an invalid CRC. Damaged chunks are
+    /// listed either way.
+    #[arg(long)]
+    pub check_crcs: bool,
+}
+
+/// The fields `print --template` fills in.
+pub const PRINT_FIELDS: &[&str] = &[
+    "index",
+    "type",
+    "length",
+    "crc",
+    "crc_ok",
+    "offset",
+    "category",
+    "flags",
+    "data_preview",
+    "data_base64",
+];
+
+fn parse_print_template(s: &str) -> Result<Template, String> {
+    Template::parse(s, PRINT_FIELDS).map_err(|e| e.to_string())
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Path to the input png file to check for structural problems
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Also fail on what the PNG spec lets decoders be lenient about: critical chunks it
+    /// doesn't define, private chunks marked critical, misplaced ancillary chunks and data in
+    /// IEND
+    #[arg(long)]
+    pub strict: bool,
+    /// Byte offset of a png embedded in a larger file. Parsing stops at its IEND.
+    #[arg(long)]
+    pub offset: Option<usize>,
+    /// Which of several png images concatenated in the file to use, counting from 0
+    #[arg(long)]
+    pub image_index: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct StripArgs {
+    /// Path to the input png file to strip
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Output path to write the stripped png file to instead of overwriting the input
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+    /// Only remove data appended after the final IEND, keeping every chunk
+    #[arg(long)]
+    pub trailing_only: bool,
+    /// Save the removed trailing data to this file
+    #[arg(long)]
+    pub save_trailing: Option<String>,
+    /// Also remove the chunk written by `pngme seal`, which is kept otherwise
+    #[arg(long, conflicts_with = "trailing_only")]
+    pub remove_seal: bool,
+    /// Also remove the audit trail written with --audit, which is kept otherwise
+    #[arg(long, conflicts_with_all = ["trailing_only", "audit"])]
+    pub remove_audit: bool,
+    #[command(flatten)]
+    pub audit: AuditArgs,
+    /// Decode the resulting image before writing it, refusing to write if that fails
+    #[arg(long)]
+    pub verify_image: bool,
+    #[command(flatten)]
+    pub lock: LockArgs,
+    /// Print the digest of the written file as `<hex>  <path>`, the way sha256sum does, or
+    /// add it to the --json summary
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        default_missing_value = "sha256"
+    )]
+    pub print_hash: Option<HashAlgorithm>,
+}
+
+#[derive(Args, Debug)]
+pub struct FindPngArgs {
+    /// Path to the file to search for embedded png images
+    #[arg(short, long)]
+    pub file_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct AtOffsetArgs {
+    /// Path to the png file the offset points into
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Byte offset into the file, in decimal or as 0x-prefixed hex as hex editors show it
+    #[arg(value_parser = parse_offset)]
+    pub offset: usize,
+    /// Also hex dump this many bytes on either side of the offset, marking where each part of
+    /// a chunk starts
+    #[arg(long, value_name = "BYTES")]
+    pub context: Option<usize>,
+}
+
+fn parse_offset(s: &str) -> Result<usize, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{} is not a byte offset: {}", s, e))
+}
+
+#[derive(Args, Debug)]
+pub struct LabelsArgs {
+    /// Path to the png file to list the labelled messages of
+    #[arg(short, long)]
+    pub file_path: String,
+}
+
+/// Labels are stored in the envelope as a length-prefixed field, so they have to be short.
+fn parse_label(s: &str) -> Result<String, String> {
+    match s.len() {
+        0 => Err("the label can't be empty".to_string()),
+        1..=255 => Ok(s.to_string()),
+        len => Err(format!(
+            "the label is {} bytes long, at most 255 are allowed",
+            len
+        )),
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct TuiArgs {
+    /// Path to the png file to browse
+    #[arg(short, long)]
+    pub file_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct RepairArgs {
+    /// Path to the damaged png file
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Output path to write the repaired png file to instead of overwriting the input
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+    /// Leave out chunks whose length and CRC can't be worked out, along with everything up to
+    /// the next intact chunk, instead of failing
+    #[arg(long)]
+    pub drop_unrecoverable: bool,
+    #[command(flatten)]
+    pub audit: AuditArgs,
+    /// Decode the resulting image before writing it, refusing to write if that fails
+    #[arg(long)]
+    pub verify_image: bool,
+    #[command(flatten)]
+    pub lock: LockArgs,
+    /// Print the digest of the written file as `<hex>  <path>`, the way sha256sum does, or
+    /// add it to the --json summary
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        default_missing_value = "sha256"
+    )]
+    pub print_hash: Option<HashAlgorithm>,
+}
+
+#[derive(Args, Debug)]
+pub struct CapacityArgs {
+    /// Path to the png file to estimate the room in
+    #[arg(short, long)]
+    pub file_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct EncodeTextArgs {
+    /// Path to the png file to add the text to
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Keyword the text is stored under, such as Title, Author or Description. 1 to 79
+    /// printable Latin-1 characters, without leading, trailing or consecutive spaces.
+    #[arg(short, long)]
+    pub keyword: String,
+    /// The text, in Latin-1. A tEXt or zTXt chunk with the same keyword is replaced where
+    /// it is.
+    #[arg(short, long)]
+    pub text: String,
+    /// Compress the text and store it in a zTXt chunk instead, for long descriptions
+    #[arg(long)]
+    pub compress: bool,
+    #[command(flatten)]
+    pub lock: LockArgs,
+    #[command(flatten)]
+    pub audit: AuditArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DecodeTextArgs {
+    /// Path to the png file to read the text from
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Only print the text stored under this keyword, without the keyword
+    #[arg(short, long)]
+    pub keyword: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct TriageArgs {
+    /// Path to the png file that won't open
+    #[arg(short, long)]
+    pub file_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct RewriteArgs {
+    /// Path to the png file whose chunks are to be rewritten
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Type of the chunks to rewrite, such as tEXt
     #[arg(short, long)]
     pub chunk_type: String,
+    /// Only rewrite text chunks whose keyword, the part of the data before the first null
+    /// byte, is this
+    #[arg(long)]
+    pub keyword: Option<String>,
+    /// The substitution to make in the chunk data, as in sed: s/find/replace/ replaces the
+    /// first match in each chunk, and the g flag every match
+    #[arg(long, value_name = "EXPRESSION")]
+    pub sed: String,
+    /// Match the find part of --sed exactly instead of as a regular expression
+    #[arg(long)]
+    pub literal: bool,
+    /// Which of several png images concatenated in the file to use, counting from 0
+    #[arg(long)]
+    pub image_index: Option<usize>,
+    /// Decode the resulting image before writing it, refusing to write if that fails
+    #[arg(long)]
+    pub verify_image: bool,
+    #[command(flatten)]
+    pub lock: LockArgs,
+    /// Print the digest of the written file as `<hex>  <path>`, the way sha256sum does, or
+    /// add it to the --json summary
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        num_args = 0..=1,
+        default_missing_value = "sha256"
+    )]
+    pub print_hash: Option<HashAlgorithm>,
+    #[command(flatten)]
+    pub audit: AuditArgs,
 }
 
 #[derive(Args, Debug)]
-pub struct RemoveArgs {
-    /// Path to the input png file from which an encoded message is to be removed
+pub struct ScanArgs {
+    /// File or directory to scan
+    #[arg(short, long, required_unless_present = "paths", default_value = "")]
+    pub file_path: String,
+    #[command(flatten)]
+    pub batch: BatchArgs,
+    /// Write the findings for every file and the totals to this JSON file
+    #[arg(long)]
+    pub report: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct EditArgs {
+    /// Path to the png file holding the message to edit
     #[arg(short, long)]
     pub file_path: String,
     /// 4 character string to use as png chunk type. Invalid if the third character is lowercase.
     #[arg(short, long)]
     pub chunk_type: String,
+    #[command(flatten)]
+    pub lock: LockArgs,
 }
 
 #[derive(Args, Debug)]
-pub struct PrintArgs {
-    /// Path to the input png file from which an encoded message is to be printed to stdout
+pub struct RedactArgs {
+    /// Path to the png file to redact a chunk in
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Type of the chunk whose data is overwritten
+    #[arg(short, long)]
+    pub chunk_type: String,
+    /// Which of several chunks of this type to redact, counting from 0. Every other copy or
+    /// shard of the message it holds is redacted with it.
+    #[arg(long, default_value_t = 0)]
+    pub index: usize,
+    /// Byte to overwrite the data with, in decimal or as 0x-prefixed hex
+    #[arg(long, default_value = "0x00", value_parser = parse_byte)]
+    pub fill: u8,
+    #[command(flatten)]
+    pub lock: LockArgs,
+}
+
+fn parse_expires(s: &str) -> Result<Expiry, String> {
+    Ok(Expiry {
+        at: expiry::parse(s, expiry::now())?,
+        relative: expiry::is_duration(s),
+    })
+}
+
+/// Reads a size in bytes with an optional unit: KiB, MiB and GiB count in powers of 1024, KB,
+/// MB and GB in powers of 1000.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let multiplier: usize = match unit.trim_start() {
+        "" | "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        unit => {
+            return Err(format!(
+                "unknown unit {:?}, use B, KiB, MiB, GiB, KB, MB or GB",
+                unit
+            ))
+        }
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("{} is not a size in bytes", s))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{} is not a byte value: {}", s, e))
+}
+
+/// Reads `--chunk-type-hex` without checking that the bytes are letters, as it is meant for
+/// types that aren't.
+fn parse_chunk_type_hex(s: &str) -> Result<ChunkType, String> {
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    if hex.len() != 8 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("{} is not 8 hex digits", s));
+    }
+    let code = u32::from_str_radix(hex, 16).map_err(|e| e.to_string())?;
+    Ok(ChunkType::from_bytes_unchecked(code.to_be_bytes()))
+}
+
+#[derive(Args, Debug)]
+pub struct SealArgs {
+    /// Path to the png file to seal
+    #[arg(short, long)]
+    pub file_path: String,
+    /// File holding the raw 32 byte key to authenticate the seal with
+    #[arg(long)]
+    pub key_file: String,
+    #[command(flatten)]
+    pub lock: LockArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AttestArgs {
+    /// Path to the sealed png file to check
+    #[arg(short, long)]
+    pub file_path: String,
+    /// File holding the raw 32 byte key the image was sealed with
+    #[arg(long)]
+    pub key_file: String,
+}
+
+#[derive(Args, Debug)]
+pub struct KeygenArgs {
+    /// Generate a random 32 byte key for --encrypt with --key-file
+    #[arg(long, required_unless_present = "signing", conflicts_with = "signing")]
+    pub symmetric: bool,
+    /// Generate an Ed25519 key pair for encode --sign, writing the public key for decode
+    /// --verify next to the private one with a .pub extension
+    #[arg(long)]
+    pub signing: bool,
+    /// Path to write the key to. Existing files are only overwritten with --force.
+    #[arg(short, long)]
+    pub out_path: String,
+    /// Replace the key in --out-path if there already is one
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ManArgs {
+    /// Write pngme.1 and a pngme-<subcommand>.1 page for every subcommand to this directory
+    /// instead of printing a page
+    #[arg(long, conflicts_with = "subcommand")]
+    pub out_dir: Option<String>,
+    /// The subcommand to print the page for, such as `encode` or `kv set`. Without it the page
+    /// for pngme itself is printed, ready for `man -l -`.
+    pub subcommand: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Path to the old version of the png
+    #[arg(short, long)]
+    pub file_path: String,
+    /// Path to the new version of the png
+    #[arg(short = 'g', long)]
+    pub other: String,
+    /// Write the changes to the ancillary and private chunks to this file, to replay them on
+    /// other files with `pngme patch --apply`
+    #[arg(long)]
+    pub emit_patch: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct PropagateArgs {
+    /// Path to the png to copy chunks from, usually the original an edited image was made from
+    #[arg(long)]
+    pub from: String,
+    /// Path to the re-encoded png to copy the chunks onto
+    #[arg(long)]
+    pub onto: String,
+    /// Write the result here instead of over --onto
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+    /// Also copy ancillary chunks of this type that aren't marked safe to copy, for types known
+    /// not to depend on the image data. Can be repeated.
+    #[arg(long, value_name = "TYPE")]
+    pub allow: Vec<String>,
+    #[command(flatten)]
+    pub lock: LockArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct PatchArgs {
+    /// Path to the png to change
     #[arg(short, long)]
     pub file_path: String,
+    /// Patch written by `pngme diff --emit-patch`
+    #[arg(long)]
+    pub apply: String,
+    /// Write the patched png here instead of over --file-path
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+    /// Apply the patch even where the file doesn't hold the chunk data the patch was made from
+    #[arg(long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub lock: LockArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GitFilterAction {
+    /// Copy the png on stdin to stdout without its pngme chunks
+    Clean {
+        /// Strip every chunk of this type instead of the chunks holding a pngme envelope. Can
+        /// be given more than once.
+        #[arg(short, long)]
+        chunk_type: Vec<String>,
+    },
+    /// Copy stdin to stdout unchanged
+    Smudge,
+}
+
+/// Set up with `git config filter.pngme.clean "pngme git-filter clean"` and
+/// `git config filter.pngme.smudge "pngme git-filter smudge"`, then `*.png filter=pngme` in
+/// .gitattributes.
+#[derive(Args, Debug)]
+pub struct GitFilterArgs {
+    #[command(subcommand)]
+    pub action: GitFilterAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyringAction {
+    /// Ask for a passphrase and store it under `id`
+    Set { id: String },
+    /// Print the passphrase stored under `id`
+    Get { id: String },
+    /// Remove the passphrase stored under `id`
+    Delete { id: String },
+}
+
+#[derive(Args, Debug)]
+pub struct KeyringArgs {
+    #[command(subcommand)]
+    pub action: KeyringAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KvAction {
+    /// Store a value under `key`, replacing any value already there
+    Set {
+        /// Path to the png file holding the store
+        #[arg(short, long)]
+        file_path: String,
+        key: String,
+        #[arg(required_unless_present = "value_file")]
+        value: Option<String>,
+        /// Read the value from this file instead, for binary data
+        #[arg(long, conflicts_with = "value")]
+        value_file: Option<String>,
+        #[command(flatten)]
+        lock: LockArgs,
+    },
+    /// Write the value stored under `key` to stdout
+    Get {
+        /// Path to the png file holding the store
+        #[arg(short, long)]
+        file_path: String,
+        key: String,
+    },
+    /// Remove `key` from the store
+    Del {
+        /// Path to the png file holding the store
+        #[arg(short, long)]
+        file_path: String,
+        key: String,
+        #[command(flatten)]
+        lock: LockArgs,
+    },
+    /// List the keys in the store
+    List {
+        /// Path to the png file holding the store
+        #[arg(short, long)]
+        file_path: String,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct KvArgs {
+    #[command(subcommand)]
+    pub action: KvAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FramesAction {
+    /// Write each frame to a png of its own, frame_000.png onward. A frame is written as the
+    /// region it stores, without the dispose and blend operations of the frames before it.
+    Extract {
+        /// Path to the animated png
+        #[arg(short, long)]
+        file_path: String,
+        /// Directory to write the frames to, created if it doesn't exist
+        #[arg(short = 'd', long)]
+        out_dir: String,
+    },
 }
 
+#[derive(Args, Debug)]
+pub struct FramesArgs {
+    #[command(subcommand)]
+    pub action: FramesAction,
+}
+
+/// Listed in `--help`, see `error::ExitCode`.
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  any other failure, including invalid arguments
+  2  the file doesn't exist or can't be read or written
+  3  the file isn't a png or can't be parsed
+  4  the chunk, message or value asked for isn't there
+  5  a CRC, checksum, seal, key, signature, HMAC, expiry or --expect check failed
+  6  the command refused to overwrite data without --force
+  130  interrupted with Ctrl-C before finishing";
+
+const CHECK_PROBLEMS: &str = "Problems:
+  Errors, bad CRCs and chunks cut short by the end of the file are problems, and make check
+  fail: with exit code 5 when the first of them is a bad CRC, with 3 otherwise. Warnings, such
+  as data after IEND, are only reported.";
+
+const ENCODE_EXAMPLES: &str = "Examples:
+  pngme encode -f image.png -c ruSt -m 'hello'
+  pngme encode -f image.png --label notes --message-file notes.txt --compress=zstd
+  pngme encode -f image.png -c ruSt -m 'secret' --encrypt --key-file key.bin
+  pngme encode -f image.png -c ruSt -m 'from me' --sign signing.key";
+
+const DECODE_EXAMPLES: &str = "Examples:
+  pngme decode -f image.png -c ruSt
+  pngme decode -f image.png --label notes
+  pngme decode -f image.png --all
+  pngme decode -f image.png -c ruSt --verify signing.key.pub
+  pngme decode -f image.png -c ruSt --mac-key shared.key";
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Report a failure as a single JSON object on stderr instead of a message
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+    /// Print one JSON document with the command's result, warnings and any error instead of
+    /// text. Same as --format json.
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// How to lay out output. Defaults to pretty on a terminal and plain when piped.
+    #[arg(long, global = true, value_enum)]
+    pub format: Option<Format>,
+    /// Print long output directly instead of through $PAGER
+    #[arg(long, global = true)]
+    pub no_pager: bool,
+    /// Give sizes in text output as exact byte counts, without units or separators, for
+    /// scripts. JSON always has exact counts.
+    #[arg(long, global = true)]
+    pub bytes: bool,
+    /// Time the read, parse, CRC, codec, serialize and write phases and print a breakdown on
+    /// stderr, or under `profile` with --json
+    #[arg(long, global = true)]
+    pub profile: bool,
+    /// Largest png to download when a file argument is an http or https URL, in bytes
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = remote::DEFAULT_MAX_DOWNLOAD)]
+    pub max_download: u64,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
 pub enum Command {
-    #[command(name = "encode", about = "encode a message into a png file")]
+    #[command(
+        name = "encode",
+        about = "encode a message into a png file",
+        after_long_help = ENCODE_EXAMPLES
+    )]
     Encode(EncodeArgs),
-    #[command(name = "decode", about = "decode a message from a png file")]
+    #[command(
+        name = "decode",
+        about = "decode a message from a png file",
+        after_long_help = DECODE_EXAMPLES
+    )]
     Decode(DecodeArgs),
     #[command(name = "remove", about = "remove a message from a png file")]
     Remove(RemoveArgs),
     #[command(name = "print", about = "print a message that is inside a png file")]
     Print(PrintArgs),
+    #[command(
+        name = "check",
+        visible_alias = "verify",
+        about = "validate the signature, chunk order, lengths and CRCs of a png file, reporting \
+                 each problem with its offset",
+        after_long_help = CHECK_PROBLEMS
+    )]
+    Check(CheckArgs),
+    #[command(
+        name = "strip",
+        about = "remove ancillary chunks and data appended after IEND from a png file"
+    )]
+    Strip(StripArgs),
+    #[command(
+        name = "find-png",
+        about = "list offsets of png images embedded in a file"
+    )]
+    FindPng(FindPngArgs),
+    #[command(
+        name = "at-offset",
+        about = "tell which chunk, and which part of it, holds a byte offset"
+    )]
+    AtOffset(AtOffsetArgs),
+    #[command(
+        name = "scan",
+        about = "audit png files for private, non-standard or oversized chunks and appended data"
+    )]
+    Scan(ScanArgs),
+    #[command(
+        name = "labels",
+        about = "list the labels of the messages stored in a png file"
+    )]
+    Labels(LabelsArgs),
+    #[command(
+        name = "edit",
+        about = "change a text message in place with $VISUAL or $EDITOR"
+    )]
+    Edit(EditArgs),
+    #[command(
+        name = "redact",
+        about = "overwrite the data of a chunk in place, keeping the file size"
+    )]
+    Redact(RedactArgs),
+    #[command(
+        name = "seal",
+        about = "store an authenticated hash of the image data in a png file"
+    )]
+    Seal(SealArgs),
+    #[command(
+        name = "attest",
+        about = "check that the image data still matches the seal in a png file"
+    )]
+    Attest(AttestArgs),
+    #[command(name = "keygen", about = "generate key material for encryption")]
+    Keygen(KeygenArgs),
+    #[command(
+        name = "keyring",
+        about = "manage passphrases stored in the platform keyring"
+    )]
+    Keyring(KeyringArgs),
+    #[command(
+        name = "tui",
+        about = "browse, delete and export the chunks of a png file (needs the `tui` feature)"
+    )]
+    Tui(TuiArgs),
+    #[command(
+        name = "kv",
+        about = "keep key-value pairs in a single chunk of a png file"
+    )]
+    Kv(KvArgs),
+    #[command(
+        name = "man",
+        about = "print or write manual pages for pngme and its subcommands"
+    )]
+    Man(ManArgs),
+    #[command(
+        name = "selftest",
+        about = "store and remove messages in a temporary png to check pngme works on this machine"
+    )]
+    Selftest,
+    #[command(
+        name = "git-filter",
+        about = "git clean and smudge filters keeping pngme chunks out of committed pngs"
+    )]
+    GitFilter(GitFilterArgs),
+    #[command(
+        name = "diff",
+        about = "list the ancillary chunks that differ between two png files"
+    )]
+    Diff(DiffArgs),
+    #[command(
+        name = "patch",
+        about = "replay chunk changes recorded with diff --emit-patch on a png file"
+    )]
+    Patch(PatchArgs),
+    #[command(
+        name = "propagate",
+        about = "copy the chunks that are safe to copy from a png onto a re-encoded version of it"
+    )]
+    Propagate(PropagateArgs),
+    #[command(
+        name = "undo",
+        about = "restore the message replaced or removed with --keep-previous"
+    )]
+    Undo(UndoArgs),
+    #[command(
+        name = "history",
+        about = "list the changes recorded in the audit trail of a png file"
+    )]
+    History(AuditTrailArgs),
+    #[command(
+        name = "frames",
+        about = "write the frames of an animated png to separate png files"
+    )]
+    Frames(FramesArgs),
+    #[command(
+        name = "triage",
+        about = "list what's wrong with a damaged png, most serious first, and whether it can be salvaged"
+    )]
+    Triage(TriageArgs),
+    #[command(
+        name = "rewrite",
+        about = "find and replace in the data of chunks of one type, in the style of sed"
+    )]
+    Rewrite(RewriteArgs),
+    #[command(
+        name = "encode-text",
+        about = "store a keyword and text in a standard tEXt or zTXt chunk that image viewers show"
+    )]
+    EncodeText(EncodeTextArgs),
+    #[command(
+        name = "decode-text",
+        about = "print the keywords and text of the tEXt and zTXt chunks in a png file"
+    )]
+    DecodeText(DecodeTextArgs),
+    #[command(
+        name = "repair",
+        about = "rewrite a damaged png with its bad CRCs recomputed and wrong chunk lengths corrected"
+    )]
+    Repair(RepairArgs),
+    #[command(
+        name = "capacity",
+        about = "estimate how many bytes a png file can hold with each way of storing a message"
+    )]
+    Capacity(CapacityArgs),
+}
+
+impl Command {
+    /// The name the command is run by.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Encode(_) => "encode",
+            Command::Decode(_) => "decode",
+            Command::Remove(_) => "remove",
+            Command::Print(_) => "print",
+            Command::Check(_) => "check",
+            Command::Strip(_) => "strip",
+            Command::FindPng(_) => "find-png",
+            Command::AtOffset(_) => "at-offset",
+            Command::Scan(_) => "scan",
+            Command::Labels(_) => "labels",
+            Command::Edit(_) => "edit",
+            Command::Redact(_) => "redact",
+            Command::Seal(_) => "seal",
+            Command::Attest(_) => "attest",
+            Command::Keygen(_) => "keygen",
+            Command::Keyring(_) => "keyring",
+            Command::Tui(_) => "tui",
+            Command::Kv(_) => "kv",
+            Command::Man(_) => "man",
+            Command::Selftest => "selftest",
+            Command::GitFilter(_) => "git-filter",
+            Command::Diff(_) => "diff",
+            Command::Patch(_) => "patch",
+            Command::Propagate(_) => "propagate",
+            Command::Undo(_) => "undo",
+            Command::History(_) => "history",
+            Command::Frames(_) => "frames",
+            Command::Triage(_) => "triage",
+            Command::Rewrite(_) => "rewrite",
+            Command::EncodeText(_) => "encode-text",
+            Command::DecodeText(_) => "decode-text",
+            Command::Repair(_) => "repair",
+            Command::Capacity(_) => "capacity",
+        }
+    }
+
+    /// The file the command works on, for error reports. None for a batch given no
+    /// --file-path, whose errors each name their own file.
+    pub fn file_path(&self) -> Option<&str> {
+        let path: Option<&str> = match self {
+            Command::Encode(args) => Some(args.carriers.as_ref().unwrap_or(&args.file_path)),
+            Command::Decode(args) => Some(args.carriers.as_ref().unwrap_or(&args.file_path)),
+            Command::Remove(args) => Some(&args.file_path),
+            Command::Print(args) => Some(&args.file_path),
+            Command::Check(args) => Some(&args.file_path),
+            Command::Strip(args) => Some(&args.file_path),
+            Command::FindPng(args) => Some(&args.file_path),
+            Command::AtOffset(args) => Some(&args.file_path),
+            Command::Scan(args) => Some(&args.file_path),
+            Command::Labels(args) => Some(&args.file_path),
+            Command::Edit(args) => Some(&args.file_path),
+            Command::Redact(args) => Some(&args.file_path),
+            Command::Seal(args) => Some(&args.file_path),
+            Command::Attest(args) => Some(&args.file_path),
+            Command::Keygen(args) => Some(&args.out_path),
+            Command::Keyring(_) | Command::Man(_) | Command::Selftest | Command::GitFilter(_) => {
+                None
+            }
+            Command::Tui(args) => Some(&args.file_path),
+            Command::Diff(args) => Some(&args.file_path),
+            Command::Patch(args) => Some(&args.file_path),
+            Command::Propagate(args) => Some(&args.onto),
+            Command::Undo(args) => Some(&args.file_path),
+            Command::History(args) => Some(&args.file_path),
+            Command::Kv(args) => match &args.action {
+                KvAction::Set { file_path, .. }
+                | KvAction::Get { file_path, .. }
+                | KvAction::Del { file_path, .. }
+                | KvAction::List { file_path } => Some(file_path),
+            },
+            Command::Triage(args) => Some(&args.file_path),
+            Command::Rewrite(args) => Some(&args.file_path),
+            Command::EncodeText(args) => Some(&args.file_path),
+            Command::DecodeText(args) => Some(&args.file_path),
+            Command::Repair(args) => Some(&args.file_path),
+            Command::Capacity(args) => Some(&args.file_path),
+            Command::Frames(args) => match &args.action {
+                FramesAction::Extract { file_path, .. } => Some(file_path),
+            },
+        };
+        path.filter(|path| !path.is_empty())
+    }
+
+    /// Where the command writes the png it changes, for those that write one back.
+    pub fn png_output(&self) -> Option<&str> {
+        match self {
+            Command::Encode(args) if args.carriers.is_none() => {
+                Some(args.out_path.as_deref().unwrap_or(&args.file_path))
+            }
+            Command::Strip(args) => Some(args.out_path.as_deref().unwrap_or(&args.file_path)),
+            Command::Repair(args) => Some(args.out_path.as_deref().unwrap_or(&args.file_path)),
+            Command::Patch(args) => Some(args.out_path.as_deref().unwrap_or(&args.file_path)),
+            Command::Propagate(args) => Some(args.out_path.as_deref().unwrap_or(&args.onto)),
+            Command::Remove(args) => Some(&args.file_path),
+            Command::Undo(args) => Some(&args.file_path),
+            Command::Edit(args) => Some(&args.file_path),
+            Command::Seal(args) => Some(&args.file_path),
+            Command::Rewrite(args) => Some(&args.file_path),
+            Command::EncodeText(args) => Some(&args.file_path),
+            Command::Kv(args) => match &args.action {
+                KvAction::Set { file_path, .. } | KvAction::Del { file_path, .. } => {
+                    Some(file_path)
+                }
+                KvAction::Get { .. } | KvAction::List { .. } => None,
+            },
+            _ => None,
+        }
+    }
 }
 
-pub fn parse_commands() -> Result<Command, &'static str> {
-    let args = Command::parse();
-    Ok(args)
+/// Parses the command line. `--help` and `--version` come back as errors too, which are
+/// printed to stdout rather than stderr.
+pub fn parse_commands() -> Result<Cli, clap::Error> {
+    Cli::try_parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte() {
+        assert_eq!(parse_byte("0xff"), Ok(255));
+        assert_eq!(parse_byte("0X1a"), Ok(26));
+        assert_eq!(parse_byte("42"), Ok(42));
+        assert!(parse_byte("256").is_err());
+        assert!(parse_byte("0xfff").is_err());
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("0x4F20"), Ok(0x4f20));
+        assert_eq!(parse_offset("20256"), Ok(20256));
+        assert!(parse_offset("0x").is_err());
+        assert!(parse_offset("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_chunk_type_hex() {
+        let ctype = parse_chunk_type_hex("00495244").unwrap();
+        assert_eq!(ctype.bytes(), [0x00, b'I', b'R', b'D']);
+        assert_eq!(
+            parse_chunk_type_hex("0x72755374").unwrap().to_string(),
+            "ruSt"
+        );
+        assert!(parse_chunk_type_hex("495244").is_err());
+        assert!(parse_chunk_type_hex("0049524g").is_err());
+    }
+
+    #[test]
+    fn test_print_template_lists_fields_on_error() {
+        let err = Cli::try_parse_from(["pngme", "print", "-f", "a.png", "--template", "{size}"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field {size}"), "{}", err);
+        assert!(err.contains("crc_ok, offset, category"), "{}", err);
+    }
+
+    #[test]
+    fn test_passphrase_is_redacted() {
+        let cli = Cli::try_parse_from([
+            "pngme",
+            "encode",
+            "-f",
+            "image.png",
+            "-c",
+            "ruSt",
+            "-m",
+            "hi",
+            "--encrypt",
+            "--passphrase",
+            "hunter2",
+        ])
+        .unwrap();
+        let command = cli.command;
+        let Command::Encode(args) = &command else {
+            panic!("expected encode");
+        };
+        assert_eq!(args.keys.passphrase.as_ref().unwrap().expose(), b"hunter2");
+        assert!(!format!("{:?}", command).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(parse_size("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_size("2MB"), Ok(2_000_000));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("1.5MiB").is_err());
+        assert!(parse_size("KiB").is_err());
+        assert!(parse_size(&format!("{}GiB", usize::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_verify_is_check() {
+        let cli = Cli::try_parse_from(["pngme", "verify", "-f", "image.png", "--strict"]).unwrap();
+        let Command::Check(args) = cli.command else {
+            panic!("expected check");
+        };
+        assert!(args.strict);
+    }
+
+    #[test]
+    fn test_input_file_is_the_message_file() {
+        let cli = Cli::try_parse_from([
+            "pngme",
+            "encode",
+            "-f",
+            "image.png",
+            "-c",
+            "ruSt",
+            "--input-file",
+            "report.pdf",
+        ])
+        .unwrap();
+        let Command::Encode(args) = cli.command else {
+            panic!("expected encode");
+        };
+        assert_eq!(args.message_file.as_deref(), Some("report.pdf"));
+    }
+
+    #[test]
+    fn test_no_pager_after_the_subcommand() {
+        let cli = Cli::try_parse_from(["pngme", "print", "-f", "image.png", "--no-pager"]);
+        assert!(cli.unwrap().no_pager);
+    }
+
+    #[test]
+    fn test_json_errors_after_the_subcommand() {
+        let cli = Cli::try_parse_from(["pngme", "check", "-f", "image.png", "--json-errors"]);
+        let cli = cli.unwrap();
+        assert!(cli.json_errors);
+        assert_eq!(cli.command.file_path(), Some("image.png"));
+    }
+
+    #[test]
+    fn test_compress_defaults_to_deflate() {
+        let compress = |extra: &[&str]| {
+            let cli = Cli::try_parse_from(
+                [
+                    &[
+                        "pngme",
+                        "encode",
+                        "-f",
+                        "image.png",
+                        "-c",
+                        "ruSt",
+                        "-m",
+                        "hi",
+                    ],
+                    extra,
+                ]
+                .concat(),
+            );
+            let Command::Encode(args) = cli.unwrap().command else {
+                panic!("expected encode");
+            };
+            args.compress
+        };
+        assert_eq!(compress(&[]), None);
+        assert_eq!(compress(&["--compress"]), Some(Algorithm::Deflate));
+        assert_eq!(compress(&["--compress=zstd"]), Some(Algorithm::Zstd));
+        assert_eq!(compress(&["--compress=none"]), Some(Algorithm::None));
+        // Without the = a following path isn't taken for the algorithm
+        let cli = Cli::try_parse_from([
+            "pngme",
+            "encode",
+            "-c",
+            "ruSt",
+            "-m",
+            "hi",
+            "--compress",
+            "a.png",
+            "b.png",
+        ]);
+        let Command::Encode(args) = cli.unwrap().command else {
+            panic!("expected encode");
+        };
+        assert_eq!(args.compress, Some(Algorithm::Deflate));
+        assert_eq!(args.batch.paths, ["a.png", "b.png"]);
+    }
 }
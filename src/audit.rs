@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::expiry;
+use crate::png::Png;
+
+/// Private and ancillary, holding the audit trail. strip keeps it unless asked not to.
+pub const AUDIT_CHUNK: &str = "auDt";
+const AUDIT_VERSION: u8 = 1;
+
+/// The audit chunk can't be read.
+#[derive(Debug)]
+pub struct AuditError {
+    reason: String,
+}
+impl AuditError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Audit trail error: {}", self.reason)
+    }
+}
+impl Error for AuditError {}
+
+/// One change made to the file. Only what was done is recorded, never the messages involved.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch, in UTC
+    pub timestamp: u64,
+    /// The pngme version that made the change
+    pub tool_version: String,
+    /// The command that made the change, such as `encode` or `strip`
+    pub operation: String,
+    /// Types of the chunks the change added or removed
+    pub chunk_types: Vec<String>,
+    pub note: Option<String>,
+}
+
+impl Entry {
+    /// An entry for `operation` made now by this build of pngme.
+    pub fn now(operation: &str, chunk_types: Vec<String>, note: Option<String>) -> Self {
+        Self {
+            timestamp: expiry::now(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            operation: operation.to_string(),
+            chunk_types,
+            note,
+        }
+    }
+}
+
+/// The entries of the audit trail in `png`, oldest first.
+pub fn entries(png: &Png) -> crate::Result<Vec<Entry>> {
+    match png.chunk_by_type(AUDIT_CHUNK) {
+        Some(chunk) => decode(chunk.data()),
+        None => Ok(vec![]),
+    }
+}
+
+/// Adds `entry` to the end of the audit trail, creating the chunk before IEND the first time.
+/// Existing records are kept byte for byte.
+pub fn append(png: &mut Png, entry: &Entry) -> crate::Result<()> {
+    let existing = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().to_string() == AUDIT_CHUNK);
+    let mut data = match existing {
+        Some(idx) => {
+            let data = png.chunks()[idx].data().to_vec();
+            // Refuse to add to a trail that can't be read back
+            decode(&data)?;
+            png.remove_chunk(idx)?;
+            data
+        }
+        None => vec![AUDIT_VERSION],
+    };
+    let record = encode(entry)?;
+    data.extend_from_slice(&(record.len() as u32).to_be_bytes());
+    data.extend_from_slice(&record);
+    let chunk = Chunk::new(ChunkType::from_str(AUDIT_CHUNK)?, data);
+    let iend = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().bytes() == *b"IEND");
+    match existing.or(iend) {
+        Some(idx) => png.insert_chunk(idx, chunk)?,
+        None => png.append_chunk(chunk),
+    }
+    Ok(())
+}
+
+/// A record as a big endian u64 timestamp, the tool version and operation each as a u8
+/// length and the text, a u8 count of chunk types and their 4 bytes each, then the note as a
+/// big endian u16 length and the text, empty when there is none.
+fn encode(entry: &Entry) -> crate::Result<Vec<u8>> {
+    let too_long = |what: &str| AuditError::boxed(format!("the {} is too long", what));
+    let mut out = entry.timestamp.to_be_bytes().to_vec();
+    for (what, text) in [
+        ("tool version", &entry.tool_version),
+        ("operation", &entry.operation),
+    ] {
+        out.push(u8::try_from(text.len()).map_err(|_| too_long(what))?);
+        out.extend_from_slice(text.as_bytes());
+    }
+    out.push(u8::try_from(entry.chunk_types.len()).map_err(|_| too_long("chunk type list"))?);
+    for chunk_type in &entry.chunk_types {
+        out.extend_from_slice(&ChunkType::from_str(chunk_type)?.bytes());
+    }
+    let note = entry.note.as_deref().unwrap_or("");
+    out.extend_from_slice(
+        &u16::try_from(note.len())
+            .map_err(|_| too_long("note"))?
+            .to_be_bytes(),
+    );
+    out.extend_from_slice(note.as_bytes());
+    Ok(out)
+}
+
+fn decode(data: &[u8]) -> crate::Result<Vec<Entry>> {
+    let truncated = || AuditError::boxed("the trail is truncated".to_string());
+    let mut rest = match data.split_first() {
+        Some((&AUDIT_VERSION, rest)) => rest,
+        Some((version, _)) => {
+            return Err(AuditError::boxed(format!(
+                "unsupported trail version {}",
+                version
+            )))
+        }
+        None => return Err(truncated()),
+    };
+    let mut entries = vec![];
+    while !rest.is_empty() {
+        let (len, after) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        let record = after.get(..len).ok_or_else(truncated)?;
+        entries.push(decode_record(record).ok_or_else(truncated)?);
+        rest = &after[len..];
+    }
+    Ok(entries)
+}
+
+fn decode_record(mut record: &[u8]) -> Option<Entry> {
+    let timestamp = u64::from_be_bytes(take(&mut record, 8)?.try_into().ok()?);
+    let len = take(&mut record, 1)?[0] as usize;
+    let tool_version = text(take(&mut record, len)?)?;
+    let len = take(&mut record, 1)?[0] as usize;
+    let operation = text(take(&mut record, len)?)?;
+    let count = take(&mut record, 1)?[0] as usize;
+    let chunk_types = (0..count)
+        .map(|_| text(take(&mut record, 4)?))
+        .collect::<Option<Vec<_>>>()?;
+    let len = u16::from_be_bytes(take(&mut record, 2)?.try_into().ok()?) as usize;
+    let note = text(take(&mut record, len)?)?;
+    Some(Entry {
+        timestamp,
+        tool_version,
+        operation,
+        chunk_types,
+        note: Some(note).filter(|note| !note.is_empty()),
+    })
+}
+
+/// Splits the first `len` bytes off `rest`.
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let (taken, after) = (rest.get(..len)?, &rest[len..]);
+    *rest = after;
+    Some(taken)
+}
+
+fn text(bytes: &[u8]) -> Option<String> {
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_appends_in_order() {
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), b"data".to_vec());
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+        assert!(entries(&png).unwrap().is_empty());
+        let first = Entry::now("encode", vec!["ruSt".to_string()], None);
+        let second = Entry::now("strip", vec![], Some("release build".to_string()));
+        append(&mut png, &first).unwrap();
+        append(&mut png, &second).unwrap();
+
+        let bytes = png.as_bytes();
+        let reread = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(reread.chunks()[2].chunk_type().to_string(), AUDIT_CHUNK);
+        assert_eq!(entries(&reread).unwrap(), vec![first, second]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Running a command over many files at once: the paths given, the png files in the
+//! directories given (and their subdirectories with `--recursive`), and the files matching
+//! glob patterns, each processed on its own so one bad file doesn't stop the rest.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Paths that can't be expanded into files, or files of a batch that failed.
+#[derive(Debug)]
+pub struct BatchError {
+    reason: String,
+}
+impl BatchError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+
+    /// The error a batch run ends with when `failed` of its `total` files failed.
+    pub fn failed(failed: usize, total: usize) -> Box<Self> {
+        Self::boxed(format!("{} of {} file(s) failed", failed, total))
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Batch error: {}", self.reason)
+    }
+}
+impl Error for BatchError {}
+
+/// Whether `paths` call for a batch run rather than a single file: more than one path, a
+/// directory or a glob pattern.
+pub fn is_batch(paths: &[String]) -> bool {
+    paths.len() > 1
+        || paths
+            .iter()
+            .any(|path| is_pattern(path) || Path::new(path).is_dir())
+}
+
+/// The files `paths` stand for, in the order given and without repeats. Directories give
+/// the files in them with a .png extension, sorted by path, and with `recursive` those of
+/// their subdirectories too. Paths that are neither are kept as they are, so a missing file
+/// is reported as such when it is processed.
+pub fn expand(paths: &[String], recursive: bool) -> crate::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = vec![];
+    for path in paths {
+        let found = match is_pattern(path) {
+            true => glob(path)?,
+            false if Path::new(path).is_dir() => {
+                let mut found = vec![];
+                collect_pngs(Path::new(path), recursive, &mut found)?;
+                found.sort();
+                found
+            }
+            false => vec![PathBuf::from(path)],
+        };
+        for file in found {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(BatchError::boxed(format!(
+            "no png files found in {}",
+            paths.join(", ")
+        )));
+    }
+    Ok(files)
+}
+
+/// `paths` with glob patterns replaced by the files they match, for commands that walk the
+/// directories given themselves.
+pub fn roots(paths: &[String]) -> crate::Result<Vec<PathBuf>> {
+    let mut roots = vec![];
+    for path in paths {
+        match is_pattern(path) {
+            true => roots.extend(glob(path)?),
+            false => roots.push(PathBuf::from(path)),
+        }
+    }
+    Ok(roots)
+}
+
+/// The files matching `pattern`, sorted by path. `*` and `?` may appear in the file name but
+/// not in the directories leading to it, and as in a shell they don't match a leading dot.
+pub fn glob(pattern: &str) -> crate::Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| BatchError::boxed(format!("{} doesn't end in a file name", pattern)))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if is_pattern(&dir.to_string_lossy()) {
+        return Err(BatchError::boxed(format!(
+            "{} has wildcards outside the file name, which aren't supported",
+            pattern
+        )));
+    }
+    let wanted: Vec<char> = name.chars().collect();
+    let mut found = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        let hidden = file_name.starts_with('.') && !name.starts_with('.');
+        let chars: Vec<char> = file_name.chars().collect();
+        if !hidden && entry.file_type()?.is_file() && matches(&wanted, &chars) {
+            found.push(path.with_file_name(file_name));
+        }
+    }
+    if found.is_empty() {
+        return Err(BatchError::boxed(format!("no file matches {}", pattern)));
+    }
+    found.sort();
+    Ok(found)
+}
+
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Adds the .png files in `dir` to `files`, skipping hidden ones as a glob would.
+fn collect_pngs(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> crate::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() && recursive {
+            collect_pngs(&path, recursive, files)?;
+        } else if file_type.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` matches the shell wildcard `pattern`.
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some(('*', rest)), _) => {
+            matches(rest, name) || (!name.is_empty() && matches(pattern, &name[1..]))
+        }
+        (Some(('?', rest)), Some((_, tail))) => matches(rest, tail),
+        (Some((wanted, rest)), Some((c, tail))) => wanted == c && matches(rest, tail),
+        (Some(_), None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+        let cases = [
+            ("*.png", "a.png", true),
+            ("*.png", "a.png.bak", false),
+            ("shot-??.png", "shot-01.png", true),
+            ("shot-??.png", "shot-1.png", false),
+            ("*", "", true),
+            ("a*b*c", "aXbYbc", true),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(
+                matches(&chars(pattern), &chars(name)),
+                expected,
+                "{}",
+                pattern
+            );
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.png", "a.png", ".hidden.png", "c.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        fs::create_dir(dir.path().join("d.png")).unwrap();
+        let pattern = dir.path().join("*.png");
+        let found = glob(pattern.to_str().unwrap()).unwrap();
+        assert_eq!(found, [dir.path().join("a.png"), dir.path().join("b.png")]);
+        let none = dir.path().join("*.gif");
+        assert!(glob(none.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        fs::create_dir_all(path("shots/old")).unwrap();
+        for name in [
+            "shots/b.png",
+            "shots/a.PNG",
+            "shots/notes.txt",
+            "shots/old/c.png",
+        ] {
+            fs::write(path(name), b"").unwrap();
+        }
+        let arg = |name: &str| path(name).to_str().unwrap().to_string();
+
+        let shots = [arg("shots")];
+        assert!(is_batch(&shots));
+        assert_eq!(
+            expand(&shots, false).unwrap(),
+            [path("shots/a.PNG"), path("shots/b.png")]
+        );
+        assert_eq!(expand(&shots, true).unwrap().len(), 3);
+
+        // Repeats are dropped, and files that don't exist are left for the command to report
+        let listed = [arg("shots/b.png"), arg("shots/*.png"), arg("missing.png")];
+        assert!(is_batch(&listed));
+        assert_eq!(
+            expand(&listed, false).unwrap(),
+            [path("shots/b.png"), path("missing.png")]
+        );
+
+        assert!(!is_batch(&[arg("shots/b.png")]));
+        assert!(expand(&[arg("shots/old/*.gif")], false).is_err());
+    }
+}
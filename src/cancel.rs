@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// The token Ctrl-C trips, shared by the whole process.
+static INTERRUPT: OnceLock<Cancel> = OnceLock::new();
+/// Set while no `Deferred` is alive, when Ctrl-C ends the process right away.
+static IDLE: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+static DEFERRED: AtomicUsize = AtomicUsize::new(0);
+
+/// An operation was cancelled before it finished, by Ctrl-C or by whoever started it.
+#[derive(Debug)]
+pub struct CancelledError;
+
+impl fmt::Display for CancelledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled before anything was written")
+    }
+}
+impl Error for CancelledError {}
+
+/// Stops a long operation part way. Clones share one flag, so the caller keeps one to cancel
+/// with and hands another to the operation, which checks it between steps.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `CancelledError` once cancelled, for use with `?` between steps.
+    pub fn check(&self) -> crate::Result<()> {
+        match self.is_cancelled() {
+            true => Err(Box::new(CancelledError)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The token Ctrl-C trips once `install` has been called.
+pub fn interrupt() -> Cancel {
+    INTERRUPT.get_or_init(Cancel::new).clone()
+}
+
+fn idle() -> Arc<AtomicBool> {
+    IDLE.get_or_init(|| Arc::new(AtomicBool::new(true))).clone()
+}
+
+/// Makes Ctrl-C end the process with `status` right away, as it would anyway, except while a
+/// `Deferred` is alive, when it trips `interrupt` instead. A second Ctrl-C always ends it.
+#[cfg(unix)]
+pub fn install(status: u8) -> io::Result<()> {
+    use signal_hook::consts::SIGINT;
+    use signal_hook::flag;
+    // Actions run in the order they were registered, so the shutdowns go before the flag
+    flag::register_conditional_shutdown(SIGINT, status.into(), idle())?;
+    flag::register_conditional_shutdown(SIGINT, status.into(), interrupt().0)?;
+    flag::register(SIGINT, interrupt().0)?;
+    Ok(())
+}
+
+/// Without signals to catch, Ctrl-C keeps ending the process however the platform does.
+#[cfg(not(unix))]
+pub fn install(_status: u8) -> io::Result<()> {
+    Ok(())
+}
+
+/// While one is alive, Ctrl-C trips `interrupt` instead of ending the process, so the running
+/// operation can stop where it leaves nothing half written.
+pub struct Deferred(());
+
+pub fn defer() -> Deferred {
+    if DEFERRED.fetch_add(1, Ordering::SeqCst) == 0 {
+        idle().store(false, Ordering::SeqCst);
+    }
+    Deferred(())
+}
+
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        if DEFERRED.fetch_sub(1, Ordering::SeqCst) == 1 {
+            idle().store(true, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! How much a png can carry under each way pngme has of storing a message, both as far as the
+//! spec allows and as far as common decoders will still read the file.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::envelope::Envelope;
+use crate::lsb;
+use crate::png::Png;
+use crate::shard;
+
+/// The most data a chunk can hold: its length field is 4 bytes, but the spec caps it at 2^31-1.
+pub const MAX_CHUNK_LEN: usize = (1 << 31) - 1;
+
+/// The biggest ancillary chunk libpng reads by default (its user chunk malloc max), and so the
+/// biggest that browsers and viewers built on it can be relied on to accept.
+pub const PRACTICAL_CHUNK_LEN: usize = 8_000_000;
+
+/// How many ancillary chunks libpng reads by default (its user chunk cache max) before it
+/// gives up on the file.
+pub const PRACTICAL_CHUNK_COUNT: usize = 1000;
+
+/// The keyword text chunks are estimated with, as pngme would pick for a message.
+const KEYWORD: &str = "Comment";
+
+/// A way of storing a message in a png.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The message as is in a single private chunk
+    Chunk,
+    /// The message split across as many chunks as it takes, as encode --max-chunk-size does
+    Split,
+    /// The message base64 encoded in a tEXt chunk
+    Text,
+    /// The message base64 encoded in a zTXt chunk
+    CompressedText,
+    /// The message in the lowest bit of every sample, as encode --mode lsb does
+    Lsb,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Strategy::Chunk => "single chunk",
+            Strategy::Split => "split chunks",
+            Strategy::Text => "tEXt",
+            Strategy::CompressedText => "zTXt",
+            Strategy::Lsb => "pixel LSB",
+        })
+    }
+}
+
+/// How many message bytes one strategy can store in a png.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Estimate {
+    pub strategy: Strategy,
+    /// The most the spec allows, or `None` when the strategy can't be used on this png
+    pub limit: Option<usize>,
+    /// The most that common decoders will still read
+    pub practical: Option<usize>,
+    /// What the numbers assume, or why the strategy can't be used
+    pub note: String,
+}
+
+/// The estimate for every strategy, in the order of `Strategy`.
+pub fn estimate(png: &Png) -> Vec<Estimate> {
+    let ancillary = png
+        .chunks()
+        .iter()
+        .filter(|c| !c.chunk_type().is_critical())
+        .count();
+    let shard_overhead = shard_overhead();
+    let shards_left = PRACTICAL_CHUNK_COUNT.saturating_sub(ancillary);
+    // Text chunks hold Latin-1 without nulls, so arbitrary bytes go in as base64
+    let text = |len: usize, header: usize| len.saturating_sub(KEYWORD.len() + header) / 4 * 3;
+    let lsb = match lsb::capacity(png) {
+        Ok(capacity) => {
+            let capacity = capacity.saturating_sub(Envelope::new(vec![]).as_bytes().len());
+            Estimate {
+                strategy: Strategy::Lsb,
+                limit: Some(capacity),
+                practical: Some(capacity),
+                note: "changes the pixels slightly; lost when the image is re-encoded".to_string(),
+            }
+        }
+        Err(e) => Estimate {
+            strategy: Strategy::Lsb,
+            limit: None,
+            practical: None,
+            note: e.to_string(),
+        },
+    };
+    vec![
+        Estimate {
+            strategy: Strategy::Chunk,
+            limit: Some(MAX_CHUNK_LEN),
+            practical: Some(PRACTICAL_CHUNK_LEN),
+            note: "envelope options such as --ecc or --encrypt take a few bytes more".to_string(),
+        },
+        Estimate {
+            strategy: Strategy::Split,
+            limit: Some(usize::from(u16::MAX) * (MAX_CHUNK_LEN - shard_overhead)),
+            practical: Some(shards_left * (PRACTICAL_CHUNK_LEN - shard_overhead)),
+            note: format!(
+                "{} more ancillary chunk(s) before libpng stops reading them",
+                shards_left
+            ),
+        },
+        Estimate {
+            strategy: Strategy::Text,
+            limit: Some(text(MAX_CHUNK_LEN, 1)),
+            practical: Some(text(PRACTICAL_CHUNK_LEN, 1)),
+            note: "as base64; Latin-1 text without nulls fits a third more".to_string(),
+        },
+        Estimate {
+            strategy: Strategy::CompressedText,
+            limit: Some(text(MAX_CHUNK_LEN, 2)),
+            practical: Some(text(PRACTICAL_CHUNK_LEN, 2)),
+            note: "as base64, before compression; libpng also caps the inflated text".to_string(),
+        },
+        lsb,
+    ]
+}
+
+/// Bytes the envelope of each shard adds to its part of the message.
+fn shard_overhead() -> usize {
+    let shards = shard::split(Envelope::new(vec![0]), MAX_CHUNK_LEN)
+        .expect("one byte always fits in a single shard");
+    shards[0].as_bytes().len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// An 8x8 8-bit RGB image.
+    fn rgb_png() -> Png {
+        let ihdr = [0, 0, 0, 8, 0, 0, 0, 8, 8, 2, 0, 0, 0];
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&[0; 8 * (1 + 8 * 3)]).unwrap();
+        Png::from_chunks(vec![
+            chunk("IHDR", &ihdr),
+            chunk("tEXt", b"Title\0t"),
+            chunk("IDAT", &encoder.finish().unwrap()),
+            chunk("IEND", b""),
+        ])
+    }
+
+    #[test]
+    fn test_estimate() {
+        let estimates = estimate(&rgb_png());
+        let strategies: Vec<Strategy> = estimates.iter().map(|e| e.strategy).collect();
+        assert_eq!(
+            strategies,
+            [
+                Strategy::Chunk,
+                Strategy::Split,
+                Strategy::Text,
+                Strategy::CompressedText,
+                Strategy::Lsb
+            ]
+        );
+        assert_eq!(estimates[0].limit, Some(MAX_CHUNK_LEN));
+        assert_eq!(estimates[0].practical, Some(PRACTICAL_CHUNK_LEN));
+        // The tEXt chunk already in the file counts against the chunks libpng reads
+        let split = estimates[1].practical.unwrap();
+        assert!(split > 998 * (PRACTICAL_CHUNK_LEN - 100) && split < 999 * PRACTICAL_CHUNK_LEN);
+        assert!(estimates[1].note.starts_with("999 "));
+        assert_eq!(
+            estimates[2].practical,
+            Some((PRACTICAL_CHUNK_LEN - 8) / 4 * 3)
+        );
+        assert!(estimates[3].practical < estimates[2].practical);
+
+        let lsb = lsb::capacity(&rgb_png()).unwrap();
+        let envelope = Envelope::new(vec![]).as_bytes().len();
+        assert_eq!(estimates[4].practical, Some(lsb - envelope));
+    }
+
+    #[test]
+    fn test_lsb_unsupported() {
+        let png = Png::from_chunks(vec![chunk("IHDR", &[0; 13]), chunk("IEND", b"")]);
+        let lsb = &estimate(&png)[4];
+        assert_eq!(lsb.limit, None);
+        assert!(lsb.note.contains("only 8-bit"), "{}", lsb.note);
+    }
+}
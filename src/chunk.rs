@@ -4,24 +4,83 @@ use std::convert::{TryFrom, TryInto};
 use std::error::Error;
 use std::fmt;
 
+/// A chunk's length, type, data and stored CRC. The CRC is computed whenever the data is set
+/// rather than lazily on first use, so a `Chunk` has no interior mutability and is `Send` and
+/// `Sync`.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Chunk {
     len: u32,
     chunktype: ChunkType,
-    data: Vec<u8>,
+    data: Data,
     crc: u32,
 }
 
-const CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+pub(crate) const CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Data of up to this many bytes is kept inside the `Chunk`, which spares files made of
+/// thousands of tiny chunks like fcTL, tIME or pHYs a heap allocation for each.
+const INLINE_LEN: usize = 32;
+
+/// The data of a chunk, kept inline when it is small.
+#[derive(Clone)]
+enum Data {
+    Inline { len: u8, bytes: [u8; INLINE_LEN] },
+    Heap(Vec<u8>),
+}
+
+impl Data {
+    fn from_slice(data: &[u8]) -> Self {
+        match data.len() {
+            len @ 0..=INLINE_LEN => {
+                let mut bytes = [0; INLINE_LEN];
+                bytes[..len].copy_from_slice(data);
+                Data::Inline {
+                    len: len as u8,
+                    bytes,
+                }
+            }
+            _ => Data::Heap(data.to_vec()),
+        }
+    }
+
+    fn from_vec(data: Vec<u8>) -> Self {
+        match data.len() {
+            0..=INLINE_LEN => Self::from_slice(&data),
+            _ => Data::Heap(data),
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Data::Inline { len, bytes } => &bytes[..*len as usize],
+            Data::Heap(data) => data,
+        }
+    }
+}
+
+impl PartialEq for Data {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+impl Eq for Data {}
+
+impl fmt::Debug for Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
 
 #[allow(dead_code)]
 impl Chunk {
-    pub fn new(chunktype: ChunkType, data: Vec<u8>) -> Self {
+    pub fn new(chunktype: ChunkType, data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        let crc = checksum(chunktype, &data);
         Self {
             len: data.len() as u32,
-            chunktype: chunktype.clone(),
-            data: data.clone(),
-            crc: CRC_PNG.checksum(&[&chunktype.bytes(), data.as_slice()].concat()),
+            chunktype,
+            data: Data::from_vec(data),
+            crc,
         }
     }
 
@@ -37,7 +96,7 @@ impl Chunk {
 
     /// The raw data contained in this chunk in bytes
     pub fn data(&self) -> &[u8] {
-        &self.data
+        self.data.as_slice()
     }
 
     /// The CRC of this chunk
@@ -45,10 +104,59 @@ impl Chunk {
         self.crc
     }
 
+    /// Builds a chunk from its already separated parts, keeping `crc` as given.
+    pub(crate) fn from_parts(chunktype: ChunkType, data: Vec<u8>, crc: u32) -> Self {
+        Self {
+            len: data.len() as u32,
+            chunktype,
+            data: Data::from_vec(data),
+            crc,
+        }
+    }
+
+    /// Replaces the data of this chunk, updating its length and CRC to match.
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.len = data.len() as u32;
+        self.data = Data::from_vec(data);
+        self.crc = self.computed_crc();
+    }
+
+    /// Parses a chunk from its byte representation without checking the stored CRC.
+    /// The chunk keeps the CRC found in `bytes`, so `has_valid_crc` reports whether it matches.
+    pub(crate) fn from_bytes_unverified(bytes: &[u8]) -> Result<Self, crate::Error> {
+        if bytes.len() < 12 {
+            return Err(ChunkDecodingError::boxed(format!(
+                "Too short (received {} bytes, need at least 12)",
+                bytes.len()
+            )));
+        }
+        let chunktype = ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]])?;
+        let data = &bytes[8..bytes.len() - 4];
+        let crc = u32::from_be_bytes(bytes[bytes.len() - 4..].try_into().unwrap());
+        Ok(Self {
+            len: data.len() as u32,
+            chunktype,
+            data: Data::from_slice(data),
+            crc,
+        })
+    }
+
+    /// The CRC computed over the type and data of this chunk, which may differ from the
+    /// stored `crc` for chunks parsed leniently.
+    pub fn computed_crc(&self) -> u32 {
+        checksum(self.chunktype, self.data())
+    }
+
+    /// Whether the stored CRC matches the chunk's type and data.
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc == self.computed_crc()
+    }
+
     /// Returns the data stored in this chunk as a `String`. This function will return an error
     /// if the stored data is not valid UTF-8.
+    #[allow(clippy::result_unit_err)]
     pub fn data_as_string(&self) -> Result<String, ()> {
-        Ok(String::from_utf8(self.data.clone()).unwrap())
+        String::from_utf8(self.data().to_vec()).map_err(|_| ())
     }
 
     /// Returns this chunk as a byte sequences described by the PNG spec.
@@ -59,29 +167,49 @@ impl Chunk {
     /// 4. The CRC of the chunk type and data *(4 bytes)*
     pub fn as_bytes(&self) -> Vec<u8> {
         [
-            self.len.to_be_bytes().to_vec(),
-            vec![
-                self.chunktype.bytes()[0],
-                self.chunktype.bytes()[1],
-                self.chunktype.bytes()[2],
-                self.chunktype.bytes()[3],
-            ],
-            self.data.clone(),
-            self.crc.to_be_bytes().to_vec(),
+            &self.len.to_be_bytes()[..],
+            &self.chunktype.bytes(),
+            self.data(),
+            &self.crc.to_be_bytes(),
         ]
         .concat()
     }
 }
 
+/// The CRC the PNG spec calls for over a chunk's type and data.
+fn checksum(chunktype: ChunkType, data: &[u8]) -> u32 {
+    let mut digest = CRC_PNG.digest();
+    digest.update(&chunktype.bytes());
+    digest.update(data);
+    digest.finalize()
+}
+
 /// Something went wrong while decoding a chunk.
 #[derive(Debug)]
 pub struct ChunkDecodingError {
     /// The reason that decoding went wrong.
     reason: String,
+    /// Whether the chunk was intact apart from its CRC.
+    bad_crc: bool,
 }
 impl ChunkDecodingError {
     fn boxed(reason: String) -> Box<Self> {
-        Box::new(Self { reason })
+        Box::new(Self {
+            reason,
+            bad_crc: false,
+        })
+    }
+
+    fn bad_crc(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            bad_crc: true,
+        })
+    }
+
+    /// Whether the stored CRC didn't match the chunk, rather than the chunk being malformed.
+    pub fn is_bad_crc(&self) -> bool {
+        self.bad_crc
     }
 }
 
@@ -96,38 +224,12 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = crate::Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let mut c = Self {
-            len: u32::from_be_bytes(bytes[0..4].try_into().unwrap()),
-            chunktype: ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]])?,
-            data: vec![],
-            crc: 0,
-        };
-
-        let mut data: Vec<u8> = vec![];
-        for (idx, b) in bytes.iter().enumerate() {
-            if idx >= 8 {
-                data.push(*b);
-            }
-        }
-        let crc: [u8; 4] = data[data.len() - 4..data.len()].try_into().unwrap();
-        (0..4).for_each(|_a| {
-            data.pop();
-        });
-        if c.len != data.len() as u32 {
-            println!(
-                "Warning: lengths mismatch actual len: {} got len: {}",
-                c.len,
-                data.len()
-            );
-            c.len = data.len() as u32;
-        }
-        c.data = data.clone();
-        let true_crc = CRC_PNG.checksum(&[&c.chunktype.bytes(), data.as_slice()].concat());
-        c.crc = u32::from_be_bytes(crc);
-        if c.crc != true_crc {
-            return Err(ChunkDecodingError::boxed(format!(
+        let c = Self::from_bytes_unverified(bytes)?;
+        if !c.has_valid_crc() {
+            return Err(ChunkDecodingError::bad_crc(format!(
                 "Bad CRC (received {:04x}, expected {:04x})",
-                c.crc, true_crc
+                c.crc,
+                c.computed_crc()
             )));
         }
 
@@ -255,6 +357,34 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_set_data_updates_length_and_crc() {
+        let mut chunk = testing_chunk();
+        chunk.set_data(b"short".to_vec());
+        assert_eq!(chunk.length(), 5);
+        assert!(chunk.has_valid_crc());
+        assert_eq!(
+            chunk,
+            Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"short".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_empty_chunk() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![]);
+        assert_eq!(chunk.length(), 0);
+        assert_eq!(chunk.data_as_string().unwrap(), "");
+        assert!(chunk.has_valid_crc());
+
+        // A bare 12 byte chunk: length, type and CRC with nothing between
+        let bytes = chunk.as_bytes();
+        assert_eq!(bytes.len(), 12);
+        let parsed = Chunk::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed, chunk);
+        assert!(parsed.data().is_empty());
+        assert_eq!(parsed.as_bytes(), bytes);
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -275,4 +405,24 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_inline_and_heap_data_behave_alike() {
+        let chunk_type = ChunkType::from_str("tIME").unwrap();
+        for len in [0, INLINE_LEN, INLINE_LEN + 1] {
+            let data: Vec<u8> = (0..len as u8).collect();
+            let chunk = Chunk::new(chunk_type, data.clone());
+            assert_eq!(chunk.data(), data);
+            let reread = Chunk::try_from(&chunk.as_bytes()[..]).unwrap();
+            assert_eq!(reread, chunk);
+        }
+
+        // Growing past the inline size and shrinking back keeps the data and CRC in step
+        let mut chunk = Chunk::new(chunk_type, vec![1; 4]);
+        chunk.set_data(vec![2; 100]);
+        assert_eq!(chunk.data(), [2; 100]);
+        chunk.set_data(vec![3; 2]);
+        assert_eq!(chunk, Chunk::new(chunk_type, vec![3; 2]));
+        assert!(chunk.has_valid_crc());
+    }
 }
@@ -3,7 +3,8 @@ use std::error::Error;
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Four bytes, so it is `Copy`, `Send` and `Sync`, and stored inline in every chunk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct ChunkType {
     code: [u8; 4],
 }
@@ -14,7 +15,7 @@ impl ChunkType {
     }
 
     pub fn is_valid_byte(byte: u8) -> bool {
-        (65..90).contains(&byte) || (97..122).contains(&byte)
+        byte.is_ascii_alphabetic()
     }
 
     pub fn is_valid(&self) -> bool {
@@ -22,7 +23,6 @@ impl ChunkType {
     }
 
     pub fn is_critical(&self) -> bool {
-        println!("{}", self.code[0] & (1 << 4));
         (self.code[0] & (1 << 5)) == 0
     }
 
@@ -37,7 +37,68 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         (self.code[3] & (1 << 5)) != 0
     }
+
+    /// Builds a type from any 4 bytes, skipping every check. Only meant for forensic work on
+    /// damaged files, such as finding a chunk whose type was mangled into non-letter bytes;
+    /// such a type must never be written into a new chunk.
+    pub fn from_bytes_unchecked(code: [u8; 4]) -> Self {
+        ChunkType { code }
+    }
+
+    /// What a chunk of this type is for, going by the case of its letters.
+    pub fn category(&self) -> Category {
+        match (self.is_critical(), self.is_public()) {
+            (true, _) => Category::Critical,
+            (false, true) => Category::Ancillary,
+            (false, false) => Category::Private,
+        }
+    }
+
+    /// The four property bits in short form, such as `crit pub unsafe` for IHDR or
+    /// `anc priv copy` for a private chunk an editor may carry over. A set reserved bit, which
+    /// makes the type invalid, is added as `reserved`.
+    pub fn flags(&self) -> String {
+        let mut flags = vec![
+            if self.is_critical() { "crit" } else { "anc" },
+            if self.is_public() { "pub" } else { "priv" },
+            if self.is_safe_to_copy() {
+                "copy"
+            } else {
+                "unsafe"
+            },
+        ];
+        if !self.is_reserved_bit_valid() {
+            flags.push("reserved");
+        }
+        flags.join(" ")
+    }
+
+    /// Whether all 4 bytes are ASCII letters, as the PNG specification requires.
+    pub fn is_letters(&self) -> bool {
+        self.code.iter().all(u8::is_ascii_alphabetic)
+    }
+}
+/// What a chunk is for, going by the case of its type.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Category {
+    /// Needed to display the image
+    Critical,
+    /// Optional and defined by the png specification or a registered extension
+    Ancillary,
+    /// Optional and application specific, where payloads usually hide
+    Private,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Category::Critical => write!(f, "critical"),
+            Category::Ancillary => write!(f, "ancillary"),
+            Category::Private => write!(f, "private"),
+        }
+    }
 }
+
 #[derive(Debug)]
 pub struct PngDecodeError {
     reason: String,
@@ -56,14 +117,15 @@ impl fmt::Display for PngDecodeError {
 impl Error for PngDecodeError {}
 
 impl FromStr for ChunkType {
-    type Err = &'static str;
+    type Err = crate::Error;
     fn from_str(str: &str) -> Result<Self, Self::Err> {
-        let mut code: [u8; 4] = [0u8; 4];
-        for (i, &byte) in str.as_bytes().iter().enumerate() {
-            if !ChunkType::is_valid_byte(byte) {
-                return Err("Invalid byte in chunk type string");
-            }
-            code[i] = byte;
+        let code: [u8; 4] = str.as_bytes().try_into().map_err(|_| {
+            PngDecodeError::boxed("Chunk type string must be 4 bytes long".to_string())
+        })?;
+        if !code.iter().all(|&byte| ChunkType::is_valid_byte(byte)) {
+            return Err(PngDecodeError::boxed(
+                "Invalid byte in chunk type string".to_string(),
+            ));
         }
         let chunktype = ChunkType { code };
         Ok(chunktype)
@@ -85,16 +147,17 @@ impl TryFrom<[u8; 4]> for ChunkType {
     }
 }
 
+/// Bytes that aren't ASCII letters are written escaped as `\xNN`, so a mangled type can't put
+/// control characters on the terminal.
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}{}{}{}",
-            char::from(self.code[0]),
-            char::from(self.code[1]),
-            char::from(self.code[2]),
-            char::from(self.code[3])
-        )
+        for byte in self.code {
+            match byte.is_ascii_alphabetic() {
+                true => write!(f, "{}", char::from(byte))?,
+                false => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -182,12 +245,43 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_must_be_four_bytes() {
+        for wrong in ["", "RuS", "RuStX", "ä"] {
+            assert!(ChunkType::from_str(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+    #[test]
+    pub fn test_z_is_a_valid_letter() {
+        assert_eq!(ChunkType::from_str("zTXt").unwrap().to_string(), "zTXt");
+        assert!(ChunkType::from_str("ZZZZ").is_ok());
+        assert!(ChunkType::from_str("zTX[").is_err());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
         assert_eq!(&chunk.to_string(), "RuSt");
     }
 
+    #[test]
+    pub fn test_unchecked_chunk_type_is_escaped() {
+        let chunk = ChunkType::from_bytes_unchecked([0x00, b'I', b'R', b'D']);
+        assert!(!chunk.is_letters());
+        assert_eq!(&chunk.to_string(), "\\x00IRD");
+        assert!(ChunkType::from_str("RuSt").unwrap().is_letters());
+    }
+
+    #[test]
+    pub fn test_flags() {
+        let flags = |t: &str| ChunkType::from_str(t).unwrap().flags();
+        assert_eq!(flags("IHDR"), "crit pub unsafe");
+        assert_eq!(flags("tEXt"), "anc pub copy");
+        assert_eq!(flags("ruSt"), "anc priv copy");
+        assert_eq!(flags("Rust"), "crit priv copy reserved");
+    }
+
     #[test]
     pub fn test_chunk_type_trait_impls() {
         let chunk_type_1: ChunkType = TryFrom::try_from([82, 117, 83, 116]).unwrap();
@@ -1,74 +1,6218 @@
-use std::io::Write;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::{ControlFlow, Range};
+use std::path::Path;
+use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 
-use crate::args::{self, Command, DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs};
+use crate::apng;
+use crate::archive;
+use crate::args::{
+    self, AtOffsetArgs, AttestArgs, AuditArgs, AuditTrailArgs, BatchArgs, CapacityArgs, CheckArgs,
+    ChunkOrder, Command, DecodeArgs, DecodeTextArgs, DiffArgs, EditArgs, EncodeArgs,
+    EncodeTextArgs, FindPngArgs, FramesAction, FramesArgs, GitFilterAction, GitFilterArgs,
+    HistoryArgs, KeyArgs, KeygenArgs, KeyringAction, KeyringArgs, KvAction, KvArgs, LabelsArgs,
+    LockArgs, ManArgs, Mode, PatchArgs, PrintArgs, PropagateArgs, RedactArgs, RemoveArgs,
+    RepairArgs, RewriteArgs, ScanArgs, SealArgs, StripArgs, TriageArgs, UndoArgs,
+};
+use crate::audit;
+use crate::batch::{self, BatchError};
+use crate::cancel::{self, Cancel};
+use crate::capacity;
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
-use crate::png::Png;
-use std::fs::File;
-
-fn print(args: PrintArgs) {
-    println!("Print: {:?}", args);
-    let file = Png::from_file(args.file_path).unwrap();
-    file.chunks().iter().for_each(|c: &Chunk| {
-        println!("{:#x?}", c);
-    });
+use crate::compress::{self, Algorithm, Codec};
+use crate::crypto::{self, Entropy, KeySource};
+use crate::diagnostic::{Diagnostic, DiagnosticKind, Warnings};
+use crate::digest::{FileDigest, HashAlgorithm};
+use crate::document::{
+    self, ArchiveFile, BatchFile, ByteLocation, Candidate, CheckResult, ChunkInfo, DiffResult,
+    ExtractedFrame, Message, PlacedShard, RepairResult,
+};
+use crate::editor::{Editor, SystemEditor};
+use crate::envelope::{self, Envelope, EnvelopeError};
+use crate::error::{Failure, MismatchError, NotFoundError, RefusedError, UsageError};
+use crate::expiry::{self, ExpiredError};
+use crate::extension;
+use crate::git_filter;
+use crate::header;
+use crate::history::{self, Target, HISTORY_CHUNK};
+use crate::keychain::{Keyring, OsKeyring};
+use crate::kv::KV_CHUNK;
+use crate::label;
+use crate::layout::Layout;
+use crate::lock::{self, FileLock};
+use crate::lsb;
+use crate::man;
+use crate::newline::Newline;
+use crate::output::{self, Format, Table};
+use crate::padding::{self, Padding};
+use crate::palette;
+use crate::patch::Patch;
+use crate::png::{ParseOptions, Placement, Png};
+use crate::profile::{self, Phase};
+use crate::prompt::{self, Prompt, TerminalPrompt};
+use crate::remote;
+use crate::repair;
+use crate::rewrite::Substitution;
+use crate::scan;
+use crate::seal;
+use crate::secret::SecretBytes;
+use crate::shard;
+use crate::signing;
+use crate::sniff;
+use crate::stream::ChunkStream;
+use crate::summary::MutationSummary;
+use crate::text::{self, TextChunk, COMPRESSED_TEXT_CHUNK, TEXT_CHUNK};
+use crate::triage;
+use crate::verify;
+use std::fs::{self, File};
+
+/// Environment variables consulted for key material when no key flag is given.
+const PASSPHRASE_VAR: &str = "PNGME_PASSPHRASE";
+const KEY_HEX_VAR: &str = "PNGME_KEY_HEX";
+
+/// The path that stands for stdin when a png is read and for stdout when one is written.
+const STDIO: &str = "-";
+
+/// A png read from a file, along with the whole file and the byte range the png was parsed
+/// from, so that changes can be written back without touching anything around it.
+struct Source {
+    bytes: Vec<u8>,
+    range: Range<usize>,
+    png: Png,
+    /// Checked before anything is written, so a cancelled command leaves the file alone
+    cancel: Cancel,
+}
+
+impl Source {
+    /// Writes the file back to `path`, with the png replaced by the edited one over the range
+    /// it was read from, and describes the change along with the digest of what was written if
+    /// `hash` is given. With `verify_image` the png is decoded first, and nothing is written if
+    /// that fails.
+    fn save(
+        &self,
+        path: &str,
+        verify_image: bool,
+        hash: Option<HashAlgorithm>,
+    ) -> crate::Result<MutationSummary> {
+        remote::local_output(path)?;
+        let png = self.png.as_bytes();
+        if verify_image {
+            verify::verify_image(&png)?;
+        }
+        let before = &self.bytes[..self.range.start];
+        let after = &self.bytes[self.range.end..];
+        let out = [before, &png, after].concat();
+        // Once the file is truncated it has to be written in full, so Ctrl-C waits until then
+        let _deferred = cancel::defer();
+        self.cancel.check()?;
+        let _timer = profile::scope(Phase::Write);
+        write_png(path, &out)?;
+        Ok(MutationSummary {
+            digest: hash.map(|algorithm| FileDigest::of(algorithm, &out, path)),
+            ..MutationSummary::between(&self.bytes, &out)
+        })
+    }
+}
+
+/// Writes `bytes` to `path` through a temporary file next to it that is then renamed over it,
+/// so a crash or a full disk never leaves half a png behind. A file that is replaced keeps its
+/// permissions, and a symlink keeps pointing at the file it names.
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write_atomically_from(path, &mut &bytes[..]).map(|_| ())
+}
+
+/// Writes what `reader` gives to `path` the way `write_atomically` does, returning how many
+/// bytes that was. The file is only replaced once `reader` has been read to the end.
+fn write_atomically_from(path: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other(format!("{} is not a file", path.display())))?;
+    let temporary = path.with_file_name(format!(
+        ".{}.pngme-{}.tmp",
+        name.to_string_lossy(),
+        process::id()
+    ));
+    let permissions = fs::metadata(&path).ok().map(|meta| meta.permissions());
+    let written = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if let Some(permissions) = &permissions {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(permissions.mode());
+        }
+        let mut file = options.open(&temporary)?;
+        // Set before any bytes go in, as the umask may have narrowed the mode it was created with
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        let copied = io::copy(reader, &mut file)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
+        Ok(copied)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// Writes the png to stdout for "-", and atomically to the file at `path` otherwise.
+fn write_png(path: &str, bytes: &[u8]) -> io::Result<()> {
+    match path {
+        STDIO => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(bytes)?;
+            stdout.flush()
+        }
+        _ => write_atomically(Path::new(path), bytes),
+    }
+}
+
+/// Copies `path` to `<path>.bak` for encode --backup, replacing any earlier backup.
+fn backup(path: &str) -> crate::Result<()> {
+    remote::local_output(path)?;
+    if path == STDIO {
+        return Err(UsageError::boxed(
+            "--backup needs a file to copy, not stdin".to_string(),
+        ));
+    }
+    let backup = format!("{}.bak", path);
+    fs::copy(path, &backup)?;
+    status(format!("Kept the previous version as {}", backup));
+    Ok(())
+}
+
+/// Reads `path` and parses the png at `offset` or the `image_index`th of several
+/// concatenated ones, reporting any parse warnings on stderr.
+fn open(path: &str, offset: Option<usize>, image_index: Option<usize>) -> crate::Result<Source> {
+    open_with(path, offset, image_index, false)
+}
+
+/// Like `open`, but with `lenient` chunks with a bad CRC are kept rather than failing.
+fn open_with(
+    path: &str,
+    offset: Option<usize>,
+    image_index: Option<usize>,
+    lenient: bool,
+) -> crate::Result<Source> {
+    let bytes = read_input(path)?;
+    let mut range = locate(&bytes, offset, image_index)?;
+    let options = ParseOptions {
+        lenient,
+        stop_at_iend: offset.is_some() || image_index.is_some(),
+        ..Default::default()
+    };
+    let (png, diagnostics) = Png::parse_report_with(&bytes[range.clone()], options);
+    report(diagnostics.iter().filter(|d| !d.is_error()));
+    let png = png?;
+    range.end = range.start + png.byte_len();
+    Ok(Source {
+        bytes,
+        range,
+        png,
+        cancel: cancel::interrupt(),
+    })
+}
+
+/// The contents of the file at `path`, downloaded if it is an http or https URL, or all of
+/// stdin for "-".
+fn read_input(path: &str) -> crate::Result<Vec<u8>> {
+    match remote::is_url(path) {
+        true => remote::fetch(path),
+        false if path == STDIO => {
+            let _timer = profile::scope(Phase::Read);
+            let mut bytes = vec![];
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+        false => {
+            let _timer = profile::scope(Phase::Read);
+            Ok(fs::read(path)?)
+        }
+    }
+}
+
+/// The byte range of the png to operate on: everything from `offset`, or the `image_index`th
+/// image when the file holds several concatenated pngs.
+fn locate(
+    bytes: &[u8],
+    offset: Option<usize>,
+    image_index: Option<usize>,
+) -> crate::Result<Range<usize>> {
+    let start = offset.unwrap_or(0);
+    if start > bytes.len() {
+        return Err(UsageError::boxed(format!(
+            "Offset {} is past the end of the file ({} bytes)",
+            start,
+            bytes.len()
+        )));
+    }
+    let Some(index) = image_index else {
+        return Ok(start..bytes.len());
+    };
+    let images = Png::image_ranges(&bytes[start..])?;
+    let image = images.get(index).ok_or_else(|| {
+        NotFoundError::boxed(format!(
+            "Image index {} is out of range, the file contains {} image(s)",
+            index,
+            images.len()
+        ))
+    })?;
+    Ok(start + image.start..start + image.end)
+}
+
+/// Takes the advisory lock on `path` that keeps other pngme processes from writing it at the
+/// same time, held until the returned guard is dropped. A URL is only ever read, and stdin and
+/// stdout aren't shared, so there is nothing to lock for those.
+fn lock_file(path: &str, args: &LockArgs) -> crate::Result<Option<FileLock>> {
+    if remote::is_url(path) || path == STDIO {
+        return Ok(None);
+    }
+    lock::acquire(Path::new(path), args.wait_lock.map(Duration::from_secs)).map(Some)
+}
+
+/// Prints warnings on stderr, or adds them to the --json document when there is one.
+struct CliWarnings;
+
+impl Warnings for CliWarnings {
+    fn warn(&mut self, warning: Diagnostic) {
+        if document::warn(&warning) {
+            return;
+        }
+        match warning.kind {
+            // Shouted, as the message may no longer be meant to be read
+            DiagnosticKind::Expired => eprintln!("WARNING: {}", warning.message),
+            _ => eprintln!("{}", warning),
+        }
+    }
+}
+
+/// Reports parse warnings through `CliWarnings`.
+fn report<'a>(diagnostics: impl IntoIterator<Item = &'a Diagnostic>) {
+    for d in diagnostics {
+        CliWarnings.warn(d.clone());
+    }
+}
+
+/// Prints a line saying what the command did, which the --json document leaves out.
+fn status(message: String) {
+    if !document::collecting() {
+        output::say(message);
+    }
+}
+
+/// The paths a command that takes several was given: --file-path if set, then the rest.
+fn batch_paths(file_path: &str, batch: &BatchArgs) -> Vec<String> {
+    let first = (!file_path.is_empty()).then(|| file_path.to_string());
+    first
+        .into_iter()
+        .chain(batch.paths.iter().cloned())
+        .collect()
+}
+
+/// Whether a command given `file_path` and `batch` works through several files.
+fn batched(file_path: &str, batch: &BatchArgs) -> bool {
+    batch::is_batch(&batch_paths(file_path, batch))
+}
+
+/// Runs `each` on every file `paths` stand for, carrying on past the ones that fail, and
+/// reports how each went: a line per file, or the list of them as the --json result. The run
+/// fails if any file did, and stops straight away on Ctrl-C.
+fn run_batch(
+    paths: &[String],
+    recursive: bool,
+    format: Format,
+    mut each: impl FnMut(String, &mut BatchFile) -> crate::Result<()>,
+) -> crate::Result<()> {
+    let mut done = vec![];
+    for path in batch::expand(paths, recursive)? {
+        let mut file = BatchFile {
+            path: path.display().to_string(),
+            ok: true,
+            ..Default::default()
+        };
+        if let Err(e) = each(file.path.clone(), &mut file) {
+            if let Failure::Cancelled(_) = Failure::of(&e) {
+                return Err(e);
+            }
+            file.ok = false;
+            file.error = Some(e.to_string());
+        }
+        if format != Format::Json {
+            print_batch_file(&file, format);
+        }
+        done.push(file);
+    }
+    let failed = done.iter().filter(|f| !f.ok).count();
+    match format {
+        Format::Json => document::emit(&done)?,
+        _ => status(format!(
+            "Processed {} file(s), {} failed",
+            done.len(),
+            failed
+        )),
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(BatchError::failed(failed, done.len())),
+    }
+}
+
+fn print_batch_file(file: &BatchFile, format: Format) {
+    if let Some(error) = &file.error {
+        eprintln!("{}: failed: {}", file.path, error);
+    } else if let Some(summary) = &file.summary {
+        output::say(format_args!("{}: {}", file.path, summary));
+    } else if let Some(message) = &file.message {
+        match format {
+            Format::Pretty => output::say(format_args!("{}: {:#?}", file.path, message.message)),
+            Format::Plain | Format::Json | Format::Csv => output::say(format_args!(
+                "{}\t{}",
+                file.path,
+                message.message.escape_debug()
+            )),
+        }
+    }
+}
+
+fn check(args: CheckArgs, format: Format) -> crate::Result<()> {
+    let bytes = read_input(&args.file_path)?;
+    let range = locate(&bytes, args.offset, args.image_index)?;
+    let images = Png::image_ranges(&bytes[range.start..]).map_or(0, |r| r.len());
+    let options = ParseOptions {
+        lenient: true,
+        stop_at_iend: args.offset.is_some() || args.image_index.is_some(),
+        strict_spec: args.strict,
+        complete: true,
+    };
+    let (_, diagnostics) = Png::parse_report_with(&bytes[range], options);
+    if format == Format::Json {
+        document::emit(&CheckResult {
+            images,
+            diagnostics: diagnostics.clone(),
+        })?;
+    } else {
+        let mut out = String::new();
+        if images > 1 {
+            out += &format!(
+                "{} contains {} concatenated png images\n",
+                args.file_path, images
+            );
+        }
+        for d in &diagnostics {
+            out += &format!("{}\n", d);
+        }
+        output::page(&out)?;
+    }
+    // Parsing leniently turns bad CRCs and a chunk cut short into warnings, so that the rest
+    // of the file gets checked too; they fail the check like errors do
+    let problems: Vec<&Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| {
+            d.is_error()
+                || matches!(
+                    d.kind,
+                    DiagnosticKind::BadCrc | DiagnosticKind::LengthMismatch
+                )
+        })
+        .collect();
+    match problems.first() {
+        None => Ok(()),
+        Some(first) => Err(Box::new(Diagnostic::error(
+            first.kind,
+            format!("{}: {} problem(s) found", args.file_path, problems.len()),
+        ))),
+    }
+}
+
+fn find_png(args: FindPngArgs, format: Format) -> crate::Result<()> {
+    let bytes = read_input(&args.file_path)?;
+    let candidates = Png::find_signatures(&bytes);
+    if format == Format::Json {
+        let found: Vec<Candidate> = candidates
+            .into_iter()
+            .map(|offset| match Png::from_bytes_at(&bytes, offset) {
+                Ok(png) => Candidate {
+                    offset,
+                    chunks: Some(png.chunks().len()),
+                    bytes: Some(png.as_bytes().len()),
+                    error: None,
+                },
+                Err(e) => Candidate {
+                    offset,
+                    chunks: None,
+                    bytes: None,
+                    error: Some(e.to_string()),
+                },
+            })
+            .collect();
+        return document::emit(&found);
+    }
+    if candidates.is_empty() {
+        println!("No png signatures found in {}", args.file_path);
+    }
+    for offset in candidates {
+        match Png::from_bytes_at(&bytes, offset) {
+            Ok(png) => println!(
+                "{:#x} ({}): png with {} chunks, {}",
+                offset,
+                offset,
+                png.chunks().len(),
+                output::size(png.as_bytes().len())
+            ),
+            Err(e) => println!("{:#x} ({}): signature only ({})", offset, offset, e),
+        }
+    }
+    Ok(())
+}
+
+fn at_offset(args: AtOffsetArgs, format: Format) -> crate::Result<()> {
+    let source = open_with(&args.file_path, None, None, true)?;
+    let layout = Layout::of(&source.png);
+    let span = layout.locate(args.offset).ok_or_else(|| {
+        NotFoundError::boxed(format!(
+            "Offset {:#x} is past the end of the file ({} bytes)",
+            args.offset,
+            layout.end()
+        ))
+    })?;
+    let (chunk_index, chunk_type) = span.chunk.clone().unzip();
+    let found = ByteLocation {
+        offset: args.offset,
+        region: span.region,
+        chunk_index,
+        chunk_type,
+        relative: args.offset - span.base,
+        region_offset: args.offset - span.bytes.start,
+    };
+    if format == Format::Json {
+        return document::emit(&found);
+    }
+    let mut table = Table::new(&[
+        "offset",
+        "region",
+        "chunk",
+        "type",
+        "relative",
+        "into region",
+    ]);
+    table.row(vec![
+        format!("{:#x}", found.offset),
+        found.region.to_string(),
+        found
+            .chunk_index
+            .map_or("-".to_string(), |index| index.to_string()),
+        found.chunk_type.unwrap_or("-".to_string()),
+        found.relative.to_string(),
+        found.region_offset.to_string(),
+    ]);
+    let mut out = table.render(format);
+    if let Some(context) = args.context {
+        let start = args.offset.saturating_sub(context);
+        let end = (args.offset + context + 1).min(layout.end());
+        out += &hex_dump(&source.bytes, &layout, start..end, args.offset);
+    }
+    output::page(&out)?;
+    Ok(())
+}
+
+/// `range` of `bytes` as rows of up to 16 bytes, each starting a new row where a part of a
+/// chunk starts so it can be labelled. The row holding `mark` is flagged with `>`.
+fn hex_dump(bytes: &[u8], layout: &Layout, range: Range<usize>, mark: usize) -> String {
+    const ROW: usize = 16;
+    let mut out = String::new();
+    for span in layout.spans_in(range.clone()) {
+        let end = span.bytes.end.min(range.end);
+        let mut row_start = span.bytes.start.max(range.start);
+        let mut label = span.label();
+        while row_start < end {
+            let row = &bytes[row_start..(row_start + ROW).min(end)];
+            let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = row
+                .iter()
+                .map(|&b| match b.is_ascii_graphic() {
+                    true => b as char,
+                    false => '.',
+                })
+                .collect();
+            let flag = match (row_start..row_start + row.len()).contains(&mark) {
+                true => '>',
+                false => ' ',
+            };
+            let line = format!(
+                "{} {:08x}  {:<47}  {:<16}  {}",
+                flag,
+                row_start,
+                hex.join(" "),
+                ascii,
+                label
+            );
+            out += line.trim_end();
+            out.push('\n');
+            label.clear();
+            row_start += row.len();
+        }
+    }
+    out
 }
 
-fn remove(args: RemoveArgs) {
-    println!("Remove: {:?}", args);
-    match Png::from_file(args.file_path) {
-        Ok(mut f) => {
-            let r = f.remove_first_chunk(&args.chunk_type).unwrap();
-            println!(
-                "Removed chunk with type {:#?} and message {:#?}",
-                args.chunk_type,
-                r.data_as_string(),
+fn scan(args: ScanArgs, format: Format) -> crate::Result<()> {
+    let roots = batch::roots(&batch_paths(&args.file_path, &args.batch))?;
+    let report = scan::scan_all(&roots, args.batch.recursive);
+    if let Some(path) = &args.report {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    // The report lists the files that couldn't be scanned itself
+    if format == Format::Json {
+        return document::emit(&report);
+    }
+    for failure in &report.failures {
+        CliWarnings.warn(Diagnostic::warning(
+            DiagnosticKind::Other,
+            format!("couldn't scan {}: {}", failure.file, failure.error),
+        ));
+    }
+    let mut out = String::new();
+    if args.report.is_none() {
+        for findings in report.files.iter().filter(|f| f.hidden_payload_bytes > 0) {
+            out += &format!(
+                "{}: {} private chunk(s), {} trailing\n",
+                findings.file,
+                findings.private_chunks.len(),
+                output::size(findings.trailing_bytes)
+            );
+        }
+        for findings in report.files.iter().filter(|f| !f.unknown_chunks.is_empty()) {
+            out += &format!(
+                "{}: non-standard chunk type(s) {}\n",
+                findings.file,
+                findings.unknown_chunks.join(", ")
             );
         }
-        Err(e) => println!("Error {:?}", e),
+        for findings in report
+            .files
+            .iter()
+            .filter(|f| !f.large_ancillary_chunks.is_empty())
+        {
+            out += &format!(
+                "{}: ancillary chunk(s) over {} in {}\n",
+                findings.file,
+                output::size(scan::LARGE_ANCILLARY),
+                findings.large_ancillary_chunks.join(", ")
+            );
+        }
+        for findings in report
+            .files
+            .iter()
+            .filter(|f| !f.expired_payloads.is_empty())
+        {
+            out += &format!(
+                "{}: expired message(s) in {}\n",
+                findings.file,
+                findings.expired_payloads.join(", ")
+            );
+        }
+        for findings in report
+            .files
+            .iter()
+            .filter(|f| !f.damaged_payloads.is_empty())
+        {
+            out += &format!(
+                "{}: damaged payload in {}\n",
+                findings.file,
+                findings.damaged_payloads.join(", ")
+            );
+        }
+    }
+    out += &format!("{}\n", report);
+    Ok(output::page(&out)?)
+}
+
+fn strip(args: StripArgs) -> crate::Result<MutationSummary> {
+    // A new output file can't be written by anyone else yet, so the input is locked instead
+    let target = args
+        .out_path
+        .as_ref()
+        .filter(|out| Path::new(out).exists())
+        .unwrap_or(&args.file_path);
+    let _lock = lock_file(target, &args.lock)?;
+    let bytes = read_input(&args.file_path)?;
+    let end = Png::trailing_offset(&bytes)?;
+    let (kept, trailing) = bytes.split_at(end);
+
+    let out = if args.trailing_only && args.audit.audit {
+        let mut png = Png::try_from(kept)?;
+        record_change(&mut png, &args.audit, "strip", &[])?;
+        png.as_bytes()
+    } else if args.trailing_only {
+        kept.to_vec()
+    } else {
+        let png = Png::try_from(kept)?;
+        let (kept, removed): (Vec<Chunk>, Vec<Chunk>) =
+            png.chunks().iter().cloned().partition(|c| {
+                let ctype = c.chunk_type().to_string();
+                c.chunk_type().is_critical()
+                    || (!args.remove_seal && ctype == seal::SEAL_CHUNK)
+                    || (!args.remove_audit && ctype == audit::AUDIT_CHUNK)
+            });
+        status(format!("Removed {} ancillary chunk(s)", removed.len()));
+        let mut stripped = Png::from_chunks(kept);
+        record_change(&mut stripped, &args.audit, "strip", &removed)?;
+        stripped.as_bytes()
+    };
+    if args.verify_image {
+        verify::verify_image(&out)?;
+    }
+    let out_path = args.out_path.as_ref().unwrap_or(&args.file_path);
+    remote::local_output(out_path)?;
+    let _deferred = cancel::defer();
+    cancel::interrupt().check()?;
+    if let Some(side_file) = &args.save_trailing {
+        fs::write(side_file, trailing)?;
+    }
+    write_png(out_path, &out)?;
+    status(format!(
+        "Removed {} after IEND",
+        output::size(trailing.len())
+    ));
+    Ok(MutationSummary {
+        digest: args
+            .print_hash
+            .map(|algorithm| FileDigest::of(algorithm, &out, out_path)),
+        ..MutationSummary::between(&bytes, &out)
+    })
+}
+
+fn print(args: PrintArgs, format: Format) -> crate::Result<()> {
+    // Lenient, so that damaged files can still be listed to see which chunks are bad
+    let source = open_with(&args.file_path, args.offset, args.image_index, true)?;
+    let file = &source.png;
+    let bad_crcs = file.verify_crcs().len();
+    let crc_result = || -> crate::Result<()> {
+        match args.check_crcs && bad_crcs > 0 {
+            true => Err(Box::new(Diagnostic::error(
+                DiagnosticKind::BadCrc,
+                crc_summary(bad_crcs, file.chunks().len()),
+            ))),
+            false => Ok(()),
+        }
+    };
+    let listed: Vec<(usize, &Chunk)> = file
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| {
+            args.chunk_type_hex
+                .as_ref()
+                .is_none_or(|ctype| c.chunk_type() == ctype)
+        })
+        .collect();
+    let listed = order_chunks(listed, args.sort, args.reverse, args.top);
+    if format == Format::Json {
+        let offsets = chunk_offsets(file, source.range.start);
+        let chunks: Vec<ChunkInfo> = listed
+            .iter()
+            .map(|&(index, c)| ChunkInfo {
+                index,
+                chunk_type: c.chunk_type().to_string(),
+                critical: c.chunk_type().is_critical(),
+                public: c.chunk_type().is_public(),
+                reserved_bit_valid: c.chunk_type().is_reserved_bit_valid(),
+                safe_to_copy: c.chunk_type().is_safe_to_copy(),
+                length: c.length(),
+                crc: format!("{:08x}", c.crc()),
+                computed_crc: format!("{:08x}", c.computed_crc()),
+                crc_ok: c.has_valid_crc(),
+                offset: offsets[index],
+                data_base64: output::base64(c.data()),
+            })
+            .collect();
+        document::emit(&chunks)?;
+        return crc_result();
+    }
+    if let Some(template) = &args.template {
+        let offsets = chunk_offsets(file, source.range.start);
+        let lines: String = listed
+            .into_iter()
+            .map(|(index, c)| {
+                template.render(|field| chunk_field(c, index, offsets[index], field)) + "\n"
+            })
+            .collect();
+        output::page(&lines)?;
+        return crc_result();
+    }
+    let mut table = Table::new(&["index", "type", "length", "crc", "crc status", "flags"]);
+    for &(index, c) in &listed {
+        table.row(vec![
+            index.to_string(),
+            c.chunk_type().to_string(),
+            c.length().to_string(),
+            format!("{:08x}", c.crc()),
+            crc_status(c),
+            c.chunk_type().flags(),
+        ]);
+    }
+    let mut out = String::new();
+    if format == Format::Pretty {
+        out += &format!("{}: {} chunk(s)\n", args.file_path, file.chunks().len());
+    }
+    out += &table.render(format);
+    if format == Format::Pretty {
+        let color_type = file.ihdr().and_then(Result::ok).map(|ihdr| ihdr.color_type);
+        for &(index, c) in &listed {
+            let ctype = c.chunk_type().bytes();
+            let description = palette::describe(&ctype, c.data())
+                .or_else(|| header::describe(&ctype, c.data(), color_type))
+                .or_else(|| extension::describe(&ctype, c.data()))
+                .or_else(|| text::describe(&ctype, c.data()));
+            if let Some(description) = description {
+                out += &format!("{} chunk {}: {}\n", c.chunk_type(), index, description);
+            }
+        }
+    }
+    if format == Format::Pretty && bad_crcs > 0 {
+        out += &format!("{}\n", crc_summary(bad_crcs, file.chunks().len()));
+    }
+    output::page(&out)?;
+    crc_result()
+}
+
+/// `ok`, or what was stored and what the chunk's type and data call for.
+fn crc_status(chunk: &Chunk) -> String {
+    match chunk.has_valid_crc() {
+        true => "ok".to_string(),
+        false => format!(
+            "BAD (stored {:08x}, want {:08x})",
+            chunk.crc(),
+            chunk.computed_crc()
+        ),
+    }
+}
+
+fn crc_summary(bad: usize, total: usize) -> String {
+    format!("{} of {} chunks have invalid CRCs", bad, total)
+}
+
+/// The offset in the file of every chunk of `png`, which starts at `start`.
+fn chunk_offsets(png: &Png, start: usize) -> Vec<usize> {
+    let mut offset = start + Png::STANDARD_HEADER.len();
+    png.chunks()
+        .iter()
+        .map(|c| {
+            let at = offset;
+            offset += 12 + c.data().len();
+            at
+        })
+        .collect()
+}
+
+/// The value of one of the `PRINT_FIELDS` for the chunk at `index`.
+fn chunk_field(chunk: &Chunk, index: usize, offset: usize, field: &str) -> String {
+    const PREVIEW_LEN: usize = 16;
+    match field {
+        "index" => index.to_string(),
+        "type" => chunk.chunk_type().to_string(),
+        "length" => chunk.length().to_string(),
+        "crc" => format!("{:08x}", chunk.crc()),
+        "crc_ok" => chunk.has_valid_crc().to_string(),
+        "offset" => offset.to_string(),
+        "category" => chunk.chunk_type().category().to_string(),
+        "flags" => chunk.chunk_type().flags(),
+        "data_preview" => {
+            let data = chunk.data();
+            let preview: String = data
+                .iter()
+                .take(PREVIEW_LEN)
+                .map(|&b| match b.is_ascii_graphic() || b == b' ' {
+                    true => b as char,
+                    false => '.',
+                })
+                .collect();
+            match data.len() > PREVIEW_LEN {
+                true => preview + "...",
+                false => preview,
+            }
+        }
+        "data_base64" => output::base64(chunk.data()),
+        _ => unreachable!("templates only hold PRINT_FIELDS"),
+    }
+}
+
+/// Puts the `(index, chunk)` pairs print lists in the requested order. The sorts are stable,
+/// so chunks that compare equal stay in file order even with `reverse`.
+fn order_chunks(
+    mut chunks: Vec<(usize, &Chunk)>,
+    order: ChunkOrder,
+    reverse: bool,
+    top: Option<usize>,
+) -> Vec<(usize, &Chunk)> {
+    if let Some(top) = top {
+        chunks.sort_by_key(|(_, c)| std::cmp::Reverse(c.length()));
+        chunks.truncate(top);
+        chunks.sort_by_key(|(index, _)| *index);
+    }
+    chunks.sort_by(|(a_index, a), (b_index, b)| {
+        let ordering = match order {
+            ChunkOrder::Offset => a_index.cmp(b_index),
+            ChunkOrder::Type => a.chunk_type().bytes().cmp(&b.chunk_type().bytes()),
+            ChunkOrder::Size => a.length().cmp(&b.length()),
+        };
+        match reverse {
+            true => ordering.reverse(),
+            false => ordering,
+        }
+    });
+    chunks
+}
+
+/// Removes the message from every file given.
+fn remove_batch(args: RemoveArgs, format: Format) -> crate::Result<()> {
+    let paths = batch_paths(&args.file_path, &args.batch);
+    run_batch(&paths, args.batch.recursive, format, |file_path, file| {
+        file.summary = Some(remove(RemoveArgs {
+            file_path,
+            ..args.clone()
+        })?);
+        Ok(())
+    })
+}
+
+fn remove(args: RemoveArgs) -> crate::Result<MutationSummary> {
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut source = open(&args.file_path, None, args.image_index)?;
+    let removed = match (&args.label, &args.chunk_type_hex) {
+        (Some(label), _) => remove_labelled(&mut source.png, label)?,
+        (None, Some(ctype)) => {
+            if !ctype.is_letters() && !args.force {
+                return Err(RefusedError::boxed(format!(
+                    "{} isn't a valid chunk type, pass --force to remove it anyway",
+                    ctype
+                )));
+            }
+            // The history keeps chunk types by name, which these don't have
+            if !ctype.is_letters() && args.history.keep_previous {
+                return Err(UsageError::boxed(format!(
+                    "--keep-previous can't keep chunks of type {}, which isn't made of letters",
+                    ctype
+                )));
+            }
+            remove_by_type(&mut source.png, ctype, args.all, args.index)?
+        }
+        (None, None) => remove_by_type(
+            &mut source.png,
+            &chunk_type(&args.chunk_type)?,
+            args.all,
+            args.index,
+        )?,
+    };
+    if args.history.keep_previous {
+        let target = match &args.label {
+            Some(label) => Target::Label(label.clone()),
+            None => Target::ChunkType(*removed[0].chunk_type()),
+        };
+        history::push(
+            &mut source.png,
+            &target,
+            removed.clone(),
+            args.history.history_depth as usize,
+        )?;
+    }
+    record_change(&mut source.png, &args.audit, "remove", &removed)?;
+    let summary = source.save(&args.file_path, args.verify_image, args.print_hash)?;
+    // Chunks removed with --all needn't hold one message between them
+    if args.all {
+        status(format!(
+            "Removed {} chunk(s) with type {:#?}",
+            removed.len(),
+            removed[0].chunk_type().to_string()
+        ));
+        return Ok(summary);
+    }
+    status(format!(
+        "Removed {} chunk(s) with type {:#?} and message {:#?}",
+        removed.len(),
+        removed[0].chunk_type().to_string(),
+        match envelope_from(&removed, &mut CliWarnings) {
+            Ok(e) if e.cipher.is_some() => "(encrypted)".to_string(),
+            Ok(e) => match e.unpack(e.payload.clone()) {
+                Ok(message) => String::from_utf8_lossy(&message).into_owned(),
+                Err(e) => e.to_string(),
+            },
+            Err(e) => e.to_string(),
+        },
+    ));
+    Ok(summary)
+}
+
+/// Removes the first message of type `chunk_type` and its redundant copies, or with `all`
+/// every chunk of the type, or with `index` only the chunk at that position among them.
+fn remove_by_type(
+    png: &mut Png,
+    chunk_type: &ChunkType,
+    all: bool,
+    index: Option<usize>,
+) -> crate::Result<Vec<Chunk>> {
+    let positions: Vec<usize> = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.chunk_type() == chunk_type)
+        .map(|(idx, _)| idx)
+        .collect();
+    let Some(&first) = positions.first() else {
+        return Err(NotFoundError::chunk(
+            &chunk_type.to_string(),
+            None,
+            format!("No chunk of type {} found", chunk_type),
+        ));
+    };
+    if all {
+        let mut removed = positions
+            .iter()
+            .rev()
+            .map(|&idx| png.remove_chunk(idx))
+            .collect::<crate::Result<Vec<Chunk>>>()?;
+        removed.reverse();
+        return Ok(removed);
+    }
+    if let Some(index) = index {
+        let Some(&idx) = positions.get(index) else {
+            return Err(NotFoundError::chunk(
+                &chunk_type.to_string(),
+                Some(index),
+                format!(
+                    "There is no {} chunk at index {}, only {} of them",
+                    chunk_type,
+                    index,
+                    positions.len()
+                ),
+            ));
+        };
+        return Ok(vec![png.remove_chunk(idx)?]);
+    }
+    let position = |png: &Png| {
+        png.chunks()
+            .iter()
+            .position(|c| c.chunk_type() == chunk_type)
+    };
+    let mut removed = vec![png.remove_chunk(first)?];
+    // A message stored in envelopes may have redundant copies, which all have to go
+    if Envelope::from_bytes(removed[0].data()).map_or(true, |e| e.is_some()) {
+        while let Some(idx) = position(png).filter(|&idx| {
+            Envelope::from_bytes(png.chunks()[idx].data()).map_or(true, |e| e.is_some())
+        }) {
+            removed.push(png.remove_chunk(idx)?);
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes every copy of the message stored under `label`, leaving messages with other labels
+/// in chunks of the same type alone.
+fn remove_labelled(png: &mut Png, label: &str) -> crate::Result<Vec<Chunk>> {
+    let found = label::find(png.chunks(), label);
+    if found.is_empty() {
+        return Err(NotFoundError::boxed(format!(
+            "No message labelled {:?} found",
+            label
+        )));
+    }
+    let mut removed = found
+        .iter()
+        .rev()
+        .map(|&idx| png.remove_chunk(idx))
+        .collect::<crate::Result<Vec<Chunk>>>()?;
+    removed.reverse();
+    Ok(removed)
+}
+
+/// Removes the message stored at `target` like remove does, returning no chunks if there is
+/// none.
+fn take_message(png: &mut Png, target: &Target) -> crate::Result<Vec<Chunk>> {
+    match target {
+        Target::ChunkType(ctype) if png.chunks().iter().any(|c| c.chunk_type() == ctype) => {
+            remove_by_type(png, ctype, false, None)
+        }
+        Target::Label(label) if !label::find(png.chunks(), label).is_empty() => {
+            remove_labelled(png, label)
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+/// Puts the message last replaced or removed at the chunk type or label back in place of the
+/// current one, taking it out of the history.
+fn undo(args: UndoArgs) -> crate::Result<MutationSummary> {
+    let target = match &args.label {
+        Some(label) => Target::Label(label.clone()),
+        None => Target::ChunkType(chunk_type(&args.chunk_type)?),
+    };
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut source = open(&args.file_path, None, None)?;
+    let Some(previous) = history::pop(&mut source.png, &target)? else {
+        return Err(NotFoundError::boxed(format!(
+            "No previous message kept for {}",
+            target
+        )));
     };
+    take_message(&mut source.png, &target)?;
+    let iend = source
+        .png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().bytes() == *b"IEND");
+    for (offset, chunk) in previous.into_iter().enumerate() {
+        match iend {
+            Some(idx) => source.png.insert_chunk(idx + offset, chunk)?,
+            None => source.png.append_chunk(chunk),
+        }
+    }
+    let summary = source.save(&args.file_path, false, None)?;
+    status(format!("Restored the previous message for {}", target));
+    Ok(summary)
+}
+
+/// Adds an entry for `operation` on `chunks` to the audit trail of `png` if `--audit` was
+/// given.
+fn record_change(
+    png: &mut Png,
+    args: &AuditArgs,
+    operation: &str,
+    chunks: &[Chunk],
+) -> crate::Result<()> {
+    if !args.audit {
+        return Ok(());
+    }
+    let mut chunk_types: Vec<String> = vec![];
+    for chunk in chunks {
+        let ctype = chunk.chunk_type().to_string();
+        if !chunk_types.contains(&ctype) {
+            chunk_types.push(ctype);
+        }
+    }
+    audit::append(
+        png,
+        &audit::Entry::now(operation, chunk_types, args.audit_note.clone()),
+    )
+}
+
+fn audit_trail(args: AuditTrailArgs, format: Format) -> crate::Result<()> {
+    let png = open(&args.file_path, None, None)?.png;
+    let entries = audit::entries(&png)?;
+    if format == Format::Json {
+        document::emit(&entries)?;
+    } else if entries.is_empty() {
+        if format == Format::Pretty {
+            println!("No audit trail found");
+        }
+    } else {
+        let mut table = Table::new(&["time", "version", "operation", "types", "note"]);
+        for entry in entries {
+            table.row(vec![
+                expiry::format(entry.timestamp),
+                entry.tool_version,
+                entry.operation,
+                entry.chunk_types.join(","),
+                entry.note.unwrap_or_default(),
+            ]);
+        }
+        print!("{}", table.render(format));
+    }
+    Ok(())
 }
 
-fn decode(args: DecodeArgs) {
-    println!("Decode: {:?}", args);
-    match Png::from_file(args.file_path) {
-        Ok(f) => {
-            let c = f.chunk_by_type(&args.chunk_type).unwrap();
-            println!("{:#?}", c.data_as_string());
+fn labels(args: LabelsArgs, format: Format) -> crate::Result<()> {
+    let png = open(&args.file_path, None, None)?.png;
+    let found = label::list(png.chunks());
+    if format == Format::Json {
+        document::emit(&found)?;
+    } else if found.is_empty() {
+        if format == Format::Pretty {
+            println!("No labelled messages found");
         }
-        Err(e) => println!("Error {:?}", e),
+    } else {
+        let mut table = Table::new(&["label", "type", "copies"]);
+        for l in found {
+            table.row(vec![l.label, l.chunk_type, l.copies.to_string()]);
+        }
+        print!("{}", table.render(format));
+    }
+    Ok(())
+}
+
+/// Decodes the message in every file given, each listed with the file it came from.
+fn decode_batch(args: DecodeArgs, format: Format) -> crate::Result<()> {
+    if args.all || args.list || args.extract_to.is_some() || args.output.is_some() {
+        return Err(UsageError::boxed(
+            "--all, --list, --extract-to and --output can't be used when decoding several files"
+                .to_string(),
+        ));
+    }
+    let paths = batch_paths(&args.file_path, &args.batch);
+    run_batch(&paths, args.batch.recursive, format, |file_path, file| {
+        let message = decode(DecodeArgs {
+            file_path,
+            ..args.clone()
+        })?;
+        file.message = Some(json_message(None, &message));
+        Ok(())
+    })
+}
+
+/// Decodes the message described by `args`, checking it against --expect if given.
+fn decode(args: DecodeArgs) -> crate::Result<Vec<u8>> {
+    let envelope = match (&args.carriers, args.mode) {
+        (Some(pattern), _) => decode_carriers(&args, pattern)?,
+        (None, Mode::Chunk) => decode_chunk(&args, &mut CliWarnings)?,
+        (None, Mode::Lsb) => decode_lsb(&args)?,
     };
+    unseal(envelope, &args)
 }
 
-fn encode(args: EncodeArgs) {
-    println!("Encode: {:?}", args);
-    match Png::from_file(&args.file_path) {
-        Ok(mut f) => {
-            f.append_chunk(Chunk::new(
-                ChunkType::from_str(&args.chunk_type).unwrap(),
-                args.message.into_bytes(),
+/// The message in `envelope`, once it has been checked and decrypted as `args` ask.
+fn unseal(envelope: Envelope, args: &DecodeArgs) -> crate::Result<Vec<u8>> {
+    check_authenticity(&envelope, args)?;
+    check_expiry(&envelope, args.strict_expiry, &mut CliWarnings)?;
+    let payload = open_envelope(
+        envelope,
+        args.decrypt,
+        &args.keys,
+        &mut TerminalPrompt,
+        &mut OsKeyring,
+        &mut CliWarnings,
+    )?;
+    if let Some(expected) = &args.expect {
+        let decoded = args.newline.apply(payload.clone());
+        if decoded != args.newline.apply(expected.clone().into_bytes()) {
+            return Err(MismatchError::boxed(
+                "Decoded message does not match --expect".to_string(),
             ));
-            let mut file = File::create(&args.file_path).unwrap();
-            let _ = file.write_all(&f.as_bytes());
         }
-        Err(e) => println!("Error {:?}", e),
+    }
+    Ok(payload)
+}
+
+/// Fails unless `envelope` is signed with the public key given with --verify and tagged with
+/// the key given with --mac-key, for whichever of them are given.
+fn check_authenticity(envelope: &Envelope, args: &DecodeArgs) -> crate::Result<()> {
+    if let Some(path) = &args.verify {
+        let key = signing::read_verifying_key(Path::new(path))?;
+        let authenticated = envelope.authenticated_bytes();
+        signing::verify(&key, &authenticated, envelope.signature.as_ref())?;
+    }
+    if let Some(path) = &args.mac_key {
+        let key = crypto::read_key_file(Path::new(path))?;
+        let authenticated = envelope.authenticated_bytes();
+        signing::check_tag(&key, &authenticated, envelope.mac.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Warns when the message in `envelope` is past its expiry, or with `strict` fails.
+fn check_expiry(
+    envelope: &Envelope,
+    strict: bool,
+    warnings: &mut dyn Warnings,
+) -> crate::Result<()> {
+    let Some(expires) = envelope
+        .expires
+        .filter(|_| envelope.is_expired(expiry::now()))
+    else {
+        return Ok(());
     };
+    let reason = format!("the message expired at {}", expiry::format(expires));
+    if strict {
+        return Err(ExpiredError::boxed(reason));
+    }
+    warnings.warn(Diagnostic::warning(DiagnosticKind::Expired, reason));
+    Ok(())
 }
 
-pub fn run(args: Command) {
-    match args {
-        args::Command::Encode(encode_args) => {
-            encode(encode_args);
+fn decode_chunk(args: &DecodeArgs, warnings: &mut dyn Warnings) -> crate::Result<Envelope> {
+    envelope_from(&message_chunks(args)?, warnings)
+}
+
+/// The chunks holding the message decode is asked for: those of --label, of --chunk-type, or
+/// of the only type messages are stored in.
+fn message_chunks(args: &DecodeArgs) -> crate::Result<Vec<Chunk>> {
+    if let Some(label) = &args.label {
+        let png = open(&args.file_path, args.offset, args.image_index)?.png;
+        let found: Vec<Chunk> = label::find(png.chunks(), label)
+            .into_iter()
+            .map(|idx| png.chunks()[idx].clone())
+            .collect();
+        if found.is_empty() {
+            return Err(NotFoundError::boxed(format!(
+                "No message labelled {:?} found",
+                label
+            )));
         }
-        args::Command::Print(print_args) => {
-            print(print_args);
+        return Ok(found);
+    }
+    if args.chunk_type.is_none() && args.chunk_type_hex.is_none() {
+        let png = open(&args.file_path, args.offset, args.image_index)?.png;
+        let ctype = match message_types(&png)[..] {
+            [] => return Err(no_messages(&args.file_path)),
+            [ref ctype] => *ctype,
+            ref types => {
+                let types: Vec<String> = types.iter().map(ChunkType::to_string).collect();
+                return Err(UsageError::boxed(format!(
+                    "Messages are stored in several chunk types ({}), pick one with --chunk-type \
+                     or decode them all with --all",
+                    types.join(", ")
+                )));
+            }
+        };
+        return Ok(chunks_of(&png, &ctype));
+    }
+    let ctype = match &args.chunk_type_hex {
+        Some(ctype) => *ctype,
+        None => chunk_type(&args.chunk_type)?,
+    };
+    let offset = args.offset.unwrap_or(0) as u64;
+    let reader: Box<dyn Read> = match args.file_path.as_str() {
+        path if args.image_index.is_some() || remote::is_url(path) => {
+            let bytes = read_input(path)?;
+            let range = locate(&bytes, args.offset, args.image_index)?;
+            Box::new(Cursor::new(bytes[range].to_vec()))
         }
-        args::Command::Remove(remove_args) => {
-            remove(remove_args);
+        // Stdin can't seek, so the bytes before the offset are read and dropped
+        STDIO => {
+            let mut stdin = io::stdin().lock();
+            io::copy(&mut (&mut stdin).take(offset), &mut io::sink())?;
+            Box::new(stdin)
         }
-        args::Command::Decode(decode_args) => {
-            decode(decode_args);
+        path => {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            Box::new(file)
+        }
+    };
+    let mut stream = ChunkStream::new(reader)?.cancellable(cancel::interrupt());
+    if args.offset.is_some() || args.image_index.is_some() {
+        stream = stream.stop_at_iend();
+    }
+    // Stream the file so decoding stops reading as soon as every copy of the message is found
+    let mut found = vec![];
+    let _ = stream.walk(|_, c| {
+        if *c.chunk_type() == ctype {
+            found.push(c.clone());
+            if expected_copies(&found).is_some_and(|total| found.len() >= total) {
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    })?;
+    if found.is_empty() {
+        return Err(NotFoundError::chunk(
+            &ctype.to_string(),
+            None,
+            format!("No chunk of type {} found", ctype),
+        ));
+    }
+    Ok(found)
+}
+
+/// Reads the shards of a message stored with encode --carriers from every file matching
+/// `pattern`, and puts the message back together. Without --chunk-type or --label any chunk
+/// holding a shard is used.
+fn decode_carriers(args: &DecodeArgs, pattern: &str) -> crate::Result<Envelope> {
+    let ctype = match &args.chunk_type {
+        Some(_) => Some(chunk_type(&args.chunk_type)?),
+        None => None,
+    };
+    let mut shards = vec![];
+    for path in batch::glob(pattern)? {
+        let path = path.to_string_lossy().into_owned();
+        let png = open(&path, None, None)?.png;
+        let positions: Vec<usize> = match (&args.label, ctype) {
+            (Some(label), _) => label::find(png.chunks(), label),
+            (None, Some(ctype)) => (0..png.chunks().len())
+                .filter(|&idx| *png.chunks()[idx].chunk_type() == ctype)
+                .collect(),
+            (None, None) => (0..png.chunks().len()).collect(),
+        };
+        let explicit = args.label.is_some() || ctype.is_some();
+        for idx in positions {
+            match Envelope::from_bytes(png.chunks()[idx].data()) {
+                Ok(Some(envelope)) if envelope.shard.is_some() => {
+                    shards.push((path.clone(), envelope))
+                }
+                Err(e) if explicit => return Err(e),
+                _ => {}
+            }
+        }
+    }
+    if shards.is_empty() {
+        return Err(NotFoundError::boxed(format!(
+            "No shard of a message is stored in the files matching {}",
+            pattern
+        )));
+    }
+    shard::join(shards)
+}
+
+fn decode_lsb(args: &DecodeArgs) -> crate::Result<Envelope> {
+    let png = open(&args.file_path, args.offset, args.image_index)?.png;
+    let envelope = Envelope::from_bytes(&lsb::extract(&png)?)?.ok_or_else(|| {
+        NotFoundError::boxed("No pngme payload found in the pixel data".to_string())
+    })?;
+    Ok(envelope::recover(&[Ok(envelope)])?.envelope)
+}
+
+/// The messages found by `decode --all`, with the chunk type each came from and, when a chunk
+/// type was given, the index of its chunk among the chunks of that type.
+type Decoded = Vec<(ChunkType, Option<usize>, Vec<u8>)>;
+
+/// Decodes the message in every chunk type holding an envelope. Messages that can't be decoded
+/// are reported as warnings and left out.
+fn decode_all(args: DecodeArgs) -> crate::Result<Decoded> {
+    if args.mode == Mode::Lsb {
+        return Err(UsageError::boxed(
+            "--all only applies to --mode chunk".to_string(),
+        ));
+    }
+    let png = open(&args.file_path, args.offset, args.image_index)?.png;
+    if args.chunk_type.is_some() {
+        return decode_each(&png, &chunk_type(&args.chunk_type)?, &args);
+    }
+    let types = message_types(&png);
+    if types.is_empty() {
+        return Err(no_messages(&args.file_path));
+    }
+    let warnings = &mut CliWarnings;
+    let mut messages = vec![];
+    for ctype in types {
+        let decoded = envelope_from(&chunks_of(&png, &ctype), warnings).and_then(|envelope| {
+            check_authenticity(&envelope, &args)?;
+            check_expiry(&envelope, args.strict_expiry, warnings)?;
+            open_envelope(
+                envelope,
+                args.decrypt,
+                &args.keys,
+                &mut TerminalPrompt,
+                &mut OsKeyring,
+                warnings,
+            )
+        });
+        match decoded {
+            Ok(message) => messages.push((ctype, None, message)),
+            Err(e) => warnings.warn(Diagnostic::warning(
+                DiagnosticKind::Undecodable,
+                format!("couldn't decode the {} message: {}", ctype, e),
+            )),
+        }
+    }
+    Ok(messages)
+}
+
+/// Decodes every chunk of type `ctype` on its own, for `decode --all --chunk-type`.
+fn decode_each(png: &Png, ctype: &ChunkType, args: &DecodeArgs) -> crate::Result<Decoded> {
+    let chunks = png.chunks_by_type(&ctype.to_string());
+    if chunks.is_empty() {
+        return Err(NotFoundError::chunk(
+            &ctype.to_string(),
+            None,
+            format!("No chunk of type {} found", ctype),
+        ));
+    }
+    let warnings = &mut CliWarnings;
+    let mut messages = vec![];
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let decoded = envelope_from(std::slice::from_ref(chunk), warnings).and_then(|envelope| {
+            check_authenticity(&envelope, args)?;
+            check_expiry(&envelope, args.strict_expiry, warnings)?;
+            open_envelope(
+                envelope,
+                args.decrypt,
+                &args.keys,
+                &mut TerminalPrompt,
+                &mut OsKeyring,
+                warnings,
+            )
+        });
+        match decoded {
+            Ok(message) => messages.push((*ctype, Some(index), message)),
+            Err(e) => warnings.warn(Diagnostic::warning(
+                DiagnosticKind::Undecodable,
+                format!("couldn't decode {} #{}: {}", ctype, index, e),
+            )),
+        }
+    }
+    Ok(messages)
+}
+
+/// The types of the chunks holding a pngme envelope, in the order they first appear. The
+/// key-value store is left out, as it isn't a message.
+fn message_types(png: &Png) -> Vec<ChunkType> {
+    let mut types: Vec<ChunkType> = vec![];
+    for chunk in png.chunks() {
+        let ctype = chunk.chunk_type();
+        if ctype.to_string() != KV_CHUNK
+            && ctype.to_string() != HISTORY_CHUNK
+            && Envelope::is_envelope(chunk.data())
+            && !types.contains(ctype)
+        {
+            types.push(*ctype);
+        }
+    }
+    types
+}
+
+fn chunks_of(png: &Png, ctype: &ChunkType) -> Vec<Chunk> {
+    png.chunks()
+        .iter()
+        .filter(|c| c.chunk_type() == ctype)
+        .cloned()
+        .collect()
+}
+
+fn no_messages(file_path: &str) -> crate::Error {
+    NotFoundError::boxed(format!(
+        "No pngme envelopes found in {}, messages stored without one need --chunk-type",
+        file_path
+    ))
+}
+
+/// Parses `--chunk-type`, which is only optional with `--label` or in LSB mode.
+fn chunk_type(arg: &Option<String>) -> crate::Result<ChunkType> {
+    let arg = arg.as_deref().ok_or_else(|| {
+        UsageError::boxed(
+            "--chunk-type or --label is required unless --mode lsb is used".to_string(),
+        )
+    })?;
+    ChunkType::from_str(arg)
+}
+
+/// How many chunks hold the message whose chunks are `found`: one for a bare message, or the
+/// copy count recorded in the first readable envelope. `None` if no envelope could be read yet.
+fn expected_copies(found: &[Chunk]) -> Option<usize> {
+    found
+        .iter()
+        .find_map(|c| match Envelope::from_bytes(c.data()) {
+            Ok(None) => Some(1),
+            Ok(Some(e)) => Some(match (e.shard, e.copy) {
+                (Some(shard), _) => shard.total as usize,
+                (None, copy) => copy.map_or(1, |copy| copy.total as usize),
+            }),
+            Err(_) => None,
+        })
+}
+
+/// Splits `found`, the chunks of one type in file order, into the messages they hold the way
+/// decode reads them: each message takes as many chunks as the copy or shard count in its
+/// envelopes says, and a bare message takes one.
+fn group_messages(found: &[Chunk]) -> Vec<Range<usize>> {
+    let mut groups = vec![];
+    let mut start = 0;
+    for end in 1..=found.len() {
+        let complete =
+            expected_copies(&found[start..end]).is_some_and(|total| end - start >= total);
+        if complete || end == found.len() {
+            groups.push(start..end);
+            start = end;
+        }
+    }
+    groups
+}
+
+/// The envelope stored in `chunks`: a bare message wrapped as is, a message split with
+/// --max-chunk-size put back together, or envelope copies reconciled by majority vote, with
+/// damaged or missing copies reported to `warnings`.
+fn envelope_from(chunks: &[Chunk], warnings: &mut dyn Warnings) -> crate::Result<Envelope> {
+    if let [chunk] = chunks {
+        if Envelope::from_bytes(chunk.data())?.is_none() {
+            return Ok(Envelope {
+                payload: chunk.data().to_vec(),
+                ..Default::default()
+            });
+        }
+    }
+    if let Some(Ok(Some(first))) = chunks.first().map(|c| Envelope::from_bytes(c.data())) {
+        if first.shard.is_some() {
+            let shards = chunks
+                .iter()
+                .enumerate()
+                .map(|(idx, c)| {
+                    let envelope = Envelope::from_bytes(c.data())?.ok_or_else(|| {
+                        EnvelopeError::boxed(format!("chunk {} is not a pngme envelope", idx + 1))
+                    })?;
+                    Ok((format!("chunk {}", idx + 1), envelope))
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+            return shard::join(shards);
+        }
+    }
+    let copies: Vec<crate::Result<Envelope>> = chunks
+        .iter()
+        .map(|c| {
+            Envelope::from_bytes(c.data())?
+                .ok_or_else(|| EnvelopeError::boxed("not a pngme envelope".to_string()).into())
+        })
+        .collect();
+    let recovered = envelope::recover(&copies).map_err(|e| {
+        let explained = match Failure::of(&e) {
+            Failure::Envelope(damaged)
+                if damaged.is_damaged() && chunks.iter().all(Chunk::has_valid_crc) =>
+            {
+                Some(damaged.behind_valid_crc())
+            }
+            _ => None,
+        };
+        explained.map_or(e, |explained| explained as crate::Error)
+    })?;
+    let total = expected_copies(chunks).unwrap_or(chunks.len());
+    if chunks.len() < total {
+        warnings.warn(Diagnostic::warning(
+            DiagnosticKind::MissingCopies,
+            format!(
+                "only {} of {} copies of the message were found",
+                chunks.len(),
+                total
+            ),
+        ));
+    }
+    for &idx in &recovered.corrupt {
+        warnings.warn(Diagnostic::warning(
+            DiagnosticKind::CorruptCopy,
+            format!("copy {} of {} is corrupt", idx + 1, total),
+        ));
+    }
+    for (idx, envelope) in copies.iter().enumerate() {
+        match envelope {
+            Ok(e) if e.corrections > 0 && total > 1 => warnings.warn(Diagnostic::warning(
+                DiagnosticKind::Recovered,
+                format!(
+                    "error correction repaired {} corrupted byte(s) in copy {} of {}",
+                    e.corrections,
+                    idx + 1,
+                    total
+                ),
+            )),
+            Ok(e) if e.corrections > 0 => warnings.warn(Diagnostic::warning(
+                DiagnosticKind::Recovered,
+                format!(
+                    "error correction repaired {} corrupted byte(s)",
+                    e.corrections
+                ),
+            )),
+            _ => {}
         }
     }
+    if !recovered.corrupt.is_empty() {
+        warnings.warn(Diagnostic::warning(
+            DiagnosticKind::Recovered,
+            format!(
+                "the message was recovered from its redundant copies ({} of {} intact)",
+                chunks.len() - recovered.corrupt.len(),
+                total
+            ),
+        ));
+    }
+    Ok(recovered.envelope)
+}
+
+/// The message inside `envelope`, decrypted if `decrypt` is set.
+fn open_envelope(
+    envelope: Envelope,
+    decrypt: bool,
+    keys: &KeyArgs,
+    prompt: &mut dyn Prompt,
+    keyring: &mut dyn Keyring,
+    warnings: &mut dyn Warnings,
+) -> crate::Result<Vec<u8>> {
+    let key = decryption_key(&envelope, decrypt, keys, prompt, keyring, warnings)?;
+    let message = match (envelope.cipher, key) {
+        (Some(cipher), Some(source)) => crypto::decrypt(&source, &cipher, &envelope.payload)?,
+        _ => envelope.payload.clone(),
+    };
+    envelope.unpack(message)
+}
+
+/// The key to decrypt the message in `envelope` with when `decrypt` is set, failing unless
+/// `decrypt` is set exactly when the message is encrypted.
+fn decryption_key(
+    envelope: &Envelope,
+    decrypt: bool,
+    keys: &KeyArgs,
+    prompt: &mut dyn Prompt,
+    keyring: &mut dyn Keyring,
+    warnings: &mut dyn Warnings,
+) -> crate::Result<Option<KeySource>> {
+    match (envelope.cipher, decrypt) {
+        (Some(_), true) => key_source(keys, prompt, keyring, false, warnings).map(Some),
+        (Some(_), false) => Err(UsageError::boxed(
+            "The message is encrypted, decode it with --decrypt".to_string(),
+        )),
+        (None, true) => Err(UsageError::boxed(
+            "The message is not encrypted".to_string(),
+        )),
+        (None, false) => Ok(None),
+    }
+}
+
+/// The key given with `--passphrase`, `--key-file` or `--use-keyring`, then `PNGME_PASSPHRASE`
+/// or `PNGME_KEY_HEX`, or else a passphrase asked for on the terminal, entered twice when
+/// `confirm` is set.
+fn key_source(
+    keys: &KeyArgs,
+    prompt: &mut dyn Prompt,
+    keyring: &mut dyn Keyring,
+    confirm: bool,
+    warnings: &mut dyn Warnings,
+) -> crate::Result<KeySource> {
+    if let Some(passphrase) = &keys.passphrase {
+        return Ok(KeySource::Passphrase(passphrase.clone()));
+    }
+    if let Some(path) = &keys.key_file {
+        return Ok(KeySource::KeyFile(path.into()));
+    }
+    if let (true, Some(id)) = (keys.use_keyring, &keys.keyring_id) {
+        let passphrase = keyring_passphrase(id, prompt, keyring, confirm, warnings)?;
+        return Ok(KeySource::Passphrase(passphrase));
+    }
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        warnings.warn(Diagnostic::warning(
+            DiagnosticKind::KeyFromEnvironment,
+            format!("using the passphrase from {}", PASSPHRASE_VAR),
+        ));
+        return Ok(KeySource::Passphrase(passphrase.into()));
+    }
+    if let Ok(hex) = std::env::var(KEY_HEX_VAR).map(SecretBytes::from) {
+        warnings.warn(Diagnostic::warning(
+            DiagnosticKind::KeyFromEnvironment,
+            format!("using the key from {}", KEY_HEX_VAR),
+        ));
+        let key = crypto::key_from_hex(&hex)
+            .map_err(|e| UsageError::boxed(format!("{}: {}", KEY_HEX_VAR, e)))?;
+        return Ok(KeySource::Key(key));
+    }
+    Ok(KeySource::Passphrase(prompt::passphrase(prompt, confirm)?))
+}
+
+/// The passphrase stored in the keyring under `id`. When it can't be read it is asked for
+/// instead, and a missing one is stored once it has been confirmed.
+fn keyring_passphrase(
+    id: &str,
+    prompt: &mut dyn Prompt,
+    keyring: &mut dyn Keyring,
+    confirm: bool,
+    warnings: &mut dyn Warnings,
+) -> crate::Result<SecretBytes> {
+    let missing = match keyring.get(id) {
+        Ok(Some(passphrase)) => return Ok(passphrase),
+        Ok(None) => {
+            eprintln!(
+                "notice: no passphrase is stored in the keyring under '{}'",
+                id
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("notice: couldn't read the keyring ({}), asking instead", e);
+            false
+        }
+    };
+    let passphrase = prompt::passphrase(prompt, confirm)?;
+    if missing && confirm {
+        match keyring.set(id, &passphrase) {
+            Ok(()) => eprintln!("Stored the passphrase in the keyring under '{}'", id),
+            Err(e) => warnings.warn(Diagnostic::warning(
+                DiagnosticKind::Keyring,
+                format!("couldn't store the passphrase: {}", e),
+            )),
+        }
+    }
+    Ok(passphrase)
+}
+
+/// The padding requested with `--pad-to` or `--pad-block`.
+fn padding(args: &EncodeArgs) -> Option<Padding> {
+    match (args.pad_to, args.pad_block) {
+        (Some(size), _) => Some(Padding::To(size as usize)),
+        (None, Some(block)) => Some(Padding::Block(block as usize)),
+        (None, None) => None,
+    }
+}
+
+/// Refuses the options that would make the output of encode differ from run to run, which
+/// `--deterministic` promises it won't.
+fn check_deterministic(args: &EncodeArgs) -> crate::Result<()> {
+    let reason = if args.audit.audit {
+        "--audit records the time of the change"
+    } else if args.expires.is_some_and(|expires| expires.relative) {
+        "--expires with a duration depends on the current time, give a UTC time instead"
+    } else if args.encrypt && args.deterministic_seed.is_none() {
+        "encryption needs a secret --deterministic-seed to derive its nonce from"
+    } else {
+        return Ok(());
+    };
+    Err(UsageError::boxed(format!(
+        "Can't encode with --deterministic: {}",
+        reason
+    )))
+}
+
+/// Wraps `message` in an envelope, compressing, padding and encrypting it and adding error
+/// correction as requested.
+fn seal(
+    args: &EncodeArgs,
+    message: Vec<u8>,
+    prompt: &mut dyn Prompt,
+    keyring: &mut dyn Keyring,
+    warnings: &mut dyn Warnings,
+) -> crate::Result<Envelope> {
+    let (message, codec) = match args.compress.and_then(Algorithm::codec) {
+        Some(codec) => {
+            let (compressed, stats) =
+                compress::compress(&message, codec, args.min_compression_gain)?;
+            status(format!("Compression: {}", stats));
+            (compressed, Some(stats.codec))
+        }
+        None => (message, None),
+    };
+    // Pad before encrypting, so that it is the length of the ciphertext that gets evened out
+    let padding = padding(args);
+    // The message is part of the seed so that one seed never gives two messages the same nonce
+    let mut entropy = match args.deterministic {
+        true => Entropy::seeded(&[
+            args.deterministic_seed.as_deref().unwrap_or("").as_bytes(),
+            &message,
+        ]),
+        false => Entropy::Os,
+    };
+    let message = match padding {
+        Some(padding) => padding::pad(&message, padding, &mut entropy)?,
+        None => message,
+    };
+    let mut envelope = if args.encrypt {
+        let source = key_source(&args.keys, prompt, keyring, true, warnings)?;
+        let (cipher, ciphertext) = crypto::encrypt(&source, &message, &mut entropy)?;
+        Envelope::new(ciphertext).with_cipher(cipher)
+    } else {
+        Envelope::new(message)
+    };
+    if padding.is_some() {
+        envelope = envelope.with_padding();
+    }
+    if let Some(codec) = codec {
+        envelope = envelope.with_codec(codec);
+    }
+    if let Some(parity) = args.ecc {
+        envelope = envelope.with_ecc(parity);
+    }
+    if let Some(label) = &args.label {
+        envelope = envelope.with_label(label);
+    }
+    if let Some(expires) = args.expires {
+        envelope = envelope.with_expiry(expires.at);
+    }
+    // Signed and tagged last, over the payload exactly as it is stored and how to read it
+    if let Some(path) = &args.sign {
+        let key = signing::read_signing_key(Path::new(path))?;
+        let signature = signing::sign(&key, &envelope.authenticated_bytes());
+        envelope = envelope.with_signature(signature);
+    }
+    if let Some(path) = &args.mac_key {
+        let key = crypto::read_key_file(Path::new(path))?;
+        let mac = signing::tag(&key, &envelope.authenticated_bytes());
+        envelope = envelope.with_mac(mac);
+    }
+    Ok(envelope)
+}
+
+/// The message to embed: `--message` verbatim, the contents of `--message-file` (stdin for
+/// "-") with the requested newline handling applied, or the files of `--payload-dir` packed
+/// into an archive.
+fn read_message(args: &EncodeArgs) -> crate::Result<Vec<u8>> {
+    if let Some(dir) = &args.payload_dir {
+        return archive::pack(Path::new(dir));
+    }
+    let Some(path) = &args.message_file else {
+        return Ok(args.message.clone().unwrap_or_default().into_bytes());
+    };
+    let bytes = if path == STDIO {
+        let mut buf = vec![];
+        std::io::stdin().read_to_end(&mut buf)?;
+        buf
+    } else {
+        fs::read(path)?
+    };
+    Ok(args.newline.apply(bytes))
+}
+
+fn encode(args: EncodeArgs) -> crate::Result<MutationSummary> {
+    check_encode_args(&args)?;
+    let message = read_message(&args)?;
+    encode_message(args, message)
+}
+
+/// Fails on combinations of encode options that can't work together, before anything is read.
+fn check_encode_args(args: &EncodeArgs) -> crate::Result<()> {
+    let keys = &args.keys;
+    if !args.encrypt && (keys.passphrase.is_some() || keys.key_file.is_some() || keys.use_keyring) {
+        return Err(UsageError::boxed(
+            "--passphrase, --key-file and --use-keyring only apply with --encrypt".to_string(),
+        ));
+    }
+    if args.history.keep_previous && !args.replace {
+        return Err(UsageError::boxed(
+            "--keep-previous only applies with --replace".to_string(),
+        ));
+    }
+    // Anything after IEND would no longer belong to the selected image
+    if args.image_index.is_some() && args.placement == Placement::End {
+        return Err(UsageError::boxed(
+            "--placement end can't be used with --image-index".to_string(),
+        ));
+    }
+    if args.message_file.as_deref() == Some(STDIO) && args.file_path == STDIO {
+        return Err(UsageError::boxed(
+            "The png and --message-file can't both be read from stdin".to_string(),
+        ));
+    }
+    if args.deterministic {
+        check_deterministic(args)?;
+    }
+    Ok(())
+}
+
+/// Stores `message`, already read from wherever `args` point to, as `args` describe.
+fn encode_message(args: EncodeArgs, message: Vec<u8>) -> crate::Result<MutationSummary> {
+    let out_path = args.out_path.as_ref().unwrap_or(&args.file_path);
+    // As with strip, a new output file can't be written by anyone else yet
+    let target = Some(out_path)
+        .filter(|out| Path::new(out).exists())
+        .unwrap_or(&args.file_path);
+    let _lock = lock_file(target, &args.lock)?;
+    let mut source = open(&args.file_path, None, args.image_index)?;
+    let save = |source: &Source| {
+        if args.backup {
+            backup(&args.file_path)?;
+        }
+        source.save(out_path, args.verify_image, args.print_hash)
+    };
+    if args.mode == Mode::Lsb {
+        if args.redundancy > 1 {
+            return Err(UsageError::boxed(
+                "--redundancy can't be used with --mode lsb".to_string(),
+            ));
+        }
+        if args.replace {
+            return Err(UsageError::boxed(
+                "--replace can't be used with --mode lsb, which always replaces".to_string(),
+            ));
+        }
+        lsb::embed(
+            &mut source.png,
+            &seal(
+                &args,
+                message,
+                &mut TerminalPrompt,
+                &mut OsKeyring,
+                &mut CliWarnings,
+            )?
+            .as_bytes(),
+        )?;
+        let idat: Vec<Chunk> = source
+            .png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+            .cloned()
+            .collect();
+        record_change(&mut source.png, &args.audit, "encode", &idat)?;
+        return save(&source);
+    }
+    if args.replace {
+        let target = match &args.label {
+            Some(label) => Target::Label(label.clone()),
+            None => Target::ChunkType(chunk_type(&args.chunk_type)?),
+        };
+        let previous = take_message(&mut source.png, &target)?;
+        if args.history.keep_previous && !previous.is_empty() {
+            history::push(
+                &mut source.png,
+                &target,
+                previous,
+                args.history.history_depth as usize,
+            )?;
+        }
+    }
+    let ctype = match &args.label {
+        Some(label) => {
+            if !label::find(source.png.chunks(), label).is_empty() {
+                return Err(UsageError::boxed(format!(
+                    "A message labelled {:?} is already stored, remove it first",
+                    label
+                )));
+            }
+            label::chunk_type(label)
+        }
+        None => chunk_type(&args.chunk_type)?,
+    };
+    // The label lives in the envelope, so labelled messages are never stored bare
+    let bare = args.redundancy == 1
+        && args.max_chunk_size.is_none()
+        && args.ecc.is_none()
+        && !args.encrypt
+        && args.compress.and_then(Algorithm::codec).is_none()
+        && args.label.is_none()
+        && args.expires.is_none()
+        && args.sign.is_none()
+        && args.mac_key.is_none();
+    let chunks: Vec<Chunk> = if bare && padding(&args).is_none() {
+        vec![Chunk::new(ctype, message)]
+    } else {
+        let envelope = seal(
+            &args,
+            message,
+            &mut TerminalPrompt,
+            &mut OsKeyring,
+            &mut CliWarnings,
+        )?;
+        let total = args.redundancy;
+        let envelopes = match args.max_chunk_size {
+            Some(max) => shard::split(envelope, max)?,
+            None => (0..total)
+                .map(|index| match total {
+                    1 => envelope.clone(),
+                    _ => envelope.clone().with_copy(index, total),
+                })
+                .collect(),
+        };
+        envelopes
+            .into_iter()
+            .map(|envelope| Chunk::new(ctype, envelope.as_bytes()))
+            .collect()
+    };
+    let operation = if args.replace { "replace" } else { "encode" };
+    record_change(&mut source.png, &args.audit, operation, &chunks)?;
+    for chunk in chunks {
+        source.png.place_chunk(chunk, args.placement);
+    }
+    save(&source)
+}
+
+/// Stores a message too big for one image across the files matching --carriers, in as few of
+/// them as --max-per-file allows. Every file is read and its shard prepared before the first
+/// one is written.
+fn encode_carriers(args: EncodeArgs) -> crate::Result<Vec<PlacedShard>> {
+    let (Some(pattern), Some(budget)) = (&args.carriers, args.max_per_file) else {
+        return Err(UsageError::boxed(
+            "--carriers needs --max-per-file".to_string(),
+        ));
+    };
+    let keys = &args.keys;
+    if !args.encrypt && (keys.passphrase.is_some() || keys.key_file.is_some() || keys.use_keyring) {
+        return Err(UsageError::boxed(
+            "--passphrase, --key-file and --use-keyring only apply with --encrypt".to_string(),
+        ));
+    }
+    if args.deterministic {
+        check_deterministic(&args)?;
+    }
+    let ctype = match &args.label {
+        Some(label) => label::chunk_type(label),
+        None => chunk_type(&args.chunk_type)?,
+    };
+    let paths = batch::glob(pattern)?;
+    let message = read_message(&args)?;
+    let envelope = seal(
+        &args,
+        message,
+        &mut TerminalPrompt,
+        &mut OsKeyring,
+        &mut CliWarnings,
+    )?;
+    let shards = shard::split(envelope, budget)?;
+    if shards.len() > paths.len() {
+        return Err(UsageError::boxed(format!(
+            "The message takes {} shards of at most {} bytes, but only {} file(s) match {}",
+            shards.len(),
+            budget,
+            paths.len(),
+            pattern
+        )));
+    }
+    let mut prepared = vec![];
+    for (path, shard) in paths.iter().zip(shards) {
+        let path = path.to_string_lossy().into_owned();
+        let lock = lock_file(&path, &args.lock)?;
+        let mut source = open(&path, None, None)?;
+        if let Some(label) = &args.label {
+            if !label::find(source.png.chunks(), label).is_empty() {
+                return Err(UsageError::boxed(format!(
+                    "A message labelled {:?} is already stored in {}, remove it first",
+                    label, path
+                )));
+            }
+        }
+        let info = shard.shard.expect("split marks every shard");
+        let chunk = Chunk::new(ctype, shard.as_bytes());
+        record_change(
+            &mut source.png,
+            &args.audit,
+            "encode",
+            std::slice::from_ref(&chunk),
+        )?;
+        source.png.place_chunk(chunk, args.placement);
+        prepared.push((path, info, source, lock));
+    }
+    let mut placed = vec![];
+    for (path, info, source, _lock) in prepared {
+        let summary = source.save(&path, args.verify_image, args.print_hash)?;
+        status(format!(
+            "Stored shard {} of {} in {}",
+            info.index + 1,
+            info.total,
+            path
+        ));
+        placed.push(PlacedShard {
+            path,
+            index: info.index,
+            total: info.total,
+            summary,
+        });
+    }
+    Ok(placed)
+}
+
+/// Encodes the same message into every file given, each written over itself. The message is
+/// read once, so one given on stdin reaches every file.
+fn encode_batch(args: EncodeArgs, format: Format) -> crate::Result<()> {
+    if args.out_path.is_some() {
+        return Err(UsageError::boxed(
+            "--out-path can't be used when encoding several files".to_string(),
+        ));
+    }
+    check_encode_args(&args)?;
+    let message = read_message(&args)?;
+    let paths = batch_paths(&args.file_path, &args.batch);
+    run_batch(&paths, args.batch.recursive, format, |file_path, file| {
+        let args = EncodeArgs {
+            file_path,
+            ..args.clone()
+        };
+        file.summary = Some(encode_message(args, message.clone())?);
+        Ok(())
+    })
+}
+
+/// Opens the text message in chunks of `--chunk-type` in an editor and stores what comes back
+/// in place of the old chunks, with the same copies, error correction, compression, padding
+/// and label. Nothing is written if the text is unchanged or the editor fails.
+fn edit(args: EditArgs, editor: &mut dyn Editor) -> crate::Result<MutationSummary> {
+    let ctype = ChunkType::from_str(&args.chunk_type)?;
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut source = open(&args.file_path, None, None)?;
+    let positions: Vec<usize> = source
+        .png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| *c.chunk_type() == ctype)
+        .map(|(idx, _)| idx)
+        .collect();
+    let found: Vec<Chunk> = positions
+        .iter()
+        .map(|&idx| source.png.chunks()[idx].clone())
+        .collect();
+    if found.is_empty() {
+        return Err(NotFoundError::chunk(
+            &ctype.to_string(),
+            None,
+            format!("No chunk of type {} found", ctype),
+        ));
+    }
+    let envelope = envelope_from(&found, &mut CliWarnings)?;
+    if envelope.cipher.is_some() {
+        return Err(UsageError::boxed(
+            "The message is encrypted, which edit doesn't support".to_string(),
+        ));
+    }
+    // Edited, the message would be stored without them, as edit has no key to make new ones
+    let authenticated = match (envelope.signature, envelope.mac) {
+        (Some(_), _) => Some("signed"),
+        (None, Some(_)) => Some("tagged with an HMAC"),
+        (None, None) => None,
+    };
+    if let Some(authenticated) = authenticated {
+        return Err(RefusedError::boxed(format!(
+            "The message is {}, which editing would drop. Remove it and encode the new one \
+             instead",
+            authenticated
+        )));
+    }
+    let message = String::from_utf8(envelope.unpack(envelope.payload.clone())?).map_err(|_| {
+        UsageError::boxed(
+            "The message is not UTF-8 text, use remove and encode --message-file to replace it"
+                .to_string(),
+        )
+    })?;
+
+    let mut file = tempfile::Builder::new()
+        .prefix("pngme-")
+        .suffix(".txt")
+        .tempfile()?;
+    file.write_all(message.as_bytes())?;
+    file.flush()?;
+    // Ctrl-C reaches the editor too, and pngme waits for it so the temporary file is removed
+    let deferred = cancel::defer();
+    editor.edit(file.path())?;
+    drop(deferred);
+    source.cancel.check()?;
+    let edited = fs::read(file.path())?;
+    if edited == message.as_bytes() {
+        status("The message is unchanged, nothing was written".to_string());
+        return Ok(MutationSummary::between(&source.bytes, &source.bytes));
+    }
+
+    let bare = found.len() == 1 && Envelope::from_bytes(found[0].data())?.is_none();
+    let chunks: Vec<Chunk> = match bare {
+        true => vec![Chunk::new(ctype, edited)],
+        false => {
+            let rewrapped = rewrap(&envelope, edited)?;
+            let total = envelope.copy.map(|copy| copy.total);
+            (0..total.unwrap_or(1))
+                .map(|index| match total {
+                    Some(total) => rewrapped.clone().with_copy(index, total),
+                    None => rewrapped.clone(),
+                })
+                .map(|e| Chunk::new(ctype, e.as_bytes()))
+                .collect()
+        }
+    };
+    for &idx in positions.iter().rev() {
+        source.png.remove_chunk(idx)?;
+    }
+    for (offset, chunk) in chunks.into_iter().enumerate() {
+        source.png.insert_chunk(positions[0] + offset, chunk)?;
+    }
+    let summary = source.save(&args.file_path, false, None)?;
+    status(format!("Stored the edited message in {}", args.chunk_type));
+    Ok(summary)
+}
+
+/// A new envelope for `message`, stored the way `old` was. A padded message is padded to a
+/// multiple of the old padded size, so it keeps its size unless it outgrows it.
+fn rewrap(old: &Envelope, message: Vec<u8>) -> crate::Result<Envelope> {
+    let (message, codec) = match old.codec {
+        Some(codec) => {
+            // A message that ended up stored as is doesn't say what it was meant to be
+            // compressed with
+            let codec = match codec {
+                Codec::Stored => Codec::Deflate,
+                codec => codec,
+            };
+            let (compressed, stats) = compress::compress(&message, codec, 0)?;
+            (compressed, Some(stats.codec))
+        }
+        None => (message, None),
+    };
+    let message = match old.padded {
+        true => padding::pad(
+            &message,
+            Padding::Block(old.payload.len()),
+            &mut Entropy::Os,
+        )?,
+        false => message,
+    };
+    let mut envelope = Envelope::new(message);
+    envelope.padded = old.padded;
+    envelope.codec = codec;
+    envelope.ecc = old.ecc;
+    envelope.label = old.label.clone();
+    envelope.expires = old.expires;
+    Ok(envelope)
+}
+
+/// Overwrites the data of one chunk with the fill byte and fixes up its CRC, writing only
+/// those bytes so the rest of the file, including the chunk's length, stays as it was.
+fn redact(args: RedactArgs) -> crate::Result<MutationSummary> {
+    let ctype = ChunkType::from_str(&args.chunk_type)?;
+    remote::local_output(&args.file_path)?;
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&args.file_path)?;
+    let mut offset = Png::STANDARD_HEADER.len();
+    let mut offsets = vec![];
+    let mut found = vec![];
+    let mut kept = None;
+    let _ = ChunkStream::new(BufReader::new(&file))?.walk(|_, chunk| {
+        if *chunk.chunk_type() == ctype {
+            offsets.push(offset);
+            found.push(chunk.clone());
+        } else if chunk.chunk_type().to_string() == HISTORY_CHUNK {
+            kept = Some(chunk.clone());
+        }
+        offset += 12 + chunk.data().len();
+        ControlFlow::Continue(())
+    })?;
+    // Redacting one copy or shard would leave the message readable from the others
+    let message = group_messages(&found)
+        .into_iter()
+        .find(|group| group.contains(&args.index))
+        .ok_or_else(|| {
+            NotFoundError::chunk(
+                &args.chunk_type,
+                Some(args.index),
+                format!(
+                    "No chunk of type {} with index {} found ({} present)",
+                    args.chunk_type,
+                    args.index,
+                    found.len()
+                ),
+            )
+        })?;
+    if let Some(kept) = kept {
+        let labels = found[message.clone()].iter().filter_map(label::label_of);
+        for target in [Target::ChunkType(ctype)]
+            .into_iter()
+            .chain(labels.map(Target::Label))
+        {
+            if history::keeps(&kept, &target)? {
+                return Err(RefusedError::boxed(format!(
+                    "{} keeps previous versions of the message at {} for undo, which redact \
+                     can't overwrite in place; remove the {} chunk first",
+                    args.file_path, target, HISTORY_CHUNK
+                )));
+            }
+        }
+    }
+
+    // Stopping between the data and the CRC would leave a chunk that fails its check
+    let _deferred = cancel::defer();
+    cancel::interrupt().check()?;
+    let mut len = 0;
+    for idx in message.clone() {
+        let filled = Chunk::new(ctype, vec![args.fill; found[idx].data().len()]);
+        file.seek(SeekFrom::Start((offsets[idx] + 8) as u64))?;
+        file.write_all(filled.data())?;
+        file.write_all(&filled.crc().to_be_bytes())?;
+        len += filled.data().len();
+    }
+    file.sync_all()?;
+    match message.len() {
+        1 => status(format!(
+            "Redacted {} of {} chunk {} at offset {:#x}",
+            output::size(len),
+            args.chunk_type,
+            args.index,
+            offsets[args.index]
+        )),
+        copies => status(format!(
+            "Redacted {} of {} chunks {} to {}, which hold {} copies or shards of one message",
+            output::size(len),
+            args.chunk_type,
+            message.start,
+            message.end - 1,
+            copies
+        )),
+    }
+    let size = file.metadata()?.len() as usize;
+    Ok(MutationSummary {
+        bytes_before: size,
+        bytes_after: size,
+        chunks_modified: message.len(),
+        ..Default::default()
+    })
+}
+
+fn seal_image(args: SealArgs) -> crate::Result<MutationSummary> {
+    let key = crypto::read_key_file(Path::new(&args.key_file))?;
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut source = open(&args.file_path, None, None)?;
+    seal::seal(&mut source.png, &key)?;
+    let summary = source.save(&args.file_path, false, None)?;
+    status(format!("Sealed the image data of {}", args.file_path));
+    Ok(summary)
+}
+
+fn attest(args: AttestArgs) -> crate::Result<()> {
+    let key = crypto::read_key_file(Path::new(&args.key_file))?;
+    seal::attest(&open(&args.file_path, None, None)?.png, &key)?;
+    println!("The image data of {} matches its seal", args.file_path);
+    Ok(())
+}
+
+fn keygen(args: KeygenArgs) -> crate::Result<()> {
+    let public_path = format!("{}.pub", args.out_path);
+    let mut paths = vec![&args.out_path];
+    if args.signing {
+        paths.push(&public_path);
+    }
+    for path in paths {
+        if !args.force && Path::new(path).exists() {
+            return Err(RefusedError::boxed(format!(
+                "{} already exists, pass --force to overwrite it",
+                path
+            )));
+        }
+    }
+    if args.signing {
+        let (seed, public) = signing::generate()?;
+        write_key(&args.out_path, seed.expose())?;
+        fs::write(&public_path, public)?;
+        println!(
+            "Wrote an Ed25519 signing key to {} and its public key to {}",
+            args.out_path, public_path
+        );
+        return Ok(());
+    }
+    let key: [u8; crypto::KEY_LEN] = crypto::random_bytes()?;
+    write_key(&args.out_path, &key)?;
+    println!(
+        "Wrote a {} byte symmetric key to {}",
+        crypto::KEY_LEN,
+        args.out_path
+    );
+    Ok(())
+}
+
+/// Writes `key` to `path`, readable only by the current user.
+fn write_key(path: &str, key: &[u8]) -> crate::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // The mode above only applies to a new file, so one overwritten with --force is narrowed
+    // before the key goes in
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.set_len(0)?;
+    file.write_all(key)?;
+    Ok(())
+}
+
+/// Prints whether each stage of `selftest` passed, skipping the rest once one fails.
+#[derive(Default)]
+struct Stages {
+    failed: Option<String>,
+}
+
+impl Stages {
+    fn run(&mut self, name: &str, stage: impl FnOnce() -> crate::Result<()>) {
+        if self.failed.is_some() {
+            println!("skip  {}", name);
+            return;
+        }
+        match stage() {
+            Ok(()) => println!("pass  {}", name),
+            Err(e) => {
+                println!("FAIL  {}: {}", name, e);
+                self.failed = Some(name.to_string());
+            }
+        }
+    }
+}
+
+/// A 1x1 black grayscale png, small but complete enough for every check to accept.
+fn selftest_png() -> crate::Result<Png> {
+    let ihdr = [
+        &1u32.to_be_bytes()[..],
+        &1u32.to_be_bytes(),
+        &[8, 0, 0, 0, 0],
+    ]
+    .concat();
+    // The zlib stream of one scanline: filter type 0 and a single 0 byte pixel
+    let idat = vec![120, 156, 99, 96, 0, 0, 0, 2, 0, 1];
+    Ok(Png::from_chunks(vec![
+        Chunk::new(ChunkType::from_str("IHDR")?, ihdr),
+        Chunk::new(ChunkType::from_str("IDAT")?, idat),
+        Chunk::new(ChunkType::from_str("IEND")?, vec![]),
+    ]))
+}
+
+/// The lenient parse `check` does, failing only on errors: messages appended after IEND are
+/// warned about, but are what encode writes.
+fn selftest_check(path: &str) -> crate::Result<()> {
+    let (png, diagnostics) = Png::parse_report_lenient(&fs::read(path)?);
+    png?;
+    match diagnostics.into_iter().find(Diagnostic::is_error) {
+        Some(d) => Err(Box::new(d)),
+        None => Ok(()),
+    }
+}
+
+/// Stores and removes a message in a png in a temporary directory in every way pngme can,
+/// going through the same code as the subcommands, and reports each stage.
+fn selftest() -> crate::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = |name: &str| -> crate::Result<String> {
+        Ok(dir
+            .path()
+            .join(name)
+            .to_str()
+            .ok_or("the temporary directory's path isn't valid UTF-8")?
+            .to_string())
+    };
+    let (file_path, key_path) = (path("selftest.png")?, path("selftest.key")?);
+    // Long and repetitive enough to be worth compressing
+    let message = "pngme selftest\n".repeat(8);
+    let original = selftest_png()?.as_bytes();
+    let encode_args = || EncodeArgs {
+        file_path: file_path.clone(),
+        carriers: None,
+        max_per_file: None,
+        max_chunk_size: None,
+        placement: Placement::default(),
+        chunk_type: Some("ruSt".to_string()),
+        label: None,
+        mode: Mode::Chunk,
+        message: Some(message.clone()),
+        message_file: None,
+        payload_dir: None,
+        expires: None,
+        newline: Newline::Keep,
+        redundancy: 1,
+        ecc: None,
+        encrypt: false,
+        keys: KeyArgs::default(),
+        lock: LockArgs::default(),
+        compress: None,
+        min_compression_gain: 0,
+        pad_to: None,
+        pad_block: None,
+        out_path: None,
+        backup: false,
+        verify_image: cfg!(feature = "image-verify"),
+        image_index: None,
+        print_hash: None,
+        replace: false,
+        history: HistoryArgs::default(),
+        audit: AuditArgs::default(),
+        deterministic: false,
+        deterministic_seed: None,
+        batch: BatchArgs::default(),
+        sign: None,
+        mac_key: None,
+    };
+    let roundtrips = [
+        ("plain", encode_args()),
+        (
+            "zstd compressed with 3 copies",
+            EncodeArgs {
+                compress: Some(Algorithm::Zstd),
+                redundancy: 3,
+                ..encode_args()
+            },
+        ),
+        (
+            "encrypted with an ephemeral key",
+            EncodeArgs {
+                encrypt: true,
+                keys: KeyArgs {
+                    key_file: Some(key_path.clone()),
+                    ..Default::default()
+                },
+                ..encode_args()
+            },
+        ),
+    ];
+
+    let mut stages = Stages::default();
+    stages.run("write a 1x1 png", || Ok(fs::write(&file_path, &original)?));
+    stages.run("check", || selftest_check(&file_path));
+    stages.run("generate an ephemeral key", || {
+        keygen(KeygenArgs {
+            symmetric: true,
+            signing: false,
+            out_path: key_path.clone(),
+            force: false,
+        })
+    });
+    for (name, args) in roundtrips {
+        let encrypted = args.encrypt;
+        stages.run(&format!("{}: encode", name), || encode(args).map(drop));
+        stages.run(&format!("{}: check", name), || selftest_check(&file_path));
+        stages.run(&format!("{}: decode", name), || {
+            decode(DecodeArgs {
+                file_path: file_path.clone(),
+                carriers: None,
+                chunk_type: Some("ruSt".to_string()),
+                label: None,
+                chunk_type_hex: None,
+                all: false,
+                mode: Mode::Chunk,
+                offset: None,
+                image_index: None,
+                decrypt: encrypted,
+                keys: KeyArgs {
+                    key_file: Some(key_path.clone()),
+                    ..Default::default()
+                },
+                expect: Some(message.clone()),
+                newline: Newline::Keep,
+                strict_expiry: false,
+                list: false,
+                extract_to: None,
+                output: None,
+                batch: BatchArgs::default(),
+                verify: None,
+                mac_key: None,
+            })
+            .map(drop)
+        });
+        stages.run(&format!("{}: remove", name), || {
+            remove(RemoveArgs {
+                file_path: file_path.clone(),
+                chunk_type: Some("ruSt".to_string()),
+                label: None,
+                chunk_type_hex: None,
+                force: false,
+                all: false,
+                index: None,
+                verify_image: cfg!(feature = "image-verify"),
+                image_index: None,
+                lock: LockArgs::default(),
+                print_hash: None,
+                audit: AuditArgs::default(),
+                history: HistoryArgs::default(),
+                batch: BatchArgs::default(),
+            })
+            .map(drop)
+        });
+        stages.run(&format!("{}: check", name), || {
+            selftest_check(&file_path)?;
+            match fs::read(&file_path)? == original {
+                true => Ok(()),
+                false => Err(MismatchError::boxed(
+                    "the png isn't back to how it started".to_string(),
+                )),
+            }
+        });
+    }
+    match stages.failed {
+        Some(stage) => Err(format!("selftest failed at: {}", stage).into()),
+        None => {
+            println!("All stages passed");
+            Ok(())
+        }
+    }
+}
+
+fn diff(args: DiffArgs, format: Format) -> crate::Result<()> {
+    let old = open(&args.file_path, None, None)?.png;
+    let new = open(&args.other, None, None)?.png;
+    let patch = Patch::between(&old, &new);
+    if let Some(path) = &args.emit_patch {
+        fs::write(path, patch.as_bytes())?;
+    }
+    if format == Format::Json {
+        let changes = patch.ops.iter().map(ToString::to_string).collect();
+        return document::emit(&DiffResult { changes });
+    }
+    if patch.is_empty() && format == Format::Pretty {
+        println!("No ancillary chunks differ");
+    }
+    for op in &patch.ops {
+        println!("{}", op);
+    }
+    Ok(())
+}
+
+fn patch(args: PatchArgs) -> crate::Result<MutationSummary> {
+    let out_path = args.out_path.as_ref().unwrap_or(&args.file_path);
+    // As with strip, a new output file can't be written by anyone else yet
+    let target = Some(out_path)
+        .filter(|out| Path::new(out).exists())
+        .unwrap_or(&args.file_path);
+    let _lock = lock_file(target, &args.lock)?;
+    let patch = Patch::from_bytes(&fs::read(&args.apply)?)?;
+    let mut source = open(&args.file_path, None, None)?;
+    patch.apply(&mut source.png, args.force)?;
+    source.save(out_path, false, None)
+}
+
+/// Copies the ancillary chunks of --from that may outlive a change to the image data onto
+/// --onto: those marked safe to copy and those of the --allow types. Chunks that came before
+/// the first IDAT go before it again, the rest before IEND.
+fn propagate(args: PropagateArgs) -> crate::Result<MutationSummary> {
+    let out_path = args.out_path.as_ref().unwrap_or(&args.onto);
+    let target = Some(out_path)
+        .filter(|out| Path::new(out).exists())
+        .unwrap_or(&args.onto);
+    let _lock = lock_file(target, &args.lock)?;
+    let allowed = args
+        .allow
+        .iter()
+        .map(|t| ChunkType::from_str(t))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(critical) = allowed.iter().find(|t| t.is_critical()) {
+        return Err(UsageError::boxed(format!(
+            "{} is a critical chunk and can't be propagated",
+            critical
+        )));
+    }
+    let donor = open(&args.from, None, None)?.png;
+    let copyable = donor.copyable_chunks();
+    let mut source = open(&args.onto, None, None)?;
+    let (mut copied, mut critical) = (0, 0);
+    let mut before_idat = true;
+    for (index, chunk) in donor.chunks().iter().enumerate() {
+        let ctype = chunk.chunk_type();
+        if ctype.bytes() == *b"IDAT" {
+            before_idat = false;
+        }
+        if ctype.is_critical() {
+            critical += 1;
+            continue;
+        }
+        if !copyable.contains(&chunk) && !allowed.contains(ctype) {
+            CliWarnings.warn(
+                Diagnostic::warning(
+                    DiagnosticKind::UnsafeToCopy,
+                    format!(
+                        "skipped the {} chunk, which isn't safe to copy once the image data \
+                         changes, give --allow {} if it doesn't depend on it",
+                        ctype, ctype
+                    ),
+                )
+                .chunk(index),
+            );
+            continue;
+        }
+        if source.png.chunks().contains(chunk) {
+            status(format!(
+                "Skipped the {} chunk at index {}, which {} already has",
+                ctype, index, args.onto
+            ));
+            continue;
+        }
+        let chunks = source.png.chunks();
+        let anchor: &[u8; 4] = if before_idat { b"IDAT" } else { b"IEND" };
+        let at = chunks
+            .iter()
+            .position(|c| c.chunk_type().bytes() == *anchor)
+            .unwrap_or(chunks.len());
+        source.png.insert_chunk(at, chunk.clone())?;
+        copied += 1;
+    }
+    status(format!(
+        "Copied {} chunk(s) from {}, leaving out its {} critical chunk(s)",
+        copied, args.from, critical
+    ));
+    source.save(out_path, false, None)
+}
+
+fn git_filter(args: GitFilterArgs) -> crate::Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    match args.action {
+        GitFilterAction::Clean { chunk_type } => {
+            let types = chunk_type
+                .iter()
+                .map(|t| ChunkType::from_str(t))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(critical) = types.iter().find(|t| t.is_critical()) {
+                return Err(UsageError::boxed(format!(
+                    "{} is a critical chunk and can't be stripped",
+                    critical
+                )));
+            }
+            git_filter::clean(io::stdin().lock(), &mut stdout, &types)?;
+        }
+        GitFilterAction::Smudge => {
+            io::copy(&mut io::stdin().lock(), &mut stdout)?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn man(args: ManArgs) -> crate::Result<()> {
+    let Some(out_dir) = &args.out_dir else {
+        io::stdout().write_all(&man::page(&args.subcommand)?.roff)?;
+        return Ok(());
+    };
+    fs::create_dir_all(out_dir)?;
+    let pages = man::pages()?;
+    for page in &pages {
+        fs::write(Path::new(out_dir).join(page.file_name()), &page.roff)?;
+    }
+    println!("Wrote {} manual pages to {}", pages.len(), out_dir);
+    Ok(())
+}
+
+fn keyring(
+    args: KeyringArgs,
+    prompt: &mut dyn Prompt,
+    keyring: &mut dyn Keyring,
+) -> crate::Result<()> {
+    match args.action {
+        KeyringAction::Set { id } => {
+            keyring.set(&id, &prompt::passphrase(prompt, true)?)?;
+            println!("Stored the passphrase for '{}'", id);
+        }
+        KeyringAction::Get { id } => {
+            let passphrase = keyring.get(&id)?.ok_or_else(|| {
+                NotFoundError::boxed(format!("No passphrase is stored under '{}'", id))
+            })?;
+            let mut stdout = std::io::stdout();
+            stdout.write_all(passphrase.expose())?;
+            stdout.write_all(b"\n")?;
+        }
+        KeyringAction::Delete { id } => {
+            if !keyring.delete(&id)? {
+                return Err(NotFoundError::boxed(format!(
+                    "No passphrase is stored under '{}'",
+                    id
+                )));
+            }
+            println!("Deleted the passphrase for '{}'", id);
+        }
+    }
+    Ok(())
+}
+
+fn kv(args: KvArgs) -> crate::Result<()> {
+    match args.action {
+        KvAction::Set {
+            file_path,
+            key,
+            value,
+            value_file,
+            lock,
+        } => {
+            let value = match value_file {
+                Some(path) => fs::read(path)?,
+                None => value.unwrap_or_default().into_bytes(),
+            };
+            let _lock = lock_file(&file_path, &lock)?;
+            let mut source = open(&file_path, None, None)?;
+            let replaced = source.png.kv()?.set(&key, value)?.is_some();
+            source.save(&file_path, false, None)?;
+            match replaced {
+                true => output::say(format_args!("Replaced the value of '{}'", key)),
+                false => output::say(format_args!("Stored '{}'", key)),
+            }
+        }
+        KvAction::Get { file_path, key } => {
+            let mut png = open(&file_path, None, None)?.png;
+            let kv = png.kv()?;
+            let value = kv.get(&key).ok_or_else(|| {
+                NotFoundError::boxed(format!("No value is stored under '{}'", key))
+            })?;
+            std::io::stdout().write_all(value)?;
+        }
+        KvAction::Del {
+            file_path,
+            key,
+            lock,
+        } => {
+            let _lock = lock_file(&file_path, &lock)?;
+            let mut source = open(&file_path, None, None)?;
+            if source.png.kv()?.remove(&key)?.is_none() {
+                return Err(NotFoundError::boxed(format!(
+                    "No value is stored under '{}'",
+                    key
+                )));
+            }
+            source.save(&file_path, false, None)?;
+            output::say(format_args!("Deleted '{}'", key));
+        }
+        KvAction::List { file_path } => {
+            let mut png = open(&file_path, None, None)?.png;
+            let kv = png.kv()?;
+            if kv.is_empty() {
+                println!("No values are stored in {}", file_path);
+            }
+            for key in kv.keys() {
+                println!("{}", key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn frames(args: FramesArgs, format: Format) -> crate::Result<()> {
+    let FramesAction::Extract { file_path, out_dir } = args.action;
+    let png = open(&file_path, None, None)?.png;
+    if png.chunk_by_type("acTL").is_none() {
+        return Err(NotFoundError::boxed(format!(
+            "{} has no acTL chunk, it isn't an animated png",
+            file_path
+        )));
+    }
+    let frames = apng::frames(&png)?;
+    let pngs = frames
+        .iter()
+        .map(|frame| frame.to_png(&png))
+        .collect::<crate::Result<Vec<Png>>>()?;
+    fs::create_dir_all(&out_dir)?;
+    let mut written = vec![];
+    for (index, (frame, frame_png)) in frames.iter().zip(&pngs).enumerate() {
+        let path = Path::new(&out_dir).join(format!("frame_{:03}.png", index));
+        let _deferred = cancel::defer();
+        cancel::interrupt().check()?;
+        fs::write(&path, frame_png.as_bytes())?;
+        written.push(ExtractedFrame {
+            index,
+            path: path.display().to_string(),
+            width: frame.control.width,
+            height: frame.control.height,
+            x_offset: frame.control.x_offset,
+            y_offset: frame.control.y_offset,
+            default_image: frame.default_image,
+        });
+    }
+    if format == Format::Json {
+        return document::emit(&written);
+    }
+    let mut table = Table::new(&["index", "file", "size", "offset"]);
+    for frame in &written {
+        table.row(vec![
+            frame.index.to_string(),
+            frame.path.clone(),
+            format!("{}x{}", frame.width, frame.height),
+            format!("{},{}", frame.x_offset, frame.y_offset),
+        ]);
+    }
+    let mut out = table.render(format);
+    if format == Format::Pretty {
+        out += &format!(
+            "Extracted {} frame(s) to {}. Each is the raw region the frame stores, without \
+             the dispose and blend operations of the frames before it applied.\n",
+            written.len(),
+            out_dir
+        );
+    }
+    Ok(output::page(&out)?)
+}
+
+fn triage(args: TriageArgs, format: Format) -> crate::Result<()> {
+    let report = triage::triage(&read_input(&args.file_path)?);
+    if format == Format::Json {
+        return document::emit(&report);
+    }
+    let mut table = Table::new(&["priority", "offset", "finding"]);
+    for finding in &report.findings {
+        table.row(vec![
+            finding.priority.to_string(),
+            finding
+                .offset
+                .map_or(String::new(), |offset| format!("{:#x}", offset)),
+            finding.message.clone(),
+        ]);
+    }
+    let mut out = String::new();
+    if !report.findings.is_empty() {
+        out += &table.render(format);
+    }
+    out += &format!("{}\n", report.summary);
+    Ok(output::page(&out)?)
+}
+
+fn rewrite(args: RewriteArgs) -> crate::Result<MutationSummary> {
+    let substitution = Substitution::parse(&args.sed, args.literal)?;
+    let ctype = ChunkType::from_str(&args.chunk_type)?;
+    let keyword = args.keyword.as_ref().map(|k| [k.as_bytes(), &[0]].concat());
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut source = open(&args.file_path, None, args.image_index)?;
+    let changed = source.png.transform_chunks(
+        |c| {
+            *c.chunk_type() == ctype
+                && keyword
+                    .as_ref()
+                    .is_none_or(|keyword| c.data().starts_with(keyword))
+        },
+        |data| Ok(substitution.apply(data)),
+    )?;
+    if changed.is_empty() {
+        status(format!(
+            "No {} chunk matched {}, the file is unchanged",
+            ctype, args.sed
+        ));
+        return Ok(MutationSummary::between(&source.bytes, &source.bytes));
+    }
+    let rewritten: Vec<Chunk> = changed
+        .iter()
+        .map(|&index| source.png.chunks()[index].clone())
+        .collect();
+    record_change(&mut source.png, &args.audit, "rewrite", &rewritten)?;
+    let summary = source.save(&args.file_path, args.verify_image, args.print_hash)?;
+    status(format!("Rewrote {} {} chunk(s)", changed.len(), ctype));
+    Ok(summary)
+}
+
+fn repair(args: RepairArgs, format: Format) -> crate::Result<()> {
+    let target = args
+        .out_path
+        .as_ref()
+        .filter(|out| Path::new(out).exists())
+        .unwrap_or(&args.file_path);
+    let _lock = lock_file(target, &args.lock)?;
+    let bytes = read_input(&args.file_path)?;
+    let mut repaired = repair::repair(&bytes, args.drop_unrecoverable)?;
+    if args.audit.audit && !repaired.fixes.is_empty() {
+        let mut png = Png::try_from(&repaired.bytes[..])?;
+        record_change(&mut png, &args.audit, "repair", &[])?;
+        repaired.bytes = png.as_bytes();
+    }
+    if args.verify_image {
+        verify::verify_image(&repaired.bytes)?;
+    }
+    let out_path = args.out_path.as_ref().unwrap_or(&args.file_path);
+    remote::local_output(out_path)?;
+    if !repaired.fixes.is_empty() || out_path != &args.file_path || out_path == STDIO {
+        let _deferred = cancel::defer();
+        cancel::interrupt().check()?;
+        write_png(out_path, &repaired.bytes)?;
+    }
+    let summary = MutationSummary {
+        digest: args
+            .print_hash
+            .map(|algorithm| FileDigest::of(algorithm, &repaired.bytes, out_path)),
+        ..MutationSummary::between(&bytes, &repaired.bytes)
+    };
+    if format == Format::Json {
+        return document::emit(&RepairResult {
+            fixes: repaired.fixes,
+            summary,
+        });
+    }
+    for fix in &repaired.fixes {
+        output::say(fix);
+    }
+    if repaired.fixes.is_empty() {
+        output::say(format_args!("{} has nothing to repair", args.file_path));
+    }
+    summary.render(false)
+}
+
+fn capacity(args: CapacityArgs, format: Format) -> crate::Result<()> {
+    let png = open(&args.file_path, None, None)?.png;
+    let estimates = capacity::estimate(&png);
+    if format == Format::Json {
+        return document::emit(&estimates);
+    }
+    let size = |bytes: Option<usize>| bytes.map_or("-".to_string(), output::size);
+    let mut table = Table::new(&["strategy", "practical", "spec limit", "note"]);
+    for estimate in &estimates {
+        table.row(vec![
+            estimate.strategy.to_string(),
+            size(estimate.practical),
+            size(estimate.limit),
+            estimate.note.clone(),
+        ]);
+    }
+    Ok(output::page(&table.render(format))?)
+}
+
+fn encode_text(args: EncodeTextArgs) -> crate::Result<MutationSummary> {
+    let mut text = TextChunk::new(&args.keyword, &args.text)?;
+    if args.compress {
+        text = text.compressed();
+    }
+    let chunk = text.to_chunk()?;
+    let _lock = lock_file(&args.file_path, &args.lock)?;
+    let mut source = open(&args.file_path, None, None)?;
+    let existing = source
+        .png
+        .chunks()
+        .iter()
+        .position(|c| TextChunk::read(c).is_ok_and(|t| t.keyword == args.keyword));
+    let iend = source
+        .png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().bytes() == *b"IEND");
+    match (existing, iend) {
+        (Some(idx), _) => {
+            source.png.remove_chunk(idx)?;
+            source.png.insert_chunk(idx, chunk.clone())?;
+        }
+        (None, Some(idx)) => source.png.insert_chunk(idx, chunk.clone())?,
+        (None, None) => source.png.append_chunk(chunk.clone()),
+    }
+    let stored = format!(
+        "{} the {} chunk for '{}'",
+        match existing {
+            Some(_) => "Replaced",
+            None => "Stored",
+        },
+        chunk.chunk_type(),
+        args.keyword
+    );
+    record_change(&mut source.png, &args.audit, "encode-text", &[chunk])?;
+    let summary = source.save(&args.file_path, false, None)?;
+    status(stored);
+    Ok(summary)
+}
+
+fn decode_text(args: DecodeTextArgs, format: Format) -> crate::Result<()> {
+    let png = open(&args.file_path, None, None)?.png;
+    let texts = png
+        .texts()
+        .into_iter()
+        .collect::<crate::Result<Vec<TextChunk>>>()?;
+    if let Some(keyword) = &args.keyword {
+        let text = texts
+            .into_iter()
+            .find(|t| &t.keyword == keyword)
+            .ok_or_else(|| {
+                NotFoundError::boxed(format!("No text chunk has the keyword '{}'", keyword))
+            })?;
+        return match format {
+            Format::Json => document::emit(&text),
+            _ => {
+                println!("{}", text.text);
+                Ok(())
+            }
+        };
+    }
+    if format == Format::Json {
+        return document::emit(&texts);
+    }
+    if texts.is_empty() {
+        if format == Format::Pretty {
+            println!("No tEXt or zTXt chunks found");
+        }
+        return Ok(());
+    }
+    let mut table = Table::new(&["keyword", "type", "text"]);
+    for text in texts {
+        let chunk_type = match text.compressed {
+            true => COMPRESSED_TEXT_CHUNK,
+            false => TEXT_CHUNK,
+        };
+        table.row(vec![text.keyword, chunk_type.to_string(), text.text]);
+    }
+    Ok(output::page(&table.render(format))?)
+}
+
+/// Prints the messages found by `decode --all`, one per line with the chunk type they came from.
+fn print_messages(messages: &Decoded, format: Format) -> crate::Result<()> {
+    if format == Format::Json {
+        let messages: Vec<Message> = messages
+            .iter()
+            .map(|(ctype, index, message)| Message {
+                index: *index,
+                ..json_message(Some(ctype), message)
+            })
+            .collect();
+        return document::emit(&messages);
+    }
+    for (ctype, index, message) in messages {
+        let source = match index {
+            Some(index) => format!("{} #{}", ctype, index),
+            None => ctype.to_string(),
+        };
+        let message = String::from_utf8_lossy(message);
+        match format {
+            Format::Pretty => println!("{}: {:#?}", source, message),
+            Format::Plain | Format::Json | Format::Csv => {
+                println!("{}\t{}", source, message.escape_debug())
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the files in a message packed with `--payload-dir` and, given a directory, unpacks
+/// them into it. Any other message is written to the directory as a single file, named with an
+/// extension guessed from its contents since envelopes don't store a file name.
+fn unarchive(
+    message: &[u8],
+    list: bool,
+    extract_to: Option<String>,
+    format: Format,
+) -> crate::Result<()> {
+    if let (false, false, Some(dir)) = (list, archive::is_archive(message), &extract_to) {
+        let kind = sniff::sniff(message);
+        fs::create_dir_all(dir)?;
+        let path = Path::new(dir).join(format!("payload.{}", kind.extension));
+        fs::write(&path, message)?;
+        if format == Format::Json {
+            return document::emit(&[ArchiveFile {
+                name: format!("payload.{}", kind.extension),
+                size: message.len(),
+                mode: 0o644,
+            }]);
+        }
+        if format == Format::Pretty {
+            println!("Extracted the {} to {}", kind.name, path.display());
+        }
+        return Ok(());
+    }
+    let entries = archive::entries(message)?;
+    if format == Format::Json {
+        let files: Vec<ArchiveFile> = entries
+            .iter()
+            .map(|entry| ArchiveFile {
+                name: entry.name.clone(),
+                size: entry.data.len(),
+                mode: entry.mode,
+            })
+            .collect();
+        document::emit(&files)?;
+    } else if list {
+        let mut table = Table::new(&["name", "size", "mode"]);
+        for entry in &entries {
+            table.row(vec![
+                entry.name.clone(),
+                entry.data.len().to_string(),
+                format!("{:o}", entry.mode),
+            ]);
+        }
+        print!("{}", table.render(format));
+    }
+    if let Some(dir) = extract_to {
+        archive::extract(&entries, Path::new(&dir))?;
+        if format == Format::Pretty {
+            println!("Extracted {} file(s) to {}", entries.len(), dir);
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the message into `path` for decode --output. A message split with --max-chunk-size
+/// is written a shard at a time, inflated as it goes, rather than put back together first;
+/// checking it with --verify, --mac-key or --expect needs all of it, so those still do.
+fn decode_to_file(args: DecodeArgs, path: &str) -> crate::Result<()> {
+    let streamed = args.carriers.is_none()
+        && args.mode == Mode::Chunk
+        && args.verify.is_none()
+        && args.mac_key.is_none()
+        && args.expect.is_none();
+    if !streamed {
+        return write_message(&decode(args)?, path);
+    }
+    remote::local_output(path)?;
+    let chunks = message_chunks(&args)?;
+    let first = chunks
+        .first()
+        .and_then(|c| Envelope::from_bytes(c.data()).ok().flatten());
+    let Some(first) = first.filter(|e| e.shard.is_some()) else {
+        let envelope = envelope_from(&chunks, &mut CliWarnings)?;
+        return write_message(&unseal(envelope, &args)?, path);
+    };
+    check_expiry(&first, args.strict_expiry, &mut CliWarnings)?;
+    let key = decryption_key(
+        &first,
+        args.decrypt,
+        &args.keys,
+        &mut TerminalPrompt,
+        &mut OsKeyring,
+        &mut CliWarnings,
+    )?;
+    let ctype = *chunks[0].chunk_type();
+    let png = Png::from_chunks(chunks);
+    let mut reader = png.payload_reader(&ctype, key.as_ref())?;
+    let _deferred = cancel::defer();
+    cancel::interrupt().check()?;
+    let written = write_atomically_from(Path::new(path), &mut reader)?;
+    status(format!(
+        "Wrote {} to {}",
+        output::size(written as usize),
+        path
+    ));
+    Ok(())
+}
+
+/// Writes a decoded message to `path` for decode --output.
+fn write_message(message: &[u8], path: &str) -> crate::Result<()> {
+    remote::local_output(path)?;
+    let _deferred = cancel::defer();
+    cancel::interrupt().check()?;
+    fs::write(path, message)?;
+    status(format!("Wrote {} to {}", output::size(message.len()), path));
+    Ok(())
+}
+
+/// Prints a decoded message: quoted when pretty, as it is when plain.
+fn print_message(message: &[u8], format: Format) -> crate::Result<()> {
+    match format {
+        Format::Pretty => println!("{:#?}", String::from_utf8_lossy(message)),
+        Format::Plain | Format::Csv => io::stdout().write_all(message)?,
+        Format::Json => document::emit(&json_message(None, message))?,
+    }
+    Ok(())
+}
+
+fn json_message(chunk_type: Option<&ChunkType>, message: &[u8]) -> Message {
+    Message {
+        chunk_type: chunk_type.map(ToString::to_string),
+        index: None,
+        message: String::from_utf8_lossy(message).into_owned(),
+        message_base64: output::base64(message),
+        mime: sniff::sniff(message).mime.to_string(),
+    }
+}
+
+/// Commands whose output has no JSON form, which refuse `--json` rather than print text.
+const TEXT_ONLY: &[&str] = &[
+    "attest",
+    "git-filter",
+    "keygen",
+    "keyring",
+    "kv",
+    "man",
+    "selftest",
+    "tui",
+];
+
+pub fn run(args: Command, format: Format) -> crate::Result<()> {
+    if format == Format::Json && TEXT_ONLY.contains(&args.name()) {
+        return Err(UsageError::boxed(format!(
+            "{} has no JSON output",
+            args.name()
+        )));
+    }
+    let to_stdout = args.png_output() == Some(STDIO);
+    if to_stdout && format == Format::Json {
+        return Err(UsageError::boxed(
+            "--json can't be used when the png is written to stdout".to_string(),
+        ));
+    }
+    output::set_stdout_taken(to_stdout);
+    match args {
+        args::Command::Encode(encode_args) if encode_args.carriers.is_some() => {
+            let placed = encode_carriers(encode_args)?;
+            match format {
+                Format::Json => document::emit(&placed)?,
+                _ => {
+                    for shard in placed {
+                        eprint!("{}: ", shard.path);
+                        shard.summary.render(false)?;
+                    }
+                }
+            }
+        }
+        args::Command::Encode(encode_args)
+            if batched(&encode_args.file_path, &encode_args.batch) =>
+        {
+            encode_batch(encode_args, format)?;
+        }
+        args::Command::Encode(encode_args) => {
+            encode(encode_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Print(print_args) => {
+            print(print_args, format)?;
+        }
+        args::Command::Remove(remove_args)
+            if batched(&remove_args.file_path, &remove_args.batch) =>
+        {
+            remove_batch(remove_args, format)?;
+        }
+        args::Command::Remove(remove_args) => {
+            remove(remove_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Decode(decode_args)
+            if batched(&decode_args.file_path, &decode_args.batch) =>
+        {
+            decode_batch(decode_args, format)?;
+        }
+        args::Command::Decode(decode_args) => {
+            let (list, extract_to) = (decode_args.list, decode_args.extract_to.clone());
+            let output = decode_args.output.clone();
+            match decode_args.all {
+                true => print_messages(&decode_all(decode_args)?, format)?,
+                false if list || extract_to.is_some() => {
+                    unarchive(&decode(decode_args)?, list, extract_to, format)?
+                }
+                false => {
+                    if let Some(path) = &output {
+                        return decode_to_file(decode_args, path);
+                    }
+                    let message = decode(decode_args)?;
+                    // An empty marker chunk was found, which isn't the same as no chunk at all
+                    if message.is_empty() && format != Format::Json {
+                        eprintln!("notice: the message is empty");
+                    } else if format == Format::Pretty {
+                        let kind = sniff::sniff(&message);
+                        println!("Payload looks like {} ({})", kind.name, kind.mime);
+                    }
+                    print_message(&message, format)?
+                }
+            }
+        }
+        args::Command::Check(check_args) => {
+            check(check_args, format)?;
+        }
+        args::Command::Strip(strip_args) => {
+            strip(strip_args)?.render(format == Format::Json)?;
+        }
+        args::Command::FindPng(find_png_args) => {
+            find_png(find_png_args, format)?;
+        }
+        args::Command::AtOffset(at_offset_args) => {
+            at_offset(at_offset_args, format)?;
+        }
+        args::Command::Scan(scan_args) => {
+            scan(scan_args, format)?;
+        }
+        #[cfg(feature = "tui")]
+        args::Command::Tui(tui_args) => {
+            crate::tui::run(Path::new(&tui_args.file_path))?;
+        }
+        #[cfg(not(feature = "tui"))]
+        args::Command::Tui(_) => {
+            return Err(UsageError::boxed(
+                "pngme was built without the `tui` feature".to_string(),
+            ));
+        }
+        args::Command::Kv(kv_args) => {
+            kv(kv_args)?;
+        }
+        args::Command::Labels(labels_args) => {
+            labels(labels_args, format)?;
+        }
+        args::Command::Edit(edit_args) => {
+            edit(edit_args, &mut SystemEditor)?.render(format == Format::Json)?;
+        }
+        args::Command::Redact(redact_args) => {
+            redact(redact_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Seal(seal_args) => {
+            seal_image(seal_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Attest(attest_args) => {
+            attest(attest_args)?;
+        }
+        args::Command::Keygen(keygen_args) => {
+            keygen(keygen_args)?;
+        }
+        args::Command::Keyring(keyring_args) => {
+            keyring(keyring_args, &mut TerminalPrompt, &mut OsKeyring)?;
+        }
+        args::Command::Man(man_args) => {
+            man(man_args)?;
+        }
+        args::Command::Selftest => {
+            selftest()?;
+        }
+        args::Command::GitFilter(git_filter_args) => {
+            git_filter(git_filter_args)?;
+        }
+        args::Command::Diff(diff_args) => {
+            diff(diff_args, format)?;
+        }
+        args::Command::History(trail_args) => {
+            audit_trail(trail_args, format)?;
+        }
+        args::Command::Undo(undo_args) => {
+            undo(undo_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Patch(patch_args) => {
+            patch(patch_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Propagate(propagate_args) => {
+            propagate(propagate_args)?.render(format == Format::Json)?;
+        }
+        args::Command::Frames(frames_args) => {
+            frames(frames_args, format)?;
+        }
+        args::Command::Triage(triage_args) => {
+            triage(triage_args, format)?;
+        }
+        args::Command::Rewrite(rewrite_args) => {
+            rewrite(rewrite_args)?.render(format == Format::Json)?;
+        }
+        args::Command::EncodeText(encode_text_args) => {
+            encode_text(encode_text_args)?.render(format == Format::Json)?;
+        }
+        args::Command::DecodeText(decode_text_args) => {
+            decode_text(decode_text_args, format)?;
+        }
+        args::Command::Repair(repair_args) => {
+            repair(repair_args, format)?;
+        }
+        args::Command::Capacity(capacity_args) => {
+            capacity(capacity_args, format)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::tests::ScriptedEditor;
+    use crate::error::ExitCode;
+    use crate::keychain::tests::MemoryKeyring;
+    use crate::payload::PayloadOptions;
+    use crate::template::Template;
+
+    fn encode_args(file_path: &str, message: &str) -> EncodeArgs {
+        EncodeArgs {
+            file_path: file_path.to_string(),
+            carriers: None,
+            max_per_file: None,
+            max_chunk_size: None,
+            placement: Placement::default(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            mode: Mode::Chunk,
+            message: Some(message.to_string()),
+            message_file: None,
+            payload_dir: None,
+            expires: None,
+            newline: Newline::Keep,
+            redundancy: 1,
+            ecc: None,
+            encrypt: false,
+            keys: KeyArgs::default(),
+            compress: None,
+            min_compression_gain: 0,
+            pad_to: None,
+            pad_block: None,
+            out_path: None,
+            backup: false,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            deterministic: false,
+            deterministic_seed: None,
+            replace: false,
+            history: HistoryArgs::default(),
+            batch: BatchArgs::default(),
+            sign: None,
+            mac_key: None,
+        }
+    }
+
+    fn minimal_png(idat: &str) -> Vec<u8> {
+        Png::from_chunks(
+            [("IHDR", "header"), ("IDAT", idat), ("IEND", "")]
+                .iter()
+                .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.as_bytes().to_vec()))
+                .collect(),
+        )
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_concatenated_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("two.png");
+        let file_path = path.to_str().unwrap();
+        let first = minimal_png("first");
+        fs::write(&path, [first.clone(), minimal_png("second")].concat()).unwrap();
+
+        // Put a payload into the second image, then encode into the first
+        encode(EncodeArgs {
+            image_index: Some(1),
+            ..encode_args(file_path, "in second")
+        })
+        .unwrap();
+        let second = fs::read(&path).unwrap()[first.len()..].to_vec();
+        encode(EncodeArgs {
+            image_index: Some(0),
+            ..encode_args(file_path, "in first")
+        })
+        .unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.ends_with(&second));
+        let images = Png::image_ranges(&bytes).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(&bytes[images[1].clone()], &second[..]);
+
+        let decoded = open(file_path, None, Some(1)).unwrap().png;
+        assert_eq!(decoded.chunk_by_type("ruSt").unwrap().data(), b"in second");
+        let decoded = open(file_path, None, Some(0)).unwrap().png;
+        assert_eq!(decoded.chunk_by_type("ruSt").unwrap().data(), b"in first");
+        assert!(open(file_path, None, Some(2)).is_err());
+    }
+
+    fn strip_args(file_path: &str, out_path: &str) -> StripArgs {
+        StripArgs {
+            file_path: file_path.to_string(),
+            out_path: Some(out_path.to_string()),
+            trailing_only: true,
+            save_trailing: None,
+            remove_seal: false,
+            verify_image: false,
+            lock: LockArgs::default(),
+            print_hash: None,
+            remove_audit: false,
+            audit: AuditArgs::default(),
+        }
+    }
+
+    #[test]
+    fn test_strip_trailing_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("polyglot.png");
+        let output = dir.path().join("out.png");
+        let side = dir.path().join("trailing.bin");
+        let png = minimal_png("pixels");
+        let appended = b"PK\x03\x04 a zip archive".to_vec();
+        fs::write(&input, [png.clone(), appended.clone()].concat()).unwrap();
+
+        strip(StripArgs {
+            save_trailing: Some(side.to_str().unwrap().to_string()),
+            ..strip_args(input.to_str().unwrap(), output.to_str().unwrap())
+        })
+        .unwrap();
+        let out = fs::read(&output).unwrap();
+        assert_eq!(out, png);
+        assert_eq!(
+            fs::metadata(&input).unwrap().len() - out.len() as u64,
+            appended.len() as u64
+        );
+        assert_eq!(fs::read(&side).unwrap(), appended);
+    }
+
+    #[test]
+    fn test_strip_trailing_only_is_noop_on_clean_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("clean.png");
+        let output = dir.path().join("out.png");
+        let png = minimal_png("pixels");
+        fs::write(&input, &png).unwrap();
+        strip(strip_args(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), png);
+    }
+
+    #[test]
+    fn test_strip_removes_ancillary_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("image.png");
+        let file_path = input.to_str().unwrap();
+        let png = minimal_png("pixels");
+        fs::write(&input, &png).unwrap();
+        encode(EncodeArgs {
+            image_index: Some(0),
+            ..encode_args(file_path, "payload")
+        })
+        .unwrap();
+        // Written to a new file renamed over the old, so a hard link keeps what was there
+        let linked = dir.path().join("linked.png");
+        fs::hard_link(&input, &linked).unwrap();
+        let encoded = fs::read(&input).unwrap();
+        strip(StripArgs {
+            trailing_only: false,
+            out_path: None,
+            ..strip_args(file_path, file_path)
+        })
+        .unwrap();
+        assert_eq!(fs::read(&input).unwrap(), png);
+        assert_eq!(fs::read(&linked).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_undo_restores_replaced_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("image.png");
+        let file_path = input.to_str().unwrap();
+        fs::write(&input, minimal_png("pixels")).unwrap();
+        let message = || {
+            let png = open(file_path, None, None).unwrap().png;
+            png.chunk_by_type("ruSt").map(|c| c.data().to_vec())
+        };
+        let keeping = |message: &str| EncodeArgs {
+            replace: true,
+            history: HistoryArgs {
+                keep_previous: true,
+                history_depth: 2,
+            },
+            ..encode_args(file_path, message)
+        };
+        let undo_args = || UndoArgs {
+            file_path: file_path.to_string(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            lock: LockArgs::default(),
+        };
+
+        encode(encode_args(file_path, "first")).unwrap();
+        encode(keeping("second")).unwrap();
+        encode(keeping("third")).unwrap();
+        assert_eq!(message().unwrap(), b"third");
+        undo(undo_args()).unwrap();
+        assert_eq!(message().unwrap(), b"second");
+        undo(undo_args()).unwrap();
+        assert_eq!(message().unwrap(), b"first");
+        let err = undo(undo_args()).unwrap_err();
+        assert!(err.downcast_ref::<NotFoundError>().is_some());
+
+        remove(RemoveArgs {
+            file_path: file_path.to_string(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            chunk_type_hex: None,
+            force: false,
+            all: false,
+            index: None,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            history: HistoryArgs {
+                keep_previous: true,
+                history_depth: 1,
+            },
+            batch: BatchArgs::default(),
+        })
+        .unwrap();
+        assert_eq!(message(), None);
+        undo(undo_args()).unwrap();
+        assert_eq!(message().unwrap(), b"first");
+    }
+
+    #[test]
+    fn test_template_fields() {
+        let png = Png::try_from(&minimal_png("\tpixel data, longer than a preview")[..]).unwrap();
+        let offsets = chunk_offsets(&png, 100);
+        assert_eq!(offsets, [108, 126, 172]);
+        let fields: Vec<String> = args::PRINT_FIELDS
+            .iter()
+            .map(|field| chunk_field(&png.chunks()[1], 1, offsets[1], field))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "1",
+                "IDAT",
+                "34",
+                &format!("{:08x}", png.chunks()[1].crc()),
+                "true",
+                "126",
+                "critical",
+                "crit pub unsafe",
+                ".pixel data, lon...",
+                "CXBpeGVsIGRhdGEsIGxvbmdlciB0aGFuIGEgcHJldmlldw==",
+            ]
+        );
+        let template = Template::parse("{type}:{category}\t{data_preview}", args::PRINT_FIELDS);
+        let line = template
+            .unwrap()
+            .render(|field| chunk_field(&png.chunks()[2], 2, offsets[2], field));
+        assert_eq!(line, "IEND:critical\t");
+    }
+
+    #[test]
+    fn test_order_chunks_keeps_file_indices() {
+        let sizes = [
+            ("IHDR", 13),
+            ("teXt", 40),
+            ("IDAT", 500),
+            ("teXt", 40),
+            ("IDAT", 20),
+            ("IEND", 0),
+        ];
+        let chunks: Vec<Chunk> = sizes
+            .iter()
+            .map(|(t, len)| Chunk::new(ChunkType::from_str(t).unwrap(), vec![0; *len]))
+            .collect();
+        let listed = || chunks.iter().enumerate().collect::<Vec<_>>();
+        let indices = |order, reverse, top| -> Vec<usize> {
+            order_chunks(listed(), order, reverse, top)
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect()
+        };
+
+        assert_eq!(indices(ChunkOrder::Offset, false, None), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(indices(ChunkOrder::Offset, true, None), [5, 4, 3, 2, 1, 0]);
+        assert_eq!(indices(ChunkOrder::Size, false, None), [5, 0, 4, 1, 3, 2]);
+        // Equal sizes stay in file order either way
+        assert_eq!(indices(ChunkOrder::Size, true, None), [2, 1, 3, 4, 0, 5]);
+        assert_eq!(indices(ChunkOrder::Type, false, None), [2, 4, 5, 0, 1, 3]);
+        assert_eq!(indices(ChunkOrder::Offset, false, Some(3)), [1, 2, 3]);
+        assert_eq!(indices(ChunkOrder::Size, true, Some(2)), [2, 1]);
+        assert_eq!(indices(ChunkOrder::Size, true, Some(10)).len(), 6);
+    }
+
+    #[test]
+    fn test_chunk_type_hex_finds_mangled_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("mangled.png");
+        let file_path = input.to_str().unwrap();
+        let mangled = ChunkType::from_bytes_unchecked([0x00, b'I', b'R', b'D']);
+        let mut png = Png::try_from(&minimal_png("pixels")[..]).unwrap();
+        png.insert_chunk(2, Chunk::new(mangled, b"recovered".to_vec()))
+            .unwrap();
+        fs::write(&input, png.as_bytes()).unwrap();
+
+        let found = decode_chunk(
+            &DecodeArgs {
+                chunk_type: None,
+                chunk_type_hex: Some(mangled),
+                ..decode_args(file_path, "", Newline::Keep)
+            },
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(found.payload, b"recovered");
+
+        let remove_args = |force| RemoveArgs {
+            file_path: file_path.to_string(),
+            chunk_type: None,
+            label: None,
+            chunk_type_hex: Some(mangled),
+            force,
+            all: false,
+            index: None,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            history: HistoryArgs::default(),
+            audit: AuditArgs::default(),
+            batch: BatchArgs::default(),
+        };
+        let err = remove(remove_args(false)).unwrap_err();
+        assert!(err.downcast_ref::<RefusedError>().is_some());
+        assert!(err.to_string().contains("\\x00IRD"), "{}", err);
+        remove(remove_args(true)).unwrap();
+        let png = open(file_path, None, None).unwrap().png;
+        assert!(png.chunks().iter().all(|c| *c.chunk_type() != mangled));
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_audit_trail_records_changes_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("image.png");
+        let file_path = input.to_str().unwrap();
+        fs::write(&input, minimal_png("pixels")).unwrap();
+        let audited = |note: Option<&str>| AuditArgs {
+            audit: true,
+            audit_note: note.map(str::to_string),
+        };
+
+        encode(EncodeArgs {
+            audit: audited(Some("TICKET-1")),
+            ..encode_args(file_path, "first")
+        })
+        .unwrap();
+        encode(EncodeArgs {
+            replace: true,
+            audit: audited(None),
+            ..encode_args(file_path, "second")
+        })
+        .unwrap();
+        strip(StripArgs {
+            trailing_only: false,
+            audit: audited(None),
+            ..strip_args(file_path, file_path)
+        })
+        .unwrap();
+
+        let png = open(file_path, None, None).unwrap().png;
+        assert!(png.chunk_by_type("ruSt").is_none());
+        let entries = audit::entries(&png).unwrap();
+        let operations: Vec<&str> = entries.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(operations, ["encode", "replace", "strip"]);
+        assert_eq!(entries[0].chunk_types, ["ruSt"]);
+        assert_eq!(entries[0].note.as_deref(), Some("TICKET-1"));
+        // The message sat before IEND, so strip removed it as an ancillary chunk
+        assert_eq!(entries[2].chunk_types, ["ruSt"]);
+        assert!(entries
+            .iter()
+            .all(|e| e.tool_version == env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn test_payload_dir_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("image.png");
+        let file_path = input.to_str().unwrap();
+        fs::write(&input, minimal_png("pixels")).unwrap();
+        let payload = dir.path().join("payload");
+        fs::create_dir_all(payload.join("thumbs")).unwrap();
+        fs::write(payload.join("manifest.json"), "{}").unwrap();
+        fs::write(payload.join("thumbs/small.png"), minimal_png("thumb")).unwrap();
+
+        encode(EncodeArgs {
+            message: None,
+            payload_dir: Some(payload.to_str().unwrap().to_string()),
+            compress: Some(Algorithm::Deflate),
+            ..encode_args(file_path, "")
+        })
+        .unwrap();
+        let message = decode(DecodeArgs {
+            expect: None,
+            ..decode_args(file_path, "", Newline::Keep)
+        })
+        .unwrap();
+        let out = dir.path().join("out");
+        unarchive(
+            &message,
+            true,
+            Some(out.to_str().unwrap().to_string()),
+            Format::Plain,
+        )
+        .unwrap();
+        assert_eq!(fs::read(out.join("manifest.json")).unwrap(), b"{}");
+        assert_eq!(
+            fs::read(out.join("thumbs/small.png")).unwrap(),
+            minimal_png("thumb")
+        );
+        assert!(unarchive(b"plain", true, None, Format::Plain).is_err());
+
+        // Messages that aren't archives are extracted whole, named after what they look like
+        let single = dir.path().join("single");
+        let pdf = b"%PDF-1.7\n%stub".to_vec();
+        unarchive(
+            &pdf,
+            false,
+            Some(single.to_str().unwrap().to_string()),
+            Format::Plain,
+        )
+        .unwrap();
+        assert_eq!(fs::read(single.join("payload.pdf")).unwrap(), pdf);
+    }
+
+    fn png_with(idat: &str, ancillary: &[(&str, &str)]) -> Vec<u8> {
+        let mut png = Png::try_from(&minimal_png(idat)[..]).unwrap();
+        for (chunk_type, data) in ancillary {
+            let chunk = Chunk::new(
+                ChunkType::from_str(chunk_type).unwrap(),
+                data.as_bytes().to_vec(),
+            );
+            png.insert_chunk(png.chunks().len() - 1, chunk).unwrap();
+        }
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_check_strict_fails_on_misplaced_ancillary_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("late.png");
+        // png_with puts gAMA after IDAT, which decoders tolerate but the spec forbids
+        fs::write(&path, png_with("data", &[("gAMA", "gamma")])).unwrap();
+        let args = |strict| CheckArgs {
+            file_path: path.to_str().unwrap().to_string(),
+            strict,
+            offset: None,
+            image_index: None,
+        };
+        assert!(check(args(false), Format::Plain).is_ok());
+        let err = check(args(true), Format::Plain).unwrap_err();
+        assert!(err.to_string().contains("1 problem(s) found"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_fails_on_missing_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("incomplete.png");
+        let args = |strict| CheckArgs {
+            file_path: path.to_str().unwrap().to_string(),
+            strict,
+            offset: None,
+            image_index: None,
+        };
+        let mut no_iend = minimal_png("data");
+        no_iend.truncate(no_iend.len() - 12);
+        fs::write(&path, no_iend).unwrap();
+        for strict in [false, true] {
+            let err = check(args(strict), Format::Plain).unwrap_err();
+            assert!(err.to_string().contains("1 problem(s) found"), "{}", err);
+        }
+
+        fs::write(&path, Png::STANDARD_HEADER).unwrap();
+        for strict in [false, true] {
+            let err = check(args(strict), Format::Plain).unwrap_err();
+            assert!(err.to_string().contains("3 problem(s) found"), "{}", err);
+        }
+
+        let mut after_iend = minimal_png("data");
+        after_iend.extend(Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1]).as_bytes());
+        fs::write(&path, after_iend).unwrap();
+        let err = check(args(false), Format::Plain).unwrap_err();
+        assert!(err.to_string().contains("problem(s) found"), "{}", err);
+        fs::write(&path, minimal_png("data")).unwrap();
+        assert!(check(args(true), Format::Plain).is_ok());
+    }
+
+    #[test]
+    fn test_patch_replays_a_diff_on_another_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("old.png"), png_with("v1", &[("tEXt", "Author\0me")])).unwrap();
+        fs::write(
+            path("new.png"),
+            png_with("v2", &[("tEXt", "Author\0you"), ("ruSt", "stamp")]),
+        )
+        .unwrap();
+        diff(
+            DiffArgs {
+                file_path: path("old.png"),
+                other: path("new.png"),
+                emit_patch: Some(path("changes.pngpatch")),
+            },
+            Format::Plain,
+        )
+        .unwrap();
+
+        let patch_args = |file: &str, force: bool| PatchArgs {
+            file_path: path(file),
+            apply: path("changes.pngpatch"),
+            out_path: Some(path("out.png")),
+            force,
+            lock: LockArgs::default(),
+        };
+        fs::write(path("third.png"), png_with("v3", &[("tEXt", "Author\0me")])).unwrap();
+        patch(patch_args("third.png", false)).unwrap();
+        assert_eq!(
+            fs::read(path("out.png")).unwrap(),
+            png_with("v3", &[("tEXt", "Author\0you"), ("ruSt", "stamp")])
+        );
+
+        // The tEXt chunk the patch changes doesn't hold what it did in old.png
+        fs::write(
+            path("edited.png"),
+            png_with("v3", &[("tEXt", "Author\0them")]),
+        )
+        .unwrap();
+        let err = patch(patch_args("edited.png", false)).unwrap_err();
+        assert!(err.downcast_ref::<RefusedError>().is_some());
+        patch(patch_args("edited.png", true)).unwrap();
+        assert_eq!(
+            fs::read(path("out.png")).unwrap(),
+            png_with("v3", &[("tEXt", "Author\0you"), ("ruSt", "stamp")])
+        );
+    }
+
+    #[test]
+    fn test_propagate_copies_only_safe_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let chunk = |t: &str, d: &str| Chunk::new(ChunkType::from_str(t).unwrap(), d);
+        let donor = Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("PLTE", "palette"),
+            chunk("tEXt", "Author\0me"),
+            chunk("prIV", "unsafe"),
+            chunk("IDAT", "original"),
+            chunk("ruSt", "payload"),
+            chunk("IEND", ""),
+        ]);
+        fs::write(path("donor.png"), donor.as_bytes()).unwrap();
+        fs::write(path("reencoded.png"), minimal_png("optimized")).unwrap();
+        let propagate_args = |allow: &[&str]| PropagateArgs {
+            from: path("donor.png"),
+            onto: path("reencoded.png"),
+            out_path: Some(path("out.png")),
+            allow: allow.iter().map(|t| t.to_string()).collect(),
+            lock: LockArgs::default(),
+        };
+
+        let summary = propagate(propagate_args(&[])).unwrap();
+        assert_eq!(summary.chunks_added, 2);
+        let out = Png::from_file(path("out.png")).unwrap();
+        let types: Vec<String> = out
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "tEXt", "IDAT", "ruSt", "IEND"]);
+        assert_eq!(out.chunk_by_type("IDAT").unwrap().data(), b"optimized");
+
+        propagate(propagate_args(&["prIV"])).unwrap();
+        let out = Png::from_file(path("out.png")).unwrap();
+        assert_eq!(out.chunk_by_type("prIV").unwrap().data(), b"unsafe");
+        assert!(out.chunk_by_type("PLTE").is_none());
+        assert!(propagate(propagate_args(&["PLTE"])).is_err());
+    }
+
+    #[test]
+    fn test_cancelled_save_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("image.png");
+        let out = dir.path().join("out.png");
+        fs::write(&input, minimal_png("pixels")).unwrap();
+        let mut source = open(input.to_str().unwrap(), None, None).unwrap();
+        source.png.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            vec![1; 64],
+        ));
+        source.cancel = Cancel::new();
+        source.cancel.cancel();
+
+        for path in [&out, &input] {
+            let error = source
+                .save(path.to_str().unwrap(), false, None)
+                .unwrap_err();
+            assert!(error.is::<cancel::CancelledError>());
+        }
+        assert_eq!(fs::read(&input).unwrap(), minimal_png("pixels"));
+        let left: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(left.len(), 1);
+    }
+
+    #[test]
+    fn test_seal_survives_strip_until_the_image_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("image.png");
+        let file_path = input.to_str().unwrap().to_string();
+        let key = dir.path().join("key.bin");
+        fs::write(&key, [1; crypto::KEY_LEN]).unwrap();
+        let key_file = key.to_str().unwrap().to_string();
+        fs::write(&input, lsb::tests::generated_png(8, 8, 2, 3).as_bytes()).unwrap();
+        let attest_args = || AttestArgs {
+            file_path: file_path.clone(),
+            key_file: key_file.clone(),
+        };
+
+        seal_image(SealArgs {
+            file_path: file_path.clone(),
+            key_file: key_file.clone(),
+            lock: LockArgs::default(),
+        })
+        .unwrap();
+        attest(attest_args()).unwrap();
+        let strip_all = |remove_seal| StripArgs {
+            trailing_only: false,
+            out_path: None,
+            remove_seal,
+            ..strip_args(&file_path, &file_path)
+        };
+        strip(strip_all(false)).unwrap();
+        attest(attest_args()).unwrap();
+
+        // Rewriting the pixel data leaves a perfectly valid png behind
+        let mut png = Png::from_file(&input).unwrap();
+        lsb::embed(&mut png, b"tampered").unwrap();
+        fs::write(&input, png.as_bytes()).unwrap();
+        assert!(Png::from_file(&input).is_ok());
+        assert!(attest(attest_args()).is_err());
+
+        strip(strip_all(true)).unwrap();
+        let err = attest(attest_args()).unwrap_err();
+        assert!(err.to_string().contains("not sealed"));
+    }
+
+    #[test]
+    fn test_concurrent_encodes_are_serialized() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let messages: Vec<String> = (0..4).map(|i| format!("job {}", i)).collect();
+        std::thread::scope(|s| {
+            for message in &messages {
+                s.spawn(move || {
+                    encode(EncodeArgs {
+                        lock: LockArgs {
+                            wait_lock: Some(10),
+                        },
+                        ..encode_args(file_path, message)
+                    })
+                    .unwrap()
+                });
+            }
+        });
+        let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        let mut found: Vec<String> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "ruSt")
+            .map(|c| String::from_utf8(c.data().to_vec()).unwrap())
+            .collect();
+        found.sort();
+        assert_eq!(found, messages);
+    }
+
+    #[test]
+    fn test_locked_file_fails_fast() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let before = fs::read(&path).unwrap();
+        let held = lock::acquire(&path, None).unwrap();
+        std::thread::scope(|s| {
+            let err = s
+                .spawn(|| {
+                    encode(encode_args(file_path, "blocked"))
+                        .unwrap_err()
+                        .to_string()
+                })
+                .join()
+                .unwrap();
+            assert!(err.contains("file is locked by another pngme process"));
+        });
+        assert_eq!(fs::read(&path).unwrap(), before);
+        drop(held);
+        // The failed attempt didn't leave the file locked behind it
+        encode(encode_args(file_path, "unblocked")).unwrap();
+    }
+
+    #[test]
+    fn test_encodes_lock_the_file_they_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        for name in ["a.png", "b.png", "out.png"] {
+            fs::write(path(name), minimal_png(name)).unwrap();
+        }
+        let held = lock::acquire(Path::new(&path("out.png")), None).unwrap();
+        let into_out = |input: &str| EncodeArgs {
+            out_path: Some(path("out.png")),
+            ..encode_args(&path(input), "into out")
+        };
+        let err = encode(into_out("a.png")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("file is locked by another pngme process"));
+        drop(held);
+        // The input isn't written, so another encode into it isn't held up
+        let input = lock::acquire(Path::new(&path("b.png")), None).unwrap();
+        encode(into_out("b.png")).unwrap();
+        drop(input);
+        assert_eq!(fs::read(path("b.png")).unwrap(), minimal_png("b.png"));
+    }
+
+    #[test]
+    fn test_encode_decode_remove_by_label() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let labelled = |label: &str, message: &str| EncodeArgs {
+            chunk_type: None,
+            label: Some(label.to_string()),
+            redundancy: 2,
+            ..encode_args(file_path, message)
+        };
+        encode(labelled("build-info", "build 1234")).unwrap();
+        encode(labelled("license", "key-5678")).unwrap();
+        let err = encode(labelled("license", "again")).unwrap_err();
+        assert!(err.to_string().contains("already stored"));
+
+        let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        let listed = label::list(png.chunks());
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[1].label, "license");
+        assert_eq!(
+            listed[1].chunk_type,
+            label::chunk_type("license").to_string()
+        );
+        assert_eq!(listed[1].copies, 2);
+
+        let by_label = |label: &str, expect: &str| DecodeArgs {
+            chunk_type: None,
+            label: Some(label.to_string()),
+            ..decode_args(file_path, expect, Newline::Keep)
+        };
+        decode(by_label("build-info", "build 1234")).unwrap();
+        decode(by_label("license", "key-5678")).unwrap();
+        assert!(decode(by_label("missing", "")).is_err());
+
+        let summary = remove(RemoveArgs {
+            file_path: file_path.to_string(),
+            chunk_type: None,
+            label: Some("build-info".to_string()),
+            chunk_type_hex: None,
+            force: false,
+            all: false,
+            index: None,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            history: HistoryArgs::default(),
+            batch: BatchArgs::default(),
+        })
+        .unwrap();
+        assert_eq!(summary.chunks_removed, 2);
+        assert!(decode(by_label("build-info", "build 1234")).is_err());
+        decode(by_label("license", "key-5678")).unwrap();
+    }
+
+    fn edit_args(file_path: &str) -> EditArgs {
+        EditArgs {
+            file_path: file_path.to_string(),
+            chunk_type: "ruSt".to_string(),
+            lock: LockArgs::default(),
+        }
+    }
+
+    #[test]
+    fn test_edit_replaces_the_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            redundancy: 3,
+            ecc: Some(8),
+            compress: Some(Algorithm::Deflate),
+            ..encode_args(file_path, "version 1")
+        })
+        .unwrap();
+
+        let mut editor = ScriptedEditor::writing("version 2, edited");
+        let summary = edit(edit_args(file_path), &mut editor).unwrap();
+        assert_eq!(editor.opened.as_deref(), Some(&b"version 1"[..]));
+        assert_eq!(summary.chunks_modified, 3);
+        decode(decode_args(file_path, "version 2, edited", Newline::Keep)).unwrap();
+        let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        let envelope = Envelope::from_bytes(png.chunk_by_type("ruSt").unwrap().data())
+            .unwrap()
+            .unwrap();
+        assert_eq!(envelope.ecc, Some(8));
+        assert_eq!(envelope.copy.map(|c| c.total), Some(3));
+        assert!(envelope.codec.is_some());
+    }
+
+    #[test]
+    fn test_edit_without_changes_or_with_a_failing_editor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(encode_args(file_path, "bare text")).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        let mut unchanged = ScriptedEditor {
+            content: None,
+            fail: false,
+            opened: None,
+        };
+        let summary = edit(edit_args(file_path), &mut unchanged).unwrap();
+        assert_eq!(summary.delta, 0);
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        let mut failing = ScriptedEditor {
+            fail: true,
+            ..ScriptedEditor::writing("half-finished edit")
+        };
+        assert!(edit(edit_args(file_path), &mut failing).is_err());
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        edit(
+            edit_args(file_path),
+            &mut ScriptedEditor::writing("new text"),
+        )
+        .unwrap();
+        decode(decode_args(file_path, "new text", Newline::Keep)).unwrap();
+    }
+
+    #[test]
+    fn test_edit_refuses_authenticated_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let key_path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        keygen(KeygenArgs {
+            symmetric: false,
+            signing: true,
+            out_path: key_path("signing"),
+            force: false,
+        })
+        .unwrap();
+        fs::write(key_path("shared.key"), [1; crypto::KEY_LEN]).unwrap();
+        for args in [
+            EncodeArgs {
+                sign: Some(key_path("signing")),
+                ..encode_args(file_path, "signed")
+            },
+            EncodeArgs {
+                mac_key: Some(key_path("shared.key")),
+                ..encode_args(file_path, "tagged")
+            },
+        ] {
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            encode(args).unwrap();
+            let before = fs::read(&path).unwrap();
+            let mut editor = ScriptedEditor::writing("forged");
+            let err = edit(edit_args(file_path), &mut editor).unwrap_err();
+            assert_eq!(ExitCode::of(&Failure::of(&err)), ExitCode::Refused);
+            assert!(editor.opened.is_none());
+            assert_eq!(fs::read(&path).unwrap(), before);
+        }
+    }
+
+    #[test]
+    fn test_edit_refuses_binary_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let message_file = dir.path().join("blob.bin");
+        fs::write(&message_file, [0xff, 0xfe, 0x00]).unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            message: None,
+            message_file: Some(message_file.to_str().unwrap().to_string()),
+            ..encode_args(file_path, "")
+        })
+        .unwrap();
+        let mut editor = ScriptedEditor::writing("text");
+        let err = edit(edit_args(file_path), &mut editor).unwrap_err();
+        assert!(err.to_string().contains("not UTF-8"));
+        assert!(editor.opened.is_none());
+    }
+
+    #[test]
+    fn test_redact_keeps_the_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        for message in ["first secret", "second secret"] {
+            encode(EncodeArgs {
+                ecc: Some(4),
+                ..encode_args(file_path, message)
+            })
+            .unwrap();
+        }
+        let before = fs::read(&path).unwrap();
+        let redact_args = |index, fill| RedactArgs {
+            file_path: file_path.to_string(),
+            chunk_type: "ruSt".to_string(),
+            index,
+            fill,
+            lock: LockArgs::default(),
+        };
+        redact(redact_args(1, 0xaa)).unwrap();
+
+        let after = fs::read(&path).unwrap();
+        assert_eq!(after.len(), before.len());
+        let payloads = |bytes: &[u8]| -> Vec<Chunk> {
+            Png::try_from(bytes)
+                .unwrap()
+                .chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() == "ruSt")
+                .cloned()
+                .collect()
+        };
+        let (old, new) = (payloads(&before), payloads(&after));
+        assert_eq!(new[0], old[0]);
+        assert_eq!(new[1].length(), old[1].length());
+        assert!(new[1].data().iter().all(|&b| b == 0xaa));
+        assert!(Envelope::from_bytes(new[1].data()).unwrap().is_none());
+        // Only the redacted data and its CRC differ
+        let changed: Vec<usize> = (0..after.len())
+            .filter(|&i| after[i] != before[i])
+            .collect();
+        let data_len = old[1].data().len();
+        // The payload is the last chunk before the 12 bytes of IEND
+        let data_start = before.len() - 12 - (12 + data_len) + 8;
+        assert!(changed
+            .iter()
+            .all(|&i| (data_start..data_start + data_len + 4).contains(&i)));
+
+        assert!(redact(redact_args(2, 0)).is_err());
+    }
+
+    #[test]
+    fn test_redact_leaves_nothing_to_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let redact_args = |index| RedactArgs {
+            file_path: file_path.to_string(),
+            chunk_type: "ruSt".to_string(),
+            index,
+            fill: 0,
+            lock: LockArgs::default(),
+        };
+        let decoded = || {
+            decode(DecodeArgs {
+                expect: None,
+                ..decode_args(file_path, "", Newline::Keep)
+            })
+        };
+        let secret = b"the launch code is 0000".to_vec();
+
+        // Redacting one copy or shard wipes the others, so decode can't outvote or rejoin it
+        for stored in [
+            EncodeArgs {
+                redundancy: 3,
+                ..encode_args(file_path, "the launch code is 0000")
+            },
+            EncodeArgs {
+                max_chunk_size: Some(48),
+                ..encode_args(file_path, "the launch code is 0000")
+            },
+        ] {
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            encode(stored).unwrap();
+            assert_eq!(decoded().unwrap(), secret);
+            let before = fs::read(&path).unwrap();
+            redact(redact_args(1)).unwrap();
+            let after = fs::read(&path).unwrap();
+            assert_eq!(after.len(), before.len());
+            let png = Png::try_from(&after[..]).unwrap();
+            let payloads: Vec<&Chunk> = png
+                .chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() == "ruSt")
+                .collect();
+            assert!(payloads.len() > 1);
+            assert!(payloads.iter().all(|c| c.data().iter().all(|&b| b == 0)));
+            assert!(decoded().map_or(true, |message| message != secret));
+        }
+
+        // A previous version kept for undo can't be wiped in place, so redact refuses
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(encode_args(file_path, "the launch code is 0000")).unwrap();
+        encode(EncodeArgs {
+            replace: true,
+            history: HistoryArgs {
+                keep_previous: true,
+                history_depth: 1,
+            },
+            ..encode_args(file_path, "the launch code is 1234")
+        })
+        .unwrap();
+        let before = fs::read(&path).unwrap();
+        let err = redact(redact_args(0)).unwrap_err();
+        assert!(err.downcast_ref::<RefusedError>().is_some(), "{}", err);
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        let mut png = Png::try_from(&before[..]).unwrap();
+        png.remove_first_chunk(HISTORY_CHUNK).unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        redact(redact_args(0)).unwrap();
+        assert!(decoded().map_or(true, |message| message != secret));
+        let err = undo(UndoArgs {
+            file_path: file_path.to_string(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            lock: LockArgs::default(),
+        })
+        .unwrap_err();
+        assert!(err.downcast_ref::<NotFoundError>().is_some(), "{}", err);
+    }
+
+    fn decode_args(file_path: &str, expect: &str, newline: Newline) -> DecodeArgs {
+        DecodeArgs {
+            file_path: file_path.to_string(),
+            carriers: None,
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            chunk_type_hex: None,
+            all: false,
+            mode: Mode::Chunk,
+            offset: None,
+            image_index: None,
+            decrypt: false,
+            keys: KeyArgs::default(),
+            expect: Some(expect.to_string()),
+            newline,
+            strict_expiry: false,
+            list: false,
+            extract_to: None,
+            output: None,
+            batch: BatchArgs::default(),
+            verify: None,
+            mac_key: None,
+        }
+    }
+
+    #[test]
+    fn test_message_file_newlines() {
+        let dir = tempfile::tempdir().unwrap();
+        let message = dir.path().join("message.txt");
+        fs::write(&message, "hello\r\nworld\r\n").unwrap();
+        let cases = [
+            (Newline::Keep, &b"hello\r\nworld\r\n"[..]),
+            (Newline::Lf, b"hello\nworld\n"),
+            (Newline::StripTrailing, b"hello\nworld"),
+        ];
+        for (newline, expected) in cases {
+            let path = dir.path().join("image.png");
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            encode(EncodeArgs {
+                message: None,
+                message_file: Some(message.to_str().unwrap().to_string()),
+                newline,
+                ..encode_args(path.to_str().unwrap(), "")
+            })
+            .unwrap();
+            let png = Png::from_file(&path).unwrap();
+            assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), expected);
+        }
+    }
+
+    #[test]
+    fn test_binary_message_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("blob.bin");
+        let blob: Vec<u8> = (0..=255u8)
+            .rev()
+            .chain([0xff, 0xfe, 0x00, b'\r', b'\n'])
+            .collect();
+        assert!(String::from_utf8(blob.clone()).is_err());
+        fs::write(&input, &blob).unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            message: None,
+            message_file: Some(input.to_str().unwrap().to_string()),
+            compress: Some(Algorithm::Deflate),
+            ..encode_args(file_path, "")
+        })
+        .unwrap();
+        let decoded = decode(DecodeArgs {
+            expect: None,
+            ..decode_args(file_path, "", Newline::Keep)
+        })
+        .unwrap();
+        assert_eq!(decoded, blob);
+    }
+
+    #[test]
+    fn test_inline_message_is_not_normalized() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            newline: Newline::StripTrailing,
+            ..encode_args(path.to_str().unwrap(), "raw\r\n")
+        })
+        .unwrap();
+        let png = Png::from_file(&path).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"raw\r\n");
+    }
+
+    #[test]
+    fn test_encode_out_path_and_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let original = minimal_png("pixels");
+        fs::write(&path, &original).unwrap();
+
+        let out = dir.path().join("out.png");
+        encode(EncodeArgs {
+            out_path: Some(out.to_str().unwrap().to_string()),
+            ..encode_args(file_path, "elsewhere")
+        })
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), original);
+        let written = Png::from_file(&out).unwrap();
+        assert_eq!(written.chunk_by_type("ruSt").unwrap().data(), b"elsewhere");
+
+        encode(EncodeArgs {
+            backup: true,
+            ..encode_args(file_path, "in place")
+        })
+        .unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("image.png.bak")).unwrap(),
+            original
+        );
+        let png = Png::from_file(&path).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"in place");
+
+        // The temporary file the png was written through is gone
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["image.png", "image.png.bak", "out.png"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_saving_keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.png");
+        fs::write(&target, minimal_png("pixels")).unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.path().join("link.png");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        encode(encode_args(link.to_str().unwrap(), "through a link")).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let png = Png::from_file(&target).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"through a link");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_empty_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let summary = encode(encode_args(file_path, "")).unwrap();
+        assert_eq!(summary.delta, 12);
+
+        // A marker chunk with no data at all
+        let png = Png::from_file(&path).unwrap();
+        let (index, marker) = png
+            .chunks()
+            .iter()
+            .enumerate()
+            .find(|(_, c)| c.chunk_type().to_string() == "ruSt")
+            .unwrap();
+        assert_eq!(marker.length(), 0);
+        assert_eq!(marker.data_as_string().unwrap(), "");
+        assert_eq!(chunk_field(marker, index, 0, "data_preview"), "");
+        assert_eq!(chunk_field(marker, index, 0, "data_base64"), "");
+
+        // Found and empty, which is not the same as not found
+        let decode_args = || decode_args(file_path, "", Newline::Keep);
+        assert_eq!(decode(decode_args()).unwrap(), b"");
+        remove(RemoveArgs {
+            file_path: file_path.to_string(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            chunk_type_hex: None,
+            force: false,
+            all: false,
+            index: None,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            history: HistoryArgs {
+                keep_previous: false,
+                history_depth: 1,
+            },
+            batch: BatchArgs::default(),
+        })
+        .unwrap();
+        let error = decode(decode_args()).unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::NotFound);
+        assert_eq!(fs::read(&path).unwrap(), minimal_png("pixels"));
+    }
+
+    #[test]
+    fn test_decode_discovers_the_chunk_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let discover = |expect: Option<&str>| DecodeArgs {
+            chunk_type: None,
+            expect: expect.map(str::to_string),
+            ..decode_args(file_path, "", Newline::Keep)
+        };
+
+        // Bare messages look like any other private chunk, so aren't found
+        encode(encode_args(file_path, "bare")).unwrap();
+        let err = decode(discover(None)).unwrap_err();
+        assert_eq!(crate::error::Failure::of(&err).kind(), "NotFound");
+        assert!(decode_all(discover(None)).is_err());
+
+        encode(EncodeArgs {
+            chunk_type: Some("seCr".to_string()),
+            redundancy: 2,
+            ..encode_args(file_path, "found it")
+        })
+        .unwrap();
+        decode(discover(Some("found it"))).unwrap();
+
+        encode(EncodeArgs {
+            chunk_type: None,
+            label: Some("license".to_string()),
+            ..encode_args(file_path, "key-5678")
+        })
+        .unwrap();
+        let err = decode(discover(None)).unwrap_err();
+        assert!(err.to_string().contains("several chunk types (seCr, eeJc)"));
+
+        let all: Vec<(String, Vec<u8>)> = decode_all(DecodeArgs {
+            all: true,
+            ..discover(None)
+        })
+        .unwrap()
+        .into_iter()
+        .map(|(ctype, _, message)| (ctype.to_string(), message))
+        .collect();
+        assert_eq!(
+            all,
+            [
+                ("seCr".to_string(), b"found it".to_vec()),
+                ("eeJc".to_string(), b"key-5678".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_expect() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(encode_args(file_path, "line\r\n")).unwrap();
+
+        assert!(decode(decode_args(file_path, "line\r\n", Newline::Keep)).is_ok());
+        assert!(decode(decode_args(file_path, "line\n", Newline::Keep)).is_err());
+        assert!(decode(decode_args(file_path, "line\n", Newline::Lf)).is_ok());
+        assert!(decode(decode_args(file_path, "line", Newline::StripTrailing)).is_ok());
+        assert!(decode(decode_args(file_path, "other", Newline::StripTrailing)).is_err());
+    }
+
+    /// Flips the last byte of every ruSt chunk in the png at `path` and recomputes the CRCs, as
+    /// a tool that rewrites chunks would.
+    fn damage_behind_valid_crc(path: &Path) {
+        let mut png = Png::from_file(path).unwrap();
+        let _ = png.walk_mut(|_, c| {
+            if c.chunk_type().to_string() == "ruSt" {
+                let mut data = c.data().to_vec();
+                *data.last_mut().unwrap() ^= 0xff;
+                c.set_data(data);
+            }
+            ControlFlow::Continue(())
+        });
+        fs::write(path, png.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_payload_modified_behind_valid_crc() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let args = decode_args(file_path, "archival", Newline::Keep);
+
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            compress: Some(Algorithm::Deflate),
+            ..encode_args(file_path, "archival")
+        })
+        .unwrap();
+        damage_behind_valid_crc(&path);
+        let error = decode_chunk(&args, &mut vec![]).unwrap_err();
+        assert!(
+            error.to_string().contains(
+                "chunk intact but payload integrity check failed — the payload was modified \
+                 after encoding"
+            ),
+            "{}",
+            error
+        );
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::Integrity);
+
+        // With error correction the same damage is repaired, and said so
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            ecc: Some(8),
+            ..encode_args(file_path, "archival")
+        })
+        .unwrap();
+        damage_behind_valid_crc(&path);
+        let mut warnings = vec![];
+        let envelope = decode_chunk(&args, &mut warnings).unwrap();
+        assert_eq!(envelope.payload, b"archival");
+        assert_eq!(
+            warnings,
+            [Diagnostic::warning(
+                DiagnosticKind::Recovered,
+                "error correction repaired 1 corrupted byte(s)".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_payload_adapters_match_encode_and_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let mut state = 1u32;
+        let message: String = (0..3000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                char::from(b'a' + (state >> 16) as u8 % 26)
+            })
+            .collect();
+        encode(EncodeArgs {
+            max_chunk_size: Some(200),
+            compress: Some(Algorithm::Deflate),
+            ..encode_args(file_path, &message)
+        })
+        .unwrap();
+        let encoded = Png::from_file(&path).unwrap();
+        assert!(encoded.chunks_by_type("ruSt").len() > 5);
+
+        let ctype = ChunkType::from_str("ruSt").unwrap();
+        let mut written = Png::try_from(&minimal_png("pixels")[..]).unwrap();
+        let options = PayloadOptions {
+            codec: Some(Codec::Deflate),
+            max_chunk_size: Some(200),
+            ..Default::default()
+        };
+        let mut writer = written.payload_writer(ctype, options);
+        for part in message.as_bytes().chunks(100) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(written.as_bytes(), encoded.as_bytes());
+
+        let mut read = vec![];
+        let mut reader = encoded.payload_reader(&ctype, None).unwrap();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(
+            read,
+            decode(decode_args(file_path, &message, Newline::Keep)).unwrap()
+        );
+
+        // decode --output streams the shards into the file
+        let output = dir.path().join("message.txt");
+        let args = DecodeArgs {
+            expect: None,
+            ..decode_args(file_path, "", Newline::Keep)
+        };
+        decode_to_file(args, output.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), message.as_bytes());
+    }
+
+    #[test]
+    fn test_max_chunk_size_splits_the_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let message: String = (0..1000)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        encode(EncodeArgs {
+            max_chunk_size: Some(200),
+            ..encode_args(file_path, &message)
+        })
+        .unwrap();
+
+        let mut png = Png::from_file(&path).unwrap();
+        let pieces: Vec<usize> = (0..png.chunks().len())
+            .filter(|&idx| png.chunks()[idx].chunk_type().to_string() == "ruSt")
+            .collect();
+        assert!(pieces.len() > 5, "{}", pieces.len());
+        assert!(pieces
+            .iter()
+            .all(|&idx| png.chunks()[idx].as_bytes().len() <= 200));
+        assert_eq!(
+            decode(decode_args(file_path, &message, Newline::Keep)).unwrap(),
+            message.as_bytes()
+        );
+
+        // The pieces are numbered, so their order in the file doesn't matter
+        let last = png.remove_chunk(*pieces.last().unwrap()).unwrap();
+        png.insert_chunk(pieces[0], last).unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        assert_eq!(
+            decode(decode_args(file_path, &message, Newline::Keep)).unwrap(),
+            message.as_bytes()
+        );
+
+        png.remove_chunk(pieces[1]).unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        let error = decode(decode_args(file_path, &message, Newline::Keep)).unwrap_err();
+        assert!(error.to_string().contains("missing shard"), "{}", error);
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::NotFound);
+    }
+
+    #[test]
+    fn test_redundant_copies_outvote_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            redundancy: 3,
+            ..encode_args(file_path, "archival")
+        })
+        .unwrap();
+
+        // Damage the second copy the way a tool that rewrites CRCs would
+        let mut png = Png::from_file(&path).unwrap();
+        let mut copies = vec![];
+        let _ = png.walk_mut(|_, c| {
+            if c.chunk_type().to_string() == "ruSt" {
+                copies.push(c.clone());
+                if copies.len() == 2 {
+                    let mut data = c.data().to_vec();
+                    *data.last_mut().unwrap() ^= 0xff;
+                    c.set_data(data);
+                }
+            }
+            ControlFlow::Continue(())
+        });
+        assert_eq!(copies.len(), 3);
+        fs::write(&path, png.as_bytes()).unwrap();
+
+        let chunks: Vec<Chunk> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "ruSt")
+            .cloned()
+            .collect();
+        let envelopes: Vec<crate::Result<Envelope>> = chunks
+            .iter()
+            .map(|c| Ok(Envelope::from_bytes(c.data()).unwrap().unwrap()))
+            .collect();
+        let recovered = envelope::recover(&envelopes).unwrap();
+        assert_eq!(recovered.envelope.payload, b"archival");
+        assert_eq!(recovered.corrupt, vec![1]);
+        assert!(decode(decode_args(file_path, "archival", Newline::Keep)).is_ok());
+
+        let mut warnings = vec![];
+        envelope_from(&chunks, &mut warnings).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                Diagnostic::warning(
+                    DiagnosticKind::CorruptCopy,
+                    "copy 2 of 3 is corrupt".to_string()
+                ),
+                Diagnostic::warning(
+                    DiagnosticKind::Recovered,
+                    "the message was recovered from its redundant copies (2 of 3 intact)"
+                        .to_string()
+                )
+            ]
+        );
+        let mut warnings = vec![];
+        let envelope = envelope_from(&[chunks[0].clone(), chunks[2].clone()], &mut warnings);
+        assert_eq!(envelope.unwrap().payload, b"archival");
+        assert_eq!(
+            warnings,
+            [Diagnostic::warning(
+                DiagnosticKind::MissingCopies,
+                "only 2 of 3 copies of the message were found".to_string()
+            )]
+        );
+
+        remove(RemoveArgs {
+            file_path: file_path.to_string(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            chunk_type_hex: None,
+            force: false,
+            all: false,
+            index: None,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            history: HistoryArgs::default(),
+            batch: BatchArgs::default(),
+        })
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), minimal_png("pixels"));
+    }
+
+    #[test]
+    fn test_mutation_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let size = |path| fs::metadata(path).unwrap().len() as i64;
+        let json = |summary: MutationSummary| -> serde_json::Value {
+            serde_json::from_str(&serde_json::to_string(&summary).unwrap()).unwrap()
+        };
+
+        let before = size(&path);
+        let summary = json(encode(encode_args(file_path, "twelve bytes")).unwrap());
+        let after = size(&path);
+        assert_eq!(summary["bytes_before"], before);
+        assert_eq!(summary["bytes_after"], after);
+        assert_eq!(summary["delta"], after - before);
+        assert_eq!(summary["delta"], 12 + 12);
+        assert_eq!(summary["chunks_added"], 1);
+        assert_eq!(summary["chunks_removed"], 0);
+
+        let summary = json(
+            remove(RemoveArgs {
+                file_path: file_path.to_string(),
+                chunk_type: Some("ruSt".to_string()),
+                label: None,
+                chunk_type_hex: None,
+                force: false,
+                all: false,
+                index: None,
+                verify_image: false,
+                image_index: None,
+                lock: LockArgs::default(),
+                print_hash: None,
+                audit: AuditArgs::default(),
+                history: HistoryArgs::default(),
+                batch: BatchArgs::default(),
+            })
+            .unwrap(),
+        );
+        assert_eq!(summary["bytes_before"], after);
+        assert_eq!(summary["bytes_after"], before);
+        assert_eq!(summary["delta"], before - after);
+        assert_eq!(summary["chunks_removed"], 1);
+        assert_eq!(summary["chunks_modified"], 0);
+    }
+
+    #[test]
+    fn test_ecc_corrects_flipped_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let message = "error correction keeps this readable";
+        encode(EncodeArgs {
+            ecc: Some(8),
+            ..encode_args(file_path, message)
+        })
+        .unwrap();
+
+        let pristine = fs::read(&path).unwrap();
+        let damage = |flips: usize| {
+            let mut png = Png::try_from(&pristine[..]).unwrap();
+            let _ = png.walk_mut(|_, c| {
+                if c.chunk_type().to_string() == "ruSt" {
+                    let mut data = c.data().to_vec();
+                    let len = data.len();
+                    for b in &mut data[len - 20..len - 20 + flips] {
+                        *b ^= 0x42;
+                    }
+                    c.set_data(data);
+                }
+                ControlFlow::Continue(())
+            });
+            png
+        };
+
+        let png = damage(4);
+        let data = png.chunk_by_type("ruSt").unwrap().data();
+        let envelope = Envelope::from_bytes(data).unwrap().unwrap();
+        assert_eq!(envelope.payload, message.as_bytes());
+        assert_eq!(envelope.corrections, 4);
+        fs::write(&path, png.as_bytes()).unwrap();
+        assert!(decode(decode_args(file_path, message, Newline::Keep)).is_ok());
+
+        // Five flips exceed what 8 parity bytes can correct
+        fs::write(&path, damage(5).as_bytes()).unwrap();
+        assert!(decode(decode_args(file_path, message, Newline::Keep)).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_options_are_usage_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let usage = |error: crate::Error| {
+            assert_eq!(Failure::of(&error).kind(), "Usage", "{}", error);
+            assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::Failure);
+        };
+        usage(
+            encode(EncodeArgs {
+                image_index: Some(0),
+                placement: Placement::End,
+                ..encode_args(file_path, "message")
+            })
+            .unwrap_err(),
+        );
+        usage(
+            encode(EncodeArgs {
+                mode: Mode::Lsb,
+                redundancy: 3,
+                ..encode_args(file_path, "message")
+            })
+            .unwrap_err(),
+        );
+        encode(encode_args(file_path, "message")).unwrap();
+        usage(
+            decode(DecodeArgs {
+                decrypt: true,
+                ..decode_args(file_path, "message", Newline::Keep)
+            })
+            .unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn test_lsb_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let original = crate::lsb::tests::generated_png(32, 32, 2, 3);
+        fs::write(&path, original.as_bytes()).unwrap();
+        encode(EncodeArgs {
+            chunk_type: None,
+            mode: Mode::Lsb,
+            ..encode_args(file_path, "no chunk to see here")
+        })
+        .unwrap();
+
+        let png = Png::from_file(&path).unwrap();
+        let types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|c| c.chunk_type().to_string())
+                .collect()
+        };
+        assert_eq!(types(&png), types(&original));
+        assert!(decode(DecodeArgs {
+            chunk_type: None,
+            mode: Mode::Lsb,
+            ..decode_args(file_path, "no chunk to see here", Newline::Keep)
+        })
+        .is_ok());
+
+        // Unlike a message in a chunk, it survives stripping every ancillary chunk
+        let stripped = dir.path().join("stripped.png");
+        let stripped = stripped.to_str().unwrap();
+        strip(StripArgs {
+            trailing_only: false,
+            ..strip_args(file_path, stripped)
+        })
+        .unwrap();
+        assert!(decode(DecodeArgs {
+            chunk_type: None,
+            mode: Mode::Lsb,
+            ..decode_args(stripped, "no chunk to see here", Newline::Keep)
+        })
+        .is_ok());
+
+        let too_long = "x".repeat(32 * 32 * 3 / 8);
+        assert!(encode(EncodeArgs {
+            chunk_type: None,
+            mode: Mode::Lsb,
+            ..encode_args(file_path, &too_long)
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_key_file_encryption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let key = dir.path().join("key.bin");
+        let key_path = key.to_str().unwrap().to_string();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        keygen(KeygenArgs {
+            symmetric: true,
+            signing: false,
+            out_path: key_path.clone(),
+            force: false,
+        })
+        .unwrap();
+        assert_eq!(fs::read(&key).unwrap().len(), crypto::KEY_LEN);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // Never overwrite an existing key unless forced
+        let first = fs::read(&key).unwrap();
+        assert!(keygen(KeygenArgs {
+            symmetric: true,
+            signing: false,
+            out_path: key_path.clone(),
+            force: false,
+        })
+        .is_err());
+        assert_eq!(fs::read(&key).unwrap(), first);
+        // Overwriting a key left readable by others narrows it to the current user again
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+            keygen(KeygenArgs {
+                symmetric: true,
+                signing: false,
+                out_path: key_path.clone(),
+                force: true,
+            })
+            .unwrap();
+            assert_ne!(fs::read(&key).unwrap(), first);
+            let mode = fs::metadata(&key).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let keys = || KeyArgs {
+            key_file: Some(key_path.clone()),
+            ..Default::default()
+        };
+        encode(EncodeArgs {
+            encrypt: true,
+            keys: keys(),
+            ..encode_args(file_path, "for ci only")
+        })
+        .unwrap();
+        let png = Png::from_file(&path).unwrap();
+        let stored = png.chunk_by_type("ruSt").unwrap().data();
+        assert!(!stored.windows(11).any(|w| w == b"for ci only"));
+
+        assert!(decode(DecodeArgs {
+            decrypt: true,
+            keys: keys(),
+            ..decode_args(file_path, "for ci only", Newline::Keep)
+        })
+        .is_ok());
+        // Without --decrypt, or with the wrong kind of key, decoding fails
+        assert!(decode(decode_args(file_path, "for ci only", Newline::Keep)).is_err());
+        assert!(decode(DecodeArgs {
+            decrypt: true,
+            keys: KeyArgs {
+                passphrase: Some("guess".into()),
+                ..Default::default()
+            },
+            ..decode_args(file_path, "for ci only", Newline::Keep)
+        })
+        .is_err());
+
+        let short_key = dir.path().join("short.bin");
+        fs::write(&short_key, [0; 16]).unwrap();
+        let err = decode(DecodeArgs {
+            decrypt: true,
+            keys: KeyArgs {
+                key_file: Some(short_key.to_str().unwrap().to_string()),
+                ..Default::default()
+            },
+            ..decode_args(file_path, "for ci only", Newline::Keep)
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("is 16 bytes, expected 32"));
+    }
+
+    #[test]
+    fn test_signed_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let key_path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        for name in ["mine", "theirs"] {
+            keygen(KeygenArgs {
+                symmetric: false,
+                signing: true,
+                out_path: key_path(name),
+                force: false,
+            })
+            .unwrap();
+        }
+        assert_eq!(fs::read(key_path("mine.pub")).unwrap().len(), 32);
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(encode_args(file_path, "unsigned")).unwrap();
+        let verify = |key: &str, expect: &str| {
+            decode(DecodeArgs {
+                verify: Some(key_path(key)),
+                ..decode_args(file_path, expect, Newline::Keep)
+            })
+        };
+        assert!(verify("mine.pub", "unsigned").is_err());
+
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            sign: Some(key_path("mine")),
+            ..encode_args(file_path, "from me")
+        })
+        .unwrap();
+        assert!(verify("mine.pub", "from me").is_ok());
+        assert!(verify("theirs.pub", "from me").is_err());
+        // Without --verify the signature is ignored
+        assert!(decode(decode_args(file_path, "from me", Newline::Keep)).is_ok());
+
+        // Copies and shards are numbered after signing, and still verify
+        for layout in [
+            EncodeArgs {
+                redundancy: 3,
+                ..encode_args(file_path, "from me")
+            },
+            EncodeArgs {
+                max_chunk_size: Some(106),
+                ..encode_args(file_path, "from me")
+            },
+        ] {
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            encode(EncodeArgs {
+                sign: Some(key_path("mine")),
+                ..layout
+            })
+            .unwrap();
+            assert!(verify("mine.pub", "from me").is_ok());
+        }
+
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            sign: Some(key_path("mine")),
+            ..encode_args(file_path, "from me")
+        })
+        .unwrap();
+        // Changing the payload, or what the envelope says about it, fails the signature even
+        // with the checksum and CRC fixed up to match
+        let signed = fs::read(&path).unwrap();
+        let tamper = |change: &dyn Fn(&mut Envelope)| {
+            let mut png = Png::try_from(&signed[..]).unwrap();
+            let chunk = png.remove_first_chunk("ruSt").unwrap();
+            let mut envelope = Envelope::from_bytes(chunk.data()).unwrap().unwrap();
+            change(&mut envelope);
+            envelope.checksum = Envelope::new(envelope.payload.clone()).checksum;
+            png.append_chunk(Chunk::new(*chunk.chunk_type(), envelope.as_bytes()));
+            fs::write(&path, png.as_bytes()).unwrap();
+        };
+        tamper(&|envelope| envelope.payload = b"from you".to_vec());
+        assert!(decode(decode_args(file_path, "from you", Newline::Keep)).is_ok());
+        let err = verify("mine.pub", "from you").unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&err)), ExitCode::Integrity);
+        tamper(&|envelope| envelope.expires = Some(u64::MAX));
+        assert!(decode(decode_args(file_path, "from me", Newline::Keep)).is_ok());
+        assert!(verify("mine.pub", "from me").is_err());
+        tamper(&|envelope| envelope.label = Some("notes".to_string()));
+        assert!(verify("mine.pub", "from me").is_err());
+        tamper(&|_| {});
+        assert!(verify("mine.pub", "from me").is_ok());
+    }
+
+    #[test]
+    fn test_mac_tagged_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let key_path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(key_path("shared.key"), [1; crypto::KEY_LEN]).unwrap();
+        fs::write(key_path("other.key"), [2; crypto::KEY_LEN]).unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            mac_key: Some(key_path("shared.key")),
+            ..encode_args(file_path, "plain but tagged")
+        })
+        .unwrap();
+        // The message itself stays readable, it is only authenticated
+        let png = Png::from_file(&path).unwrap();
+        let stored = png.chunk_by_type("ruSt").unwrap().data();
+        assert!(stored.windows(16).any(|w| w == b"plain but tagged"));
+
+        let check = |key: &str| {
+            decode(DecodeArgs {
+                mac_key: Some(key_path(key)),
+                ..decode_args(file_path, "plain but tagged", Newline::Keep)
+            })
+        };
+        assert!(check("shared.key").is_ok());
+        let err = check("other.key").unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&err)), ExitCode::Integrity);
+        assert!(
+            err.to_string().contains("HMAC tag doesn't match"),
+            "{}",
+            err
+        );
+
+        // Metadata is covered too: an expiry added after the fact fails the tag
+        let mut png = Png::from_file(&path).unwrap();
+        let chunk = png.remove_first_chunk("ruSt").unwrap();
+        let mut envelope = Envelope::from_bytes(chunk.data()).unwrap().unwrap();
+        envelope.expires = Some(u64::MAX);
+        png.append_chunk(Chunk::new(*chunk.chunk_type(), envelope.as_bytes()));
+        fs::write(&path, png.as_bytes()).unwrap();
+        assert!(decode(decode_args(file_path, "plain but tagged", Newline::Keep)).is_ok());
+        assert!(check("shared.key").is_err());
+
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(encode_args(file_path, "plain but tagged")).unwrap();
+        assert!(check("shared.key").is_err());
+    }
+
+    #[test]
+    fn test_key_source_precedence() {
+        use crate::prompt::tests::ScriptedPrompt;
+        let mut keyring = MemoryKeyring::default();
+        let flag = KeyArgs {
+            passphrase: Some("from flag".into()),
+            ..Default::default()
+        };
+        let mut prompt = ScriptedPrompt::new(&["typed", "typed"]);
+        let source = key_source(&flag, &mut prompt, &mut keyring, true, &mut vec![]).unwrap();
+        assert!(matches!(source, KeySource::Passphrase(p) if p.expose() == b"from flag"));
+        assert!(prompt.asked.is_empty());
+
+        let file = KeyArgs {
+            key_file: Some("key.bin".to_string()),
+            ..Default::default()
+        };
+        let source = key_source(&file, &mut prompt, &mut keyring, true, &mut vec![]).unwrap();
+        assert!(matches!(source, KeySource::KeyFile(_)));
+        assert!(prompt.asked.is_empty());
+
+        let source = key_source(
+            &KeyArgs::default(),
+            &mut prompt,
+            &mut keyring,
+            true,
+            &mut vec![],
+        )
+        .unwrap();
+        assert!(matches!(source, KeySource::Passphrase(p) if p.expose() == b"typed"));
+        assert!(key_source(
+            &KeyArgs::default(),
+            &mut prompt,
+            &mut keyring,
+            false,
+            &mut vec![]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_prompted_passphrase_decrypts() {
+        use crate::prompt::tests::ScriptedPrompt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        let args = EncodeArgs {
+            encrypt: true,
+            ..encode_args(file_path, "typed in")
+        };
+        let keyring = &mut MemoryKeyring::default();
+        let mut prompt = ScriptedPrompt::new(&["pass", "pass"]);
+        let envelope = seal(
+            &args,
+            b"typed in".to_vec(),
+            &mut prompt,
+            keyring,
+            &mut vec![],
+        )
+        .unwrap();
+
+        let mut prompt = ScriptedPrompt::new(&["pass"]);
+        let keys = KeyArgs::default();
+        let opened = open_envelope(
+            envelope.clone(),
+            true,
+            &keys,
+            &mut prompt,
+            keyring,
+            &mut vec![],
+        );
+        assert_eq!(opened.unwrap(), b"typed in");
+        assert_eq!(prompt.asked, ["Passphrase: "]);
+
+        let mut prompt = ScriptedPrompt::new(&["wrong"]);
+        assert!(open_envelope(envelope, true, &keys, &mut prompt, keyring, &mut vec![]).is_err());
+
+        let mut prompt = ScriptedPrompt::new(&["pass", "typo"]);
+        assert!(seal(
+            &args,
+            b"typed in".to_vec(),
+            &mut prompt,
+            keyring,
+            &mut vec![]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_padding_hides_message_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key.bin");
+        fs::write(&key, [5; crypto::KEY_LEN]).unwrap();
+        let keys = || KeyArgs {
+            key_file: Some(key.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let mut stored_lengths = vec![];
+        for message in ["short", "a rather longer message than that"] {
+            let path = dir.path().join(format!("{}.png", message.len()));
+            let file_path = path.to_str().unwrap();
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            encode(EncodeArgs {
+                encrypt: true,
+                keys: keys(),
+                pad_to: Some(256),
+                ..encode_args(file_path, message)
+            })
+            .unwrap();
+            let png = Png::from_file(&path).unwrap();
+            stored_lengths.push(png.chunk_by_type("ruSt").unwrap().data().len());
+
+            assert!(decode(DecodeArgs {
+                decrypt: true,
+                keys: keys(),
+                ..decode_args(file_path, message, Newline::Keep)
+            })
+            .is_ok());
+        }
+        assert_eq!(stored_lengths[0], stored_lengths[1]);
+    }
+
+    #[test]
+    fn test_padding_without_encryption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, minimal_png("pixels")).unwrap();
+        encode(EncodeArgs {
+            pad_block: Some(64),
+            ..encode_args(file_path, "plain but padded")
+        })
+        .unwrap();
+        let png = Png::from_file(&path).unwrap();
+        let envelope = Envelope::from_bytes(png.chunk_by_type("ruSt").unwrap().data());
+        let envelope = envelope.unwrap().unwrap();
+        assert!(envelope.padded);
+        assert_eq!(envelope.payload.len(), 64);
+        assert!(decode(decode_args(file_path, "plain but padded", Newline::Keep)).is_ok());
+
+        let err = encode(EncodeArgs {
+            pad_to: Some(8),
+            ..encode_args(file_path, "too long for the target")
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("--pad-to 8"));
+
+        // Padding counts against what the pixel data can hold
+        fs::write(&path, lsb::tests::generated_png(16, 16, 2, 3).as_bytes()).unwrap();
+        let lsb = |pad_to| EncodeArgs {
+            chunk_type: None,
+            mode: Mode::Lsb,
+            pad_to,
+            ..encode_args(file_path, "fits")
+        };
+        assert!(encode(lsb(Some(1024))).is_err());
+        assert!(encode(lsb(Some(16))).is_ok());
+    }
+
+    #[test]
+    fn test_compress_only_if_smaller() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let stored_codec = |message: &[u8], min_compression_gain| {
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            fs::write(dir.path().join("message"), message).unwrap();
+            encode(EncodeArgs {
+                message: None,
+                message_file: Some(dir.path().join("message").to_str().unwrap().to_string()),
+                compress: Some(Algorithm::Deflate),
+                min_compression_gain,
+                ..encode_args(file_path, "")
+            })
+            .unwrap();
+            let png = Png::from_file(&path).unwrap();
+            let data = png.chunk_by_type("ruSt").unwrap().data();
+            let envelope = Envelope::from_bytes(data).unwrap().unwrap();
+            (envelope.codec.unwrap(), envelope.payload.len())
+        };
+
+        let text = "all work and no play makes jack a dull boy\n".repeat(30);
+        let (codec, stored) = stored_codec(text.as_bytes(), 0);
+        assert_eq!(codec, Codec::Deflate);
+        assert!(stored < text.len() / 5);
+        // Decode has no compression flags and goes by what the envelope records
+        assert!(decode(decode_args(file_path, &text, Newline::Keep)).is_ok());
+
+        let blob: [u8; 600] = crypto::random_bytes().unwrap();
+        assert_eq!(stored_codec(&blob, 0), (Codec::Stored, 600));
+        assert_eq!(
+            stored_codec(text.as_bytes(), 100),
+            (Codec::Stored, text.len())
+        );
+    }
+
+    #[test]
+    fn test_compress_algorithms() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let text = "all work and no play makes jack a dull boy\n".repeat(30);
+        let stored = |compress| {
+            fs::write(&path, minimal_png("pixels")).unwrap();
+            encode(EncodeArgs {
+                compress,
+                ..encode_args(file_path, &text)
+            })
+            .unwrap();
+            assert!(decode(decode_args(file_path, &text, Newline::Keep)).is_ok());
+            let png = Png::from_file(&path).unwrap();
+            let data = png.chunk_by_type("ruSt").unwrap().data().to_vec();
+            Envelope::from_bytes(&data).unwrap().and_then(|e| e.codec)
+        };
+        assert_eq!(stored(Some(Algorithm::Zstd)), Some(Codec::Zstd));
+        assert_eq!(stored(Some(Algorithm::Deflate)), Some(Codec::Deflate));
+        // --compress none stores the message bare, as without --compress
+        assert_eq!(stored(Some(Algorithm::None)), None);
+        let png = Png::from_file(&path).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), text.as_bytes());
+    }
+
+    fn keyring_keys(id: &str) -> KeyArgs {
+        KeyArgs {
+            use_keyring: true,
+            keyring_id: Some(id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_keyring_passphrase() {
+        use crate::prompt::tests::ScriptedPrompt;
+        let mut keyring = MemoryKeyring::default();
+        keyring
+            .entries
+            .insert("project-x".to_string(), "stored".into());
+
+        // A stored passphrase is used without asking, but an explicit flag still wins
+        let mut prompt = ScriptedPrompt::new(&[]);
+        let source = key_source(
+            &keyring_keys("project-x"),
+            &mut prompt,
+            &mut keyring,
+            true,
+            &mut vec![],
+        );
+        assert!(matches!(source.unwrap(), KeySource::Passphrase(p) if p.expose() == b"stored"));
+        let flag = KeyArgs {
+            passphrase: Some("from flag".into()),
+            ..keyring_keys("project-x")
+        };
+        let source = key_source(&flag, &mut prompt, &mut keyring, true, &mut vec![]).unwrap();
+        assert!(matches!(source, KeySource::Passphrase(p) if p.expose() == b"from flag"));
+        assert!(prompt.asked.is_empty());
+
+        // A missing entry is asked for, and stored once confirmed while encoding
+        let mut prompt = ScriptedPrompt::new(&["typo"]);
+        let source = key_source(
+            &keyring_keys("project-y"),
+            &mut prompt,
+            &mut keyring,
+            false,
+            &mut vec![],
+        );
+        assert!(matches!(source.unwrap(), KeySource::Passphrase(p) if p.expose() == b"typo"));
+        assert!(!keyring.entries.contains_key("project-y"));
+        let mut prompt = ScriptedPrompt::new(&["new", "new"]);
+        key_source(
+            &keyring_keys("project-y"),
+            &mut prompt,
+            &mut keyring,
+            true,
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(keyring.entries["project-y"].expose(), b"new");
+
+        // An unreadable keyring falls back to asking, and isn't written to
+        keyring.broken = true;
+        let mut prompt = ScriptedPrompt::new(&["typed", "typed"]);
+        let source = key_source(
+            &keyring_keys("project-z"),
+            &mut prompt,
+            &mut keyring,
+            true,
+            &mut vec![],
+        );
+        assert!(matches!(source.unwrap(), KeySource::Passphrase(p) if p.expose() == b"typed"));
+        assert_eq!(prompt.asked.len(), 2);
+        keyring.broken = false;
+        assert!(!keyring.entries.contains_key("project-z"));
+    }
+
+    #[test]
+    fn test_keyring_command() {
+        use crate::prompt::tests::ScriptedPrompt;
+        let mut keyring_store = MemoryKeyring::default();
+        let action = |action| KeyringArgs { action };
+        let id = || "project-x".to_string();
+
+        let mut prompt = ScriptedPrompt::new(&["hunter2", "hunter2"]);
+        keyring(
+            action(KeyringAction::Set { id: id() }),
+            &mut prompt,
+            &mut keyring_store,
+        )
+        .unwrap();
+        assert_eq!(keyring_store.entries["project-x"].expose(), b"hunter2");
+
+        let mut prompt = ScriptedPrompt::new(&[]);
+        keyring(
+            action(KeyringAction::Get { id: id() }),
+            &mut prompt,
+            &mut keyring_store,
+        )
+        .unwrap();
+        keyring(
+            action(KeyringAction::Delete { id: id() }),
+            &mut prompt,
+            &mut keyring_store,
+        )
+        .unwrap();
+        assert!(keyring_store.entries.is_empty());
+        for action in [
+            action(KeyringAction::Get { id: id() }),
+            action(KeyringAction::Delete { id: id() }),
+        ] {
+            let err = keyring(action, &mut prompt, &mut keyring_store).unwrap_err();
+            assert!(err.to_string().contains("No passphrase is stored"));
+        }
+    }
+
+    #[cfg(feature = "image-verify")]
+    #[test]
+    fn test_encode_passes_image_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, crate::verify::tests::palette_png()).unwrap();
+        encode(EncodeArgs {
+            verify_image: true,
+            ..encode_args(path.to_str().unwrap(), "hello")
+        })
+        .unwrap();
+        let png = Png::from_file(&path).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"hello");
+    }
+
+    #[cfg(feature = "image-verify")]
+    #[test]
+    fn test_failed_verification_leaves_original_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let original = crate::verify::tests::palette_png();
+        fs::write(&path, &original).unwrap();
+        let result = remove(RemoveArgs {
+            file_path: path.to_str().unwrap().to_string(),
+            chunk_type: Some("PLTE".to_string()),
+            label: None,
+            chunk_type_hex: None,
+            force: false,
+            all: false,
+            index: None,
+            verify_image: true,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            history: HistoryArgs::default(),
+            batch: BatchArgs::default(),
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_frames_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anim.png");
+        let source = apng::tests::animation();
+        fs::write(&path, source.as_bytes()).unwrap();
+        let out_dir = dir.path().join("frames");
+        let extract = |file_path: &Path| FramesArgs {
+            action: FramesAction::Extract {
+                file_path: file_path.to_str().unwrap().to_string(),
+                out_dir: out_dir.to_str().unwrap().to_string(),
+            },
+        };
+        frames(extract(&path), Format::Plain).unwrap();
+
+        let mut written: Vec<String> = fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        written.sort();
+        assert_eq!(written, ["frame_000.png", "frame_001.png", "frame_002.png"]);
+        for name in &written {
+            let (png, diagnostics) = Png::parse_report(&fs::read(out_dir.join(name)).unwrap());
+            assert!(png.is_ok(), "{}", name);
+            assert!(diagnostics.is_empty(), "{}: {:?}", name, diagnostics);
+        }
+        let idat = |png: &Png| -> Vec<u8> {
+            png.chunks()
+                .iter()
+                .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+                .flat_map(|c| c.data().to_vec())
+                .collect()
+        };
+        let first = Png::try_from(out_dir.join("frame_000.png").as_path()).unwrap();
+        assert_eq!(idat(&first), idat(&source));
+
+        let still = dir.path().join("still.png");
+        fs::write(&still, minimal_png("pixels")).unwrap();
+        let error = frames(extract(&still), Format::Plain).unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::NotFound);
+    }
+
+    fn rewrite_args(file_path: &str, sed: &str) -> RewriteArgs {
+        RewriteArgs {
+            file_path: file_path.to_string(),
+            chunk_type: "tEXt".to_string(),
+            keyword: None,
+            sed: sed.to_string(),
+            literal: false,
+            image_index: None,
+            verify_image: false,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+        }
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let texts = [
+            ("tEXt", "Copyright\0(c) 2023 A. Person"),
+            ("tEXt", "Comment\0made in 2023"),
+            ("ruSt", "2023"),
+        ];
+        fs::write(&path, png_with("pixels", &texts)).unwrap();
+        let data = |index: usize| {
+            let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+            let chunk = png.chunks()[index].clone();
+            assert!(chunk.has_valid_crc());
+            String::from_utf8(chunk.data().to_vec()).unwrap()
+        };
+
+        let summary = rewrite(RewriteArgs {
+            keyword: Some("Copyright".to_string()),
+            literal: true,
+            ..rewrite_args(file_path, "s/(c) 2023/(c) 2024/")
+        })
+        .unwrap();
+        assert_eq!(summary.chunks_modified, 1);
+        assert_eq!(data(2), "Copyright\0(c) 2024 A. Person");
+        assert_eq!(data(3), "Comment\0made in 2023");
+
+        rewrite(rewrite_args(file_path, r"s/in (\d{4})/in $1-2024/")).unwrap();
+        assert_eq!(data(3), "Comment\0made in 2023-2024");
+        assert_eq!(data(4), "2023");
+
+        // A bad expression fails before the file is read
+        let error = rewrite(rewrite_args("missing.png", "s/a/b")).unwrap_err();
+        assert!(error.to_string().contains("missing the closing /"));
+    }
+
+    #[test]
+    fn test_rewrite_without_a_match_leaves_the_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let original = png_with("pixels", &[("tEXt", "Copyright\0(c) 2023")]);
+        fs::write(&path, &original).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let summary = rewrite(rewrite_args(path.to_str().unwrap(), "s/1999/2024/g")).unwrap();
+        assert_eq!((summary.delta, summary.chunks_modified), (0, 0));
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+    }
+
+    #[test]
+    fn test_encode_and_decode_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        fs::write(&path, png_with("pixels", &[("tEXt", "Author\0me")])).unwrap();
+        let encode = |keyword: &str, text: &str| {
+            encode_text(EncodeTextArgs {
+                file_path: file_path.to_string(),
+                keyword: keyword.to_string(),
+                text: text.to_string(),
+                compress: false,
+                lock: LockArgs::default(),
+                audit: AuditArgs::default(),
+            })
+        };
+
+        encode("Title", "Café").unwrap();
+        let summary = encode("Author", "you").unwrap();
+        assert_eq!(summary.chunks_modified, 1);
+        let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "tEXt", "tEXt", "IEND"]);
+        assert_eq!(png.chunks()[3].data(), b"Title\0Caf\xe9");
+        let texts: Vec<TextChunk> = png.texts().into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            texts,
+            [
+                TextChunk::new("Author", "you").unwrap(),
+                TextChunk::new("Title", "Café").unwrap()
+            ]
+        );
+
+        // A compressed text replaces the plain one with the same keyword, and reads back the same
+        encode_text(EncodeTextArgs {
+            file_path: file_path.to_string(),
+            keyword: "Title".to_string(),
+            text: "Café".to_string(),
+            compress: true,
+            lock: LockArgs::default(),
+            audit: AuditArgs::default(),
+        })
+        .unwrap();
+        let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        assert_eq!(png.chunks()[3].chunk_type().to_string(), "zTXt");
+        let title = png.texts().pop().unwrap().unwrap();
+        assert_eq!((title.text.as_str(), title.compressed), ("Café", true));
+
+        // Bad keywords are refused before the file is touched
+        assert!(encode("  ", "x").is_err());
+        let decode = |keyword: &str| {
+            decode_text(
+                DecodeTextArgs {
+                    file_path: file_path.to_string(),
+                    keyword: Some(keyword.to_string()),
+                },
+                Format::Plain,
+            )
+        };
+        assert!(decode("Title").is_ok());
+        let error = decode("Comment").unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::NotFound);
+    }
+
+    #[test]
+    fn test_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("image.png"), png_with("pixels", &[("ruSt", "hello")])).unwrap();
+        let mut bytes = fs::read(path("image.png")).unwrap();
+        let crc_at = bytes.len() - 12 - 4;
+        bytes[crc_at] ^= 0xff;
+        fs::write(path("image.png"), &bytes).unwrap();
+        let decode_image = || {
+            decode(DecodeArgs {
+                expect: None,
+                ..decode_args(&path("image.png"), "", Newline::Keep)
+            })
+        };
+        let error = decode_image().unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::Integrity);
+
+        let repair_args = |out_path: Option<String>| RepairArgs {
+            file_path: path("image.png"),
+            out_path,
+            drop_unrecoverable: false,
+            audit: AuditArgs::default(),
+            verify_image: false,
+            lock: LockArgs::default(),
+            print_hash: None,
+        };
+        repair(repair_args(Some(path("fixed.png"))), Format::Plain).unwrap();
+        assert_eq!(fs::read(path("image.png")).unwrap(), bytes);
+        repair(repair_args(None), Format::Plain).unwrap();
+        assert_eq!(
+            fs::read(path("image.png")).unwrap(),
+            fs::read(path("fixed.png")).unwrap()
+        );
+        assert_eq!(decode_image().unwrap(), b"hello");
+
+        fs::write(path("image.png"), &bytes).unwrap();
+        let audited = RepairArgs {
+            audit: AuditArgs {
+                audit: true,
+                audit_note: None,
+            },
+            ..repair_args(Some(path("audited.png")))
+        };
+        repair(audited, Format::Plain).unwrap();
+        let entries = audit::entries(&Png::from_file(path("audited.png")).unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].operation, "repair");
+
+        // The image data is made up, so the real decoder rejects the repaired file
+        #[cfg(feature = "image-verify")]
+        {
+            let verified = RepairArgs {
+                verify_image: true,
+                ..repair_args(Some(path("verified.png")))
+            };
+            assert!(repair(verified, Format::Plain).is_err());
+            assert!(!Path::new(&path("verified.png")).exists());
+        }
+    }
+
+    #[test]
+    fn test_remove_all_or_by_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let chunks = [
+            ("ruSt", "one"),
+            ("ruSt", "two"),
+            ("tEXt", "a\0b"),
+            ("ruSt", "three"),
+        ];
+        fs::write(&path, png_with("pixels", &chunks)).unwrap();
+        let remove_args = |all, index| RemoveArgs {
+            file_path: file_path.to_string(),
+            chunk_type: Some("ruSt".to_string()),
+            label: None,
+            chunk_type_hex: None,
+            force: false,
+            all,
+            index,
+            verify_image: false,
+            image_index: None,
+            lock: LockArgs::default(),
+            print_hash: None,
+            audit: AuditArgs::default(),
+            history: HistoryArgs {
+                keep_previous: false,
+                history_depth: 1,
+            },
+            batch: BatchArgs::default(),
+        };
+        let left = || {
+            Png::try_from(&fs::read(&path).unwrap()[..])
+                .unwrap()
+                .chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() == "ruSt")
+                .map(|c| c.data_as_string().unwrap())
+                .collect::<Vec<String>>()
+        };
+
+        remove(remove_args(false, Some(1))).unwrap();
+        assert_eq!(left(), ["one", "three"]);
+        let error = remove(remove_args(false, Some(2))).unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::NotFound);
+        assert!(error.to_string().contains("only 2"));
+
+        let summary = remove(remove_args(true, None)).unwrap();
+        assert_eq!(summary.chunks_removed, 2);
+        assert!(left().is_empty());
+        let png = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        assert!(png.chunk_by_type("tEXt").is_some());
+    }
+
+    #[test]
+    fn test_decode_every_chunk_of_a_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let file_path = path.to_str().unwrap();
+        let chunks = [("ruSt", "one"), ("ruSt", "two")];
+        let mut png = Png::try_from(&png_with("pixels", &chunks)[..]).unwrap();
+        // Each chunk is decoded on its own, whether or not it holds an envelope
+        let envelope = Envelope::new("three").as_bytes();
+        let at = png.chunks().len() - 1;
+        png.insert_chunk(
+            at,
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), envelope),
+        )
+        .unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        let all = decode_all(DecodeArgs {
+            all: true,
+            expect: None,
+            ..decode_args(file_path, "", Newline::Keep)
+        })
+        .unwrap();
+        let all: Vec<(Option<usize>, Vec<u8>)> = all
+            .into_iter()
+            .map(|(_, index, message)| (index, message))
+            .collect();
+        assert_eq!(
+            all,
+            [
+                (Some(0), b"one".to_vec()),
+                (Some(1), b"two".to_vec()),
+                (Some(2), b"three".to_vec())
+            ]
+        );
+
+        let error = decode_all(DecodeArgs {
+            all: true,
+            chunk_type: Some("noNe".to_string()),
+            expect: None,
+            ..decode_args(file_path, "", Newline::Keep)
+        })
+        .unwrap_err();
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::NotFound);
+    }
+
+    #[test]
+    fn test_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let shots = dir.path().join("shots");
+        fs::create_dir_all(shots.join("old")).unwrap();
+        for name in ["a.png", "b.png", "old/c.png"] {
+            fs::write(shots.join(name), minimal_png("pixels")).unwrap();
+        }
+        fs::write(shots.join("broken.png"), b"not a png").unwrap();
+        let shots = shots.to_str().unwrap().to_string();
+        let recursive = BatchArgs {
+            paths: vec![],
+            recursive: true,
+        };
+
+        // The broken file fails on its own, and the run as a whole
+        let error = encode_batch(
+            EncodeArgs {
+                batch: recursive,
+                ..encode_args(&shots, "hi")
+            },
+            Format::Plain,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Batch error: 1 of 4 file(s) failed");
+        assert_eq!(ExitCode::of(&Failure::of(&error)), ExitCode::Failure);
+        for name in ["a.png", "b.png", "old/c.png"] {
+            let path = dir.path().join("shots").join(name);
+            decode(decode_args(path.to_str().unwrap(), "hi", Newline::Keep)).unwrap();
+        }
+
+        // Globs and further paths, without the broken file
+        let nested = format!("{}/old/c.png", shots);
+        let listed = BatchArgs {
+            paths: vec![format!("{}/*.png", shots), nested.clone()],
+            recursive: false,
+        };
+        fs::remove_file(dir.path().join("shots/broken.png")).unwrap();
+        decode_batch(
+            DecodeArgs {
+                batch: listed.clone(),
+                ..decode_args("", "hi", Newline::Keep)
+            },
+            Format::Plain,
+        )
+        .unwrap();
+        assert!(decode_batch(
+            DecodeArgs {
+                all: true,
+                batch: listed.clone(),
+                ..decode_args("", "hi", Newline::Keep)
+            },
+            Format::Plain,
+        )
+        .is_err());
+        remove_batch(
+            RemoveArgs {
+                file_path: String::new(),
+                chunk_type: Some("ruSt".to_string()),
+                label: None,
+                chunk_type_hex: None,
+                force: false,
+                all: false,
+                index: None,
+                verify_image: false,
+                image_index: None,
+                lock: LockArgs::default(),
+                print_hash: None,
+                audit: AuditArgs::default(),
+                history: HistoryArgs::default(),
+                batch: listed,
+            },
+            Format::Plain,
+        )
+        .unwrap();
+        assert!(decode(decode_args(&nested, "hi", Newline::Keep)).is_err());
+    }
 }
@@ -0,0 +1,270 @@
+use clap::ValueEnum;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::output;
+use crate::profile::{self, Phase};
+
+/// A stored payload couldn't be decompressed.
+#[derive(Debug)]
+pub struct CompressError {
+    reason: String,
+}
+impl CompressError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compression error: {}", self.reason)
+    }
+}
+impl Error for CompressError {}
+
+/// The most a message is inflated to: far more than anyone stores in a png, but a small chunk
+/// crafted to inflate without end can't take all memory.
+pub const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+/// zstd level to compress with: well into the slow levels, as messages are small.
+const ZSTD_LEVEL: i32 = 19;
+
+/// What `--compress` should compress a message with.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub enum Algorithm {
+    #[default]
+    Deflate,
+    Zstd,
+    /// Store the message as is, as without --compress
+    None,
+}
+
+impl Algorithm {
+    /// The codec to try, or `None` when the message shouldn't be compressed at all.
+    pub fn codec(self) -> Option<Codec> {
+        match self {
+            Algorithm::Deflate => Some(Codec::Deflate),
+            Algorithm::Zstd => Some(Codec::Zstd),
+            Algorithm::None => None,
+        }
+    }
+}
+
+/// How a message compressed with `--compress` was actually stored.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Codec {
+    /// As is, because compressing didn't save enough
+    Stored,
+    /// zlib-wrapped deflate
+    Deflate,
+    /// A zstd frame
+    Zstd,
+}
+
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Stored => 0,
+            Codec::Deflate => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> crate::Result<Self> {
+        match id {
+            0 => Ok(Codec::Stored),
+            1 => Ok(Codec::Deflate),
+            2 => Ok(Codec::Zstd),
+            _ => Err(CompressError::boxed(format!("unknown codec {}", id))),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Stored => write!(f, "stored"),
+            Codec::Deflate => write!(f, "deflate"),
+            Codec::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// What compressing a message achieved.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Stats {
+    pub codec: Codec,
+    pub original: usize,
+    pub stored: usize,
+}
+
+impl Stats {
+    /// Stored size as a fraction of the original size.
+    pub fn ratio(&self) -> f64 {
+        match self.original {
+            0 => 1.0,
+            original => self.stored as f64 / original as f64,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} stored as {} ({}, ratio {:.2})",
+            output::size(self.original),
+            output::size(self.stored),
+            self.codec,
+            self.ratio()
+        )
+    }
+}
+
+/// Compresses `data` with `codec`, falling back to storing it as is unless compression saves
+/// at least `min_gain` percent.
+pub fn compress(data: &[u8], codec: Codec, min_gain: u8) -> crate::Result<(Vec<u8>, Stats)> {
+    let _timer = profile::scope(Phase::Codec);
+    let compressed = match codec {
+        Codec::Stored => data.to_vec(),
+        Codec::Deflate => {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Codec::Zstd => zstd::encode_all(data, ZSTD_LEVEL)?,
+    };
+    let saved = data.len().saturating_sub(compressed.len());
+    let helps = compressed.len() < data.len() && saved * 100 >= data.len() * min_gain as usize;
+    let (codec, out) = match helps {
+        true => (codec, compressed),
+        false => (Codec::Stored, data.to_vec()),
+    };
+    let stats = Stats {
+        codec,
+        original: data.len(),
+        stored: out.len(),
+    };
+    Ok((out, stats))
+}
+
+/// Reads `reader` to the end, failing once it gives more than `limit` bytes instead of
+/// reading on.
+pub fn read_limited(reader: impl Read, limit: usize) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    reader.take(limit as u64 + 1).read_to_end(&mut out)?;
+    if out.len() > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("it inflates to more than {}", output::size(limit)),
+        ));
+    }
+    Ok(out)
+}
+
+/// Reverses `compress` for data stored with `codec`.
+pub fn decompress(codec: Codec, data: &[u8]) -> crate::Result<Vec<u8>> {
+    let _timer = profile::scope(Phase::Codec);
+    decompress_limited(codec, data, MAX_DECOMPRESSED_LEN)
+        .map_err(|e| CompressError::boxed(e.to_string()).into())
+}
+
+/// Reads what `compress` stored with `codec` from `reader`, inflating it as it goes.
+pub fn decoder<'a>(codec: Codec, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match codec {
+        Codec::Stored => Box::new(reader),
+        Codec::Deflate => Box::new(ZlibDecoder::new(reader)),
+        Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+    })
+}
+
+/// Inflates `data` as a stream, stopping as soon as it gives more than `limit` bytes.
+fn decompress_limited(codec: Codec, data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    read_limited(decoder(codec, data)?, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressible_text_is_deflated() {
+        let text = "the quick brown fox jumps over the lazy dog\n".repeat(20);
+        let (stored, stats) = compress(text.as_bytes(), Codec::Deflate, 0).unwrap();
+        assert_eq!(stats.codec, Codec::Deflate);
+        assert_eq!(stats.original, 880);
+        assert_eq!(stats.stored, stored.len());
+        assert!(stats.ratio() < 0.2);
+        assert_eq!(decompress(stats.codec, &stored).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_random_blob_is_stored() {
+        let blob: [u8; 512] = crate::crypto::random_bytes().unwrap();
+        let (stored, stats) = compress(&blob, Codec::Deflate, 0).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                codec: Codec::Stored,
+                original: 512,
+                stored: 512
+            }
+        );
+        assert_eq!(stored, blob);
+        assert_eq!(
+            stats.to_string(),
+            "512 bytes stored as 512 bytes (stored, ratio 1.00)"
+        );
+    }
+
+    #[test]
+    fn test_min_gain() {
+        let text = "pngme ".repeat(10);
+        let (_, stats) = compress(text.as_bytes(), Codec::Deflate, 0).unwrap();
+        let gain = (stats.original - stats.stored) * 100 / stats.original;
+        let (_, stats) = compress(text.as_bytes(), Codec::Deflate, gain as u8).unwrap();
+        assert_eq!(stats.codec, Codec::Deflate);
+        let (_, stats) = compress(text.as_bytes(), Codec::Deflate, gain as u8 + 1).unwrap();
+        assert_eq!(stats.codec, Codec::Stored);
+    }
+
+    #[test]
+    fn test_zstd() {
+        let text = "the quick brown fox jumps over the lazy dog\n".repeat(20);
+        let (stored, stats) = compress(text.as_bytes(), Codec::Zstd, 0).unwrap();
+        assert_eq!(stats.codec, Codec::Zstd);
+        assert!(stats.ratio() < 0.2);
+        assert_eq!(decompress(Codec::Zstd, &stored).unwrap(), text.as_bytes());
+        assert!(decompress(Codec::Zstd, b"not a zstd frame").is_err());
+
+        // A frame that inflates past the limit is cut off rather than read whole
+        let (stored, _) = compress(&[0; 10_000], Codec::Zstd, 0).unwrap();
+        assert!(decompress_limited(Codec::Zstd, &stored, 10_000).is_ok());
+        let err = decompress_limited(Codec::Zstd, &stored, 9_999).unwrap_err();
+        assert!(err.to_string().contains("inflates to more than"), "{}", err);
+
+        let blob: [u8; 512] = crate::crypto::random_bytes().unwrap();
+        let (_, stats) = compress(&blob, Codec::Zstd, 0).unwrap();
+        assert_eq!(stats.codec, Codec::Stored);
+    }
+
+    #[test]
+    fn test_read_limited() {
+        let zeros = [0; 1000];
+        assert_eq!(read_limited(&zeros[..], 1000).unwrap().len(), 1000);
+        let err = read_limited(&zeros[..], 999).unwrap_err();
+        assert_eq!(err.to_string(), "it inflates to more than 999 bytes");
+    }
+
+    #[test]
+    fn test_unknown_codec() {
+        assert!(Codec::from_id(7).is_err());
+        assert_eq!(Codec::from_id(Codec::Deflate.id()).unwrap(), Codec::Deflate);
+        assert_eq!(Codec::from_id(Codec::Zstd.id()).unwrap(), Codec::Zstd);
+    }
+}
@@ -0,0 +1,302 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::profile::{self, Phase};
+use crate::secret::SecretBytes;
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 24;
+
+/// Encrypting or decrypting a payload failed.
+#[derive(Debug)]
+pub struct CryptoError {
+    reason: String,
+}
+impl CryptoError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Encryption error: {}", self.reason)
+    }
+}
+impl Error for CryptoError {}
+
+/// Where the key for encrypting a payload comes from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// A passphrase that is stretched into a key with Argon2id
+    Passphrase(SecretBytes),
+    /// A file holding exactly `KEY_LEN` bytes of raw key material, used as is
+    KeyFile(PathBuf),
+    /// `KEY_LEN` bytes of raw key material given directly
+    Key(SecretBytes),
+}
+
+/// The parameters stored next to a ciphertext. `salt` is only present when the key was derived
+/// from a passphrase.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Cipher {
+    pub salt: Option<[u8; SALT_LEN]>,
+    pub nonce: [u8; NONCE_LEN],
+}
+
+impl Cipher {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut out = self.nonce.to_vec();
+        if let Some(salt) = self.salt {
+            out.extend_from_slice(&salt);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        let (nonce, salt) = match bytes.len() {
+            NONCE_LEN => (bytes, None),
+            len if len == NONCE_LEN + SALT_LEN => {
+                let (nonce, salt) = bytes.split_at(NONCE_LEN);
+                (nonce, Some(salt.try_into()?))
+            }
+            len => {
+                return Err(CryptoError::boxed(format!(
+                    "cipher parameters are {} bytes, expected {} or {}",
+                    len,
+                    NONCE_LEN,
+                    NONCE_LEN + SALT_LEN
+                )))
+            }
+        };
+        Ok(Self {
+            salt,
+            nonce: nonce.try_into()?,
+        })
+    }
+}
+
+/// Fills `buf` with bytes from the operating system's secure random number generator.
+pub fn fill_random(buf: &mut [u8]) -> crate::Result<()> {
+    getrandom::fill(buf).map_err(|e| CryptoError::boxed(e.to_string()).into())
+}
+
+/// Where the random bytes for nonces, salts and padding come from.
+pub enum Entropy {
+    /// The operating system's secure random number generator
+    Os,
+    /// A stream derived from a seed, for `--deterministic`, so the same inputs always give the
+    /// same output. Only as unpredictable as the seed.
+    Seeded(Box<blake3::OutputReader>),
+}
+
+impl Entropy {
+    /// A stream derived from `parts`, each hashed along with its length so that moving bytes
+    /// from one part to the next gives a different stream.
+    pub fn seeded(parts: &[&[u8]]) -> Self {
+        let mut hasher = blake3::Hasher::new_derive_key("pngme deterministic output v1");
+        for part in parts {
+            hasher.update(&(part.len() as u64).to_be_bytes());
+            hasher.update(part);
+        }
+        Entropy::Seeded(Box::new(hasher.finalize_xof()))
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) -> crate::Result<()> {
+        match self {
+            Entropy::Os => fill_random(buf),
+            Entropy::Seeded(reader) => {
+                reader.fill(buf);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn bytes<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.fill(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// An array of bytes from the operating system's secure random number generator.
+pub fn random_bytes<const N: usize>() -> crate::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    fill_random(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads a raw key, which must be exactly `KEY_LEN` bytes long.
+pub fn read_key_file(path: &Path) -> crate::Result<SecretBytes> {
+    let key = SecretBytes::from(fs::read(path)?);
+    if key.len() != KEY_LEN {
+        return Err(CryptoError::boxed(format!(
+            "key file {} is {} bytes, expected {}",
+            path.display(),
+            key.len(),
+            KEY_LEN
+        )));
+    }
+    Ok(key)
+}
+
+/// Parses `KEY_LEN` bytes of key material written as hex. The error never includes the input.
+pub fn key_from_hex(hex: &SecretBytes) -> crate::Result<SecretBytes> {
+    let hex = hex.expose().trim_ascii();
+    if hex.len() != KEY_LEN * 2 || !hex.iter().all(|b| b.is_ascii_hexdigit()) {
+        return Err(CryptoError::boxed(format!(
+            "a hex key must be {} hex digits ({} characters were given)",
+            KEY_LEN * 2,
+            hex.len()
+        )));
+    }
+    let digit = |b: u8| (b as char).to_digit(16).unwrap_or(0) as u8;
+    Ok(SecretBytes::from(
+        hex.chunks(2)
+            .map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+            .collect::<Vec<u8>>(),
+    ))
+}
+
+fn derive_key(passphrase: &SecretBytes, salt: &[u8; SALT_LEN]) -> crate::Result<SecretBytes> {
+    let mut key = SecretBytes::from(vec![0; KEY_LEN]);
+    Argon2::default()
+        .hash_password_into(passphrase.expose(), salt, key.expose_mut())
+        .map_err(|e| CryptoError::boxed(e.to_string()))?;
+    Ok(key)
+}
+
+/// The key from `source`, checking it is the kind of key the payload was encrypted with: a
+/// passphrase when there is a `salt`, a key file otherwise.
+fn key_for(source: &KeySource, salt: Option<&[u8; SALT_LEN]>) -> crate::Result<SecretBytes> {
+    match (source, salt) {
+        (KeySource::Passphrase(passphrase), Some(salt)) => derive_key(passphrase, salt),
+        (KeySource::KeyFile(path), None) => read_key_file(path),
+        (KeySource::Key(key), None) => Ok(key.clone()),
+        (KeySource::Passphrase(_), None) => Err(CryptoError::boxed(
+            "the payload was encrypted with a raw key, not a passphrase".to_string(),
+        )),
+        (KeySource::KeyFile(_) | KeySource::Key(_), Some(_)) => Err(CryptoError::boxed(
+            "the payload was encrypted with a passphrase, not a raw key".to_string(),
+        )),
+    }
+}
+
+fn aead(key: &SecretBytes) -> crate::Result<XChaCha20Poly1305> {
+    XChaCha20Poly1305::new_from_slice(key.expose()).map_err(|_| {
+        CryptoError::boxed(format!("keys must be {} bytes, got {}", KEY_LEN, key.len())).into()
+    })
+}
+
+/// Encrypts `plaintext` with XChaCha20-Poly1305 under a fresh nonce (and salt, for passphrases)
+/// taken from `entropy`.
+pub fn encrypt(
+    source: &KeySource,
+    plaintext: &[u8],
+    entropy: &mut Entropy,
+) -> crate::Result<(Cipher, Vec<u8>)> {
+    let _timer = profile::scope(Phase::Codec);
+    let cipher = Cipher {
+        salt: match source {
+            KeySource::Passphrase(_) => Some(entropy.bytes()?),
+            KeySource::KeyFile(_) | KeySource::Key(_) => None,
+        },
+        nonce: entropy.bytes()?,
+    };
+    let key = key_for(source, cipher.salt.as_ref())?;
+    let ciphertext = aead(&key)?
+        .encrypt(XNonce::from_slice(&cipher.nonce), plaintext)
+        .map_err(|_| CryptoError::boxed("encryption failed".to_string()))?;
+    Ok((cipher, ciphertext))
+}
+
+/// Decrypts and authenticates a payload written by `encrypt`.
+pub fn decrypt(source: &KeySource, cipher: &Cipher, ciphertext: &[u8]) -> crate::Result<Vec<u8>> {
+    let _timer = profile::scope(Phase::Codec);
+    let key = key_for(source, cipher.salt.as_ref())?;
+    aead(&key)?
+        .decrypt(XNonce::from_slice(&cipher.nonce), ciphertext)
+        .map_err(|_| {
+            CryptoError::boxed(
+                "wrong key or passphrase, or the payload has been modified".to_string(),
+            )
+            .into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passphrase_round_trip() {
+        let source = KeySource::Passphrase("correct horse".into());
+        let (cipher, ciphertext) = encrypt(&source, b"secret", &mut Entropy::Os).unwrap();
+        assert!(cipher.salt.is_some());
+        assert_ne!(ciphertext, b"secret");
+        assert_eq!(decrypt(&source, &cipher, &ciphertext).unwrap(), b"secret");
+
+        let wrong = KeySource::Passphrase("battery staple".into());
+        assert!(decrypt(&wrong, &cipher, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.bin");
+        fs::write(&path, [7; KEY_LEN]).unwrap();
+        let source = KeySource::KeyFile(path);
+        let (cipher, ciphertext) = encrypt(&source, b"secret", &mut Entropy::Os).unwrap();
+        assert!(cipher.salt.is_none());
+        assert_eq!(decrypt(&source, &cipher, &ciphertext).unwrap(), b"secret");
+
+        let passphrase = KeySource::Passphrase("guess".into());
+        assert!(decrypt(&passphrase, &cipher, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.bin");
+        fs::write(&path, [7; 31]).unwrap();
+        let err = read_key_file(&path).unwrap_err();
+        assert!(err.to_string().contains("is 31 bytes, expected 32"));
+    }
+
+    #[test]
+    fn test_key_from_hex() {
+        let hex = "00ff".repeat(KEY_LEN / 2);
+        let key = key_from_hex(&hex.as_str().into()).unwrap();
+        assert_eq!(key.expose()[..2], [0x00, 0xff]);
+        assert!(key_from_hex(&hex[2..].into()).is_err());
+        let bad = "zz".repeat(KEY_LEN);
+        let err = key_from_hex(&bad.as_str().into()).unwrap_err();
+        assert!(!err.to_string().contains("zz"));
+    }
+
+    #[test]
+    fn test_tampered_ciphertext_fails() {
+        let source = KeySource::Passphrase("pass".into());
+        let (cipher, mut ciphertext) = encrypt(&source, b"secret", &mut Entropy::Os).unwrap();
+        ciphertext[0] ^= 1;
+        assert!(decrypt(&source, &cipher, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_cipher_parameters_round_trip() {
+        for salt in [None, Some([3; SALT_LEN])] {
+            let cipher = Cipher {
+                salt,
+                nonce: [9; NONCE_LEN],
+            };
+            assert_eq!(Cipher::from_bytes(&cipher.as_bytes()).unwrap(), cipher);
+        }
+        assert!(Cipher::from_bytes(&[0; 10]).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// How serious a `Diagnostic` is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// The class of problem a `Diagnostic` describes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiagnosticKind {
+    /// The file does not start with the PNG signature.
+    BadSignature,
+    /// A chunk declares more data than the file contains.
+    LengthMismatch,
+    /// A chunk has an invalid type code.
+    BadChunkType,
+    /// A chunk's stored CRC does not match its contents.
+    BadCrc,
+    /// Bytes that are not part of any chunk follow IEND.
+    TrailingData,
+    /// Chunks appear in an order the PNG spec does not allow.
+    Ordering,
+    /// IHDR, IDAT or IEND, which every PNG must have, isn't there.
+    MissingChunk,
+    /// The file is an Apple CgBI-optimized PNG that standard viewers may not render.
+    AppleCgbi,
+    /// A public chunk is marked critical but isn't one the PNG spec defines, so decoders must
+    /// reject the file.
+    UnknownCritical,
+    /// A private chunk is marked critical, which only its own application can decode.
+    PrivateCritical,
+    /// An ancillary chunk the spec places before PLTE or IDAT comes after it.
+    AncillaryOrdering,
+    /// IEND holds data, where the spec requires it to be empty.
+    IendData,
+    /// A decoded message is past the expiry it was stored with.
+    Expired,
+    /// Fewer copies of a message were found than it was stored with.
+    MissingCopies,
+    /// A copy of a message couldn't be read and was outvoted by the others.
+    CorruptCopy,
+    /// A message was skipped because it couldn't be decoded, as by `decode --all`.
+    Undecodable,
+    /// The key was taken from an environment variable rather than given explicitly.
+    KeyFromEnvironment,
+    /// A passphrase couldn't be stored in the keyring.
+    Keyring,
+    /// An ancillary chunk wasn't carried over to another image because it isn't marked safe
+    /// to copy.
+    UnsafeToCopy,
+    /// A damaged message was read anyway, repaired by error correction or outvoted by its
+    /// redundant copies.
+    Recovered,
+    /// An sPLT or hIST chunk's data doesn't have the layout the spec gives it.
+    BadPalette,
+    /// hIST doesn't hold a frequency for each PLTE entry, or there is no PLTE for it to match.
+    HistogramMismatch,
+    /// Two sPLT chunks share a name, which the spec requires to be unique.
+    DuplicatePaletteName,
+    /// tRNS can't be read for the image's color type, is longer than PLTE, or is in an image
+    /// that has an alpha channel already.
+    BadTransparency,
+    /// bKGD can't be read for the image's color type.
+    BadBackground,
+    /// An oFFs, sCAL or sTER chunk's data doesn't have the layout the extensions spec gives it.
+    BadExtension,
+    /// A problem a command ran into that has no kind of its own, such as a file scan couldn't
+    /// read.
+    Other,
+}
+
+/// A single problem found while parsing or checking a PNG.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    /// Index of the chunk the problem belongs to, if any
+    pub chunk_index: Option<usize>,
+    /// Byte offset into the file where the problem was found, if known
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(kind: DiagnosticKind, message: String) -> Self {
+        Self::new(Severity::Warning, kind, message)
+    }
+
+    pub fn error(kind: DiagnosticKind, message: String) -> Self {
+        Self::new(Severity::Error, kind, message)
+    }
+
+    /// A warning when parsing leniently, an error otherwise.
+    pub fn recoverable(lenient: bool, kind: DiagnosticKind, message: String) -> Self {
+        if lenient {
+            Self::warning(kind, message)
+        } else {
+            Self::error(kind, message)
+        }
+    }
+
+    fn new(severity: Severity, kind: DiagnosticKind, message: String) -> Self {
+        Self {
+            severity,
+            kind,
+            chunk_index: None,
+            offset: None,
+            message,
+        }
+    }
+
+    /// Attaches the chunk index this diagnostic refers to.
+    pub fn chunk(mut self, index: usize) -> Self {
+        self.chunk_index = Some(index);
+        self
+    }
+
+    /// Attaches the byte offset this diagnostic refers to.
+    pub fn at(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// Receives the problems an operation ran into that didn't stop it. The command line prints
+/// them or adds them to the --json document, while a `Vec<Diagnostic>` collects them.
+pub trait Warnings {
+    fn warn(&mut self, warning: Diagnostic);
+}
+
+impl Warnings for Vec<Diagnostic> {
+    fn warn(&mut self, warning: Diagnostic) {
+        self.push(warning);
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+        match (self.chunk_index, self.offset) {
+            (Some(index), Some(offset)) => write!(f, " (chunk {}, offset {:#x})", index, offset),
+            (Some(index), None) => write!(f, " (chunk {})", index),
+            (None, Some(offset)) => write!(f, " (offset {:#x})", offset),
+            (None, None) => Ok(()),
+        }
+    }
+}
+impl Error for Diagnostic {}
@@ -0,0 +1,65 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// A hash function to fingerprint written files with.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+/// The digest of the bytes a command wrote to `path`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub algorithm: HashAlgorithm,
+    pub hex: String,
+    pub path: String,
+}
+
+impl FileDigest {
+    pub fn of(algorithm: HashAlgorithm, bytes: &[u8], path: &str) -> Self {
+        let hex = match algorithm {
+            HashAlgorithm::Sha256 => to_hex(&Sha256::digest(bytes)),
+            HashAlgorithm::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+        };
+        Self {
+            algorithm,
+            hex,
+            path: path.to_string(),
+        }
+    }
+}
+
+/// The line sha256sum and b3sum print, so their `--check` can read it.
+impl fmt::Display for FileDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {}", self.hex, self.path)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let sha = FileDigest::of(HashAlgorithm::Sha256, b"abc", "a.png");
+        assert_eq!(
+            sha.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  a.png"
+        );
+        let blake = FileDigest::of(HashAlgorithm::Blake3, b"abc", "a.png");
+        assert_eq!(
+            blake.hex,
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+
+use crate::args::Command;
+use crate::commands;
+use crate::diagnostic::Diagnostic;
+use crate::error::Report;
+use crate::layout::Region;
+use crate::output::Format;
+use crate::profile::{self, PhaseTiming};
+use crate::repair::Fix;
+use crate::summary::MutationSummary;
+
+/// Bumped whenever a field is renamed or removed, or its meaning changes. New fields may be
+/// added without a bump.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Everything a command run with `--json` prints, as a single document on stdout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document<R = Value> {
+    /// The command that was run, such as `encode`
+    pub command: String,
+    /// The `SCHEMA_VERSION` the document follows
+    pub version: u32,
+    /// The files the command worked on
+    pub files: Vec<String>,
+    pub ok: bool,
+    /// What the command found or did, shaped by the command. Failed commands may still have
+    /// one, such as check listing the problems it failed on.
+    pub result: Option<R>,
+    /// Problems that didn't stop the command, which it would otherwise print on stderr
+    pub warnings: Vec<Diagnostic>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Report>,
+    /// Time spent in each phase, with `--profile`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Vec<PhaseTiming>>,
+}
+
+/// A chunk as listed by print.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub index: usize,
+    pub chunk_type: String,
+    pub critical: bool,
+    pub public: bool,
+    pub reserved_bit_valid: bool,
+    pub safe_to_copy: bool,
+    pub length: u32,
+    /// The stored CRC, as 8 hex digits
+    pub crc: String,
+    /// The CRC the chunk's type and data call for, as 8 hex digits
+    pub computed_crc: String,
+    pub crc_ok: bool,
+    /// Where the chunk starts in the file, at its length field
+    pub offset: usize,
+    pub data_base64: String,
+}
+
+/// What check found.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CheckResult {
+    /// How many png images are concatenated in the file
+    pub images: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A decoded message. Bytes that aren't UTF-8 are replaced with U+FFFD.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    /// The chunk the message came from, given for `decode --all`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_type: Option<String>,
+    /// Position of the chunk among chunks of its type, given for `decode --all --chunk-type`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// The message as text, with bytes that aren't UTF-8 replaced
+    pub message: String,
+    /// The exact bytes of the message
+    pub message_base64: String,
+    /// The type of payload the message looks like
+    pub mime: String,
+}
+
+/// A file in a payload packed with `--payload-dir`, or the single file a plain payload was
+/// extracted to.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveFile {
+    pub name: String,
+    pub size: usize,
+    pub mode: u32,
+}
+
+/// A frame written by `frames extract`, with the region of the canvas it covers.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExtractedFrame {
+    pub index: usize,
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub default_image: bool,
+}
+
+/// What repair changed, and what that did to the file.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RepairResult {
+    pub fixes: Vec<Fix>,
+    #[serde(flatten)]
+    pub summary: MutationSummary,
+}
+
+/// A file given a shard of the message by encode --carriers, and what storing it changed.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PlacedShard {
+    pub path: String,
+    /// Which shard the file holds, counting from 0
+    pub index: u16,
+    pub total: u16,
+    #[serde(flatten)]
+    pub summary: MutationSummary,
+}
+
+/// One file of a batch run of encode, decode or remove: what the command did with it, or why
+/// it failed.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BatchFile {
+    pub path: String,
+    pub ok: bool,
+    /// What encode or remove changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<MutationSummary>,
+    /// What decode found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A png signature found by find-png.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub offset: usize,
+    /// How many chunks the png starting there has, if one does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<usize>,
+    /// Why no png could be read there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What at-offset found at a byte offset.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ByteLocation {
+    pub offset: usize,
+    pub region: Region,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_type: Option<String>,
+    /// How far into the chunk the offset is, counting from its length field, or into the
+    /// signature or trailing data
+    pub relative: usize,
+    /// How far into the region the offset is, such as the byte of the chunk's data
+    pub region_offset: usize,
+}
+
+/// The ancillary chunk changes diff found, one line per change as diff prints them.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DiffResult {
+    pub changes: Vec<String>,
+}
+
+/// What the running command has handed over so far.
+#[derive(Default)]
+struct Collected {
+    result: Option<Value>,
+    warnings: Vec<Diagnostic>,
+}
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Collected>> = const { RefCell::new(None) };
+}
+
+/// Runs `command` with its result and warnings collected into a document, which is returned
+/// along with the exit code.
+pub fn run(command: Command) -> (Document, u8) {
+    let name = command.name();
+    let files: Vec<String> = command
+        .file_path()
+        .map(str::to_string)
+        .into_iter()
+        .collect();
+    COLLECTED.set(Some(Collected::default()));
+    let outcome = commands::run(command, Format::Json);
+    let collected = COLLECTED.take().unwrap_or_default();
+    let error = outcome
+        .err()
+        .map(|e| Report::new(&e, files.first().map(String::as_str)));
+    let exit_code = error.as_ref().map_or(0, |report| report.exit_code);
+    let document = Document {
+        command: name.to_string(),
+        version: SCHEMA_VERSION,
+        files,
+        ok: error.is_none(),
+        result: collected.result,
+        warnings: collected.warnings,
+        error,
+        profile: profile::is_enabled().then(profile::take),
+    };
+    (document, exit_code)
+}
+
+/// Hands over the result of a command run with `--format json`. Outside of `run`, as when a
+/// command is called directly, it is printed on its own instead.
+pub fn emit<T: Serialize>(result: &T) -> crate::Result<()> {
+    let value = serde_json::to_value(result)?;
+    let printed = COLLECTED.with_borrow_mut(|collected| match collected {
+        Some(collected) => {
+            collected.result = Some(value.clone());
+            false
+        }
+        None => true,
+    });
+    if printed {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    }
+    Ok(())
+}
+
+/// Whether a document is being collected, in which case commands print nothing themselves.
+pub fn collecting() -> bool {
+    COLLECTED.with_borrow(Option::is_some)
+}
+
+/// Adds `warning` to the document being collected. Returns false when there is none, for the
+/// caller to print it instead.
+pub fn warn(warning: &Diagnostic) -> bool {
+    COLLECTED.with_borrow_mut(|collected| match collected {
+        Some(collected) => {
+            collected.warnings.push(warning.clone());
+            true
+        }
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Cli;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use crate::scan::CorpusReport;
+    use crate::summary::MutationSummary;
+    use clap::Parser;
+    use serde::de::DeserializeOwned;
+    use std::fs;
+    use std::str::FromStr;
+
+    /// Runs pngme with `args` and `--json`, reading the printed document back as `R` results.
+    fn run_json<R: DeserializeOwned>(args: &[&str]) -> (Document<R>, u8) {
+        let cli = Cli::try_parse_from(["pngme"].iter().chain(args).chain(&["--json"])).unwrap();
+        let (document, exit_code) = run(cli.command);
+        let printed = serde_json::to_string_pretty(&document).unwrap();
+        (serde_json::from_str(&printed).unwrap(), exit_code)
+    }
+
+    fn png(dir: &std::path::Path, chunks: &[(&str, &[u8])]) -> String {
+        let path = dir.join("image.png");
+        let chunks = chunks
+            .iter()
+            .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec()))
+            .collect();
+        fs::write(&path, Png::from_chunks(chunks).as_bytes()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_mutations_and_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = &png(dir.path(), &[("IHDR", b"header"), ("IEND", b"")]);
+
+        let (encoded, code) =
+            run_json::<MutationSummary>(&["encode", "-f", file, "-c", "ruSt", "-m", "hi"]);
+        assert_eq!((code, encoded.ok), (0, true));
+        assert_eq!(encoded.command, "encode");
+        assert_eq!(encoded.version, SCHEMA_VERSION);
+        assert_eq!(encoded.files, [file.to_string()]);
+        assert_eq!(encoded.result.unwrap().chunks_added, 1);
+
+        let (decoded, _) = run_json::<Message>(&["decode", "-f", file, "-c", "ruSt"]);
+        let message = decoded.result.unwrap();
+        assert_eq!(
+            (message.message.as_str(), message.mime.as_str()),
+            ("hi", "text/plain")
+        );
+
+        let (removed, _) = run_json::<MutationSummary>(&["remove", "-f", file, "-c", "ruSt"]);
+        assert_eq!(removed.result.unwrap().chunks_removed, 1);
+
+        let (missing, code) = run_json::<Message>(&["decode", "-f", file, "-c", "ruSt"]);
+        assert_eq!((code, missing.ok), (4, false));
+        assert!(missing.result.is_none());
+        assert_eq!(missing.error.unwrap().kind, "NotFound");
+    }
+
+    #[test]
+    fn test_inspections() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = &png(
+            dir.path(),
+            &[
+                ("IHDR", b"header"),
+                ("IDAT", b"data"),
+                ("gAMA", b"late"),
+                ("IEND", b""),
+            ],
+        );
+
+        let (printed, _) = run_json::<Vec<ChunkInfo>>(&["print", "-f", file]);
+        let types: Vec<String> = printed
+            .result
+            .unwrap()
+            .into_iter()
+            .map(|c| c.chunk_type)
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "gAMA", "IEND"]);
+
+        let (checked, code) = run_json::<CheckResult>(&["check", "-f", file]);
+        assert_eq!((code, checked.ok), (0, true));
+        assert_eq!(checked.result.unwrap().images, 1);
+        let (checked, code) = run_json::<CheckResult>(&["check", "-f", file, "--strict"]);
+        assert_eq!((code, checked.ok), (3, false));
+        assert_eq!(checked.result.unwrap().diagnostics.len(), 1);
+
+        let (scanned, _) = run_json::<CorpusReport>(&["scan", "-f", file]);
+        assert_eq!(scanned.result.unwrap().png_files, 1);
+    }
+
+    #[test]
+    fn test_text_only_commands_refuse() {
+        let (document, code) = run_json::<Value>(&["man", "encode"]);
+        assert_eq!((code, document.ok), (1, false));
+        assert!(document.error.unwrap().message.contains("no JSON output"));
+    }
+}
@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::profile::{self, Phase};
+
+/// Largest number of bytes, data and parity together, in one block.
+pub const BLOCK_LEN: usize = 255;
+
+/// exp and log tables for GF(2^8) with the primitive polynomial x^8 + x^4 + x^3 + x^2 + 1.
+const TABLES: ([u8; 512], [u8; 256]) = build_tables();
+
+const fn build_tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    (exp, log)
+}
+
+/// The payload had more corrupted bytes than its parity can correct.
+#[derive(Debug)]
+pub struct EccError {
+    reason: String,
+}
+impl EccError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for EccError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error correction failed: {}", self.reason)
+    }
+}
+impl Error for EccError {}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    TABLES.0[TABLES.1[a as usize] as usize + TABLES.1[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    TABLES.0[(TABLES.1[a as usize] as usize + 255 - TABLES.1[b as usize] as usize) % 255]
+}
+
+/// 2 raised to `power`, which may be negative.
+fn alpha_pow(power: i32) -> u8 {
+    TABLES.0[power.rem_euclid(255) as usize]
+}
+
+fn inverse(x: u8) -> u8 {
+    TABLES.0[255 - TABLES.1[x as usize] as usize]
+}
+
+// Polynomials are stored with the highest degree coefficient first.
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut r = vec![0; len];
+    for (i, &c) in p.iter().enumerate() {
+        r[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        r[i + len - q.len()] ^= c;
+    }
+    r
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0; p.len() + q.len() - 1];
+    for (j, &qc) in q.iter().enumerate() {
+        for (i, &pc) in p.iter().enumerate() {
+            r[i + j] ^= mul(pc, qc);
+        }
+    }
+    r
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().skip(1).fold(p[0], |y, &c| mul(y, x) ^ c)
+}
+
+fn generator(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |g, i| poly_mul(&g, &[1, alpha_pow(i as i32)]))
+}
+
+fn encode_block(data: &[u8], generator: &[u8]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    out.resize(data.len() + generator.len() - 1, 0);
+    for i in 0..data.len() {
+        let coef = out[i];
+        if coef != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                out[i + j] ^= mul(g, coef);
+            }
+        }
+    }
+    out[..data.len()].copy_from_slice(data);
+    out
+}
+
+/// Syndromes of `block`, with a leading zero so indices line up with the locator search.
+fn syndromes(block: &[u8], parity: usize) -> Vec<u8> {
+    std::iter::once(0)
+        .chain((0..parity).map(|i| poly_eval(block, alpha_pow(i as i32))))
+        .collect()
+}
+
+/// Berlekamp-Massey: the error locator polynomial for the given syndromes.
+fn error_locator(synd: &[u8], parity: usize) -> Option<Vec<u8>> {
+    let mut err_loc = vec![1];
+    let mut old_loc = vec![1];
+    let shift = synd.len() - parity;
+    for i in 0..parity {
+        let k = i + shift;
+        let mut delta = synd[k];
+        for j in 1..err_loc.len() {
+            delta ^= mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+        }
+        old_loc.push(0);
+        if delta != 0 {
+            if old_loc.len() > err_loc.len() {
+                let new_loc = poly_scale(&old_loc, delta);
+                old_loc = poly_scale(&err_loc, inverse(delta));
+                err_loc = new_loc;
+            }
+            err_loc = poly_add(&err_loc, &poly_scale(&old_loc, delta));
+        }
+    }
+    let leading_zeros = err_loc.iter().take_while(|&&c| c == 0).count();
+    err_loc.drain(..leading_zeros);
+    let errors = err_loc.len().checked_sub(1)?;
+    (errors * 2 <= parity).then_some(err_loc)
+}
+
+/// Chien search: positions in a block of `len` bytes where the locator has roots.
+fn error_positions(err_loc: &[u8], len: usize) -> Option<Vec<usize>> {
+    let reversed: Vec<u8> = err_loc.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..len)
+        .filter(|&i| poly_eval(&reversed, alpha_pow(i as i32)) == 0)
+        .map(|i| len - 1 - i)
+        .collect();
+    (positions.len() == err_loc.len() - 1).then_some(positions)
+}
+
+/// Forney: fixes the bytes at `positions` in place.
+fn correct_errata(block: &mut [u8], synd: &[u8], positions: &[usize]) -> Option<()> {
+    let coef_pos: Vec<usize> = positions.iter().map(|p| block.len() - 1 - p).collect();
+    let err_loc = coef_pos.iter().fold(vec![1], |loc, &i| {
+        poly_mul(&loc, &poly_add(&[1], &[alpha_pow(i as i32), 0]))
+    });
+    let synd_rev: Vec<u8> = synd.iter().rev().copied().collect();
+    let product = poly_mul(&synd_rev, &err_loc);
+    let keep = err_loc.len().min(product.len());
+    let err_eval: Vec<u8> = product[product.len() - keep..].to_vec();
+
+    let x: Vec<u8> = coef_pos
+        .iter()
+        .map(|&p| alpha_pow(-(255 - p as i32)))
+        .collect();
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = inverse(xi);
+        let err_loc_prime = x
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, &xj)| mul(acc, 1 ^ mul(xi_inv, xj)));
+        if err_loc_prime == 0 {
+            return None;
+        }
+        let y = mul(xi, poly_eval(&err_eval, xi_inv));
+        block[positions[i]] ^= div(y, err_loc_prime);
+    }
+    Some(())
+}
+
+/// Corrects `block` in place, returning how many bytes were changed.
+fn correct_block(block: &mut [u8], parity: usize) -> Option<usize> {
+    let synd = syndromes(block, parity);
+    if synd.iter().all(|&s| s == 0) {
+        return Some(0);
+    }
+    let err_loc = error_locator(&synd, parity)?;
+    let positions = error_positions(&err_loc, block.len())?;
+    correct_errata(block, &synd, &positions)?;
+    syndromes(block, parity)
+        .iter()
+        .all(|&s| s == 0)
+        .then_some(positions.len())
+}
+
+/// Reed-Solomon encodes `data`: it is split into blocks of at most `255 - parity` bytes, each
+/// followed by `parity` parity bytes, so up to `parity / 2` corrupted bytes per block can later be
+/// corrected by `decode`.
+pub fn encode(data: &[u8], parity: u8) -> Vec<u8> {
+    let _timer = profile::scope(Phase::Codec);
+    let generator = generator(parity as usize);
+    data.chunks(BLOCK_LEN - parity as usize)
+        .flat_map(|block| encode_block(block, &generator))
+        .collect()
+}
+
+/// Strips the parity written by `encode`, correcting corrupted bytes on the way. Returns the
+/// data and the number of bytes that were corrected.
+pub fn decode(encoded: &[u8], parity: u8) -> crate::Result<(Vec<u8>, usize)> {
+    let _timer = profile::scope(Phase::Codec);
+    let parity = parity as usize;
+    let blocks = encoded.len().div_ceil(BLOCK_LEN);
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut corrections = 0;
+    for (idx, block) in encoded.chunks(BLOCK_LEN).enumerate() {
+        if block.len() <= parity {
+            return Err(EccError::boxed(format!(
+                "block {} of {} is only {} bytes, shorter than its {} parity bytes",
+                idx + 1,
+                blocks,
+                block.len(),
+                parity
+            )));
+        }
+        let mut block = block.to_vec();
+        corrections += correct_block(&mut block, parity).ok_or_else(|| {
+            EccError::boxed(format!(
+                "block {} of {} has more than {} corrupted byte(s), the most {} parity bytes can correct",
+                idx + 1,
+                blocks,
+                parity / 2,
+                parity
+            ))
+        })?;
+        data.extend_from_slice(&block[..block.len() - parity]);
+    }
+    Ok((data, corrections))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for len in [0, 1, 100, 245, 246, 1000] {
+            let encoded = encode(&data(len), 10);
+            assert_eq!(decode(&encoded, 10).unwrap(), (data(len), 0));
+        }
+    }
+
+    #[test]
+    fn test_corrects_errors_in_every_block() {
+        let mut encoded = encode(&data(600), 8);
+        // Four flips per block, in data and parity alike
+        for block_start in (0..encoded.len()).step_by(BLOCK_LEN) {
+            for offset in [0, 17, 100, 60] {
+                if let Some(b) = encoded.get_mut(block_start + offset) {
+                    *b ^= 0x5a;
+                }
+            }
+        }
+        let (decoded, corrections) = decode(&encoded, 8).unwrap();
+        assert_eq!(decoded, data(600));
+        assert_eq!(corrections, 12);
+    }
+
+    #[test]
+    fn test_corrects_parity_bytes() {
+        let mut encoded = encode(&data(20), 4);
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xff;
+        assert_eq!(decode(&encoded, 4).unwrap(), (data(20), 1));
+    }
+
+    #[test]
+    fn test_too_many_errors_is_an_error() {
+        let mut encoded = encode(&data(50), 4);
+        for b in encoded.iter_mut().take(10) {
+            *b ^= 0xff;
+        }
+        let result = decode(&encoded, 4);
+        assert!(result.is_err() || result.unwrap().0 != data(50));
+    }
+
+    #[test]
+    fn test_truncated_block_is_an_error() {
+        let encoded = encode(&data(10), 8);
+        assert!(decode(&encoded[..5], 8).is_err());
+    }
+}
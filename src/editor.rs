@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::process;
+
+/// Used when neither VISUAL nor EDITOR is set.
+#[cfg(windows)]
+const FALLBACK_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const FALLBACK_EDITOR: &str = "vi";
+
+/// The editor couldn't be started, or exited unsuccessfully.
+#[derive(Debug)]
+pub struct EditorError {
+    reason: String,
+}
+impl EditorError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+impl Error for EditorError {}
+
+/// Lets the user change a file. The editor process is swapped for a scripted edit in tests.
+pub trait Editor {
+    /// Opens `path` and returns once the user is done with it. Fails if the editor can't be
+    /// started or exits unsuccessfully.
+    fn edit(&mut self, path: &Path) -> crate::Result<()>;
+}
+
+/// Runs the editor named by VISUAL or EDITOR, falling back to vi, or notepad on Windows.
+pub struct SystemEditor;
+
+impl Editor for SystemEditor {
+    fn edit(&mut self, path: &Path) -> crate::Result<()> {
+        let command = editor_command(std::env::var("VISUAL").ok(), std::env::var("EDITOR").ok());
+        let (program, args) = command.split_first().expect("the command is never empty");
+        let status = process::Command::new(program)
+            .args(args)
+            .arg(path)
+            .status()
+            .map_err(|e| {
+                EditorError::boxed(format!("Couldn't start the editor {}: {}", program, e))
+            })?;
+        if !status.success() {
+            return Err(EditorError::boxed(format!(
+                "The editor {} exited with {}",
+                program, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The program and arguments to edit a file with. The variables may hold arguments too, as in
+/// `EDITOR="code --wait"`.
+fn editor_command(visual: Option<String>, editor: Option<String>) -> Vec<String> {
+    [visual, editor]
+        .into_iter()
+        .flatten()
+        .map(|var| {
+            var.split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .find(|command| !command.is_empty())
+        .unwrap_or_else(|| vec![FALLBACK_EDITOR.to_string()])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Replaces the file's contents with `content`, leaves it alone if that is `None`, or
+    /// fails like an editor exiting with an error when `fail` is set.
+    pub(crate) struct ScriptedEditor {
+        pub(crate) content: Option<Vec<u8>>,
+        pub(crate) fail: bool,
+        pub(crate) opened: Option<Vec<u8>>,
+    }
+
+    impl ScriptedEditor {
+        pub(crate) fn writing(content: &str) -> Self {
+            Self {
+                content: Some(content.as_bytes().to_vec()),
+                fail: false,
+                opened: None,
+            }
+        }
+    }
+
+    impl Editor for ScriptedEditor {
+        fn edit(&mut self, path: &Path) -> crate::Result<()> {
+            self.opened = Some(fs::read(path)?);
+            if let Some(content) = &self.content {
+                fs::write(path, content)?;
+            }
+            match self.fail {
+                true => Err(EditorError::boxed(
+                    "The editor fake exited with exit status: 1".to_string(),
+                )),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_editor_choice() {
+        let var = |s: &str| Some(s.to_string());
+        assert_eq!(editor_command(var("nano"), var("vim")), ["nano"]);
+        assert_eq!(editor_command(None, var("code --wait")), ["code", "--wait"]);
+        assert_eq!(editor_command(var("  "), var("vim")), ["vim"]);
+        assert_eq!(editor_command(None, None), [FALLBACK_EDITOR]);
+    }
+}
@@ -0,0 +1,578 @@
+use crate::compress::{self, Codec};
+use crate::crypto::Cipher;
+use crate::ecc;
+use crate::padding;
+use crate::signing::{MAC_LEN, SIGNATURE_LEN};
+use crc::{Crc, CRC_32_ISO_HDLC};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Marks chunk data as an envelope rather than a bare message.
+pub const MAGIC: &[u8; 4] = b"pgME";
+const VERSION: u8 = 1;
+
+const CHECKSUM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+// Field tags. Each field is written as tag, big endian u16 length and value, and the field
+// list ends with `TAG_END`. Unknown tags are skipped so older builds can still read the payload.
+const TAG_END: u8 = 0;
+const TAG_COPY: u8 = 1;
+const TAG_CHECKSUM: u8 = 2;
+const TAG_ECC: u8 = 3;
+const TAG_CIPHER: u8 = 4;
+const TAG_PADDING: u8 = 5;
+const TAG_CODEC: u8 = 6;
+const TAG_LABEL: u8 = 7;
+const TAG_EXPIRES: u8 = 8;
+const TAG_SHARD: u8 = 9;
+const TAG_SIGNATURE: u8 = 10;
+const TAG_MAC: u8 = 11;
+
+/// Something is wrong with the envelope around a payload.
+#[derive(Debug)]
+pub struct EnvelopeError {
+    reason: String,
+    damaged: bool,
+}
+impl EnvelopeError {
+    pub(crate) fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            damaged: false,
+        })
+    }
+
+    fn damaged(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            damaged: true,
+        })
+    }
+
+    /// Whether the envelope was read but its payload no longer matches its checksum, rather
+    /// than the envelope itself being unreadable.
+    pub fn is_damaged(&self) -> bool {
+        self.damaged
+    }
+
+    /// The same error for a payload held in chunks whose CRCs still match, which means the
+    /// payload was changed by something that then recomputed the CRCs.
+    pub fn behind_valid_crc(&self) -> Box<Self> {
+        Self::damaged(format!(
+            "chunk intact but payload integrity check failed — the payload was modified after \
+             encoding ({})",
+            self.reason
+        ))
+    }
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad payload envelope: {}", self.reason)
+    }
+}
+impl Error for EnvelopeError {}
+
+/// Which of several redundant copies of a payload a chunk holds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CopyInfo {
+    pub index: u8,
+    pub total: u8,
+}
+
+/// Which part of a payload split across several carrier files a chunk holds, see `shard`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ShardInfo {
+    pub index: u16,
+    pub total: u16,
+    /// Checksum of the whole payload, which tells the shards of different payloads apart
+    pub id: u32,
+}
+
+/// A payload along with the metadata pngme stores next to it inside a chunk.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Envelope {
+    pub copy: Option<CopyInfo>,
+    /// Set when the payload is one part of a larger one, stored in several files.
+    pub shard: Option<ShardInfo>,
+    pub checksum: Option<u32>,
+    /// Reed-Solomon parity bytes per block the payload is stored with.
+    pub ecc: Option<u8>,
+    /// How many corrupted bytes error correction fixed while reading the envelope.
+    pub corrections: usize,
+    /// Set when the payload is encrypted.
+    pub cipher: Option<Cipher>,
+    /// Set when the message was padded before encryption, see `padding::pad`.
+    pub padded: bool,
+    /// How the message was compressed, set when encoded with `--compress`.
+    pub codec: Option<Codec>,
+    /// Name the payload was stored under with `--label`.
+    pub label: Option<String>,
+    /// When the payload stops being valid, in seconds since the Unix epoch, set with
+    /// `--expires`.
+    pub expires: Option<u64>,
+    /// Ed25519 signature over the payload, set when encoded with `--sign`.
+    pub signature: Option<[u8; SIGNATURE_LEN]>,
+    /// HMAC-SHA256 tag over the payload, set when encoded with `--mac-key`.
+    pub mac: Option<[u8; MAC_LEN]>,
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Wraps `payload` with a checksum of its contents.
+    pub fn new(payload: impl Into<Vec<u8>>) -> Self {
+        let payload = payload.into();
+        Self {
+            checksum: Some(CHECKSUM.checksum(&payload)),
+            payload,
+            ..Default::default()
+        }
+    }
+
+    /// Marks this envelope as copy `index` of `total`.
+    pub fn with_copy(mut self, index: u8, total: u8) -> Self {
+        self.copy = Some(CopyInfo { index, total });
+        self
+    }
+
+    /// Marks this envelope as shard `index` of `total` of the payload with checksum `id`.
+    pub fn with_shard(mut self, index: u16, total: u16, id: u32) -> Self {
+        self.shard = Some(ShardInfo { index, total, id });
+        self
+    }
+
+    /// Stores the payload with `parity` Reed-Solomon parity bytes per block.
+    pub fn with_ecc(mut self, parity: u8) -> Self {
+        self.ecc = Some(parity);
+        self
+    }
+
+    /// Records that the payload is ciphertext produced with `cipher`.
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Records that the message was padded before being stored.
+    pub fn with_padding(mut self) -> Self {
+        self.padded = true;
+        self
+    }
+
+    /// Records that the message was stored with `codec` before padding and encryption.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    /// Records the label the payload is stored under.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Records when the payload expires, in seconds since the Unix epoch.
+    pub fn with_expiry(mut self, expires: u64) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Records a signature over the payload, see `signing::sign`.
+    pub fn with_signature(mut self, signature: [u8; SIGNATURE_LEN]) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Records an HMAC tag over the payload, see `signing::tag`.
+    pub fn with_mac(mut self, mac: [u8; MAC_LEN]) -> Self {
+        self.mac = Some(mac);
+        self
+    }
+
+    /// Whether the payload is past its expiry at `now`.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Strips the padding from `message`, the payload or its decryption, and decompresses it,
+    /// as recorded in this envelope.
+    pub fn unpack(&self, message: Vec<u8>) -> crate::Result<Vec<u8>> {
+        let message = match self.padded {
+            true => padding::unpad(&message)?,
+            false => message,
+        };
+        match self.codec {
+            Some(codec) => compress::decompress(codec, &message),
+            None => Ok(message),
+        }
+    }
+
+    /// What `--sign` and `--mac-key` authenticate: the payload along with every field that
+    /// changes how it is read, encoded as `as_bytes` does. The fields that only say how it is
+    /// stored (copies, shards, error correction and the checksum) are left out, as they are set
+    /// per chunk after signing, and so are the signature and tag themselves.
+    pub fn authenticated_bytes(&self) -> Vec<u8> {
+        Envelope {
+            cipher: self.cipher,
+            padded: self.padded,
+            codec: self.codec,
+            label: self.label.clone(),
+            expires: self.expires,
+            payload: self.payload.clone(),
+            ..Default::default()
+        }
+        .as_bytes()
+    }
+
+    /// Whether the payload still matches the checksum it was stored with.
+    pub fn is_intact(&self) -> bool {
+        self.checksum
+            .is_none_or(|sum| sum == CHECKSUM.checksum(&self.payload))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        if let Some(copy) = self.copy {
+            push_field(&mut out, TAG_COPY, &[copy.index, copy.total]);
+        }
+        if let Some(shard) = self.shard {
+            let mut value = [0; 8];
+            value[..2].copy_from_slice(&shard.index.to_be_bytes());
+            value[2..4].copy_from_slice(&shard.total.to_be_bytes());
+            value[4..].copy_from_slice(&shard.id.to_be_bytes());
+            push_field(&mut out, TAG_SHARD, &value);
+        }
+        if let Some(sum) = self.checksum {
+            push_field(&mut out, TAG_CHECKSUM, &sum.to_be_bytes());
+        }
+        if let Some(parity) = self.ecc {
+            push_field(&mut out, TAG_ECC, &[parity]);
+        }
+        if let Some(cipher) = &self.cipher {
+            push_field(&mut out, TAG_CIPHER, &cipher.as_bytes());
+        }
+        if self.padded {
+            push_field(&mut out, TAG_PADDING, &[]);
+        }
+        if let Some(codec) = self.codec {
+            push_field(&mut out, TAG_CODEC, &[codec.id()]);
+        }
+        if let Some(label) = &self.label {
+            push_field(&mut out, TAG_LABEL, label.as_bytes());
+        }
+        if let Some(expires) = self.expires {
+            push_field(&mut out, TAG_EXPIRES, &expires.to_be_bytes());
+        }
+        if let Some(signature) = &self.signature {
+            push_field(&mut out, TAG_SIGNATURE, signature);
+        }
+        if let Some(mac) = &self.mac {
+            push_field(&mut out, TAG_MAC, mac);
+        }
+        out.push(TAG_END);
+        match self.ecc {
+            Some(parity) => out.extend(ecc::encode(&self.payload, parity)),
+            None => out.extend_from_slice(&self.payload),
+        }
+        out
+    }
+
+    /// Whether `data` starts like an envelope of this version, whether or not the rest of it
+    /// can be read.
+    pub fn is_envelope(data: &[u8]) -> bool {
+        data.strip_prefix(MAGIC)
+            .is_some_and(|rest| rest.first() == Some(&VERSION))
+    }
+
+    /// Parses chunk data written by `as_bytes`. Returns `None` for data without the envelope
+    /// magic, which is a bare message from an older pngme or another tool.
+    pub fn from_bytes(data: &[u8]) -> crate::Result<Option<Self>> {
+        let Some(rest) = data.strip_prefix(MAGIC) else {
+            return Ok(None);
+        };
+        let (&version, mut rest) = rest
+            .split_first()
+            .ok_or_else(|| EnvelopeError::boxed("missing version".to_string()))?;
+        if version != VERSION {
+            return Err(EnvelopeError::boxed(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let mut envelope = Envelope::default();
+        loop {
+            let (&tag, after_tag) = rest
+                .split_first()
+                .ok_or_else(|| EnvelopeError::boxed("field list is not terminated".to_string()))?;
+            if tag == TAG_END {
+                rest = after_tag;
+                break;
+            }
+            if after_tag.len() < 2 {
+                return Err(EnvelopeError::boxed(format!("field {} is truncated", tag)));
+            }
+            let len = u16::from_be_bytes([after_tag[0], after_tag[1]]) as usize;
+            let value = after_tag
+                .get(2..2 + len)
+                .ok_or_else(|| EnvelopeError::boxed(format!("field {} is truncated", tag)))?;
+            match (tag, value) {
+                (TAG_COPY, &[index, total]) => envelope.copy = Some(CopyInfo { index, total }),
+                (TAG_SHARD, &[i0, i1, t0, t1, a, b, c, d]) => {
+                    envelope.shard = Some(ShardInfo {
+                        index: u16::from_be_bytes([i0, i1]),
+                        total: u16::from_be_bytes([t0, t1]),
+                        id: u32::from_be_bytes([a, b, c, d]),
+                    })
+                }
+                (TAG_CHECKSUM, &[a, b, c, d]) => {
+                    envelope.checksum = Some(u32::from_be_bytes([a, b, c, d]))
+                }
+                (TAG_ECC, &[parity]) if parity > 0 => envelope.ecc = Some(parity),
+                (TAG_CIPHER, _) => envelope.cipher = Some(Cipher::from_bytes(value)?),
+                (TAG_PADDING, &[]) => envelope.padded = true,
+                (TAG_CODEC, &[id]) => envelope.codec = Some(Codec::from_id(id)?),
+                (TAG_LABEL, _) => {
+                    envelope.label = Some(String::from_utf8(value.to_vec()).map_err(|_| {
+                        EnvelopeError::boxed("label is not valid UTF-8".to_string())
+                    })?)
+                }
+                (TAG_EXPIRES, _) if len == 8 => {
+                    envelope.expires = Some(u64::from_be_bytes(value.try_into()?))
+                }
+                (TAG_SIGNATURE, _) if len == SIGNATURE_LEN => {
+                    envelope.signature = Some(value.try_into()?)
+                }
+                (TAG_MAC, _) if len == MAC_LEN => envelope.mac = Some(value.try_into()?),
+                (
+                    TAG_COPY | TAG_SHARD | TAG_CHECKSUM | TAG_ECC | TAG_PADDING | TAG_CODEC
+                    | TAG_EXPIRES | TAG_SIGNATURE | TAG_MAC,
+                    _,
+                ) => {
+                    return Err(EnvelopeError::boxed(format!(
+                        "field {} has bad length {}",
+                        tag, len
+                    )))
+                }
+                _ => {}
+            }
+            rest = &after_tag[2 + len..];
+        }
+        match envelope.ecc {
+            Some(parity) => (envelope.payload, envelope.corrections) = ecc::decode(rest, parity)?,
+            None => envelope.payload = rest.to_vec(),
+        }
+        Ok(Some(envelope))
+    }
+}
+
+fn push_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+/// The envelope agreed on by a set of redundant copies.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Recovered {
+    pub envelope: Envelope,
+    /// Positions of the copies that disagreed with the recovered payload or couldn't be read.
+    pub corrupt: Vec<usize>,
+}
+
+/// Picks the payload held by a majority of `copies`, or failing that the first copy whose
+/// checksum still matches. Copies that couldn't be parsed count as corrupt.
+pub fn recover(copies: &[crate::Result<Envelope>]) -> crate::Result<Recovered> {
+    let mut votes: HashMap<&[u8], usize> = HashMap::new();
+    for envelope in copies.iter().flatten() {
+        *votes.entry(&envelope.payload).or_default() += 1;
+    }
+    let majority = copies.iter().flatten().find(|e| {
+        e.is_intact() && votes.get(e.payload.as_slice()).copied().unwrap_or(0) * 2 > copies.len()
+    });
+    let winner = majority
+        .or_else(|| copies.iter().flatten().find(|e| e.is_intact()))
+        .ok_or_else(|| match copies {
+            [Err(e)] => EnvelopeError::boxed(e.to_string()),
+            [Ok(_)] => EnvelopeError::damaged("payload does not match its checksum".to_string()),
+            _ if copies.iter().all(Result::is_ok) => {
+                EnvelopeError::damaged(format!("all {} copies are corrupt", copies.len()))
+            }
+            _ => EnvelopeError::boxed(format!("all {} copies are corrupt", copies.len())),
+        })?;
+    let corrupt = copies
+        .iter()
+        .enumerate()
+        .filter(|(_, copy)| {
+            copy.as_ref()
+                .map_or(true, |e| e.payload != winner.payload || !e.is_intact())
+        })
+        .map(|(idx, _)| idx)
+        .collect();
+    Ok(Recovered {
+        envelope: winner.clone(),
+        corrupt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copies(payload: &[u8], total: u8) -> Vec<Envelope> {
+        (0..total)
+            .map(|i| Envelope::new(payload.to_vec()).with_copy(i, total))
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let envelope = Envelope::new(b"hello".to_vec()).with_copy(1, 3);
+        let parsed = Envelope::from_bytes(&envelope.as_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, envelope);
+        assert!(parsed.is_intact());
+
+        let shard = Envelope::new(b"part".to_vec()).with_shard(2, 300, 0xdeadbeef);
+        let parsed = Envelope::from_bytes(&shard.as_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, shard);
+
+        let signed = Envelope::new(b"signed".to_vec())
+            .with_signature([7; SIGNATURE_LEN])
+            .with_mac([8; MAC_LEN]);
+        let parsed = Envelope::from_bytes(&signed.as_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, signed);
+    }
+
+    #[test]
+    fn test_authenticated_bytes() {
+        let envelope = Envelope::new(b"message".to_vec()).with_label("notes");
+        let authenticated = envelope.authenticated_bytes();
+        // How the payload is stored doesn't matter, what it means does
+        let stored = envelope
+            .clone()
+            .with_copy(1, 3)
+            .with_ecc(8)
+            .with_mac([1; MAC_LEN]);
+        assert_eq!(stored.authenticated_bytes(), authenticated);
+        let expiring = envelope.clone().with_expiry(1);
+        assert_ne!(expiring.authenticated_bytes(), authenticated);
+        let relabelled = envelope.clone().with_label("other");
+        assert_ne!(relabelled.authenticated_bytes(), authenticated);
+    }
+
+    #[test]
+    fn test_cipher_round_trip() {
+        let cipher = Cipher {
+            salt: Some([1; crate::crypto::SALT_LEN]),
+            nonce: [2; crate::crypto::NONCE_LEN],
+        };
+        let envelope = Envelope::new(b"ciphertext".to_vec())
+            .with_cipher(cipher)
+            .with_padding()
+            .with_codec(Codec::Deflate)
+            .with_label("build-info");
+        let parsed = Envelope::from_bytes(&envelope.as_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, envelope);
+    }
+
+    #[test]
+    fn test_bare_message_is_not_an_envelope() {
+        assert!(Envelope::from_bytes(b"hello").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        assert!(Envelope::from_bytes(b"").unwrap().is_none());
+        let envelope = Envelope::new(vec![]);
+        let parsed = Envelope::from_bytes(&envelope.as_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, envelope);
+        assert!(parsed.payload.is_empty());
+        assert!(parsed.is_intact());
+    }
+
+    #[test]
+    fn test_unknown_fields_are_skipped() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        push_field(&mut bytes, 200, b"future");
+        bytes.push(TAG_END);
+        bytes.extend_from_slice(b"payload");
+        let parsed = Envelope::from_bytes(&bytes).unwrap().unwrap();
+        assert_eq!(parsed.payload, b"payload");
+    }
+
+    #[test]
+    fn test_truncated_envelope_is_an_error() {
+        let bytes = Envelope::new(b"hello".to_vec()).with_copy(0, 2).as_bytes();
+        assert!(Envelope::from_bytes(&bytes[..8]).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_payload_fails_checksum() {
+        let mut envelope = Envelope::new(b"hello".to_vec());
+        envelope.payload[0] = b'j';
+        assert!(!envelope.is_intact());
+    }
+
+    #[test]
+    fn test_ecc_round_trip() {
+        let envelope = Envelope::new(vec![7; 600]).with_ecc(16);
+        let mut bytes = envelope.as_bytes();
+        let parsed = Envelope::from_bytes(&bytes).unwrap().unwrap();
+        assert_eq!(parsed, envelope);
+
+        let len = bytes.len();
+        for b in &mut bytes[len - 100..len - 92] {
+            *b ^= 0x81;
+        }
+        let parsed = Envelope::from_bytes(&bytes).unwrap().unwrap();
+        assert_eq!(parsed.payload, envelope.payload);
+        assert_eq!(parsed.corrections, 8);
+        assert!(parsed.is_intact());
+    }
+
+    #[test]
+    fn test_majority_vote() {
+        let mut copies: Vec<crate::Result<Envelope>> =
+            copies(b"payload", 3).into_iter().map(Ok).collect();
+        if let Ok(e) = &mut copies[1] {
+            e.payload[0] = b'X';
+        }
+        let recovered = recover(&copies).unwrap();
+        assert_eq!(recovered.envelope.payload, b"payload");
+        assert_eq!(recovered.corrupt, vec![1]);
+    }
+
+    #[test]
+    fn test_falls_back_to_intact_copy() {
+        let mut copies: Vec<crate::Result<Envelope>> =
+            copies(b"payload", 2).into_iter().map(Ok).collect();
+        if let Ok(e) = &mut copies[0] {
+            e.payload[0] = b'X';
+        }
+        let recovered = recover(&copies).unwrap();
+        assert_eq!(recovered.envelope.payload, b"payload");
+        assert_eq!(recovered.corrupt, vec![0]);
+    }
+
+    #[test]
+    fn test_unreadable_copy_counts_as_corrupt() {
+        let mut copies: Vec<crate::Result<Envelope>> =
+            copies(b"payload", 3).into_iter().map(Ok).collect();
+        copies[2] = Err("garbage".into());
+        let recovered = recover(&copies).unwrap();
+        assert_eq!(recovered.envelope.payload, b"payload");
+        assert_eq!(recovered.corrupt, vec![2]);
+    }
+
+    #[test]
+    fn test_all_copies_corrupt() {
+        let mut copies: Vec<crate::Result<Envelope>> =
+            copies(b"payload", 2).into_iter().map(Ok).collect();
+        for e in copies.iter_mut().flatten() {
+            e.payload[0] = b'X';
+        }
+        assert!(recover(&copies).is_err());
+    }
+}
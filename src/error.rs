@@ -0,0 +1,607 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::apng::ApngError;
+use crate::archive::ArchiveError;
+use crate::audit::AuditError;
+use crate::batch::BatchError;
+use crate::cancel::CancelledError;
+use crate::chunk::ChunkDecodingError;
+use crate::chunk_type::PngDecodeError;
+use crate::compress::CompressError;
+use crate::crypto::CryptoError;
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::ecc::EccError;
+use crate::editor::EditorError;
+use crate::envelope::EnvelopeError;
+use crate::expiry::ExpiredError;
+use crate::extension::ExtensionError;
+use crate::header::HeaderError;
+use crate::history::HistoryError;
+use crate::keychain::KeyringError;
+use crate::kv::KvError;
+use crate::lock::LockError;
+use crate::lsb::LsbError;
+use crate::man::ManError;
+use crate::padding::PaddingError;
+use crate::palette::PaletteError;
+use crate::patch::PatchError;
+use crate::remote::RemoteError;
+use crate::repair::RepairError;
+use crate::seal::SealError;
+use crate::shard::ShardError;
+use crate::signing::SigningError;
+use crate::stream::ChunkStreamError;
+use crate::text::TextError;
+use crate::verify::ImageVerifyError;
+
+/// A chunk, message or stored value the command was asked for isn't there.
+#[derive(Debug)]
+pub struct NotFoundError {
+    reason: String,
+    chunk_type: Option<String>,
+    chunk_index: Option<usize>,
+}
+impl NotFoundError {
+    pub fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            chunk_type: None,
+            chunk_index: None,
+        })
+    }
+
+    /// No chunk of `chunk_type` found, or not as many as `index` needs.
+    pub fn chunk(chunk_type: &str, index: Option<usize>, reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            chunk_type: Some(chunk_type.to_string()),
+            chunk_index: index,
+        })
+    }
+}
+
+impl fmt::Display for NotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+impl Error for NotFoundError {}
+
+/// A decoded payload or image doesn't match what the command was told to expect.
+#[derive(Debug)]
+pub struct MismatchError {
+    reason: String,
+}
+impl MismatchError {
+    pub fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for MismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+impl Error for MismatchError {}
+
+/// The command would destroy existing data and wasn't given `--force`.
+#[derive(Debug)]
+pub struct RefusedError {
+    reason: String,
+}
+impl RefusedError {
+    pub fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for RefusedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+impl Error for RefusedError {}
+
+/// The command was given options that can't be used together, or that don't apply to the file
+/// or message it was run on.
+#[derive(Debug)]
+pub struct UsageError {
+    reason: String,
+}
+impl UsageError {
+    pub fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+impl Error for UsageError {}
+
+/// The exit status of pngme. Scripts rely on these values, so they must never change:
+///
+/// | code | meaning                                                                        |
+/// |------|--------------------------------------------------------------------------------|
+/// | 0    | success                                                                        |
+/// | 1    | any other failure, including invalid arguments                                 |
+/// | 2    | the file doesn't exist or can't be read or written                             |
+/// | 3    | the file isn't a png or can't be parsed                                        |
+/// | 4    | the chunk, message or value asked for isn't there                              |
+/// | 5    | a CRC, checksum, seal, key, signature, HMAC, expiry or `--expect` check failed |
+/// | 6    | the command refused to overwrite data without `--force`                        |
+/// | 130  | interrupted with Ctrl-C before finishing                                       |
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ExitCode {
+    Success = 0,
+    Failure = 1,
+    Unreadable = 2,
+    NotPng = 3,
+    NotFound = 4,
+    Integrity = 5,
+    Refused = 6,
+    Interrupted = 130,
+}
+
+impl ExitCode {
+    /// The exit code for a command that failed with `failure`.
+    pub fn of(failure: &Failure) -> Self {
+        match failure {
+            Failure::FileNotFound(_) | Failure::Io(_) | Failure::Remote(_) => ExitCode::Unreadable,
+            Failure::Parse(d) if d.kind == DiagnosticKind::BadCrc => ExitCode::Integrity,
+            Failure::Chunk(e) if e.is_bad_crc() => ExitCode::Integrity,
+            Failure::Stream(e) if e.is_bad_crc() => ExitCode::Integrity,
+            Failure::Parse(_)
+            | Failure::ChunkType(_)
+            | Failure::Chunk(_)
+            | Failure::Stream(_)
+            | Failure::Patch(_)
+            | Failure::Palette(_)
+            | Failure::Header(_)
+            | Failure::Apng(_)
+            | Failure::Extension(_)
+            | Failure::Repair(_) => ExitCode::NotPng,
+            Failure::NotFound(_) => ExitCode::NotFound,
+            Failure::Shard(e) if !e.missing().is_empty() => ExitCode::NotFound,
+            Failure::Shard(e) if e.is_damaged() => ExitCode::Integrity,
+            Failure::Envelope(_)
+            | Failure::Expired(_)
+            | Failure::Ecc(_)
+            | Failure::Crypto(_)
+            | Failure::Compression(_)
+            | Failure::Padding(_)
+            | Failure::Seal(_)
+            | Failure::Signing(_)
+            | Failure::ImageVerify(_)
+            | Failure::Mismatch(_) => ExitCode::Integrity,
+            Failure::Refused(_) => ExitCode::Refused,
+            Failure::Cancelled(_) => ExitCode::Interrupted,
+            Failure::Lsb(_)
+            | Failure::Keyring(_)
+            | Failure::Lock(_)
+            | Failure::Kv(_)
+            | Failure::Audit(_)
+            | Failure::Archive(_)
+            | Failure::History(_)
+            | Failure::Man(_)
+            | Failure::Editor(_)
+            | Failure::Shard(_)
+            | Failure::Batch(_)
+            | Failure::Text(_)
+            | Failure::Json(_)
+            | Failure::Usage(_)
+            | Failure::Other(_) => ExitCode::Failure,
+        }
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code as u8)
+    }
+}
+
+/// A pngme error sorted by the module it came from, so it can be reported with whatever
+/// details that kind of error carries. New kinds of error get new variants, so matches outside
+/// this crate need a catch-all arm.
+#[non_exhaustive]
+pub enum Failure<'a> {
+    FileNotFound(&'a io::Error),
+    Io(&'a io::Error),
+    Parse(&'a Diagnostic),
+    ChunkType(&'a PngDecodeError),
+    Chunk(&'a ChunkDecodingError),
+    Stream(&'a ChunkStreamError),
+    Envelope(&'a EnvelopeError),
+    Expired(&'a ExpiredError),
+    Ecc(&'a EccError),
+    Crypto(&'a CryptoError),
+    Compression(&'a CompressError),
+    Padding(&'a PaddingError),
+    Remote(&'a RemoteError),
+    Repair(&'a RepairError),
+    Patch(&'a PatchError),
+    Palette(&'a PaletteError),
+    Header(&'a HeaderError),
+    Apng(&'a ApngError),
+    Extension(&'a ExtensionError),
+    Lsb(&'a LsbError),
+    Keyring(&'a KeyringError),
+    Seal(&'a SealError),
+    Lock(&'a LockError),
+    Kv(&'a KvError),
+    Audit(&'a AuditError),
+    Archive(&'a ArchiveError),
+    History(&'a HistoryError),
+    Man(&'a ManError),
+    Editor(&'a EditorError),
+    Shard(&'a ShardError),
+    Signing(&'a SigningError),
+    Batch(&'a BatchError),
+    Text(&'a TextError),
+    ImageVerify(&'a ImageVerifyError),
+    Json(&'a serde_json::Error),
+    NotFound(&'a NotFoundError),
+    Mismatch(&'a MismatchError),
+    Refused(&'a RefusedError),
+    Usage(&'a UsageError),
+    Cancelled(&'a CancelledError),
+    /// Errors built from a plain message
+    Other(&'a (dyn Error + 'static)),
+}
+
+impl<'a> Failure<'a> {
+    pub fn of(error: &'a crate::Error) -> Self {
+        Self::sort(error.as_ref())
+    }
+
+    fn sort(error: &'a (dyn Error + 'static)) -> Self {
+        // `?` on a `Result<_, Box<SomeError>>` goes through the blanket `From<E: Error>`
+        // rather than unsizing, which leaves the box itself as the error
+        macro_rules! downcast {
+            ($($variant:ident($error:ty)),* $(,)?) => {
+                $(if let Some(e) = error.downcast_ref::<$error>() {
+                    return Failure::$variant(e);
+                }
+                if let Some(e) = error.downcast_ref::<Box<$error>>() {
+                    return Failure::$variant(e);
+                })*
+            };
+        }
+        if let Some(e) = error.downcast_ref::<io::Error>() {
+            // Readers such as `PayloadReader` can only report bad data they come across part
+            // way as an io::Error, holding the error that says what was wrong
+            let inner = e
+                .get_ref()
+                .filter(|_| e.kind() == io::ErrorKind::InvalidData);
+            if let Some(inner) = inner.map(|inner| Self::sort(inner)) {
+                if !matches!(inner, Failure::Other(_)) {
+                    return inner;
+                }
+            }
+            return match e.kind() {
+                io::ErrorKind::NotFound => Failure::FileNotFound(e),
+                _ => Failure::Io(e),
+            };
+        }
+        downcast!(
+            Parse(Diagnostic),
+            ChunkType(PngDecodeError),
+            Chunk(ChunkDecodingError),
+            Stream(ChunkStreamError),
+            Envelope(EnvelopeError),
+            Expired(ExpiredError),
+            Ecc(EccError),
+            Crypto(CryptoError),
+            Compression(CompressError),
+            Padding(PaddingError),
+            Remote(RemoteError),
+            Repair(RepairError),
+            Patch(PatchError),
+            Palette(PaletteError),
+            Header(HeaderError),
+            Apng(ApngError),
+            Extension(ExtensionError),
+            Lsb(LsbError),
+            Keyring(KeyringError),
+            Seal(SealError),
+            Lock(LockError),
+            Kv(KvError),
+            Audit(AuditError),
+            Archive(ArchiveError),
+            History(HistoryError),
+            Man(ManError),
+            Editor(EditorError),
+            Shard(ShardError),
+            Signing(SigningError),
+            Batch(BatchError),
+            Text(TextError),
+            ImageVerify(ImageVerifyError),
+            Json(serde_json::Error),
+            NotFound(NotFoundError),
+            Mismatch(MismatchError),
+            Refused(RefusedError),
+            Usage(UsageError),
+            Cancelled(CancelledError),
+        );
+        Failure::Other(error)
+    }
+
+    /// The error that was sorted.
+    pub fn error(&self) -> &'a (dyn Error + 'static) {
+        match *self {
+            Failure::FileNotFound(e) | Failure::Io(e) => e,
+            Failure::Parse(e) => e,
+            Failure::ChunkType(e) => e,
+            Failure::Chunk(e) => e,
+            Failure::Stream(e) => e,
+            Failure::Envelope(e) => e,
+            Failure::Expired(e) => e,
+            Failure::Ecc(e) => e,
+            Failure::Crypto(e) => e,
+            Failure::Compression(e) => e,
+            Failure::Padding(e) => e,
+            Failure::Remote(e) => e,
+            Failure::Repair(e) => e,
+            Failure::Patch(e) => e,
+            Failure::Palette(e) => e,
+            Failure::Header(e) => e,
+            Failure::Apng(e) => e,
+            Failure::Extension(e) => e,
+            Failure::Lsb(e) => e,
+            Failure::Keyring(e) => e,
+            Failure::Seal(e) => e,
+            Failure::Lock(e) => e,
+            Failure::Kv(e) => e,
+            Failure::Audit(e) => e,
+            Failure::Archive(e) => e,
+            Failure::History(e) => e,
+            Failure::Man(e) => e,
+            Failure::Editor(e) => e,
+            Failure::Shard(e) => e,
+            Failure::Signing(e) => e,
+            Failure::Batch(e) => e,
+            Failure::Text(e) => e,
+            Failure::ImageVerify(e) => e,
+            Failure::Json(e) => e,
+            Failure::NotFound(e) => e,
+            Failure::Mismatch(e) => e,
+            Failure::Refused(e) => e,
+            Failure::Usage(e) => e,
+            Failure::Cancelled(e) => e,
+            Failure::Other(e) => e,
+        }
+    }
+
+    /// The name of the variant, as reported in `--json-errors` output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Failure::FileNotFound(_) => "FileNotFound",
+            Failure::Io(_) => "Io",
+            Failure::Parse(_) => "Parse",
+            Failure::ChunkType(_) => "ChunkType",
+            Failure::Chunk(_) => "Chunk",
+            Failure::Stream(_) => "Stream",
+            Failure::Envelope(_) => "Envelope",
+            Failure::Expired(_) => "Expired",
+            Failure::Ecc(_) => "Ecc",
+            Failure::Crypto(_) => "Crypto",
+            Failure::Compression(_) => "Compression",
+            Failure::Padding(_) => "Padding",
+            Failure::Remote(_) => "Remote",
+            Failure::Repair(_) => "Repair",
+            Failure::Patch(_) => "Patch",
+            Failure::Palette(_) => "Palette",
+            Failure::Header(_) => "Header",
+            Failure::Apng(_) => "Apng",
+            Failure::Extension(_) => "Extension",
+            Failure::Lsb(_) => "Lsb",
+            Failure::Keyring(_) => "Keyring",
+            Failure::Seal(_) => "Seal",
+            Failure::Lock(_) => "Lock",
+            Failure::Kv(_) => "Kv",
+            Failure::Audit(_) => "Audit",
+            Failure::Archive(_) => "Archive",
+            Failure::History(_) => "History",
+            Failure::Man(_) => "Man",
+            Failure::Editor(_) => "Editor",
+            Failure::Shard(_) => "Shard",
+            Failure::Signing(_) => "Signing",
+            Failure::Batch(_) => "Batch",
+            Failure::Text(_) => "Text",
+            Failure::ImageVerify(_) => "ImageVerify",
+            Failure::Json(_) => "Json",
+            Failure::NotFound(_) => "NotFound",
+            Failure::Mismatch(_) => "Mismatch",
+            Failure::Refused(_) => "Refused",
+            Failure::Usage(_) => "Usage",
+            Failure::Cancelled(_) => "Cancelled",
+            Failure::Other(_) => "Other",
+        }
+    }
+}
+
+/// A failed command as reported with `--json-errors`, and in the `--json` document.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub kind: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    pub exit_code: u8,
+}
+
+impl Report {
+    /// Describes `error` from a command working on `file`.
+    pub fn new(error: &crate::Error, file: Option<&str>) -> Self {
+        let failure = Failure::of(error);
+        let mut report = Self {
+            kind: failure.kind().to_string(),
+            message: failure.error().to_string(),
+            file: file.map(str::to_string),
+            chunk_type: None,
+            chunk_index: None,
+            offset: None,
+            exit_code: ExitCode::of(&failure) as u8,
+        };
+        match failure {
+            Failure::Parse(d) => {
+                report.chunk_index = d.chunk_index;
+                report.offset = d.offset;
+            }
+            Failure::NotFound(e) => {
+                report.chunk_type = e.chunk_type.clone();
+                report.chunk_index = e.chunk_index;
+            }
+            Failure::FileNotFound(_)
+            | Failure::Io(_)
+            | Failure::ChunkType(_)
+            | Failure::Chunk(_)
+            | Failure::Stream(_)
+            | Failure::Envelope(_)
+            | Failure::Expired(_)
+            | Failure::Ecc(_)
+            | Failure::Crypto(_)
+            | Failure::Compression(_)
+            | Failure::Padding(_)
+            | Failure::Remote(_)
+            | Failure::Repair(_)
+            | Failure::Patch(_)
+            | Failure::Palette(_)
+            | Failure::Header(_)
+            | Failure::Apng(_)
+            | Failure::Extension(_)
+            | Failure::Lsb(_)
+            | Failure::Keyring(_)
+            | Failure::Seal(_)
+            | Failure::Lock(_)
+            | Failure::Kv(_)
+            | Failure::Audit(_)
+            | Failure::Archive(_)
+            | Failure::History(_)
+            | Failure::Man(_)
+            | Failure::Editor(_)
+            | Failure::Shard(_)
+            | Failure::Signing(_)
+            | Failure::Batch(_)
+            | Failure::Text(_)
+            | Failure::ImageVerify(_)
+            | Failure::Json(_)
+            | Failure::Mismatch(_)
+            | Failure::Refused(_)
+            | Failure::Usage(_)
+            | Failure::Cancelled(_)
+            | Failure::Other(_) => {}
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::Png;
+
+    #[test]
+    fn test_kinds() {
+        let missing: crate::Error = std::fs::read("/definitely/not/here.png")
+            .unwrap_err()
+            .into();
+        assert_eq!(Failure::of(&missing).kind(), "FileNotFound");
+        let plain: crate::Error = "selftest failed at: parse".into();
+        assert_eq!(Failure::of(&plain).kind(), "Other");
+        let usage: crate::Error =
+            UsageError::boxed("--redundancy can't be used with --mode lsb".to_string());
+        assert_eq!(Failure::of(&usage).kind(), "Usage");
+        assert_eq!(ExitCode::of(&Failure::of(&usage)), ExitCode::Failure);
+        let formatted: crate::Error = format!("{} problem(s) found", 2).into();
+        assert_eq!(Failure::of(&formatted).kind(), "Other");
+        let rebox = || -> crate::Result<()> {
+            Err(()).map_err(|_| NotFoundError::boxed("gone".to_string()))?;
+            Ok(())
+        };
+        assert_eq!(Failure::of(&rebox().unwrap_err()).kind(), "NotFound");
+        let cancel = crate::cancel::Cancel::new();
+        cancel.cancel();
+        let cancelled = cancel.check().unwrap_err();
+        assert_eq!(Failure::of(&cancelled).kind(), "Cancelled");
+        assert_eq!(
+            ExitCode::of(&Failure::of(&cancelled)),
+            ExitCode::Interrupted
+        );
+    }
+
+    #[test]
+    fn test_library_errors_can_be_told_apart() {
+        use crate::chunk::Chunk;
+        use crate::chunk_type::ChunkType;
+        use std::str::FromStr;
+
+        let kind = |error: crate::Error| {
+            (
+                Failure::of(&error).kind(),
+                ExitCode::of(&Failure::of(&error)),
+            )
+        };
+        assert_eq!(
+            kind(Png::from_bytes_at(&Png::STANDARD_HEADER, 9).unwrap_err()),
+            ("Parse", ExitCode::NotPng)
+        );
+        assert_eq!(
+            kind(ChunkType::from_str("Ru1t").unwrap_err()),
+            ("ChunkType", ExitCode::NotPng)
+        );
+        let trns = Chunk::new(ChunkType::from_str("tRNS").unwrap(), vec![0, 1]);
+        let no_ihdr = Png::from_chunks(vec![trns]);
+        assert_eq!(
+            kind(no_ihdr.transparency().unwrap().unwrap_err()),
+            ("NotFound", ExitCode::NotFound)
+        );
+        let mut shards = vec![];
+        let not_an_envelope = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"bare".to_vec());
+        shards.push(&not_an_envelope);
+        assert_eq!(
+            kind(crate::shard::ShardReader::new(&shards).err().unwrap()),
+            ("Envelope", ExitCode::Integrity)
+        );
+    }
+
+    #[test]
+    fn test_parse_error_carries_its_position() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(b"IEND");
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        let error = Png::try_from(&bytes[..]).unwrap_err();
+        let report = Report::new(&error, Some("image.png"));
+        assert_eq!(report.kind, "Parse");
+        assert_eq!(report.exit_code, ExitCode::Integrity as u8);
+        assert_eq!(report.chunk_index, Some(0));
+        assert_eq!(report.offset, Some(8 + 8));
+        assert_eq!(report.file.as_deref(), Some("image.png"));
+    }
+
+    #[test]
+    fn test_not_found_carries_the_chunk() {
+        let error: crate::Error = NotFoundError::chunk("ruSt", Some(2), "no ruSt".to_string());
+        let json = serde_json::to_value(Report::new(&error, None)).unwrap();
+        assert_eq!(json["kind"], "NotFound");
+        assert_eq!(json["exit_code"], 4);
+        assert_eq!(json["chunk_type"], "ruSt");
+        assert_eq!(json["chunk_index"], 2);
+        assert!(json.get("file").is_none());
+    }
+}
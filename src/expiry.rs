@@ -0,0 +1,223 @@
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A message is past the expiry it was stored with, and `--strict-expiry` was given.
+#[derive(Debug)]
+pub struct ExpiredError {
+    reason: String,
+}
+impl ExpiredError {
+    pub fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for ExpiredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expired: {}", self.reason)
+    }
+}
+impl Error for ExpiredError {}
+
+/// An `--expires` value, remembering whether it was given as a duration from now.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Expiry {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub relative: bool,
+}
+
+/// The current time in seconds since the Unix epoch. Expiry is always compared in UTC.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Parses `--expires`: either a UTC timestamp like `2030-01-31T12:00:00Z`, or a duration
+/// from `now` like `90m`, `12h`, `30d` or `2w`. Returns seconds since the Unix epoch.
+pub fn parse(value: &str, now: u64) -> Result<u64, String> {
+    if let Some(seconds) = parse_duration(value) {
+        return now
+            .checked_add(seconds?)
+            .ok_or_else(|| format!("{} is too far in the future", value));
+    }
+    parse_timestamp(value)
+}
+
+/// Whether `value` is a duration like `30d` rather than a timestamp.
+pub fn is_duration(value: &str) -> bool {
+    parse_duration(value).is_some()
+}
+
+fn parse_duration(value: &str) -> Option<Result<u64, String>> {
+    let unit = match value.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => SECONDS_PER_DAY,
+        'w' => 7 * SECONDS_PER_DAY,
+        _ => return None,
+    };
+    let count = &value[..value.len() - 1];
+    if count.is_empty() || !count.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(
+        count
+            .parse::<u64>()
+            .ok()
+            .and_then(|count| count.checked_mul(unit))
+            .ok_or_else(|| format!("{} is too long a duration", value)),
+    )
+}
+
+/// RFC 3339 with a `Z` or `+00:00` offset. Other offsets are refused rather than converted,
+/// so what is stored is exactly the UTC time that was typed.
+fn parse_timestamp(value: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "{} is neither a UTC time like 2030-01-31T12:00:00Z nor a duration like 30d",
+            value
+        )
+    };
+    if !value.is_ascii() {
+        return Err(invalid());
+    }
+    let (datetime, offset) = match value.len().checked_sub(1).map(|i| value.split_at(i)) {
+        Some((datetime, "Z" | "z")) => (datetime, ""),
+        _ => match value.len().checked_sub(6).map(|i| value.split_at(i)) {
+            Some((datetime, offset)) if offset.starts_with(['+', '-']) && &offset[3..4] == ":" => {
+                (datetime, offset)
+            }
+            _ => return Err(invalid()),
+        },
+    };
+    if !matches!(offset, "" | "+00:00" | "-00:00") {
+        return Err(format!(
+            "{} isn't in UTC, give the time with a Z or +00:00 offset",
+            value
+        ));
+    }
+    // Fractions of a second don't matter for expiry
+    let datetime = datetime.split('.').next().unwrap_or(datetime);
+    let bytes = datetime.as_bytes();
+    if bytes.len() != 19
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return Err(invalid());
+    }
+    let field = |range: std::ops::Range<usize>| -> Result<u64, String> {
+        let digits = &datetime[range];
+        match digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => digits.parse().map_err(|_| invalid()),
+            false => Err(invalid()),
+        }
+    };
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+    Ok(days_since_epoch(year, month, day) * SECONDS_PER_DAY
+        + hour * 3600
+        + minute * 60
+        + second.min(59))
+}
+
+fn is_leap(year: u64) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let years: u64 = (1970..year)
+        .map(|y| if is_leap(y) { 366 } else { 365 })
+        .sum();
+    let months: u64 = (1..month).map(|m| days_in_month(year, m)).sum();
+    years + months + day - 1
+}
+
+/// `seconds` since the Unix epoch as an RFC 3339 UTC timestamp.
+pub fn format(seconds: u64) -> String {
+    let mut days = seconds / SECONDS_PER_DAY;
+    let mut year = 1970;
+    while days >= if is_leap(year) { 366 } else { 365 } {
+        days -= if is_leap(year) { 366 } else { 365 };
+        year += 1;
+    }
+    let mut month = 1;
+    while days >= days_in_month(year, month) {
+        days -= days_in_month(year, month);
+        month += 1;
+    }
+    let time = seconds % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        days + 1,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(parse("1970-01-01T00:00:00Z", 0), Ok(0));
+        assert_eq!(parse("2000-03-01T00:00:00Z", 0), Ok(951_868_800));
+        assert_eq!(parse("2030-01-31T12:34:56.789+00:00", 0), Ok(1_896_093_296));
+        assert_eq!(format(1_896_093_296), "2030-01-31T12:34:56Z");
+        assert_eq!(format(951_868_800 - 1), "2000-02-29T23:59:59Z");
+
+        let err = parse("2030-01-31T12:00:00+02:00", 0).unwrap_err();
+        assert!(err.contains("isn't in UTC"), "{}", err);
+        for bad in [
+            "2030-02-30T00:00:00Z",
+            "2030-01-31",
+            "tomorrow",
+            "30",
+            "-1d",
+            "",
+        ] {
+            assert!(parse(bad, 0).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse("30d", 1_000), Ok(1_000 + 30 * SECONDS_PER_DAY));
+        assert_eq!(parse("2w", 0), Ok(14 * SECONDS_PER_DAY));
+        assert_eq!(parse("90m", 0), Ok(5_400));
+        assert!(parse("99999999999999999999d", 0).is_err());
+        assert!(is_duration("30d"));
+        assert!(!is_duration("2030-01-31T12:00:00Z"));
+    }
+}
@@ -0,0 +1,267 @@
+//! The registered extension chunks common in scientific and scanned images: oFFs, the image's
+//! position on a page, sCAL, the physical size of its pixels, and sTER, marking it as a stereo
+//! pair.
+
+use std::error::Error;
+use std::fmt;
+
+/// An oFFs, sCAL or sTER chunk's data doesn't have the layout the extensions spec gives it.
+#[derive(Debug)]
+pub struct ExtensionError {
+    reason: String,
+}
+impl ExtensionError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad extension chunk: {}", self.reason)
+    }
+}
+impl Error for ExtensionError {}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OffsetUnit {
+    Pixel,
+    Micrometre,
+}
+
+/// Where the image sits on a page or screen, from an oFFs chunk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Offset {
+    pub x: i32,
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+impl Offset {
+    /// Parses the 9 bytes of an oFFs chunk: signed x and y positions and their unit.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let data: &[u8; 9] = data.try_into().map_err(|_| {
+            ExtensionError::boxed(format!("oFFs is {} bytes instead of 9", data.len()))
+        })?;
+        let unit = match data[8] {
+            0 => OffsetUnit::Pixel,
+            1 => OffsetUnit::Micrometre,
+            unit => {
+                return Err(ExtensionError::boxed(format!(
+                    "oFFs unit is {}, it must be 0 for pixels or 1 for micrometres",
+                    unit
+                )))
+            }
+        };
+        Ok(Self {
+            x: i32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            y: i32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            unit,
+        })
+    }
+}
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            OffsetUnit::Pixel => "pixels",
+            OffsetUnit::Micrometre => "micrometres",
+        };
+        write!(f, "offset: ({}, {}) {}", self.x, self.y, unit)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ScaleUnit {
+    Metre,
+    Radian,
+}
+
+/// The physical width and height of a pixel, from an sCAL chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub unit: ScaleUnit,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Reads one of sCAL's ASCII floating-point fields: an optional sign, digits with at most one
+/// decimal point, and an optional exponent. Words like `inf` that Rust would also accept
+/// aren't allowed, and the value must be above zero.
+fn parse_float(field: &[u8], name: &str) -> crate::Result<f64> {
+    let invalid = || {
+        ExtensionError::boxed(format!(
+            "sCAL {} \"{}\" is not a floating-point number",
+            name,
+            String::from_utf8_lossy(field).escape_debug()
+        ))
+    };
+    let text = std::str::from_utf8(field).map_err(|_| invalid())?;
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let digits = mantissa.bytes().filter(u8::is_ascii_digit).count();
+    let points = mantissa.bytes().filter(|&b| b == b'.').count();
+    let exponent_ok = exponent.is_none_or(|e| {
+        let e = e.strip_prefix(['+', '-']).unwrap_or(e);
+        !e.is_empty() && e.bytes().all(|b| b.is_ascii_digit())
+    });
+    if digits == 0 || points > 1 || digits + points != mantissa.len() || !exponent_ok {
+        return Err(invalid());
+    }
+    let value: f64 = text.parse().map_err(|_| invalid())?;
+    if !(value.is_finite() && value > 0.0) {
+        return Err(ExtensionError::boxed(format!(
+            "sCAL {} is {}, it must be above zero",
+            name, text
+        )));
+    }
+    Ok(value)
+}
+
+impl Scale {
+    /// Parses the data of an sCAL chunk: the unit, then the pixel width and height as ASCII
+    /// floating-point numbers separated by a null.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let (&unit, fields) = data
+            .split_first()
+            .ok_or_else(|| ExtensionError::boxed("sCAL is empty".to_string()))?;
+        let unit = match unit {
+            1 => ScaleUnit::Metre,
+            2 => ScaleUnit::Radian,
+            _ => {
+                return Err(ExtensionError::boxed(format!(
+                    "sCAL unit is {}, it must be 1 for metres or 2 for radians",
+                    unit
+                )))
+            }
+        };
+        let separator = fields.iter().position(|&b| b == 0).ok_or_else(|| {
+            ExtensionError::boxed("sCAL has no null between its width and height".to_string())
+        })?;
+        Ok(Self {
+            unit,
+            width: parse_float(&fields[..separator], "width")?,
+            height: parse_float(&fields[separator + 1..], "height")?,
+        })
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            ScaleUnit::Metre => "metres",
+            ScaleUnit::Radian => "radians",
+        };
+        write!(
+            f,
+            "scale: pixels are {} x {} {}",
+            self.width, self.height, unit
+        )
+    }
+}
+
+/// How the two halves of a stereo pair are laid out, from an sTER chunk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stereo {
+    /// The right-eye image is on the left
+    CrossFused,
+    /// The left-eye image is on the left
+    DivergingFused,
+}
+
+impl Stereo {
+    /// Parses the single byte of an sTER chunk.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        match data {
+            [0] => Ok(Stereo::CrossFused),
+            [1] => Ok(Stereo::DivergingFused),
+            [mode] => Err(ExtensionError::boxed(format!(
+                "sTER mode is {}, it must be 0 or 1",
+                mode
+            ))),
+            _ => Err(ExtensionError::boxed(format!(
+                "sTER is {} bytes instead of 1",
+                data.len()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Stereo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stereo::CrossFused => write!(f, "stereo pair: cross-fused"),
+            Stereo::DivergingFused => write!(f, "stereo pair: diverging-fused"),
+        }
+    }
+}
+
+/// A line describing an oFFs, sCAL or sTER chunk's data, or why it can't be read. `None` for
+/// other chunks.
+pub fn describe(chunk_type: &[u8; 4], data: &[u8]) -> Option<String> {
+    let described = match chunk_type {
+        b"oFFs" => Offset::parse(data).map(|o| o.to_string()),
+        b"sCAL" => Scale::parse(data).map(|s| s.to_string()),
+        b"sTER" => Stereo::parse(data).map(|s| s.to_string()),
+        _ => return None,
+    };
+    Some(described.unwrap_or_else(|e| e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset() {
+        let data = [(-20i32).to_be_bytes(), 300i32.to_be_bytes()].concat();
+        let offset = Offset::parse(&[&data[..], &[1]].concat()).unwrap();
+        assert_eq!((offset.x, offset.y), (-20, 300));
+        assert_eq!(offset.to_string(), "offset: (-20, 300) micrometres");
+
+        assert!(Offset::parse(&data).is_err());
+        assert!(Offset::parse(&[&data[..], &[2]].concat())
+            .unwrap_err()
+            .to_string()
+            .contains("unit is 2"));
+    }
+
+    #[test]
+    fn test_scale() {
+        let scale = Scale::parse(b"\x011.5e-4\x00+.25").unwrap();
+        assert_eq!(scale.unit, ScaleUnit::Metre);
+        assert_eq!((scale.width, scale.height), (1.5e-4, 0.25));
+        assert_eq!(
+            Scale::parse(b"\x022\x003.").unwrap().to_string(),
+            "scale: pixels are 2 x 3 radians"
+        );
+
+        let cases: [(&[u8], &str); 9] = [
+            (b"", "empty"),
+            (b"\x031\x001", "unit is 3"),
+            (b"\x0112.5", "no null between"),
+            (b"\x01inf\x001", "width \"inf\" is not"),
+            (b"\x011\x001.2.3", "height \"1.2.3\" is not"),
+            (b"\x011e\x001", "width \"1e\" is not"),
+            (b"\x011\x00", "height \"\" is not"),
+            (b"\x01-1\x001", "width is -1, it must be above zero"),
+            (b"\x011\x001\x00", "height \"1\\0\" is not"),
+        ];
+        for (data, expected) in cases {
+            let error = Scale::parse(data).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_stereo() {
+        assert_eq!(Stereo::parse(&[1]).unwrap(), Stereo::DivergingFused);
+        assert_eq!(describe(b"sTER", &[0]).unwrap(), "stereo pair: cross-fused");
+        assert!(Stereo::parse(&[2]).is_err());
+        assert!(Stereo::parse(&[0, 0]).is_err());
+        assert_eq!(describe(b"tEXt", &[0]), None);
+    }
+}
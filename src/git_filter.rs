@@ -0,0 +1,131 @@
+use std::io::{self, Read, Write};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+use crate::png::Png;
+use crate::stream::ChunkStream;
+
+/// Keeps a copy of everything read through it, so a chunk can be written out exactly as it
+/// was read, or the input passed on untouched if it turns out not to parse.
+struct Recorder<R> {
+    inner: R,
+    seen: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.seen.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Copies the png on `input` to `output` without the chunks `clean` strips: those of
+/// `chunk_types` if any are given, otherwise every chunk holding a pngme envelope. Everything
+/// else is copied byte for byte, and input that isn't a png, or stops parsing part way, is
+/// passed on unchanged from there, so git never sees a spurious change. Only one chunk is held
+/// in memory at a time. Returns how many chunks were stripped.
+pub fn clean(
+    input: impl Read,
+    output: &mut impl Write,
+    chunk_types: &[ChunkType],
+) -> crate::Result<usize> {
+    let mut recorder = Recorder {
+        inner: input,
+        seen: vec![],
+    };
+    let mut signature = vec![];
+    (&mut recorder).take(8).read_to_end(&mut signature)?;
+    output.write_all(&signature)?;
+    if signature != Png::STANDARD_HEADER {
+        io::copy(&mut recorder.inner, output)?;
+        return Ok(0);
+    }
+    recorder.seen.clear();
+
+    let strip = |chunk: &Chunk| match chunk_types.is_empty() {
+        true => !chunk.chunk_type().is_critical() && Envelope::is_envelope(chunk.data()),
+        false => chunk_types.contains(chunk.chunk_type()),
+    };
+    let mut stream = ChunkStream::after_signature(recorder);
+    let mut stripped = 0;
+    loop {
+        let next = stream.next();
+        let raw = std::mem::take(&mut stream.get_mut().seen);
+        match next {
+            None => break,
+            Some(Ok(chunk)) if strip(&chunk) => stripped += 1,
+            Some(Ok(_)) => output.write_all(&raw)?,
+            Some(Err(_)) => {
+                output.write_all(&raw)?;
+                io::copy(&mut stream.into_inner().inner, output)?;
+                break;
+            }
+        }
+    }
+    Ok(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn cleaned(input: &[u8], chunk_types: &[&str]) -> (Vec<u8>, usize) {
+        let types: Vec<ChunkType> = chunk_types
+            .iter()
+            .map(|t| ChunkType::from_str(t).unwrap())
+            .collect();
+        let mut out = vec![];
+        let stripped = clean(input, &mut out, &types).unwrap();
+        (out, stripped)
+    }
+
+    fn png(extra: Vec<Chunk>) -> Vec<u8> {
+        let mut chunks = vec![chunk("IHDR", b"header"), chunk("tEXt", b"Comment\0hi")];
+        chunks.extend(extra);
+        chunks.push(chunk("IEND", b""));
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_strips_envelopes_only() {
+        let envelope = Envelope::new(b"build 1234".to_vec()).as_bytes();
+        let stamped = png(vec![
+            chunk("ruSt", &envelope),
+            chunk("baRe", b"bare message"),
+            chunk("kvSt", &envelope),
+        ]);
+        let (out, stripped) = cleaned(&stamped, &[]);
+        assert_eq!(stripped, 2);
+        assert_eq!(out, png(vec![chunk("baRe", b"bare message")]));
+        assert_eq!(cleaned(&out, &[]), (out.clone(), 0));
+
+        let (out, stripped) = cleaned(&stamped, &["baRe", "ruSt"]);
+        assert_eq!(stripped, 2);
+        assert_eq!(out, png(vec![chunk("kvSt", &envelope)]));
+    }
+
+    #[test]
+    fn test_passes_anything_else_through() {
+        let plain = png(vec![]);
+        assert_eq!(cleaned(&plain, &[]), (plain.clone(), 0));
+
+        let mut damaged = png(vec![chunk("ruSt", &Envelope::new(vec![1]).as_bytes())]);
+        let len = damaged.len();
+        damaged[len - 20] ^= 0xff;
+        assert_eq!(cleaned(&damaged, &[]).0, damaged);
+
+        let trailing = [plain.clone(), b"appended".to_vec()].concat();
+        assert_eq!(cleaned(&trailing, &[]).0, trailing);
+
+        for input in [&b"not a png at all"[..], b"", &plain[..5]] {
+            assert_eq!(cleaned(input, &[]), (input.to_vec(), 0));
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::fmt;
+
+/// An IHDR, tRNS or bKGD chunk's data doesn't fit the layout the image's color type gives it.
+#[derive(Debug)]
+pub struct HeaderError {
+    reason: String,
+}
+impl HeaderError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad header chunk: {}", self.reason)
+    }
+}
+impl Error for HeaderError {}
+
+/// How the image's pixels are laid out, which decides how tRNS and bKGD are read.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// Whether every pixel carries its own alpha sample, which leaves no room for tRNS.
+    pub fn has_alpha(self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> crate::Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(HeaderError::boxed(format!(
+                "color type {} is not one of 0, 2, 3, 4 or 6",
+                value
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "truecolor",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale with alpha",
+            ColorType::Rgba => "truecolor with alpha",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The image header from the IHDR chunk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl Ihdr {
+    /// Parses the 13 bytes of an IHDR chunk.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let data: &[u8; 13] = data.try_into().map_err(|_| {
+            HeaderError::boxed(format!("IHDR is {} bytes instead of 13", data.len()))
+        })?;
+        Ok(Self {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            bit_depth: data[8],
+            color_type: ColorType::try_from(data[9])?,
+            interlaced: data[12] != 0,
+        })
+    }
+}
+
+/// A colour as `#rrggbb`, or `#rrrrggggbbbb` when a sample doesn't fit in a byte.
+fn hex(red: u16, green: u16, blue: u16) -> String {
+    match red.max(green).max(blue) {
+        0..=255 => format!("#{:02x}{:02x}{:02x}", red, green, blue),
+        _ => format!("#{:04x}{:04x}{:04x}", red, green, blue),
+    }
+}
+
+/// The big-endian u16 samples of a tRNS or bKGD chunk, which must be `count` of them.
+fn samples(
+    data: &[u8],
+    count: usize,
+    chunk: &str,
+    color_type: ColorType,
+) -> crate::Result<Vec<u16>> {
+    if data.len() != count * 2 {
+        return Err(HeaderError::boxed(format!(
+            "{} is {} bytes, a {} image needs {}",
+            chunk,
+            data.len(),
+            color_type,
+            count * 2
+        )));
+    }
+    Ok(data
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+/// Which pixels are transparent, from a tRNS chunk.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Transparency {
+    /// The alpha of each PLTE entry in turn; entries past the end are opaque.
+    PaletteAlpha(Vec<u8>),
+    /// The one gray level that is transparent.
+    Gray(u16),
+    /// The one colour that is transparent.
+    Rgb(u16, u16, u16),
+}
+
+impl Transparency {
+    /// Parses the data of a tRNS chunk in an image of `color_type`, which can't be one with
+    /// an alpha channel of its own.
+    pub fn parse(data: &[u8], color_type: ColorType) -> crate::Result<Self> {
+        match color_type {
+            ColorType::Indexed => Ok(Transparency::PaletteAlpha(data.to_vec())),
+            ColorType::Grayscale => {
+                Ok(Transparency::Gray(samples(data, 1, "tRNS", color_type)?[0]))
+            }
+            ColorType::Rgb => {
+                let rgb = samples(data, 3, "tRNS", color_type)?;
+                Ok(Transparency::Rgb(rgb[0], rgb[1], rgb[2]))
+            }
+            ColorType::GrayscaleAlpha | ColorType::Rgba => Err(HeaderError::boxed(format!(
+                "tRNS is not allowed in a {} image, which has an alpha channel already",
+                color_type
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Transparency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transparency::PaletteAlpha(alpha) => {
+                write!(f, "palette alpha: {} entries", alpha.len())
+            }
+            Transparency::Gray(level) => write!(f, "transparent gray level: {}", level),
+            Transparency::Rgb(red, green, blue) => {
+                write!(f, "transparent colour: {}", hex(*red, *green, *blue))
+            }
+        }
+    }
+}
+
+/// The colour to show the image against, from a bKGD chunk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Background {
+    /// An index into PLTE.
+    PaletteIndex(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+impl Background {
+    /// Parses the data of a bKGD chunk in an image of `color_type`.
+    pub fn parse(data: &[u8], color_type: ColorType) -> crate::Result<Self> {
+        match color_type {
+            ColorType::Indexed => match data {
+                [index] => Ok(Background::PaletteIndex(*index)),
+                _ => Err(HeaderError::boxed(format!(
+                    "bKGD is {} bytes, an indexed image needs 1",
+                    data.len()
+                ))),
+            },
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+                Ok(Background::Gray(samples(data, 1, "bKGD", color_type)?[0]))
+            }
+            ColorType::Rgb | ColorType::Rgba => {
+                let rgb = samples(data, 3, "bKGD", color_type)?;
+                Ok(Background::Rgb(rgb[0], rgb[1], rgb[2]))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Background::PaletteIndex(index) => write!(f, "background: palette entry {}", index),
+            Background::Gray(level) => write!(f, "background: gray level {}", level),
+            Background::Rgb(red, green, blue) => {
+                write!(f, "background: {}", hex(*red, *green, *blue))
+            }
+        }
+    }
+}
+
+/// A line describing a tRNS or bKGD chunk's data as an image of `color_type` reads it, or why
+/// it can't be read. `None` for other chunks, and for these two when the color type is unknown.
+pub fn describe(
+    chunk_type: &[u8; 4],
+    data: &[u8],
+    color_type: Option<ColorType>,
+) -> Option<String> {
+    let described = match (chunk_type, color_type?) {
+        (b"tRNS", color_type) => Transparency::parse(data, color_type).map(|t| t.to_string()),
+        (b"bKGD", color_type) => Background::parse(data, color_type).map(|b| b.to_string()),
+        _ => return None,
+    };
+    Some(described.unwrap_or_else(|e| e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ihdr() {
+        let mut data = [0, 0, 1, 0, 0, 0, 0, 20, 8, 3, 0, 0, 1];
+        let ihdr = Ihdr::parse(&data).unwrap();
+        assert_eq!((ihdr.width, ihdr.height, ihdr.bit_depth), (256, 20, 8));
+        assert_eq!(ihdr.color_type, ColorType::Indexed);
+        assert!(ihdr.interlaced);
+
+        data[9] = 5;
+        assert!(Ihdr::parse(&data)
+            .unwrap_err()
+            .to_string()
+            .contains("color type 5"));
+        assert!(Ihdr::parse(b"header").is_err());
+    }
+
+    #[test]
+    fn test_transparency_by_color_type() {
+        assert_eq!(
+            Transparency::parse(&[255, 128, 0], ColorType::Indexed).unwrap(),
+            Transparency::PaletteAlpha(vec![255, 128, 0])
+        );
+        assert_eq!(
+            Transparency::parse(&[0, 7], ColorType::Grayscale).unwrap(),
+            Transparency::Gray(7)
+        );
+        let rgb = Transparency::parse(&[0, 255, 0, 0, 0, 16], ColorType::Rgb).unwrap();
+        assert_eq!(rgb, Transparency::Rgb(255, 0, 16));
+        assert_eq!(rgb.to_string(), "transparent colour: #ff0010");
+        assert_eq!(
+            Transparency::PaletteAlpha(vec![0; 14]).to_string(),
+            "palette alpha: 14 entries"
+        );
+
+        assert!(Transparency::parse(&[0, 7, 0], ColorType::Grayscale).is_err());
+        for color_type in [ColorType::GrayscaleAlpha, ColorType::Rgba] {
+            let error = Transparency::parse(&[0, 7], color_type).unwrap_err();
+            assert!(error.to_string().contains("alpha channel already"));
+        }
+    }
+
+    #[test]
+    fn test_background_by_color_type() {
+        assert_eq!(
+            Background::parse(&[4], ColorType::Indexed).unwrap(),
+            Background::PaletteIndex(4)
+        );
+        assert_eq!(
+            Background::parse(&[1, 0], ColorType::GrayscaleAlpha).unwrap(),
+            Background::Gray(256)
+        );
+        let rgb = Background::parse(&[0, 18, 0, 52, 0, 86], ColorType::Rgba).unwrap();
+        assert_eq!(rgb.to_string(), "background: #123456");
+        assert_eq!(
+            Background::Rgb(65535, 0, 4660).to_string(),
+            "background: #ffff00001234"
+        );
+
+        assert!(Background::parse(&[0, 4], ColorType::Indexed).is_err());
+        assert!(Background::parse(&[0, 1, 0, 2], ColorType::Rgb).is_err());
+        assert_eq!(describe(b"bKGD", &[4], None), None);
+        assert_eq!(describe(b"IDAT", &[4], Some(ColorType::Indexed)), None);
+    }
+}
@@ -0,0 +1,245 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compress::{self, Codec};
+use crate::envelope::Envelope;
+use crate::png::Png;
+
+/// Private and ancillary, holding the messages `undo` can bring back.
+pub const HISTORY_CHUNK: &str = "unDo";
+const HISTORY_VERSION: u8 = 1;
+
+/// The history chunk can't be read.
+#[derive(Debug)]
+pub struct HistoryError {
+    reason: String,
+}
+impl HistoryError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "History error: {}", self.reason)
+    }
+}
+impl Error for HistoryError {}
+
+/// Where a message was stored, which is what its previous versions are kept under.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Target {
+    ChunkType(ChunkType),
+    Label(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::ChunkType(ctype) => write!(f, "chunk type {}", ctype),
+            Target::Label(label) => write!(f, "label {:?}", label),
+        }
+    }
+}
+
+/// The chunks a message was stored in before it was replaced or removed.
+struct Entry {
+    target: Target,
+    chunks: Vec<Chunk>,
+}
+
+/// Records `chunks` as the most recent previous version of the message at `target`, keeping
+/// at most `depth` versions of it and dropping the oldest beyond that.
+pub fn push(png: &mut Png, target: &Target, chunks: Vec<Chunk>, depth: usize) -> crate::Result<()> {
+    let mut entries = read(png)?;
+    entries.push(Entry {
+        target: target.clone(),
+        chunks,
+    });
+    let kept = entries.iter().filter(|e| e.target == *target).count();
+    for _ in depth..kept {
+        let oldest = entries.iter().position(|e| e.target == *target);
+        entries.remove(oldest.expect("counted above"));
+    }
+    write(png, &entries)
+}
+
+/// Takes the most recent previous version of the message at `target` out of the history.
+pub fn pop(png: &mut Png, target: &Target) -> crate::Result<Option<Vec<Chunk>>> {
+    let mut entries = read(png)?;
+    let Some(latest) = entries.iter().rposition(|e| e.target == *target) else {
+        return Ok(None);
+    };
+    let entry = entries.remove(latest);
+    write(png, &entries)?;
+    Ok(Some(entry.chunks))
+}
+
+/// Whether `chunk`, the history chunk of a png, keeps any previous version of the message at
+/// `target`.
+pub fn keeps(chunk: &Chunk, target: &Target) -> crate::Result<bool> {
+    Ok(entries(chunk)?.iter().any(|e| e.target == *target))
+}
+
+fn read(png: &Png) -> crate::Result<Vec<Entry>> {
+    match png.chunk_by_type(HISTORY_CHUNK) {
+        Some(chunk) => entries(chunk),
+        None => Ok(vec![]),
+    }
+}
+
+fn entries(chunk: &Chunk) -> crate::Result<Vec<Entry>> {
+    let envelope = Envelope::from_bytes(chunk.data())?.ok_or_else(|| {
+        HistoryError::boxed("the chunk doesn't hold a pngme envelope".to_string())
+    })?;
+    if !envelope.is_intact() {
+        return Err(HistoryError::boxed(
+            "the history doesn't match its checksum".to_string(),
+        ));
+    }
+    let data = match envelope.codec {
+        Some(codec) => compress::decompress(codec, &envelope.payload)?,
+        None => envelope.payload,
+    };
+    decode(&data)
+}
+
+/// Rewrites the history chunk where it was, or before IEND, removing it once it is empty.
+fn write(png: &mut Png, entries: &[Entry]) -> crate::Result<()> {
+    let existing = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().to_string() == HISTORY_CHUNK);
+    if let Some(idx) = existing {
+        png.remove_chunk(idx)?;
+    }
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let (compressed, stats) = compress::compress(&encode(entries), Codec::Deflate, 0)?;
+    let data = Envelope::new(compressed).with_codec(stats.codec).as_bytes();
+    let chunk = Chunk::new(ChunkType::from_str(HISTORY_CHUNK)?, data);
+    let iend = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().bytes() == *b"IEND");
+    match existing.or(iend) {
+        Some(idx) => png.insert_chunk(idx, chunk)?,
+        None => png.append_chunk(chunk),
+    }
+    Ok(())
+}
+
+/// The history as a version byte followed by each entry, oldest first, as a kind byte (0 for
+/// a chunk type, 1 for a label), a big endian u16 name length, the name, a big endian u32
+/// chunk count and each chunk as its type, a big endian u32 data length and the data.
+fn encode(entries: &[Entry]) -> Vec<u8> {
+    let mut out = vec![HISTORY_VERSION];
+    for entry in entries {
+        let (kind, name) = match &entry.target {
+            Target::ChunkType(ctype) => (0, ctype.to_string()),
+            Target::Label(label) => (1, label.clone()),
+        };
+        out.push(kind);
+        out.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&(entry.chunks.len() as u32).to_be_bytes());
+        for chunk in &entry.chunks {
+            out.extend_from_slice(&chunk.chunk_type().bytes());
+            out.extend_from_slice(&chunk.length().to_be_bytes());
+            out.extend_from_slice(chunk.data());
+        }
+    }
+    out
+}
+
+fn decode(data: &[u8]) -> crate::Result<Vec<Entry>> {
+    let truncated = || HistoryError::boxed("the history is truncated".to_string());
+    let mut rest = match data.split_first() {
+        Some((&HISTORY_VERSION, rest)) => rest,
+        Some((version, _)) => {
+            return Err(HistoryError::boxed(format!(
+                "unsupported history version {}",
+                version
+            )))
+        }
+        None => return Err(truncated()),
+    };
+    let mut entries = vec![];
+    while let Some((&kind, after)) = rest.split_first() {
+        let (len, after) = after.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let len = u16::from_be_bytes(*len) as usize;
+        let name = after.get(..len).ok_or_else(truncated)?;
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| HistoryError::boxed("a name is not valid UTF-8".to_string()))?;
+        let target = match kind {
+            0 => Target::ChunkType(ChunkType::from_str(&name)?),
+            1 => Target::Label(name),
+            _ => return Err(HistoryError::boxed(format!("unknown entry kind {}", kind))),
+        };
+        let (count, mut after) = after[len..]
+            .split_first_chunk::<4>()
+            .ok_or_else(truncated)?;
+        let mut chunks = vec![];
+        for _ in 0..u32::from_be_bytes(*count) {
+            let (ctype, next) = after.split_first_chunk::<4>().ok_or_else(truncated)?;
+            let (len, next) = next.split_first_chunk::<4>().ok_or_else(truncated)?;
+            let len = u32::from_be_bytes(*len) as usize;
+            let data = next.get(..len).ok_or_else(truncated)?;
+            chunks.push(Chunk::new(ChunkType::try_from(*ctype)?, data.to_vec()));
+            after = &next[len..];
+        }
+        entries.push(Entry { target, chunks });
+        rest = after;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn png() -> Png {
+        Png::from_chunks(vec![chunk("IHDR", b"data"), chunk("IEND", b"")])
+    }
+
+    #[test]
+    fn test_pops_most_recent_first() {
+        let mut png = png();
+        let original = png.as_bytes();
+        let rust = Target::ChunkType(ChunkType::from_str("ruSt").unwrap());
+        let notes = Target::Label("notes".to_string());
+        push(&mut png, &rust, vec![chunk("ruSt", b"first")], 2).unwrap();
+        push(&mut png, &notes, vec![chunk("abCd", b"note")], 2).unwrap();
+        push(&mut png, &rust, vec![chunk("ruSt", b"second")], 2).unwrap();
+
+        let bytes = png.as_bytes();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), HISTORY_CHUNK);
+        assert_eq!(pop(&mut png, &rust).unwrap().unwrap()[0].data(), b"second");
+        assert_eq!(pop(&mut png, &rust).unwrap().unwrap()[0].data(), b"first");
+        assert!(pop(&mut png, &rust).unwrap().is_none());
+        assert_eq!(pop(&mut png, &notes).unwrap().unwrap()[0].data(), b"note");
+        assert_eq!(png.as_bytes(), original);
+    }
+
+    #[test]
+    fn test_depth_drops_the_oldest() {
+        let mut png = png();
+        let rust = Target::ChunkType(ChunkType::from_str("ruSt").unwrap());
+        for message in [&b"1"[..], b"2", b"3"] {
+            push(&mut png, &rust, vec![chunk("ruSt", message)], 2).unwrap();
+        }
+        assert_eq!(pop(&mut png, &rust).unwrap().unwrap()[0].data(), b"3");
+        assert_eq!(pop(&mut png, &rust).unwrap().unwrap()[0].data(), b"2");
+        assert!(pop(&mut png, &rust).unwrap().is_none());
+    }
+}
@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::secret::SecretBytes;
+
+/// Service name the passphrases are stored under in the platform credential store.
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "pngme";
+
+/// The credential store couldn't be used.
+#[derive(Debug)]
+pub struct KeyringError {
+    reason: String,
+}
+impl KeyringError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Keyring error: {}", self.reason)
+    }
+}
+impl Error for KeyringError {}
+
+/// Stores passphrases by id. The platform credential store is swapped for an in-memory map in
+/// tests.
+pub trait Keyring {
+    /// The passphrase stored under `id`, or `None` if there is none.
+    fn get(&self, id: &str) -> crate::Result<Option<SecretBytes>>;
+    /// Stores `passphrase` under `id`, replacing any passphrase already there.
+    fn set(&mut self, id: &str, passphrase: &SecretBytes) -> crate::Result<()>;
+    /// Removes the passphrase stored under `id`, returning whether there was one.
+    fn delete(&mut self, id: &str) -> crate::Result<bool>;
+}
+
+/// The platform credential store: the Keychain on macOS, the Credential Manager on Windows and
+/// the kernel keyring on Linux. Every operation fails when built without the `keyring` feature.
+pub struct OsKeyring;
+
+#[cfg(feature = "keyring")]
+impl OsKeyring {
+    fn entry(id: &str) -> crate::Result<::keyring::Entry> {
+        ::keyring::Entry::new(SERVICE, id).map_err(|e| KeyringError::boxed(e.to_string()).into())
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl Keyring for OsKeyring {
+    fn get(&self, id: &str) -> crate::Result<Option<SecretBytes>> {
+        match Self::entry(id)?.get_secret() {
+            Ok(secret) => Ok(Some(secret.into())),
+            Err(::keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(KeyringError::boxed(e.to_string())),
+        }
+    }
+
+    fn set(&mut self, id: &str, passphrase: &SecretBytes) -> crate::Result<()> {
+        Self::entry(id)?
+            .set_secret(passphrase.expose())
+            .map_err(|e| KeyringError::boxed(e.to_string()).into())
+    }
+
+    fn delete(&mut self, id: &str) -> crate::Result<bool> {
+        match Self::entry(id)?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(::keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(KeyringError::boxed(e.to_string())),
+        }
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+impl OsKeyring {
+    fn unsupported<T>() -> crate::Result<T> {
+        Err(KeyringError::boxed(
+            "pngme was built without the `keyring` feature".to_string(),
+        ))
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+impl Keyring for OsKeyring {
+    fn get(&self, _id: &str) -> crate::Result<Option<SecretBytes>> {
+        Self::unsupported()
+    }
+
+    fn set(&mut self, _id: &str, _passphrase: &SecretBytes) -> crate::Result<()> {
+        Self::unsupported()
+    }
+
+    fn delete(&mut self, _id: &str) -> crate::Result<bool> {
+        Self::unsupported()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Keeps passphrases in memory, or fails every operation like an unreachable store when
+    /// `broken` is set.
+    #[derive(Default)]
+    pub(crate) struct MemoryKeyring {
+        pub(crate) entries: HashMap<String, SecretBytes>,
+        pub(crate) broken: bool,
+    }
+
+    impl MemoryKeyring {
+        fn check(&self) -> crate::Result<()> {
+            match self.broken {
+                true => Err(KeyringError::boxed("store is locked".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl Keyring for MemoryKeyring {
+        fn get(&self, id: &str) -> crate::Result<Option<SecretBytes>> {
+            self.check()?;
+            Ok(self.entries.get(id).cloned())
+        }
+
+        fn set(&mut self, id: &str, passphrase: &SecretBytes) -> crate::Result<()> {
+            self.check()?;
+            self.entries.insert(id.to_string(), passphrase.clone());
+            Ok(())
+        }
+
+        fn delete(&mut self, id: &str) -> crate::Result<bool> {
+            self.check()?;
+            Ok(self.entries.remove(id).is_some())
+        }
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    #[test]
+    fn test_unsupported_without_feature() {
+        let err = OsKeyring.get("project-x").unwrap_err();
+        assert!(err.to_string().contains("`keyring` feature"));
+        assert!(OsKeyring.set("project-x", &"pass".into()).is_err());
+    }
+}
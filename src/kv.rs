@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+use crate::png::Png;
+
+/// Private and ancillary, holding every key of the store.
+pub const KV_CHUNK: &str = "kvSt";
+const KV_VERSION: u8 = 1;
+
+/// The key-value chunk can't be read, or a value can't be stored.
+#[derive(Debug)]
+pub struct KvError {
+    reason: String,
+}
+impl KvError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key-value store error: {}", self.reason)
+    }
+}
+impl Error for KvError {}
+
+/// A map of keys to values kept in a single chunk of a png. Every change is written back to
+/// the png straight away: the chunk is created by the first `set`, rewritten where it is by
+/// later changes, and removed when the last key is deleted.
+pub struct KvStore<'a> {
+    png: &'a mut Png,
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl<'a> KvStore<'a> {
+    /// Reads the store held in `png`, which is empty if the png has no key-value chunk.
+    pub fn open(png: &'a mut Png) -> crate::Result<Self> {
+        let entries = match png.chunk_by_type(KV_CHUNK) {
+            Some(chunk) => {
+                let envelope = Envelope::from_bytes(chunk.data())?.ok_or_else(|| {
+                    KvError::boxed("the chunk doesn't hold a pngme envelope".to_string())
+                })?;
+                if !envelope.is_intact() {
+                    return Err(KvError::boxed(
+                        "the map doesn't match its checksum".to_string(),
+                    ));
+                }
+                decode(&envelope.payload)?
+            }
+            None => BTreeMap::new(),
+        };
+        Ok(Self { png, entries })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(Vec::as_slice)
+    }
+
+    /// The keys in the store, in sorted order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Stores `value` under `key`, returning the value it replaced.
+    pub fn set(&mut self, key: &str, value: Vec<u8>) -> crate::Result<Option<Vec<u8>>> {
+        if key.is_empty() || key.len() > u16::MAX as usize {
+            return Err(KvError::boxed(format!(
+                "keys must be 1 to {} bytes long",
+                u16::MAX
+            )));
+        }
+        if u32::try_from(value.len()).is_err() {
+            return Err(KvError::boxed(format!(
+                "the value for {} is too large",
+                key
+            )));
+        }
+        let previous = self.entries.insert(key.to_string(), value);
+        self.write()?;
+        Ok(previous)
+    }
+
+    /// Deletes `key`, returning its value if it was there.
+    pub fn remove(&mut self, key: &str) -> crate::Result<Option<Vec<u8>>> {
+        let previous = self.entries.remove(key);
+        if previous.is_some() {
+            self.write()?;
+        }
+        Ok(previous)
+    }
+
+    fn write(&mut self) -> crate::Result<()> {
+        let existing = self
+            .png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().to_string() == KV_CHUNK);
+        if let Some(idx) = existing {
+            self.png.remove_chunk(idx)?;
+        }
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let data = Envelope::new(encode(&self.entries)).as_bytes();
+        let chunk = Chunk::new(ChunkType::from_str(KV_CHUNK)?, data);
+        let iend = self
+            .png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().bytes() == *b"IEND");
+        match existing.or(iend) {
+            Some(idx) => self.png.insert_chunk(idx, chunk)?,
+            None => self.png.append_chunk(chunk),
+        }
+        Ok(())
+    }
+}
+
+/// The map as a version byte followed by each entry as a big endian u16 key length, the key,
+/// a big endian u32 value length and the value.
+fn encode(entries: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+    let mut out = vec![KV_VERSION];
+    for (key, value) in entries {
+        out.extend_from_slice(&(key.len() as u16).to_be_bytes());
+        out.extend_from_slice(key.as_bytes());
+        out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+fn decode(data: &[u8]) -> crate::Result<BTreeMap<String, Vec<u8>>> {
+    let truncated = || KvError::boxed("the map is truncated".to_string());
+    let mut rest = match data.split_first() {
+        Some((&KV_VERSION, rest)) => rest,
+        Some((version, _)) => {
+            return Err(KvError::boxed(format!(
+                "unsupported map version {}",
+                version
+            )))
+        }
+        None => return Err(truncated()),
+    };
+    let mut entries = BTreeMap::new();
+    while !rest.is_empty() {
+        let (len, after) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let len = u16::from_be_bytes(*len) as usize;
+        let key = after.get(..len).ok_or_else(truncated)?;
+        let key = String::from_utf8(key.to_vec())
+            .map_err(|_| KvError::boxed("a key is not valid UTF-8".to_string()))?;
+        let (len, after) = after[len..]
+            .split_first_chunk::<4>()
+            .ok_or_else(truncated)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        let value = after.get(..len).ok_or_else(truncated)?;
+        entries.insert(key, value.to_vec());
+        rest = &after[len..];
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png() -> Png {
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), b"data".to_vec());
+        Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")])
+    }
+
+    fn kv_chunks(png: &Png) -> Vec<usize> {
+        png.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.chunk_type().to_string() == KV_CHUNK)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    #[test]
+    fn test_first_set_creates_the_chunk() {
+        let mut png = png();
+        assert!(png.kv().unwrap().is_empty());
+        assert!(kv_chunks(&png).is_empty());
+        png.kv().unwrap().set("build", b"1234".to_vec()).unwrap();
+        assert_eq!(kv_chunks(&png), vec![2]);
+
+        let bytes = png.as_bytes();
+        let mut reread = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(reread.kv().unwrap().get("build"), Some(&b"1234"[..]));
+    }
+
+    #[test]
+    fn test_overwrite_keeps_a_single_chunk() {
+        let mut png = png();
+        let mut kv = png.kv().unwrap();
+        kv.set("build", b"1234".to_vec()).unwrap();
+        kv.set("branch", b"main".to_vec()).unwrap();
+        let previous = kv.set("build", b"1235".to_vec()).unwrap();
+        assert_eq!(previous.as_deref(), Some(&b"1234"[..]));
+        assert_eq!(kv.keys().collect::<Vec<_>>(), ["branch", "build"]);
+        assert_eq!(kv_chunks(&png), vec![2]);
+        assert_eq!(png.kv().unwrap().get("build"), Some(&b"1235"[..]));
+    }
+
+    #[test]
+    fn test_deleting_the_last_key_removes_the_chunk() {
+        let mut png = png();
+        let original = png.as_bytes();
+        let mut kv = png.kv().unwrap();
+        kv.set("a", b"1".to_vec()).unwrap();
+        kv.set("b", b"2".to_vec()).unwrap();
+        assert_eq!(kv.remove("a").unwrap().as_deref(), Some(&b"1"[..]));
+        assert_eq!(kv.remove("a").unwrap(), None);
+        assert_eq!(kv_chunks(&png).len(), 1);
+        png.kv().unwrap().remove("b").unwrap();
+        assert!(kv_chunks(&png).is_empty());
+        assert_eq!(png.as_bytes(), original);
+    }
+
+    #[test]
+    fn test_binary_values() {
+        let value: Vec<u8> = (0..=255).collect();
+        let mut png = png();
+        png.kv().unwrap().set("blob", value.clone()).unwrap();
+        png.kv().unwrap().set("empty", vec![]).unwrap();
+        let bytes = png.as_bytes();
+        let mut reread = Png::try_from(&bytes[..]).unwrap();
+        let kv = reread.kv().unwrap();
+        assert_eq!(kv.get("blob"), Some(&value[..]));
+        assert_eq!(kv.get("empty"), Some(&[][..]));
+    }
+
+    #[test]
+    fn test_damaged_map_is_an_error() {
+        let mut map = BTreeMap::new();
+        map.insert("key".to_string(), b"value".to_vec());
+        let data = encode(&map);
+        assert_eq!(decode(&data).unwrap(), map);
+        assert!(decode(&data[..data.len() - 1]).is_err());
+        assert!(decode(&[2]).is_err());
+    }
+}
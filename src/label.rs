@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+
+/// The chunk type a payload stored under `label` is written to: ancillary, private and safe
+/// to copy, with the letters taken from a hash of the label. Only 'a' to 'y' are used, as
+/// `ChunkType` didn't accept 'z' when labels were added, and changing the letters would lose
+/// track of payloads already stored.
+pub fn chunk_type(label: &str) -> ChunkType {
+    let hash = Sha256::digest(label.as_bytes());
+    let letter = |byte: u8| b'a' + byte % 25;
+    let code = [
+        letter(hash[0]),
+        letter(hash[1]),
+        letter(hash[2]).to_ascii_uppercase(),
+        letter(hash[3]),
+    ];
+    ChunkType::try_from(code).expect("derived chunk types only use valid letters")
+}
+
+/// The label recorded in the envelope stored in `chunk`, if it has one.
+pub fn label_of(chunk: &Chunk) -> Option<String> {
+    Envelope::from_bytes(chunk.data()).ok()??.label
+}
+
+/// Positions of the chunks holding the payload stored under `label`. Chunks of the derived
+/// type are preferred, and only if none of those carry the label are the other private chunks
+/// searched, so payloads stay reachable even if the derivation changes. Other labels that
+/// happen to derive the same type are told apart by the label in their envelopes.
+pub fn find(chunks: &[Chunk], label: &str) -> Vec<usize> {
+    let derived = chunk_type(label);
+    let labelled: Vec<usize> = chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !c.chunk_type().is_public() && label_of(c).as_deref() == Some(label))
+        .map(|(idx, _)| idx)
+        .collect();
+    let preferred: Vec<usize> = labelled
+        .iter()
+        .copied()
+        .filter(|&idx| *chunks[idx].chunk_type() == derived)
+        .collect();
+    match preferred.is_empty() {
+        true => labelled,
+        false => preferred,
+    }
+}
+
+/// A labelled payload found in a png.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Labelled {
+    pub label: String,
+    pub chunk_type: String,
+    /// How many chunks hold copies of the payload
+    pub copies: usize,
+}
+
+/// Every labelled payload in `chunks`, in the order they first appear.
+pub fn list(chunks: &[Chunk]) -> Vec<Labelled> {
+    let mut found: Vec<Labelled> = vec![];
+    for chunk in chunks.iter().filter(|c| !c.chunk_type().is_public()) {
+        let Some(label) = label_of(chunk) else {
+            continue;
+        };
+        let chunk_type = chunk.chunk_type().to_string();
+        match found
+            .iter_mut()
+            .find(|l| l.label == label && l.chunk_type == chunk_type)
+        {
+            Some(existing) => existing.copies += 1,
+            None => found.push(Labelled {
+                label,
+                chunk_type,
+                copies: 1,
+            }),
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    fn labelled(chunk_type: &ChunkType, label: &str, payload: &[u8]) -> Chunk {
+        let envelope = Envelope::new(payload.to_vec()).with_label(label);
+        Chunk::new(*chunk_type, envelope.as_bytes())
+    }
+
+    #[test]
+    fn test_derivation_is_stable() {
+        // Changing these would leave existing payloads to be found only by scanning
+        assert_eq!(chunk_type("build-info").to_string(), "yhGu");
+        assert_eq!(chunk_type("license").to_string(), "eeJc");
+        assert_eq!(chunk_type("build-info"), chunk_type("build-info"));
+        let ctype = chunk_type("anything else");
+        assert!(!ctype.is_critical() && !ctype.is_public() && ctype.is_safe_to_copy());
+        assert!(ctype.is_reserved_bit_valid());
+    }
+
+    #[test]
+    fn test_find_by_label() {
+        let other = ChunkType::from_str("ruSt").unwrap();
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![]),
+            labelled(&chunk_type("license"), "license", b"key"),
+            labelled(&other, "build-info", b"moved"),
+            Chunk::new(other, b"bare message".to_vec()),
+        ];
+        assert_eq!(find(&chunks, "license"), vec![1]);
+        // Found even though it isn't stored under the derived type
+        assert_eq!(find(&chunks, "build-info"), vec![2]);
+        assert!(find(&chunks, "missing").is_empty());
+    }
+
+    #[test]
+    fn test_colliding_labels() {
+        let mut seen = HashMap::new();
+        let (a, b) = (0..)
+            .map(|i| format!("label-{}", i))
+            .find_map(|label| {
+                let previous = seen.insert(chunk_type(&label).bytes(), label.clone());
+                previous.map(|previous| (previous, label))
+            })
+            .unwrap();
+        let ctype = chunk_type(&a);
+        let chunks = vec![
+            labelled(&ctype, &a, b"first"),
+            labelled(&ctype, &b, b"second"),
+            labelled(&ctype, &a, b"first"),
+        ];
+        assert_eq!(find(&chunks, &a), vec![0, 2]);
+        assert_eq!(find(&chunks, &b), vec![1]);
+        let listed = list(&chunks);
+        assert_eq!(listed.len(), 2);
+        assert_eq!(
+            (listed[0].label.as_str(), listed[0].copies),
+            (a.as_str(), 2)
+        );
+        assert_eq!(
+            (listed[1].label.as_str(), listed[1].copies),
+            (b.as_str(), 1)
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+
+use crate::png::Png;
+
+/// The part of a png file a byte belongs to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Signature,
+    /// The 4 byte length at the start of a chunk
+    Length,
+    Type,
+    Data,
+    Crc,
+    /// Bytes after the last chunk, appended after IEND or left over from a truncated chunk
+    Trailing,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Region::Signature => "signature",
+            Region::Length => "length",
+            Region::Type => "type",
+            Region::Data => "data",
+            Region::Crc => "crc",
+            Region::Trailing => "trailing",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A run of bytes belonging to the same region, and to the same chunk if it is part of one.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Span {
+    pub bytes: Range<usize>,
+    pub region: Region,
+    /// The index and type of the chunk the bytes are part of
+    pub chunk: Option<(usize, String)>,
+    /// Where the chunk starts, or the span itself when it isn't part of a chunk
+    pub base: usize,
+}
+
+impl Span {
+    /// Names the span the way a person would point at it, like `IDAT chunk 3 data`.
+    pub fn label(&self) -> String {
+        match &self.chunk {
+            Some((index, chunk_type)) => {
+                format!("{} chunk {} {}", chunk_type, index, self.region)
+            }
+            None => self.region.to_string(),
+        }
+    }
+}
+
+/// Every span of a png file in order, for finding what lives at a byte offset.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    spans: Vec<Span>,
+}
+
+impl Layout {
+    /// The layout of `png` as it is stored, which is where the parser found each byte.
+    pub fn of(png: &Png) -> Self {
+        let signature = Png::STANDARD_HEADER.len();
+        let mut spans = vec![Span {
+            bytes: 0..signature,
+            region: Region::Signature,
+            chunk: None,
+            base: 0,
+        }];
+        let mut start = signature;
+        for (index, chunk) in png.chunks().iter().enumerate() {
+            let data_end = start + 8 + chunk.data().len();
+            let regions = [
+                (Region::Length, start..start + 4),
+                (Region::Type, start + 4..start + 8),
+                (Region::Data, start + 8..data_end),
+                (Region::Crc, data_end..data_end + 4),
+            ];
+            for (region, bytes) in regions.into_iter().filter(|(_, r)| !r.is_empty()) {
+                spans.push(Span {
+                    bytes,
+                    region,
+                    chunk: Some((index, chunk.chunk_type().to_string())),
+                    base: start,
+                });
+            }
+            start = data_end + 4;
+        }
+        let end = start + png.trailing_data().len();
+        if end > start {
+            spans.push(Span {
+                bytes: start..end,
+                region: Region::Trailing,
+                chunk: None,
+                base: start,
+            });
+        }
+        Self { spans }
+    }
+
+    /// The offset just past the last byte the layout covers, which is the size of the file.
+    pub fn end(&self) -> usize {
+        self.spans.last().map_or(0, |span| span.bytes.end)
+    }
+
+    /// The span holding the byte at `offset`, or `None` past the end of the file.
+    pub fn locate(&self, offset: usize) -> Option<&Span> {
+        let idx = self.spans.partition_point(|span| span.bytes.end <= offset);
+        self.spans.get(idx)
+    }
+
+    /// The spans overlapping `range`, in order.
+    pub fn spans_in(&self, range: Range<usize>) -> impl Iterator<Item = &Span> {
+        self.spans
+            .iter()
+            .filter(move |span| span.bytes.start < range.end && range.start < span.bytes.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_locate() {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        let mut bytes =
+            Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("IEND", b"")]).as_bytes();
+        bytes.extend_from_slice(b"tail");
+        let (png, _) = Png::parse_report_lenient(&bytes);
+        let layout = Layout::of(&png.unwrap());
+        assert_eq!(layout.end(), bytes.len());
+
+        // IHDR spans 8..26 and IEND, which has no data, 26..38
+        let at = |offset| {
+            layout
+                .locate(offset)
+                .map(|span| (span.label(), offset - span.base))
+        };
+        let expected = [
+            (0, "signature", 0),
+            (7, "signature", 7),
+            (8, "IHDR chunk 0 length", 0),
+            (13, "IHDR chunk 0 type", 5),
+            (17, "IHDR chunk 0 data", 9),
+            (25, "IHDR chunk 0 crc", 17),
+            (29, "IEND chunk 1 length", 3),
+            (31, "IEND chunk 1 type", 5),
+            (35, "IEND chunk 1 crc", 9),
+            (39, "trailing", 1),
+        ];
+        for (offset, label, relative) in expected {
+            assert_eq!(
+                at(offset),
+                Some((label.to_string(), relative)),
+                "{}",
+                offset
+            );
+        }
+        assert_eq!(at(bytes.len()), None);
+
+        let labels: Vec<String> = layout.spans_in(20..32).map(Span::label).collect();
+        assert_eq!(
+            labels,
+            [
+                "IHDR chunk 0 data",
+                "IHDR chunk 0 crc",
+                "IEND chunk 1 length",
+                "IEND chunk 1 type"
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! pngme hides messages in the chunks of png files. The command line in `main.rs` is a thin
+//! layer over these modules, which are also what the benchmarks exercise. The few modules of
+//! the command line's own, such as `args` and `commands`, are only public for `main.rs` and are
+//! left out of these docs.
+//!
+//! To embed or extract a message from another program, read a `Png`, add a `Chunk` holding an
+//! `Envelope` and write it back, as `tests/prelude.rs` does, or stream the message through
+//! `Png::payload_writer` and `Png::payload_reader`. `Png`, `Chunk`, `ChunkType` and
+//! `Envelope` report bad input, including chunk indices out of range, as errors rather than
+//! panicking.
+//!
+//! Errors are boxed as `pngme::Error`, each of a type defined by the module it came from.
+//! `error::Failure::of` sorts one into an enum to match on: a bad CRC, a bad chunk type, a
+//! truncated file, a missing chunk, an io error and so on. The command line's exit codes come
+//! from that enum too.
+
+pub mod apng;
+pub mod archive;
+#[doc(hidden)]
+pub mod args;
+pub mod audit;
+pub(crate) mod batch;
+#[doc(hidden)]
+pub mod cancel;
+pub(crate) mod capacity;
+pub mod chunk;
+pub mod chunk_type;
+#[doc(hidden)]
+pub mod commands;
+pub mod compress;
+pub mod crypto;
+pub mod diagnostic;
+pub(crate) mod digest;
+#[doc(hidden)]
+pub mod document;
+pub mod ecc;
+pub(crate) mod editor;
+pub mod envelope;
+pub mod error;
+pub mod expiry;
+pub mod extension;
+pub(crate) mod git_filter;
+pub mod header;
+pub mod history;
+pub(crate) mod keychain;
+pub mod kv;
+pub(crate) mod label;
+pub(crate) mod layout;
+pub(crate) mod lock;
+pub mod lsb;
+pub(crate) mod man;
+pub(crate) mod newline;
+#[doc(hidden)]
+pub mod output;
+pub mod padding;
+pub mod palette;
+pub mod patch;
+pub mod payload;
+pub mod png;
+#[doc(hidden)]
+pub mod profile;
+pub(crate) mod prompt;
+#[cfg(feature = "python")]
+pub mod python;
+#[doc(hidden)]
+pub mod remote;
+pub mod repair;
+pub(crate) mod rewrite;
+pub mod scan;
+pub mod seal;
+pub mod secret;
+pub mod shard;
+pub mod signing;
+pub(crate) mod sniff;
+pub mod stream;
+pub(crate) mod summary;
+pub(crate) mod template;
+pub mod text;
+pub(crate) mod triage;
+#[cfg(feature = "tui")]
+pub(crate) mod tui;
+pub mod verify;
+
+pub use chunk::Chunk;
+pub use chunk_type::ChunkType;
+pub use envelope::Envelope;
+pub use png::Png;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The types most uses of the library need, so `use pngme::prelude::*` is enough to read a
+/// png, add or find a chunk and wrap or unwrap a payload.
+pub mod prelude {
+    pub use crate::chunk::Chunk;
+    pub use crate::chunk_type::ChunkType;
+    pub use crate::diagnostic::{Diagnostic, DiagnosticKind, Warnings};
+    pub use crate::envelope::{self, Envelope};
+    pub use crate::error::{ExitCode, Failure};
+    pub use crate::png::{ParseOptions, Png};
+    pub use crate::{Error, Result};
+}
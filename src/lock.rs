@@ -0,0 +1,148 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a locked file is retried while waiting for it.
+const RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Another process holds the lock on the file.
+#[derive(Debug)]
+pub struct LockError {
+    reason: String,
+}
+impl LockError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lock error: {}", self.reason)
+    }
+}
+impl Error for LockError {}
+
+/// An exclusive advisory lock on a file, released when dropped. It only keeps out other
+/// pngme invocations, not programs that write the file without asking for the lock.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock too, this only makes it explicit
+        let _ = self.file.unlock();
+    }
+}
+
+/// Locks `path` for writing. Without `wait` this fails straight away if the file is already
+/// locked, otherwise it keeps trying for that long.
+pub fn acquire(path: &Path, wait: Option<Duration>) -> crate::Result<FileLock> {
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    loop {
+        // Opened afresh on every try: a writer that held the lock may have renamed a new file
+        // over the path, and a lock on the old one would keep nobody out
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        match file.try_lock() {
+            Ok(()) if is_current(&file, path)? => return Ok(FileLock { file }),
+            Ok(()) => continue,
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+            Err(TryLockError::WouldBlock) => {}
+        }
+        match deadline {
+            None => {
+                return Err(LockError::boxed(
+                    "file is locked by another pngme process".to_string(),
+                ))
+            }
+            Some(deadline) if Instant::now() >= deadline => {
+                return Err(LockError::boxed(format!(
+                    "file is still locked by another pngme process after {:?}",
+                    wait.unwrap_or_default()
+                )))
+            }
+            Some(_) => thread::sleep(RETRY_INTERVAL),
+        }
+    }
+}
+
+/// Whether `file` is still the file at `path`, rather than one a new file was renamed over.
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (locked, current) = (file.metadata()?, fs::metadata(path)?);
+    Ok(locked.dev() == current.dev() && locked.ino() == current.ino())
+}
+
+/// Windows doesn't rename over a file that is open, so the locked file is always current.
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_fails_fast() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let lock = acquire(file.path(), None).unwrap();
+        let err = acquire(file.path(), None).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("file is locked by another pngme process"));
+        drop(lock);
+        assert!(acquire(file.path(), None).is_ok());
+    }
+
+    #[test]
+    fn test_wait_times_out() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let _lock = acquire(file.path(), None).unwrap();
+        let start = Instant::now();
+        let err = acquire(file.path(), Some(Duration::from_millis(100))).unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(err.to_string().contains("still locked"));
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let lock = acquire(file.path(), None).unwrap();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| acquire(file.path(), Some(Duration::from_secs(10))).is_ok());
+            thread::sleep(Duration::from_millis(50));
+            drop(lock);
+            assert!(waiter.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_lock_follows_a_replaced_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+        let lock = acquire(&path, None).unwrap();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| acquire(&path, Some(Duration::from_secs(10))).unwrap());
+            thread::sleep(Duration::from_millis(50));
+            // What an atomic write does while holding the lock
+            let temporary = dir.path().join(".image.png.tmp");
+            fs::write(&temporary, b"new").unwrap();
+            fs::rename(&temporary, &path).unwrap();
+            drop(lock);
+            let lock = waiter.join().unwrap();
+            // The waiter holds the lock on the new file, so a newcomer is kept out
+            assert!(acquire(&path, None).is_err());
+            drop(lock);
+            assert!(acquire(&path, None).is_ok());
+        });
+    }
+}
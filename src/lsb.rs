@@ -0,0 +1,361 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compress::MAX_DECOMPRESSED_LEN;
+use crate::png::Png;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Bytes used in front of the payload to record its length.
+const LENGTH_PREFIX: usize = 4;
+
+/// The image can't carry a payload in its pixel data.
+#[derive(Debug)]
+pub struct LsbError {
+    reason: String,
+}
+impl LsbError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for LsbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LSB embedding failed: {}", self.reason)
+    }
+}
+impl Error for LsbError {}
+
+/// The decoded image: every scanline's filter type and the unfiltered sample bytes.
+struct Pixels {
+    stride: usize,
+    bpp: usize,
+    filters: Vec<u8>,
+    samples: Vec<u8>,
+}
+
+impl Pixels {
+    fn read(png: &Png) -> crate::Result<Self> {
+        let ihdr = png
+            .chunk_by_type("IHDR")
+            .ok_or_else(|| LsbError::boxed("no IHDR chunk".to_string()))?
+            .data();
+        if ihdr.len() != 13 {
+            return Err(LsbError::boxed(format!(
+                "IHDR is {} bytes instead of 13",
+                ihdr.len()
+            )));
+        }
+        let width = u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]) as usize;
+        let height = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]) as usize;
+        let (depth, color_type, interlace) = (ihdr[8], ihdr[9], ihdr[12]);
+        let channels = match (depth, color_type) {
+            (8, 0) => 1,
+            (8, 2) => 3,
+            (8, 4) => 2,
+            (8, 6) => 4,
+            _ => {
+                return Err(LsbError::boxed(format!(
+                    "only 8-bit grayscale and truecolor images are supported \
+                     (this one has color type {} at bit depth {})",
+                    color_type, depth
+                )))
+            }
+        };
+        if interlace != 0 {
+            return Err(LsbError::boxed(
+                "interlaced images are not supported".to_string(),
+            ));
+        }
+
+        let compressed: Vec<u8> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        let stride = width * channels;
+        let expected = height
+            .checked_mul(stride + 1)
+            .filter(|&expected| expected <= MAX_DECOMPRESSED_LEN)
+            .ok_or_else(|| LsbError::boxed(format!("a {}x{} image is too large", width, height)))?;
+        // Whatever inflates past the scanlines is never read, so it isn't inflated either
+        let mut raw = vec![];
+        ZlibDecoder::new(&compressed[..])
+            .take(expected as u64)
+            .read_to_end(&mut raw)?;
+        if raw.len() < expected {
+            return Err(LsbError::boxed(format!(
+                "image data is {} bytes, expected {}",
+                raw.len(),
+                expected
+            )));
+        }
+        let mut pixels = Pixels {
+            stride,
+            bpp: channels,
+            filters: Vec::with_capacity(height),
+            samples: vec![0; height * stride],
+        };
+        for row in 0..height {
+            let line = &raw[row * (stride + 1)..(row + 1) * (stride + 1)];
+            pixels.filters.push(line[0]);
+            pixels.unfilter(row, &line[1..])?;
+        }
+        Ok(pixels)
+    }
+
+    fn unfilter(&mut self, row: usize, line: &[u8]) -> crate::Result<()> {
+        let (before, current) = self.samples.split_at_mut(row * self.stride);
+        let current = &mut current[..self.stride];
+        let prior = (row > 0).then(|| &before[(row - 1) * self.stride..]);
+        for i in 0..self.stride {
+            let a = if i >= self.bpp {
+                current[i - self.bpp]
+            } else {
+                0
+            };
+            let b = prior.map_or(0, |p| p[i]);
+            let c = match prior {
+                Some(p) if i >= self.bpp => p[i - self.bpp],
+                _ => 0,
+            };
+            current[i] = line[i].wrapping_add(predict(self.filters[row], a, b, c)?);
+        }
+        Ok(())
+    }
+
+    /// How many payload bytes fit in the lowest bits of the samples.
+    fn capacity(&self) -> usize {
+        (self.samples.len() / 8).saturating_sub(LENGTH_PREFIX)
+    }
+
+    /// Filters and compresses the samples again, using each row's original filter type.
+    fn compress(&self) -> crate::Result<Vec<u8>> {
+        let mut raw = Vec::with_capacity(self.samples.len() + self.filters.len());
+        for (row, &filter) in self.filters.iter().enumerate() {
+            let current = &self.samples[row * self.stride..(row + 1) * self.stride];
+            let prior =
+                (row > 0).then(|| &self.samples[(row - 1) * self.stride..row * self.stride]);
+            raw.push(filter);
+            for i in 0..self.stride {
+                let a = if i >= self.bpp {
+                    current[i - self.bpp]
+                } else {
+                    0
+                };
+                let b = prior.map_or(0, |p| p[i]);
+                let c = match prior {
+                    Some(p) if i >= self.bpp => p[i - self.bpp],
+                    _ => 0,
+                };
+                raw.push(current[i].wrapping_sub(predict(filter, a, b, c)?));
+            }
+        }
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&raw)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Replaces the image's IDAT chunks, keeping as many as there were before so the list of
+    /// chunk types doesn't change.
+    fn write(&self, png: &mut Png) -> crate::Result<()> {
+        let compressed = self.compress()?;
+        let first = png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().bytes() == *b"IDAT")
+            .ok_or_else(|| LsbError::boxed("no IDAT chunk".to_string()))?;
+        let mut count = 0;
+        while png.remove_first_chunk("IDAT").is_ok() {
+            count += 1;
+        }
+        let part = compressed.len().div_ceil(count).max(1);
+        let mut parts: Vec<&[u8]> = compressed.chunks(part).collect();
+        parts.resize(count, &[]);
+        for (idx, data) in parts.into_iter().enumerate() {
+            let chunk = Chunk::new(ChunkType::from_str("IDAT")?, data.to_vec());
+            png.insert_chunk(first + idx, chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> crate::Result<u8> {
+    Ok(match filter {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => {
+            let p = a as i16 + b as i16 - c as i16;
+            let (pa, pb, pc) = (
+                (p - a as i16).abs(),
+                (p - b as i16).abs(),
+                (p - c as i16).abs(),
+            );
+            if pa <= pb && pa <= pc {
+                a
+            } else if pb <= pc {
+                b
+            } else {
+                c
+            }
+        }
+        _ => return Err(LsbError::boxed(format!("unknown filter type {}", filter))),
+    })
+}
+
+/// How many payload bytes the pixel data of `png` can carry, length prefix aside.
+pub fn capacity(png: &Png) -> crate::Result<usize> {
+    Ok(Pixels::read(png)?.capacity())
+}
+
+/// Hides `payload` in the least significant bit of each sample byte, preceded by its length,
+/// and rebuilds the IDAT chunks.
+pub fn embed(png: &mut Png, payload: &[u8]) -> crate::Result<()> {
+    let mut pixels = Pixels::read(png)?;
+    let capacity = pixels.capacity();
+    if payload.len() > capacity {
+        return Err(LsbError::boxed(format!(
+            "payload is {} bytes but the image can hold at most {}",
+            payload.len(),
+            capacity
+        )));
+    }
+    let bytes = [&(payload.len() as u32).to_be_bytes()[..], payload].concat();
+    let bits = bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1));
+    for (sample, bit) in pixels.samples.iter_mut().zip(bits) {
+        *sample = (*sample & !1) | bit;
+    }
+    pixels.write(png)
+}
+
+/// Reads a payload hidden by `embed`.
+pub fn extract(png: &Png) -> crate::Result<Vec<u8>> {
+    let pixels = Pixels::read(png)?;
+    let mut bytes = pixels.samples.chunks_exact(8).map(|bits| {
+        bits.iter()
+            .fold(0u8, |byte, sample| (byte << 1) | (sample & 1))
+    });
+    let prefix: Vec<u8> = bytes.by_ref().take(LENGTH_PREFIX).collect();
+    let len = match prefix[..] {
+        [a, b, c, d] => u32::from_be_bytes([a, b, c, d]) as usize,
+        _ => {
+            return Err(LsbError::boxed(
+                "image is too small to hold a payload".to_string(),
+            ))
+        }
+    };
+    let capacity = pixels.capacity();
+    if len > capacity {
+        return Err(LsbError::boxed(format!(
+            "no payload found (recorded length {} exceeds the capacity of {} bytes)",
+            len, capacity
+        )));
+    }
+    Ok(bytes.take(len).collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A `width` x `height` image with the given color type, cycling through all filter types.
+    pub(crate) fn generated_png(width: u32, height: u32, color_type: u8, channels: u32) -> Png {
+        let mut ihdr = [width.to_be_bytes(), height.to_be_bytes()].concat();
+        ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+        let stride = (width * channels) as usize;
+        let mut raw = vec![];
+        for row in 0..height as usize {
+            raw.push((row % 5) as u8);
+            raw.extend((0..stride).map(|i| (i * 7 + row * 13) as u8));
+        }
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let idat = encoder.finish().unwrap();
+        let (first, second) = idat.split_at(idat.len() / 2);
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        Png::from_chunks(vec![
+            chunk("IHDR", &ihdr),
+            chunk("tEXt", b"Comment\0generated"),
+            chunk("IDAT", first),
+            chunk("IDAT", second),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (color_type, channels) in [(0, 1), (2, 3), (4, 2), (6, 4)] {
+            let mut png = generated_png(20, 10, color_type, channels);
+            let original = Pixels::read(&png).unwrap().samples;
+            embed(&mut png, b"hidden in plain sight").unwrap();
+            let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+            assert_eq!(extract(&png).unwrap(), b"hidden in plain sight");
+
+            // Only the lowest bit of any sample changed
+            let changed = Pixels::read(&png).unwrap().samples;
+            assert!(original.iter().zip(&changed).all(|(a, b)| a & !1 == b & !1));
+        }
+    }
+
+    #[test]
+    fn test_chunk_types_are_unchanged() {
+        let mut png = generated_png(16, 16, 2, 3);
+        let before = chunk_types(&png);
+        embed(&mut png, b"payload").unwrap();
+        assert_eq!(chunk_types(&png), before);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut png = generated_png(8, 8, 2, 3);
+        let capacity = Pixels::read(&png).unwrap().capacity();
+        assert_eq!(capacity, 8 * 8 * 3 / 8 - LENGTH_PREFIX);
+        assert!(embed(&mut png, &vec![1; capacity + 1]).is_err());
+        embed(&mut png, &vec![1; capacity]).unwrap();
+        assert_eq!(extract(&png).unwrap(), vec![1; capacity]);
+    }
+
+    #[test]
+    fn test_unsupported_color_type() {
+        let mut png = generated_png(8, 8, 3, 1);
+        let err = embed(&mut png, b"x").unwrap_err();
+        assert!(err.to_string().contains("color type 3"));
+    }
+
+    #[test]
+    fn test_huge_dimensions() {
+        let mut png = generated_png(8, 8, 2, 3);
+        let mut ihdr = png.chunk_by_type("IHDR").unwrap().data().to_vec();
+        ihdr[..8].copy_from_slice(&[0xff; 8]);
+        png.remove_first_chunk("IHDR").unwrap();
+        png.insert_chunk(0, Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr))
+            .unwrap();
+        let err = capacity(&png).unwrap_err();
+        assert!(err.to_string().contains("too large"), "{}", err);
+    }
+
+    #[cfg(feature = "image-verify")]
+    #[test]
+    fn test_output_still_decodes() {
+        let mut png = generated_png(20, 10, 6, 4);
+        embed(&mut png, b"payload").unwrap();
+        crate::verify::verify_image(&png.as_bytes()).unwrap();
+    }
+}
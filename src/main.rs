@@ -1,14 +1,56 @@
-mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+use pngme::error::ExitCode;
+use pngme::{args, cancel, commands, document, error, output, profile, remote};
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
-
-fn main() -> Result<()> {
-    let args = args::parse_commands();
-    commands::run(args?);
-    Ok(())
+fn main() -> std::process::ExitCode {
+    let cli = match args::parse_commands() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            // clap exits with 2 for usage errors, which would read as an unreadable file
+            return match e.use_stderr() {
+                true => ExitCode::Failure.into(),
+                false => ExitCode::Success.into(),
+            };
+        }
+    };
+    // Without a handler, Ctrl-C would end the process with whatever it was writing half done
+    let _ = cancel::install(ExitCode::Interrupted as u8);
+    remote::set_max_download(cli.max_download);
+    output::set_no_pager(cli.no_pager);
+    output::set_exact_bytes(cli.bytes);
+    if cli.profile {
+        profile::enable();
+    }
+    let format = output::Format::select(cli.json.then_some(output::Format::Json).or(cli.format));
+    if format == output::Format::Json {
+        let (document, exit_code) = document::run(cli.command);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&document).expect("documents always serialize")
+        );
+        if let (true, Some(report)) = (cli.json_errors, &document.error) {
+            eprintln!(
+                "{}",
+                serde_json::to_string(report).expect("reports always serialize")
+            );
+        }
+        return std::process::ExitCode::from(exit_code);
+    }
+    let file = cli.command.file_path().map(str::to_string);
+    let outcome = commands::run(cli.command, format);
+    if cli.profile {
+        eprint!("{}", profile::render(&profile::take()));
+    }
+    let Err(e) = outcome else {
+        return ExitCode::Success.into();
+    };
+    let report = error::Report::new(&e, file.as_deref());
+    match cli.json_errors {
+        true => eprintln!(
+            "{}",
+            serde_json::to_string(&report).expect("reports always serialize")
+        ),
+        false => eprintln!("Error: {}", e),
+    }
+    std::process::ExitCode::from(report.exit_code)
 }
@@ -0,0 +1,125 @@
+use clap::CommandFactory;
+use clap_mangen::Man;
+use std::error::Error;
+use std::fmt;
+
+use crate::args::Cli;
+
+/// The manual page asked for doesn't exist.
+#[derive(Debug)]
+pub struct ManError {
+    reason: String,
+}
+impl ManError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for ManError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Manual error: {}", self.reason)
+    }
+}
+impl Error for ManError {}
+
+/// A rendered manual page.
+pub struct Page {
+    /// The page's name, such as `pngme-kv-set`
+    pub name: String,
+    pub roff: Vec<u8>,
+}
+
+impl Page {
+    pub fn file_name(&self) -> String {
+        format!("{}.1", self.name)
+    }
+}
+
+/// The page for pngme itself followed by one for every subcommand, nested ones included, all
+/// rendered from the clap definitions so they can't drift from `--help`.
+pub fn pages() -> crate::Result<Vec<Page>> {
+    let mut cmd = Cli::command();
+    // Building fills in names like `pngme-encode` and `pngme encode` for the subcommands
+    cmd.build();
+    let mut pages = vec![];
+    render(&cmd, &mut pages)?;
+    Ok(pages)
+}
+
+/// The page for `subcommand`, given as `encode` or `kv set`, or for pngme itself.
+pub fn page(subcommand: &[String]) -> crate::Result<Page> {
+    let name = ["pngme".to_string()]
+        .iter()
+        .chain(subcommand)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("-");
+    pages()?
+        .into_iter()
+        .find(|page| page.name == name)
+        .ok_or_else(|| {
+            ManError::boxed(format!("there is no {} subcommand", subcommand.join(" "))).into()
+        })
+}
+
+fn render(cmd: &clap::Command, pages: &mut Vec<Page>) -> crate::Result<()> {
+    let mut roff = vec![];
+    Man::new(cmd.clone()).render(&mut roff)?;
+    pages.push(Page {
+        name: cmd
+            .get_display_name()
+            .unwrap_or_else(|| cmd.get_name())
+            .to_string(),
+        roff,
+    });
+    for sub in cmd.get_subcommands().filter(|s| s.get_name() != "help") {
+        render(sub, pages)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(page: &Page) -> String {
+        String::from_utf8(page.roff.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_every_subcommand_has_a_page() {
+        let pages = pages().unwrap();
+        let names: Vec<&str> = pages.iter().map(|p| p.name.as_str()).collect();
+        for name in [
+            "pngme",
+            "pngme-encode",
+            "pngme-decode",
+            "pngme-kv-set",
+            "pngme-man",
+        ] {
+            assert!(names.contains(&name), "no page for {}", name);
+        }
+        assert!(!names.iter().any(|n| n.ends_with("help")));
+        assert_eq!(pages[1].file_name(), format!("{}.1", names[1]));
+
+        let top = text(&pages[0]);
+        assert!(top.contains(".TH pngme 1"));
+        assert!(top.contains("encode") && top.contains("decode") && top.contains("Exit codes"));
+    }
+
+    #[test]
+    fn test_single_page() {
+        let encode = text(&page(&["encode".to_string()]).unwrap());
+        assert!(encode.contains(".TH pngme-encode 1"));
+        assert!(encode.contains("\\fBpngme encode\\fR"));
+        assert!(encode.contains("pngme encode \\-f image.png \\-\\-label notes"));
+        assert!(encode.contains("\\-\\-chunk\\-type"));
+        assert!(encode.contains("Where to hide the message"));
+        assert!(encode.contains("least significant bits"));
+
+        let set = text(&page(&["kv".to_string(), "set".to_string()]).unwrap());
+        assert!(set.contains("\\-\\-value\\-file"));
+        assert!(page(&["nope".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use clap::ValueEnum;
+
+/// How line endings in a text message are treated before it is embedded or compared.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub enum Newline {
+    /// Leave the bytes exactly as they are
+    #[default]
+    Keep,
+    /// Convert CRLF line endings to LF
+    Lf,
+    /// Convert CRLF line endings to LF and drop any line endings at the very end
+    StripTrailing,
+}
+
+impl Newline {
+    /// Applies this mode to `bytes`.
+    pub fn apply(self, bytes: Vec<u8>) -> Vec<u8> {
+        match self {
+            Newline::Keep => bytes,
+            Newline::Lf => crlf_to_lf(&bytes),
+            Newline::StripTrailing => {
+                let mut out = crlf_to_lf(&bytes);
+                while out.last() == Some(&b'\n') {
+                    out.pop();
+                }
+                out
+            }
+        }
+    }
+}
+
+fn crlf_to_lf(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for (idx, &b) in bytes.iter().enumerate() {
+        if b == b'\r' && bytes.get(idx + 1) == Some(&b'\n') {
+            continue;
+        }
+        out.push(b);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRLF: &[u8] = b"line one\r\nline two\r\n";
+
+    #[test]
+    fn test_keep() {
+        assert_eq!(Newline::Keep.apply(CRLF.to_vec()), CRLF);
+    }
+
+    #[test]
+    fn test_lf() {
+        assert_eq!(Newline::Lf.apply(CRLF.to_vec()), b"line one\nline two\n");
+    }
+
+    #[test]
+    fn test_strip_trailing() {
+        assert_eq!(
+            Newline::StripTrailing.apply(CRLF.to_vec()),
+            b"line one\nline two"
+        );
+        assert_eq!(Newline::StripTrailing.apply(b"hi\n\n".to_vec()), b"hi");
+        assert_eq!(Newline::StripTrailing.apply(vec![]), b"");
+    }
+
+    #[test]
+    fn test_lone_carriage_returns_are_kept() {
+        assert_eq!(Newline::Lf.apply(b"a\rb\r".to_vec()), b"a\rb\r");
+    }
+}
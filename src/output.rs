@@ -0,0 +1,448 @@
+use clap::ValueEnum;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Used when `$PAGER` isn't set. -F quits right away when the text fits on one screen.
+const DEFAULT_PAGER: &str = "less -FRX";
+
+/// Set once from `--no-pager` before the command runs.
+static NO_PAGER: AtomicBool = AtomicBool::new(false);
+
+/// Set once from `--bytes` before the command runs.
+static EXACT_BYTES: AtomicBool = AtomicBool::new(false);
+
+/// Set before the command runs when the png it writes goes to stdout.
+static STDOUT_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The binary units sizes are given in, each 1024 times the one before, starting from KiB.
+const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+/// How a command lays out what it prints to stdout.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Headers and tables, for reading on a terminal
+    Pretty,
+    /// One tab-separated record per line and nothing else, for scripts
+    Plain,
+    /// One JSON document per run, as described in the document module
+    Json,
+    /// RFC 4180 CSV with a header row for tables, and plain otherwise
+    Csv,
+}
+
+impl Format {
+    /// `requested` if given, otherwise pretty when stdout is a terminal and plain when it is
+    /// piped or redirected.
+    pub fn select(requested: Option<Format>) -> Self {
+        Self::select_for(requested, io::stdout().is_terminal())
+    }
+
+    fn select_for(requested: Option<Format>, terminal: bool) -> Self {
+        match (requested, terminal) {
+            (Some(format), _) => format,
+            (None, true) => Format::Pretty,
+            (None, false) => Format::Plain,
+        }
+    }
+}
+
+/// Rows of values printed as a box-drawn table with a header when pretty, as CSV with a header
+/// when csv, and as bare tab-separated lines otherwise.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self {
+            headers: headers.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    pub fn row(&mut self, values: Vec<String>) {
+        debug_assert_eq!(values.len(), self.headers.len());
+        self.rows.push(values);
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Pretty => self.boxed(),
+            Format::Csv => self.csv(),
+            Format::Plain | Format::Json => self
+                .rows
+                .iter()
+                .map(|row| format!("{}\n", row.join("\t")))
+                .collect(),
+        }
+    }
+
+    /// Fields holding a comma, quote or line break are quoted, with quotes doubled, and every
+    /// record ends in CRLF as RFC 4180 asks.
+    fn csv(&self) -> String {
+        let record = |values: &mut dyn Iterator<Item = &str>| -> String {
+            let fields: Vec<String> = values
+                .map(|value| match value.contains([',', '"', '\r', '\n']) {
+                    true => format!("\"{}\"", value.replace('"', "\"\"")),
+                    false => value.to_string(),
+                })
+                .collect();
+            format!("{}\r\n", fields.join(","))
+        };
+        let mut out = record(&mut self.headers.iter().copied());
+        for row in &self.rows {
+            out += &record(&mut row.iter().map(String::as_str));
+        }
+        out
+    }
+
+    fn boxed(&self) -> String {
+        let widths: Vec<usize> = (0..self.headers.len())
+            .map(|col| {
+                self.rows
+                    .iter()
+                    .map(|row| row[col].chars().count())
+                    .chain([self.headers[col].chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let rule = |left: &str, mid: &str, right: &str| {
+            let cells: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+            format!("{}{}{}\n", left, cells.join(mid), right)
+        };
+        let line = |values: &[String]| {
+            let cells: Vec<String> = values
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| format!(" {:<width$} ", value, width = width))
+                .collect();
+            format!("│{}│\n", cells.join("│"))
+        };
+        let headers: Vec<String> = self.headers.iter().map(|h| h.to_string()).collect();
+        let mut out = rule("┌", "┬", "┐");
+        out += &line(&headers);
+        out += &rule("├", "┼", "┤");
+        for row in &self.rows {
+            out += &line(row);
+        }
+        out + &rule("└", "┴", "┘")
+    }
+}
+
+pub fn set_no_pager(no_pager: bool) {
+    NO_PAGER.store(no_pager, Ordering::Relaxed);
+}
+
+/// Prints `text` on stdout, through `$PAGER` when stdout is a terminal too short to show all
+/// of it and `--no-pager` wasn't given. Output that is piped is never paged.
+pub fn page(text: &str) -> io::Result<()> {
+    let height = match NO_PAGER.load(Ordering::Relaxed) || !io::stdout().is_terminal() {
+        true => None,
+        false => Some(terminal_height()),
+    };
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty());
+    page_with(
+        text,
+        height,
+        pager.as_deref().unwrap_or(DEFAULT_PAGER),
+        &mut io::stdout().lock(),
+    )
+}
+
+/// Runs `pager` on `text` if it has more lines than `height`, and writes it to `out` when
+/// there is no height to fill or the pager can't be started.
+fn page_with(
+    text: &str,
+    height: Option<usize>,
+    pager: &str,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if height.is_some_and(|height| text.lines().count() > height) && run_pager(pager, text).is_ok()
+    {
+        return Ok(());
+    }
+    out.write_all(text.as_bytes())
+}
+
+/// Feeds `text` to `pager` and waits for it to exit. The command is split on whitespace, with
+/// no shell quoting.
+fn run_pager(pager: &str, text: &str) -> io::Result<()> {
+    let mut words = pager.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::other("the pager command is empty"))?;
+    let mut child = process::Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let written = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes());
+    child.wait()?;
+    match written {
+        // Quitting the pager before the end closes the pipe, which is no reason to print again
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Rows of the terminal from `$LINES` or `stty size`, or 24 when neither tells.
+fn terminal_height() -> usize {
+    env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+        .or_else(stty_rows)
+        .unwrap_or(24)
+}
+
+#[cfg(unix)]
+fn stty_rows() -> Option<usize> {
+    let tty = fs::File::open("/dev/tty").ok()?;
+    let output = process::Command::new("stty")
+        .arg("size")
+        .stdin(tty)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8(output.stdout)
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn stty_rows() -> Option<usize> {
+    None
+}
+
+pub fn set_stdout_taken(taken: bool) {
+    STDOUT_TAKEN.store(taken, Ordering::Relaxed);
+}
+
+/// Prints a line for people to read on stdout, or on stderr when the png itself is being
+/// written to stdout and any text there would end up in the image.
+pub fn say(line: impl Display) {
+    match STDOUT_TAKEN.load(Ordering::Relaxed) {
+        true => eprintln!("{}", line),
+        false => println!("{}", line),
+    }
+}
+
+pub fn set_exact_bytes(exact: bool) {
+    EXACT_BYTES.store(exact, Ordering::Relaxed);
+}
+
+/// A size for people to read, such as `1.2 MiB (1,258,291 bytes)`, or `1,023 bytes` below a
+/// KiB. With `--bytes` only the exact count is given, as `1258291 bytes`, for scripts to parse.
+/// Every text report prints sizes through here so they read the same in all commands.
+pub fn size(bytes: usize) -> String {
+    size_with(bytes as u64, EXACT_BYTES.load(Ordering::Relaxed))
+}
+
+/// A change in size written like `size` with its sign in front, such as `+12 bytes`.
+pub fn size_change(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!(
+        "{}{}",
+        sign,
+        size_with(delta.unsigned_abs(), EXACT_BYTES.load(Ordering::Relaxed))
+    )
+}
+
+/// The value in the largest unit `bytes` reaches is rounded to the nearest tenth, halves up.
+/// One that rounds to 1024.0 is given in the next unit instead, so a byte under 1 MiB reads as
+/// `1.0 MiB` rather than `1024.0 KiB`.
+fn size_with(bytes: u64, exact: bool) -> String {
+    if exact {
+        return format!("{} bytes", bytes);
+    }
+    let count = format!("{} bytes", thousands(bytes));
+    let tenths = |power: usize| (bytes as u128 * 20 / (1u128 << (10 * power))).div_ceil(2);
+    let Some(mut power) = (1..=UNITS.len())
+        .take_while(|&p| bytes >> (10 * p) > 0)
+        .last()
+    else {
+        return count;
+    };
+    if tenths(power) >= 10240 && power < UNITS.len() {
+        power += 1;
+    }
+    let tenths = tenths(power);
+    format!(
+        "{}.{} {} ({})",
+        tenths / 10,
+        tenths % 10,
+        UNITS[power - 1],
+        count
+    )
+}
+
+/// `n` with its digits grouped in threes by commas, as `1,258,291`.
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// `bytes` as standard base64 with padding, for binary data in text output.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let n = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= group.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection() {
+        assert_eq!(Format::select_for(None, true), Format::Pretty);
+        assert_eq!(Format::select_for(None, false), Format::Plain);
+        assert_eq!(
+            Format::select_for(Some(Format::Pretty), false),
+            Format::Pretty
+        );
+        assert_eq!(Format::select_for(Some(Format::Json), true), Format::Json);
+    }
+
+    #[test]
+    fn test_page_bypass() {
+        let text = "one\ntwo\nthree\n";
+        for height in [None, Some(3)] {
+            let mut out = vec![];
+            page_with(text, height, "/no/such/pager", &mut out).unwrap();
+            assert_eq!(out, text.as_bytes());
+        }
+        // A pager that can't be started leaves the text to be printed directly
+        let mut out = vec![];
+        page_with(text, Some(2), "/no/such/pager", &mut out).unwrap();
+        assert_eq!(out, text.as_bytes());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_page_through_pager() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let pager = dir.path().join("pager");
+        fs::write(&pager, "#!/bin/sh\ncat > \"$1\"\n").unwrap();
+        fs::set_permissions(&pager, fs::Permissions::from_mode(0o755)).unwrap();
+        let recorded = dir.path().join("stdin");
+        let command = format!("{} {}", pager.display(), recorded.display());
+
+        let text = "one\ntwo\nthree\n";
+        let mut out = vec![];
+        page_with(text, Some(2), &command, &mut out).unwrap();
+        assert!(out.is_empty());
+        assert_eq!(fs::read_to_string(recorded).unwrap(), text);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe, 0x00, 0x3e]), "//4APg==");
+    }
+
+    #[test]
+    fn test_sizes() {
+        let cases = [
+            (0, "0 bytes"),
+            (1023, "1,023 bytes"),
+            (1024, "1.0 KiB (1,024 bytes)"),
+            (1536, "1.5 KiB (1,536 bytes)"),
+            (1023 * 1024 + 972, "1023.9 KiB (1,048,524 bytes)"),
+            ((1 << 20) - 1, "1.0 MiB (1,048,575 bytes)"),
+            (1 << 20, "1.0 MiB (1,048,576 bytes)"),
+            (1_258_291, "1.2 MiB (1,258,291 bytes)"),
+            (5 << 30, "5.0 GiB (5,368,709,120 bytes)"),
+            (1 << 50, "1024.0 TiB (1,125,899,906,842,624 bytes)"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(size_with(bytes, false), expected);
+        }
+        assert_eq!(size_with(1_258_291, true), "1258291 bytes");
+        assert_eq!(size_with(1023, true), "1023 bytes");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1000), "1,000");
+        assert_eq!(thousands(u64::MAX), "18,446,744,073,709,551,615");
+        assert_eq!(size_change(12), "+12 bytes");
+        assert_eq!(size_change(-2048), "-2.0 KiB (2,048 bytes)");
+    }
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["type", "length"]);
+        table.row(vec!["IHDR".to_string(), "13".to_string()]);
+        table.row(vec!["IEND".to_string(), "0".to_string()]);
+        assert_eq!(table.render(Format::Plain), "IHDR\t13\nIEND\t0\n");
+        assert_eq!(
+            table.render(Format::Pretty),
+            "┌──────┬────────┐\n\
+             │ type │ length │\n\
+             ├──────┼────────┤\n\
+             │ IHDR │ 13     │\n\
+             │ IEND │ 0      │\n\
+             └──────┴────────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut table = Table::new(&["type", "note"]);
+        table.row(vec!["tEXt".to_string(), "a, b".to_string()]);
+        table.row(vec!["ruSt".to_string(), "say \"hi\"".to_string()]);
+        let csv = table.render(Format::Csv);
+        assert_eq!(
+            csv,
+            "type,note\r\ntEXt,\"a, b\"\r\nruSt,\"say \"\"hi\"\"\"\r\n"
+        );
+        // Splitting on commas outside quotes gives back the same number of fields per record
+        for record in csv.split_terminator("\r\n") {
+            let mut quoted = false;
+            let fields = 1 + record
+                .chars()
+                .filter(|&c| {
+                    quoted ^= c == '"';
+                    c == ',' && !quoted
+                })
+                .count();
+            assert_eq!(fields, 2, "{}", record);
+        }
+    }
+}
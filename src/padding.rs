@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::crypto::Entropy;
+
+/// Bytes used in front of the message to record its length.
+const LENGTH_PREFIX: usize = 4;
+
+/// The message can't be padded as requested, or the padding can't be removed.
+#[derive(Debug)]
+pub struct PaddingError {
+    reason: String,
+}
+impl PaddingError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for PaddingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Padding error: {}", self.reason)
+    }
+}
+impl Error for PaddingError {}
+
+/// The size a message is padded to, counting the length header.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Padding {
+    /// Exactly this many bytes
+    To(usize),
+    /// The next multiple of this many bytes
+    Block(usize),
+}
+
+/// Prefixes `message` with its length and appends bytes from `entropy` up to the requested
+/// size.
+pub fn pad(message: &[u8], padding: Padding, entropy: &mut Entropy) -> crate::Result<Vec<u8>> {
+    let len = u32::try_from(message.len())
+        .map_err(|_| PaddingError::boxed("message is too long to pad".to_string()))?;
+    let needed = message.len() + LENGTH_PREFIX;
+    let target = match padding {
+        Padding::To(size) if size < needed => {
+            return Err(PaddingError::boxed(format!(
+                "message needs {} bytes with its length header, more than --pad-to {}",
+                needed, size
+            )))
+        }
+        Padding::To(size) => size,
+        Padding::Block(block) => needed.div_ceil(block.max(1)) * block.max(1),
+    };
+    let mut out = Vec::with_capacity(target);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(message);
+    out.resize(target, 0);
+    entropy.fill(&mut out[needed..])?;
+    Ok(out)
+}
+
+/// Recovers the message from data written by `pad`.
+pub fn unpad(padded: &[u8]) -> crate::Result<Vec<u8>> {
+    let (prefix, rest) = padded
+        .split_first_chunk::<LENGTH_PREFIX>()
+        .ok_or_else(|| PaddingError::boxed("length header is missing".to_string()))?;
+    let len = u32::from_be_bytes(*prefix) as usize;
+    rest.get(..len).map(<[u8]>::to_vec).ok_or_else(|| {
+        PaddingError::boxed(format!(
+            "recorded length {} exceeds the {} bytes stored",
+            len,
+            rest.len()
+        ))
+        .into()
+    })
+}
+
+/// Reads the message out of data written by `pad` as the data is read, rather than all at once
+/// as `unpad` does.
+pub struct Unpadded<R> {
+    inner: io::Take<R>,
+    len: u64,
+}
+
+impl<R: Read> Unpadded<R> {
+    /// Reads the length header from `reader`, leaving it at the start of the message.
+    pub fn new(mut reader: R) -> crate::Result<Self> {
+        let mut prefix = [0; LENGTH_PREFIX];
+        reader.read_exact(&mut prefix).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                PaddingError::boxed("length header is missing".to_string()).into()
+            }
+            _ => crate::Error::from(e),
+        })?;
+        let len = u64::from(u32::from_be_bytes(prefix));
+        Ok(Self {
+            inner: reader.take(len),
+            len,
+        })
+    }
+}
+
+impl<R: Read> Read for Unpadded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read == 0 && !buf.is_empty() && self.inner.limit() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                PaddingError {
+                    reason: format!(
+                        "recorded length {} exceeds the {} bytes stored",
+                        self.len,
+                        self.len - self.inner.limit()
+                    ),
+                },
+            ));
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pad_to() {
+        let padded = pad(b"short", Padding::To(64), &mut Entropy::Os).unwrap();
+        assert_eq!(padded.len(), 64);
+        assert_eq!(unpad(&padded).unwrap(), b"short");
+        assert_eq!(
+            pad(b"", Padding::To(4), &mut Entropy::Os).unwrap(),
+            [0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_pad_to_too_small() {
+        let err = pad(b"message", Padding::To(10), &mut Entropy::Os).unwrap_err();
+        assert!(err.to_string().contains("needs 11 bytes"));
+        assert!(pad(b"message", Padding::To(11), &mut Entropy::Os).is_ok());
+    }
+
+    #[test]
+    fn test_pad_block() {
+        assert_eq!(
+            pad(b"abc", Padding::Block(16), &mut Entropy::Os)
+                .unwrap()
+                .len(),
+            16
+        );
+        assert_eq!(
+            pad(&[1; 12], Padding::Block(16), &mut Entropy::Os)
+                .unwrap()
+                .len(),
+            16
+        );
+        assert_eq!(
+            pad(&[1; 13], Padding::Block(16), &mut Entropy::Os)
+                .unwrap()
+                .len(),
+            32
+        );
+    }
+
+    #[test]
+    fn test_unpad_rejects_bad_length() {
+        assert!(unpad(&[0, 0]).is_err());
+        assert!(unpad(&[0, 0, 0, 9, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_unpadded() {
+        let padded = pad(b"streamed", Padding::Block(32), &mut Entropy::Os).unwrap();
+        let mut message = vec![];
+        Unpadded::new(padded.as_slice())
+            .unwrap()
+            .read_to_end(&mut message)
+            .unwrap();
+        assert_eq!(message, b"streamed");
+
+        assert!(Unpadded::new([0, 0].as_slice()).is_err());
+        let mut short = Unpadded::new([0, 0, 0, 9, 1, 2].as_slice()).unwrap();
+        let err = short.read_to_end(&mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("exceeds the 2 bytes"), "{}", err);
+    }
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::fmt;
+
+/// The longest palette name the spec allows, in bytes.
+pub const MAX_NAME_LEN: usize = 79;
+
+/// An sPLT or hIST chunk's data doesn't have the layout the spec gives it.
+#[derive(Debug)]
+pub struct PaletteError {
+    reason: String,
+}
+impl PaletteError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad palette chunk: {}", self.reason)
+    }
+}
+impl Error for PaletteError {}
+
+/// One colour of a suggested palette. With a sample depth of 8 the samples are 0 to 255.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    /// How often the colour is used, relative to the palette's other entries
+    pub frequency: u16,
+}
+
+/// A suggested palette from an sPLT chunk, for viewers that can't show every colour.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// 8 or 16 bits per sample
+    pub sample_depth: u8,
+    pub entries: Vec<PaletteEntry>,
+}
+
+impl SuggestedPalette {
+    /// Parses the data of an sPLT chunk: a name of 1 to 79 Latin-1 bytes ending in a null, the
+    /// sample depth, and entries of 6 bytes at depth 8 or 10 bytes at depth 16.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let end = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| PaletteError::boxed("sPLT name is not null-terminated".to_string()))?;
+        let name = &data[..end];
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(PaletteError::boxed(format!(
+                "sPLT name is {} bytes, it must be 1 to {}",
+                name.len(),
+                MAX_NAME_LEN
+            )));
+        }
+        let (&sample_depth, entries) = data[end + 1..]
+            .split_first()
+            .ok_or_else(|| PaletteError::boxed("sPLT has no sample depth".to_string()))?;
+        let size = match sample_depth {
+            8 => 6,
+            16 => 10,
+            _ => {
+                return Err(PaletteError::boxed(format!(
+                    "sPLT sample depth is {}, it must be 8 or 16",
+                    sample_depth
+                )))
+            }
+        };
+        if !entries.len().is_multiple_of(size) {
+            return Err(PaletteError::boxed(format!(
+                "sPLT entries take {} bytes, not a multiple of {} for {}-bit samples",
+                entries.len(),
+                size,
+                sample_depth
+            )));
+        }
+        let entries = entries
+            .chunks_exact(size)
+            .map(|entry| {
+                let sample = |i: usize| match sample_depth {
+                    8 => u16::from(entry[i]),
+                    _ => u16::from_be_bytes([entry[2 * i], entry[2 * i + 1]]),
+                };
+                PaletteEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16::from_be_bytes([entry[size - 2], entry[size - 1]]),
+                }
+            })
+            .collect();
+        Ok(Self {
+            // Latin-1 maps each byte to the code point of the same value
+            name: name.iter().map(|&b| char::from(b)).collect(),
+            sample_depth,
+            entries,
+        })
+    }
+}
+
+impl fmt::Display for SuggestedPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "palette \"{}\", {} colour(s) of {}-bit samples",
+            self.name,
+            self.entries.len(),
+            self.sample_depth
+        )
+    }
+}
+
+/// How often each PLTE entry is used, from an hIST chunk.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Histogram {
+    pub frequencies: Vec<u16>,
+}
+
+impl Histogram {
+    /// Parses the data of an hIST chunk, a big-endian u16 per PLTE entry.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        if !data.len().is_multiple_of(2) {
+            return Err(PaletteError::boxed(format!(
+                "hIST is {} bytes, it must hold 2 per palette entry",
+                data.len()
+            )));
+        }
+        Ok(Self {
+            frequencies: data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frequencies of {} palette entries",
+            self.frequencies.len()
+        )?;
+        let busiest = self
+            .frequencies
+            .iter()
+            .enumerate()
+            .max_by_key(|&(index, &frequency)| (frequency, std::cmp::Reverse(index)));
+        if let Some((index, frequency)) = busiest {
+            write!(f, ", entry {} most used ({})", index, frequency)?;
+        }
+        Ok(())
+    }
+}
+
+/// A line describing an sPLT or hIST chunk's data, or why it can't be read. `None` for other
+/// chunks.
+pub fn describe(chunk_type: &[u8; 4], data: &[u8]) -> Option<String> {
+    let described = match chunk_type {
+        b"sPLT" => SuggestedPalette::parse(data).map(|p| p.to_string()),
+        b"hIST" => Histogram::parse(data).map(|h| h.to_string()),
+        _ => return None,
+    };
+    Some(described.unwrap_or_else(|e| e.to_string()))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// sPLT data named `name` holding `entries` at `depth`, each entry a colour and frequency.
+    pub fn splt(name: &str, depth: u8, entries: &[[u16; 5]]) -> Vec<u8> {
+        let mut data = [name.as_bytes(), &[0, depth]].concat();
+        for entry in entries {
+            for (i, &value) in entry.iter().enumerate() {
+                match (depth, i) {
+                    (8, 0..=3) => data.push(value as u8),
+                    _ => data.extend_from_slice(&value.to_be_bytes()),
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_suggested_palette() {
+        let palette = SuggestedPalette::parse(&splt(
+            "web",
+            8,
+            &[[255, 0, 0, 255, 10], [0, 0, 255, 128, 3]],
+        ))
+        .unwrap();
+        assert_eq!(palette.name, "web");
+        assert_eq!(palette.sample_depth, 8);
+        assert_eq!(
+            palette.entries[1],
+            PaletteEntry {
+                red: 0,
+                green: 0,
+                blue: 255,
+                alpha: 128,
+                frequency: 3
+            }
+        );
+        assert_eq!(
+            palette.to_string(),
+            "palette \"web\", 2 colour(s) of 8-bit samples"
+        );
+
+        let deep = SuggestedPalette::parse(&splt("deep", 16, &[[65535, 1, 2, 3, 4]])).unwrap();
+        assert_eq!(deep.entries[0].red, 65535);
+        assert_eq!(deep.entries[0].frequency, 4);
+    }
+
+    #[test]
+    fn test_malformed_suggested_palettes() {
+        let long = "x".repeat(MAX_NAME_LEN + 1);
+        let mut uneven = splt("web", 16, &[[1, 2, 3, 4, 5]]);
+        uneven.pop();
+        let cases = [
+            (b"web".to_vec(), "not null-terminated"),
+            (splt("", 8, &[]), "name is 0 bytes"),
+            (splt(&long, 8, &[]), "name is 80 bytes"),
+            (b"web\0".to_vec(), "no sample depth"),
+            (splt("web", 4, &[]), "sample depth is 4"),
+            (uneven, "take 9 bytes, not a multiple of 10"),
+        ];
+        for (data, expected) in cases {
+            let error = SuggestedPalette::parse(&data).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::parse(&[0, 5, 1, 0, 0, 7]).unwrap();
+        assert_eq!(histogram.frequencies, [5, 256, 7]);
+        assert_eq!(
+            histogram.to_string(),
+            "frequencies of 3 palette entries, entry 1 most used (256)"
+        );
+        assert!(Histogram::parse(&[0, 5, 1]).is_err());
+    }
+}
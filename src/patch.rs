@@ -0,0 +1,497 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::RefusedError;
+use crate::png::Png;
+
+/// Starts every patch file.
+const MAGIC: &[u8; 4] = b"pgPT";
+const VERSION: u8 = 1;
+
+const OP_ADD: u8 = 1;
+const OP_REMOVE: u8 = 2;
+const OP_MODIFY: u8 = 3;
+
+/// A patch file can't be read.
+#[derive(Debug)]
+pub struct PatchError {
+    reason: String,
+}
+impl PatchError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad patch: {}", self.reason)
+    }
+}
+impl Error for PatchError {}
+
+/// One change to the ancillary chunks of a png. Existing chunks are identified by their type
+/// and which occurrence of that type they are, and carry the CRC they had when the patch was
+/// made so a patch isn't applied over data it wasn't made for.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Op {
+    /// Insert a chunk just before the first chunk of the critical type `before`.
+    Add { chunk: Chunk, before: ChunkType },
+    Remove {
+        chunk_type: ChunkType,
+        index: u32,
+        crc: u32,
+    },
+    /// Replace the data of a chunk.
+    Modify {
+        chunk_type: ChunkType,
+        index: u32,
+        crc: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Add { chunk, before } => write!(
+                f,
+                "+ {} ({} bytes, before {})",
+                chunk.chunk_type(),
+                chunk.data().len(),
+                before
+            ),
+            Op::Remove {
+                chunk_type, index, ..
+            } => write!(f, "- {} #{}", chunk_type, index),
+            Op::Modify {
+                chunk_type,
+                index,
+                data,
+                ..
+            } => write!(f, "~ {} #{} ({} bytes)", chunk_type, index, data.len()),
+        }
+    }
+}
+
+/// The changes to the ancillary and private chunks between two versions of a png. Critical
+/// chunks are never part of a patch, so it can be applied to variants of the same image, such
+/// as other sizes of it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Patch {
+    pub ops: Vec<Op>,
+}
+
+impl Patch {
+    /// The patch turning the ancillary chunks of `old` into those of `new`. The nth chunk of a
+    /// type in one is compared with the nth of that type in the other.
+    pub fn between(old: &Png, new: &Png) -> Self {
+        let mut ops = vec![];
+        let mut types: Vec<ChunkType> = vec![];
+        for chunk in old.chunks().iter().chain(new.chunks()) {
+            if !chunk.chunk_type().is_critical() && !types.contains(chunk.chunk_type()) {
+                types.push(*chunk.chunk_type());
+            }
+        }
+        for chunk_type in types {
+            let old_chunks = occurrences(old, &chunk_type);
+            let new_chunks = occurrences(new, &chunk_type);
+            for index in 0..old_chunks.len().max(new_chunks.len()) {
+                match (old_chunks.get(index), new_chunks.get(index)) {
+                    (Some(a), Some(b)) if a.data() == b.data() => {}
+                    (Some(a), Some(b)) => ops.push(Op::Modify {
+                        chunk_type,
+                        index: index as u32,
+                        crc: a.crc(),
+                        data: b.data().to_vec(),
+                    }),
+                    (Some(a), None) => ops.push(Op::Remove {
+                        chunk_type,
+                        index: index as u32,
+                        crc: a.crc(),
+                    }),
+                    (None, Some(b)) => ops.push(Op::Add {
+                        chunk: (*b).clone(),
+                        before: critical_after(new, b),
+                    }),
+                    (None, None) => unreachable!("index is below the longer list's length"),
+                }
+            }
+        }
+        Self { ops }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Makes the changes to `png`. A chunk to be modified or removed that is missing or holds
+    /// other data than the patch was made from is refused, unless `force` is given: then
+    /// modifications to missing chunks become additions and removals of them are skipped.
+    pub fn apply(&self, png: &mut Png, force: bool) -> crate::Result<()> {
+        // Check every precondition before touching anything, so a refused patch changes nothing
+        for op in &self.ops {
+            let (Op::Remove {
+                chunk_type,
+                index,
+                crc,
+            }
+            | Op::Modify {
+                chunk_type,
+                index,
+                crc,
+                ..
+            }) = op
+            else {
+                continue;
+            };
+            let found = position(png, chunk_type, *index).map(|idx| png.chunks()[idx].crc());
+            if !force && found != Some(*crc) {
+                return Err(RefusedError::boxed(format!(
+                    "{} #{} {} than the patch expects, pass --force to apply it anyway",
+                    chunk_type,
+                    index,
+                    match found {
+                        Some(_) => "holds other data",
+                        None => "is missing, so the file has fewer chunks",
+                    }
+                )));
+            }
+        }
+        for op in &self.ops {
+            if let Op::Modify {
+                chunk_type,
+                index,
+                data,
+                ..
+            } = op
+            {
+                let chunk = Chunk::new(*chunk_type, data.clone());
+                match position(png, chunk_type, *index) {
+                    Some(idx) => {
+                        png.remove_chunk(idx)?;
+                        png.insert_chunk(idx, chunk)?;
+                    }
+                    None => insert_before(png, chunk, &iend())?,
+                }
+            }
+        }
+        // Later occurrences first, so removing one doesn't move the others
+        let mut removals: Vec<(&ChunkType, u32)> = self
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Remove {
+                    chunk_type, index, ..
+                } => Some((chunk_type, *index)),
+                _ => None,
+            })
+            .collect();
+        removals.sort_by_key(|&(_, index)| std::cmp::Reverse(index));
+        for (chunk_type, index) in removals {
+            if let Some(idx) = position(png, chunk_type, index) {
+                png.remove_chunk(idx)?;
+            }
+        }
+        for op in &self.ops {
+            if let Op::Add { chunk, before } = op {
+                insert_before(png, chunk.clone(), before)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&(self.ops.len() as u32).to_be_bytes());
+        for op in &self.ops {
+            match op {
+                Op::Add { chunk, before } => {
+                    out.push(OP_ADD);
+                    out.extend_from_slice(&chunk.chunk_type().bytes());
+                    out.extend_from_slice(&before.bytes());
+                    push_data(&mut out, chunk.data());
+                }
+                Op::Remove {
+                    chunk_type,
+                    index,
+                    crc,
+                } => {
+                    out.push(OP_REMOVE);
+                    out.extend_from_slice(&chunk_type.bytes());
+                    out.extend_from_slice(&index.to_be_bytes());
+                    out.extend_from_slice(&crc.to_be_bytes());
+                }
+                Op::Modify {
+                    chunk_type,
+                    index,
+                    crc,
+                    data,
+                } => {
+                    out.push(OP_MODIFY);
+                    out.extend_from_slice(&chunk_type.bytes());
+                    out.extend_from_slice(&index.to_be_bytes());
+                    out.extend_from_slice(&crc.to_be_bytes());
+                    push_data(&mut out, data);
+                }
+            }
+        }
+        out
+    }
+
+    /// Parses a patch written by `as_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(PatchError::boxed("not a pngme patch".to_string()));
+        }
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(PatchError::boxed(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let count = reader.u32()?;
+        let mut ops = vec![];
+        for _ in 0..count {
+            let op = reader.take(1)?[0];
+            let chunk_type = reader.chunk_type()?;
+            if chunk_type.is_critical() {
+                return Err(PatchError::boxed(format!(
+                    "critical chunk {} can't be patched",
+                    chunk_type
+                )));
+            }
+            ops.push(match op {
+                OP_ADD => {
+                    let before = reader.chunk_type()?;
+                    let data = reader.data()?;
+                    Op::Add {
+                        chunk: Chunk::new(chunk_type, data),
+                        before,
+                    }
+                }
+                OP_REMOVE => Op::Remove {
+                    chunk_type,
+                    index: reader.u32()?,
+                    crc: reader.u32()?,
+                },
+                OP_MODIFY => Op::Modify {
+                    chunk_type,
+                    index: reader.u32()?,
+                    crc: reader.u32()?,
+                    data: reader.data()?,
+                },
+                other => return Err(PatchError::boxed(format!("unknown operation {}", other))),
+            });
+        }
+        if !reader.0.is_empty() {
+            return Err(PatchError::boxed(format!(
+                "{} unexpected byte(s) at the end",
+                reader.0.len()
+            )));
+        }
+        Ok(Self { ops })
+    }
+}
+
+fn occurrences<'a>(png: &'a Png, chunk_type: &ChunkType) -> Vec<&'a Chunk> {
+    png.chunks()
+        .iter()
+        .filter(|c| c.chunk_type() == chunk_type)
+        .collect()
+}
+
+/// Where the `index`th chunk of `chunk_type` is in `png`.
+fn position(png: &Png, chunk_type: &ChunkType, index: u32) -> Option<usize> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.chunk_type() == chunk_type)
+        .nth(index as usize)
+        .map(|(idx, _)| idx)
+}
+
+/// The first critical chunk type following `chunk` in `png`, which is where it is put back.
+fn critical_after(png: &Png, chunk: &Chunk) -> ChunkType {
+    png.chunks()
+        .iter()
+        .skip_while(|c| !std::ptr::eq(*c, chunk))
+        .find(|c| c.chunk_type().is_critical())
+        .map_or(iend(), |c| *c.chunk_type())
+}
+
+/// Inserts `chunk` before the first chunk of type `before`, or before IEND or at the end if
+/// there is none.
+fn insert_before(png: &mut Png, chunk: Chunk, before: &ChunkType) -> crate::Result<()> {
+    let find = |t: &ChunkType| png.chunks().iter().position(|c| c.chunk_type() == t);
+    match find(before).or_else(|| find(&iend())) {
+        Some(idx) => png.insert_chunk(idx, chunk)?,
+        None => png.append_chunk(chunk),
+    }
+    Ok(())
+}
+
+fn iend() -> ChunkType {
+    ChunkType::try_from(*b"IEND").expect("IEND is a valid chunk type")
+}
+
+fn push_data(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(PatchError::boxed("the patch is truncated".to_string()));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> crate::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn chunk_type(&mut self) -> crate::Result<ChunkType> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+        ChunkType::try_from(bytes)
+    }
+
+    fn data(&mut self) -> crate::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn png(chunks: &[(&str, &[u8])]) -> Png {
+        Png::from_chunks(
+            chunks
+                .iter()
+                .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec()))
+                .collect(),
+        )
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| format!("{}={}", c.chunk_type(), String::from_utf8_lossy(c.data())))
+            .collect()
+    }
+
+    #[test]
+    fn test_between_ignores_critical_chunks() {
+        let old = png(&[
+            ("IHDR", b"big"),
+            ("tEXt", b"old"),
+            ("gAMA", b"g"),
+            ("IDAT", b"big"),
+            ("ruSt", b"gone"),
+            ("IEND", b""),
+        ]);
+        let new = png(&[
+            ("IHDR", b"other"),
+            ("tEXt", b"new"),
+            ("gAMA", b"g"),
+            ("pHYs", b"p"),
+            ("IDAT", b"other"),
+            ("IEND", b""),
+        ]);
+        let patch = Patch::between(&old, &new);
+        let shown: Vec<String> = patch.ops.iter().map(Op::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "~ tEXt #0 (3 bytes)",
+                "- ruSt #0",
+                "+ pHYs (1 bytes, before IDAT)"
+            ]
+        );
+        assert_eq!(Patch::from_bytes(&patch.as_bytes()).unwrap(), patch);
+        assert!(Patch::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_apply_to_a_variant() {
+        let old = png(&[
+            ("IHDR", b"1"),
+            ("tEXt", b"a"),
+            ("IDAT", b"1"),
+            ("IEND", b""),
+        ]);
+        let new = png(&[
+            ("IHDR", b"1"),
+            ("tEXt", b"b"),
+            ("pHYs", b"p"),
+            ("IDAT", b"1"),
+            ("ruSt", b"r"),
+            ("IEND", b""),
+        ]);
+        let patch = Patch::between(&old, &new);
+        let mut variant = png(&[
+            ("IHDR", b"2"),
+            ("tEXt", b"a"),
+            ("IDAT", b"2"),
+            ("IEND", b""),
+        ]);
+        patch.apply(&mut variant, false).unwrap();
+        assert_eq!(
+            types(&variant),
+            ["IHDR=2", "tEXt=b", "pHYs=p", "IDAT=2", "ruSt=r", "IEND="]
+        );
+    }
+
+    #[test]
+    fn test_preconditions() {
+        let old = png(&[
+            ("IHDR", b"1"),
+            ("tEXt", b"a"),
+            ("iTXt", b"i"),
+            ("IEND", b""),
+        ]);
+        let new = png(&[("IHDR", b"1"), ("tEXt", b"b"), ("IEND", b"")]);
+        let patch = Patch::between(&old, &new);
+
+        let mut edited = png(&[("IHDR", b"1"), ("tEXt", b"x"), ("IEND", b"")]);
+        let err = patch.apply(&mut edited, false).unwrap_err();
+        assert!(err.to_string().contains("tEXt #0 holds other data"));
+        assert_eq!(types(&edited), ["IHDR=1", "tEXt=x", "IEND="]);
+
+        patch.apply(&mut edited, true).unwrap();
+        assert_eq!(types(&edited), ["IHDR=1", "tEXt=b", "IEND="]);
+    }
+
+    #[test]
+    fn test_bad_patches() {
+        assert!(Patch::from_bytes(b"nope").is_err());
+        let mut bytes = Patch::default().as_bytes();
+        bytes[4] = 2;
+        assert!(Patch::from_bytes(&bytes).is_err());
+        let patch = Patch {
+            ops: vec![Op::Remove {
+                chunk_type: ChunkType::from_str("tEXt").unwrap(),
+                index: 0,
+                crc: 1,
+            }],
+        };
+        let bytes = patch.as_bytes();
+        assert!(Patch::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut critical = bytes.clone();
+        critical[10..14].copy_from_slice(b"IDAT");
+        assert!(Patch::from_bytes(&critical).is_err());
+    }
+}
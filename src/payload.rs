@@ -0,0 +1,334 @@
+//! Streaming access to a message stored in the chunks of one type, the way encode stores it.
+//! `PayloadReader` gives the message back through `Read`, unwrapping, decrypting and inflating
+//! it as it is read, and `PayloadWriter` takes one through `Write` and stores it once finished.
+
+use std::io::{self, Cursor, Read, Write};
+use std::mem;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compress::{self, Codec};
+use crate::crypto::{self, Entropy, KeySource};
+use crate::envelope::{self, Envelope, EnvelopeError};
+use crate::error::{NotFoundError, UsageError};
+use crate::padding::Unpadded;
+use crate::png::{Placement, Png};
+use crate::shard::{self, ShardReader};
+
+/// How `PayloadWriter` stores a message, following the encode options of the same names.
+#[derive(Debug, Clone, Default)]
+pub struct PayloadOptions {
+    /// Compress the message with this codec, unless that doesn't make it smaller
+    pub codec: Option<Codec>,
+    /// Encrypt the message with this key
+    pub key: Option<KeySource>,
+    /// Add this many parity bytes of error correction to every block
+    pub ecc: Option<u8>,
+    /// Label the message, as encode --label does
+    pub label: Option<String>,
+    /// Split the message over as many chunks as it takes for each to be at most this many
+    /// bytes, as encode --max-chunk-size does
+    pub max_chunk_size: Option<usize>,
+    /// Where the chunks go
+    pub placement: Placement,
+}
+
+/// Reads the message stored in the chunks of one type of a png, see `Png::payload_reader`.
+///
+/// Shards of a message split with `max_chunk_size` are read one at a time, and compressed
+/// messages are inflated as they are read, so neither is ever held whole. An encrypted message
+/// is the exception: it can only be authenticated whole, so it is decrypted up front.
+pub struct PayloadReader<'a> {
+    envelope: Envelope,
+    inner: Box<dyn Read + 'a>,
+}
+
+impl<'a> PayloadReader<'a> {
+    /// Finds the message in the chunks of `chunk_type`, decrypting it with `key` if it is
+    /// encrypted.
+    pub fn new(
+        png: &'a Png,
+        chunk_type: &ChunkType,
+        key: Option<&KeySource>,
+    ) -> crate::Result<Self> {
+        let chunks: Vec<&'a Chunk> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type() == chunk_type)
+            .collect();
+        let Some(first) = chunks.first() else {
+            return Err(NotFoundError::chunk(
+                &chunk_type.to_string(),
+                None,
+                format!("No {} chunk found", chunk_type),
+            ));
+        };
+        let (envelope, mut source): (Envelope, Box<dyn Read + 'a>) =
+            match Envelope::from_bytes(first.data())? {
+                None if chunks.len() == 1 => (Envelope::default(), Box::new(first.data())),
+                Some(envelope) if envelope.shard.is_some() => {
+                    let shards = ShardReader::new(&chunks)?;
+                    (shards.envelope().clone(), Box::new(shards))
+                }
+                _ => {
+                    let copies: Vec<crate::Result<Envelope>> = chunks
+                        .iter()
+                        .map(|c| {
+                            Envelope::from_bytes(c.data())?.ok_or_else(|| {
+                                EnvelopeError::boxed("not a pngme envelope".to_string()).into()
+                            })
+                        })
+                        .collect();
+                    let mut envelope = envelope::recover(&copies)?.envelope;
+                    let payload = mem::take(&mut envelope.payload);
+                    (envelope, Box::new(Cursor::new(payload)))
+                }
+            };
+        let source: Box<dyn Read + 'a> = match (envelope.cipher, key) {
+            (Some(cipher), Some(key)) => {
+                let mut ciphertext = vec![];
+                source.read_to_end(&mut ciphertext)?;
+                Box::new(Cursor::new(crypto::decrypt(key, &cipher, &ciphertext)?))
+            }
+            (Some(_), None) => {
+                return Err(UsageError::boxed(
+                    "The message is encrypted, it needs a key".to_string(),
+                ))
+            }
+            (None, _) => source,
+        };
+        let source: Box<dyn Read + 'a> = match envelope.padded {
+            true => Box::new(Unpadded::new(source)?),
+            false => source,
+        };
+        let inner = match envelope.codec {
+            Some(codec) => compress::decoder(codec, source)?,
+            None => source,
+        };
+        Ok(Self { envelope, inner })
+    }
+
+    /// The envelope the message was stored in, without the payload: its label, expiry,
+    /// signature and the like. A message stored bare has an empty one.
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+}
+
+impl Read for PayloadReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Stores a message written to it in chunks of one type of a png, see `Png::payload_writer`.
+///
+/// The message is held until it is finished, as its checksum, its encryption and how many
+/// shards it takes all depend on the whole of it. `finish` stores it and reports any error;
+/// flushing or dropping the writer stores it too.
+pub struct PayloadWriter<'a> {
+    png: &'a mut Png,
+    chunk_type: ChunkType,
+    options: PayloadOptions,
+    message: Vec<u8>,
+    finished: bool,
+}
+
+impl<'a> PayloadWriter<'a> {
+    pub fn new(png: &'a mut Png, chunk_type: ChunkType, options: PayloadOptions) -> Self {
+        Self {
+            png,
+            chunk_type,
+            options,
+            message: vec![],
+            finished: false,
+        }
+    }
+
+    /// Stores the message written so far, after which nothing more can be written.
+    pub fn finish(mut self) -> crate::Result<()> {
+        self.store()
+    }
+
+    fn store(&mut self) -> crate::Result<()> {
+        if mem::replace(&mut self.finished, true) {
+            return Ok(());
+        }
+        let envelope = seal(mem::take(&mut self.message), &self.options)?;
+        let envelopes = match self.options.max_chunk_size {
+            Some(max) => shard::split(envelope, max)?,
+            None => vec![envelope],
+        };
+        for envelope in envelopes {
+            let chunk = Chunk::new(self.chunk_type, envelope.as_bytes());
+            self.png.place_chunk(chunk, self.options.placement);
+        }
+        Ok(())
+    }
+}
+
+impl Write for PayloadWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("the message has already been stored"));
+        }
+        self.message.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.store().map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+impl Drop for PayloadWriter<'_> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, which is what `finish` is for
+        let _ = self.store();
+    }
+}
+
+/// Wraps `message` in an envelope, compressing and encrypting it and adding error correction
+/// as `options` ask, in the order encode does.
+fn seal(message: Vec<u8>, options: &PayloadOptions) -> crate::Result<Envelope> {
+    let (message, codec) = match options.codec {
+        Some(codec) => {
+            let (compressed, stats) = compress::compress(&message, codec, 0)?;
+            (compressed, Some(stats.codec))
+        }
+        None => (message, None),
+    };
+    let mut envelope = match &options.key {
+        Some(key) => {
+            let (cipher, ciphertext) = crypto::encrypt(key, &message, &mut Entropy::Os)?;
+            Envelope::new(ciphertext).with_cipher(cipher)
+        }
+        None => Envelope::new(message),
+    };
+    if let Some(codec) = codec {
+        envelope = envelope.with_codec(codec);
+    }
+    if let Some(parity) = options.ecc {
+        envelope = envelope.with_ecc(parity);
+    }
+    if let Some(label) = &options.label {
+        envelope = envelope.with_label(label);
+    }
+    Ok(envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ExitCode, Failure};
+    use crate::secret::SecretBytes;
+    use std::str::FromStr;
+
+    fn png() -> Png {
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]);
+        Png::from_chunks(vec![iend])
+    }
+
+    fn message() -> Vec<u8> {
+        (0..5000u32).flat_map(|i| (i % 251).to_be_bytes()).collect()
+    }
+
+    fn read(png: &Png, key: Option<&KeySource>) -> crate::Result<Vec<u8>> {
+        let mut out = vec![];
+        let mut reader = png.payload_reader(&ChunkType::from_str("ruSt")?, key)?;
+        reader.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn write(options: PayloadOptions) -> Png {
+        let mut png = png();
+        let mut writer = png.payload_writer(ChunkType::from_str("ruSt").unwrap(), options);
+        for part in message().chunks(333) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap();
+        png
+    }
+
+    #[test]
+    fn test_round_trip_in_shards() {
+        let options = PayloadOptions {
+            codec: Some(Codec::Deflate),
+            ecc: Some(16),
+            max_chunk_size: Some(256),
+            ..Default::default()
+        };
+        let png = write(options);
+        assert!(png.chunks_by_type("ruSt").len() > 1);
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+        assert_eq!(read(&png, None).unwrap(), message());
+    }
+
+    #[test]
+    fn test_round_trip_encrypted() {
+        let key = KeySource::Key(SecretBytes::from(vec![7; crypto::KEY_LEN]));
+        let options = PayloadOptions {
+            codec: Some(Codec::Zstd),
+            key: Some(key.clone()),
+            label: Some("notes".to_string()),
+            max_chunk_size: Some(512),
+            ..Default::default()
+        };
+        let png = write(options);
+        assert_eq!(read(&png, Some(&key)).unwrap(), message());
+        let err = read(&png, None).unwrap_err();
+        assert_eq!(Failure::of(&err).kind(), "Usage");
+        let reader = png
+            .payload_reader(&ChunkType::from_str("ruSt").unwrap(), Some(&key))
+            .unwrap();
+        assert_eq!(reader.envelope().label.as_deref(), Some("notes"));
+    }
+
+    #[test]
+    fn test_bare_chunk() {
+        let mut png = png();
+        png.place_chunk(
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"bare".to_vec()),
+            Placement::BeforeIend,
+        );
+        assert_eq!(read(&png, None).unwrap(), b"bare");
+        let err = png
+            .payload_reader(&ChunkType::from_str("miSs").unwrap(), None)
+            .err()
+            .unwrap();
+        assert_eq!(ExitCode::of(&Failure::of(&err)), ExitCode::NotFound);
+    }
+
+    #[test]
+    fn test_writer_stores_on_drop() {
+        let mut png = png();
+        {
+            let mut writer =
+                png.payload_writer(ChunkType::from_str("ruSt").unwrap(), Default::default());
+            writer.write_all(b"dropped").unwrap();
+        }
+        assert_eq!(read(&png, None).unwrap(), b"dropped");
+    }
+
+    #[test]
+    fn test_damaged_shard() {
+        let png = write(PayloadOptions {
+            max_chunk_size: Some(256),
+            ..Default::default()
+        });
+        let mut chunks = png.chunks().to_vec();
+        let mut data = chunks[1].data().to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        chunks[1] = Chunk::new(*chunks[1].chunk_type(), data);
+        let err = read(&Png::from_chunks(chunks), None).unwrap_err();
+        assert!(
+            err.to_string().contains("doesn't match its checksum"),
+            "{}",
+            err
+        );
+        assert_eq!(ExitCode::of(&Failure::of(&err)), ExitCode::Integrity);
+    }
+}
@@ -1,23 +1,89 @@
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
+use crate::crypto::KeySource;
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::error::NotFoundError;
+use crate::extension::{Offset, Scale, Stereo};
+use crate::header::{Background, ColorType, Ihdr, Transparency};
+use crate::kv::KvStore;
+use crate::palette::{Histogram, SuggestedPalette};
+use crate::payload::{PayloadOptions, PayloadReader, PayloadWriter};
+use crate::profile::{self, Phase};
+use crate::stream::ChunkStream;
+use crate::text::TextChunk;
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::ops::{ControlFlow, Range};
 use std::path::Path;
 use std::str::FromStr;
 
-#[derive(Debug)]
+/// Where `Png::place_chunk` puts a new chunk.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub enum Placement {
+    /// Just before IEND, which the spec allows for any ancillary chunk
+    #[default]
+    BeforeIend,
+    /// Before the first IDAT, with the metadata decoders read before the image data
+    BeforeIdat,
+    /// After IEND, where older versions of pngme put chunks. Strict decoders and optimizers
+    /// drop anything there.
+    End,
+}
+
+/// Controls how `Png::parse_report_with` treats damaged or embedded data.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// Report bad CRCs and a truncated final chunk as warnings instead of failing
+    pub lenient: bool,
+    /// Stop after IEND and ignore the rest of the input, for PNGs embedded in other files
+    pub stop_at_iend: bool,
+    /// Also report as errors what the spec lets decoders be lenient about: unknown or private
+    /// critical chunks, misplaced ancillary chunks and data in IEND. These never fail the parse.
+    pub strict_spec: bool,
+    /// Report as errors what makes a file not a complete png: a missing IHDR, IDAT or IEND,
+    /// IHDR anywhere but first, and the ordering problems otherwise only warned about, such
+    /// as chunks after IEND. These never fail the parse either.
+    pub complete: bool,
+}
+
+/// The only critical chunks the PNG spec defines.
+const KNOWN_CRITICAL: [&[u8; 4]; 4] = [b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+/// Ancillary chunks the spec places before both PLTE and IDAT.
+const BEFORE_PLTE: [&[u8; 4]; 8] = [
+    b"cHRM", b"cICP", b"cLLI", b"gAMA", b"iCCP", b"mDCV", b"sBIT", b"sRGB",
+];
+/// Ancillary chunks the spec places after PLTE, when there is one, and before IDAT.
+const AFTER_PLTE: [&[u8; 4]; 3] = [b"bKGD", b"hIST", b"tRNS"];
+/// Ancillary chunks the spec, or for oFFs, sCAL and sTER its extensions, places before IDAT.
+const BEFORE_IDAT: [&[u8; 4]; 7] = [
+    b"acTL", b"eXIf", b"oFFs", b"pHYs", b"sCAL", b"sPLT", b"sTER",
+];
+/// Ancillary chunks the spec, or for fcTL and fdAT its APNG additions, lets appear anywhere.
+const ANYWHERE: [&[u8; 4]; 6] = [b"fcTL", b"fdAT", b"iTXt", b"tEXt", b"tIME", b"zTXt"];
+
+/// A parsed png: its signature, its chunks and whatever follows IEND. Nothing in it changes
+/// behind a shared reference, so a `Png` is `Send` and `Sync`, and worker threads can read
+/// one through `&Png` at the same time.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Png {
     signature: [u8; 8],
     chunks: Vec<Chunk>,
-    // Write me!
+    /// Bytes following IEND that could not be parsed as chunks
+    trailing: Vec<u8>,
 }
 
 #[allow(dead_code)]
 impl Png {
     pub fn new(signature: [u8; 8], chunks: Vec<Chunk>) -> Self {
-        Self { signature, chunks }
+        Self {
+            signature,
+            chunks,
+            trailing: vec![],
+        }
     }
 
     // Fill in this array with the correct values per the PNG spec
@@ -28,18 +94,576 @@ impl Png {
         Self {
             signature: Self::STANDARD_HEADER,
             chunks,
+            trailing: vec![],
         }
     }
 
     /// Creates a `Png` from a file path
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
-        let mut pngfile = match File::open(&path) {
-            Ok(pngfile) => pngfile,
-            Err(e) => return Err(e),
-        };
+        let mut pngfile = File::open(&path)?;
         let mut buffer = Vec::new(); // Create an empty buffer
         pngfile.read_to_end(&mut buffer)?;
-        Self::try_from(&buffer[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        Self::try_from(&buffer[..])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Reads a `Png` from `reader` one chunk at a time through a `ChunkStream`, so unlike
+    /// `from_file` the raw file is never held in memory next to the parsed chunks. Whatever
+    /// follows IEND is kept as trailing data. To look at a file too big to keep in memory at
+    /// all, walk a `ChunkStream` over it instead.
+    pub fn from_reader<R: Read>(reader: R) -> crate::Result<Png> {
+        let mut stream = ChunkStream::new(reader)?.stop_at_iend();
+        let chunks = stream.by_ref().collect::<crate::Result<Vec<Chunk>>>()?;
+        let mut trailing = vec![];
+        stream.into_inner().read_to_end(&mut trailing)?;
+        Ok(Self {
+            signature: Self::STANDARD_HEADER,
+            chunks,
+            trailing,
+        })
+    }
+
+    /// Parses a `Png` from bytes, collecting every problem found along the way as a
+    /// `Diagnostic`. Warnings never fail the parse; the result is an error only if one of
+    /// the diagnostics has `Severity::Error`. A bad CRC is an error here; use
+    /// `parse_report_lenient` to keep such chunks instead.
+    pub fn parse_report(bytes: &[u8]) -> (crate::Result<Png>, Vec<Diagnostic>) {
+        Self::parse_report_with(bytes, ParseOptions::default())
+    }
+
+    /// Like `parse_report`, but chunks with a bad CRC and a truncated final chunk are
+    /// reported as warnings rather than failing the parse.
+    pub fn parse_report_lenient(bytes: &[u8]) -> (crate::Result<Png>, Vec<Diagnostic>) {
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        Self::parse_report_with(bytes, options)
+    }
+
+    /// Like `parse_report`, with explicit `ParseOptions`.
+    pub fn parse_report_with(
+        bytes: &[u8],
+        options: ParseOptions,
+    ) -> (crate::Result<Png>, Vec<Diagnostic>) {
+        let mut diagnostics = vec![];
+        let png = Self::parse(bytes, options, &mut diagnostics);
+        (png, diagnostics)
+    }
+
+    /// Parses a PNG embedded in `bytes` starting at `offset`, such as one inside an ICO file
+    /// or firmware blob. Parsing stops at IEND; whatever follows it is ignored.
+    pub fn from_bytes_at(bytes: &[u8], offset: usize) -> crate::Result<Png> {
+        let embedded = bytes.get(offset..).ok_or_else(|| {
+            Diagnostic::error(
+                DiagnosticKind::BadSignature,
+                format!(
+                    "Offset {} is past the end of the input ({} bytes)",
+                    offset,
+                    bytes.len()
+                ),
+            )
+            .at(offset)
+        })?;
+        let options = ParseOptions {
+            stop_at_iend: true,
+            ..Default::default()
+        };
+        Self::parse_report_with(embedded, options).0
+    }
+
+    /// Returns the offset of every PNG signature found in `bytes`. These are only candidates;
+    /// use `from_bytes_at` to find out whether a real PNG starts there.
+    pub fn find_signatures(bytes: &[u8]) -> Vec<usize> {
+        bytes
+            .windows(Self::STANDARD_HEADER.len())
+            .enumerate()
+            .filter(|(_, w)| *w == Self::STANDARD_HEADER)
+            .map(|(offset, _)| offset)
+            .collect()
+    }
+
+    /// Splits input made of several PNGs written back to back into the byte range of each
+    /// image. Each image ends at its IEND; a new one starts if a signature follows directly.
+    pub fn image_ranges(bytes: &[u8]) -> crate::Result<Vec<Range<usize>>> {
+        let options = ParseOptions {
+            stop_at_iend: true,
+            ..Default::default()
+        };
+        let mut ranges = vec![];
+        let mut start = 0;
+        loop {
+            let png = Self::parse_report_with(&bytes[start..], options).0?;
+            let end = start + png.byte_len();
+            ranges.push(start..end);
+            if !bytes[end..].starts_with(&Self::STANDARD_HEADER) {
+                return Ok(ranges);
+            }
+            start = end;
+        }
+    }
+
+    /// The offset just past the IEND of the last image in `bytes`, where data appended to a
+    /// PNG file begins. This is `bytes.len()` if nothing follows IEND.
+    pub fn trailing_offset(bytes: &[u8]) -> crate::Result<usize> {
+        let images = Self::image_ranges(bytes)?;
+        Ok(images.last().map_or(bytes.len(), |r| r.end))
+    }
+
+    /// The number of bytes `as_bytes` produces.
+    pub(crate) fn byte_len(&self) -> usize {
+        let chunks: usize = self.chunks.iter().map(|c| 12 + c.data().len()).sum();
+        self.signature.len() + chunks + self.trailing.len()
+    }
+
+    fn parse(
+        bytes: &[u8],
+        options: ParseOptions,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> crate::Result<Png> {
+        let _timer = profile::scope(Phase::Parse);
+        let lenient = options.lenient;
+        if bytes.len() < 8 || bytes[0..8] != Self::STANDARD_HEADER {
+            let d = Diagnostic::error(
+                DiagnosticKind::BadSignature,
+                "Invalid header passed".to_string(),
+            )
+            .at(0);
+            diagnostics.push(d.clone());
+            return Err(Box::new(d));
+        }
+        let signature: [u8; 8] = bytes[0..8].try_into().unwrap();
+        let mut chunks: Vec<Chunk> = vec![];
+        let mut offsets: Vec<usize> = vec![];
+        let mut trailing: Vec<u8> = vec![];
+        let mut seen_iend = false;
+        let mut cgbi = false;
+        let mut position: usize = 8;
+        while position < bytes.len() {
+            let index = chunks.len();
+            let remaining = bytes.len() - position;
+            let declared = if remaining >= 4 {
+                u32::from_be_bytes(bytes[position..position + 4].try_into().unwrap()) as usize
+            } else {
+                0
+            };
+            if remaining < 12 || declared > remaining - 12 {
+                if seen_iend {
+                    trailing = bytes[position..].to_vec();
+                    break;
+                }
+                let d = Diagnostic::recoverable(
+                    lenient,
+                    DiagnosticKind::LengthMismatch,
+                    format!(
+                        "Chunk declares {} data bytes but only {} bytes remain",
+                        declared,
+                        remaining.saturating_sub(12)
+                    ),
+                )
+                .chunk(index)
+                .at(position);
+                diagnostics.push(d.clone());
+                if !lenient {
+                    return Err(Box::new(d));
+                }
+                trailing = bytes[position..].to_vec();
+                break;
+            }
+
+            let end = position + 12 + declared;
+            let chunk = match Chunk::from_bytes_unverified(&bytes[position..end]) {
+                Ok(chunk) => chunk,
+                Err(_) if seen_iend => {
+                    trailing = bytes[position..].to_vec();
+                    break;
+                }
+                Err(e) => {
+                    let d = Diagnostic::error(DiagnosticKind::BadChunkType, e.to_string())
+                        .chunk(index)
+                        .at(position + 4);
+                    diagnostics.push(d.clone());
+                    return Err(Box::new(d));
+                }
+            };
+            if index == 0 && chunk.chunk_type().bytes() == *b"CgBI" {
+                cgbi = true;
+                diagnostics.push(
+                    Diagnostic::warning(
+                        DiagnosticKind::AppleCgbi,
+                        "File is Apple CgBI-optimized and may not render in standard viewers"
+                            .to_string(),
+                    )
+                    .chunk(index)
+                    .at(position),
+                );
+            }
+            // CgBI files routinely carry byteswapped or zeroed CRCs, so never fail on them
+            let valid_crc = {
+                let _timer = profile::scope(Phase::Crc);
+                chunk.has_valid_crc()
+            };
+            if !valid_crc {
+                if seen_iend {
+                    trailing = bytes[position..].to_vec();
+                    break;
+                }
+                let d = Diagnostic::recoverable(
+                    lenient || cgbi,
+                    DiagnosticKind::BadCrc,
+                    format!(
+                        "Bad CRC on {} chunk (stored {:08x}, expected {:08x})",
+                        chunk.chunk_type(),
+                        chunk.crc(),
+                        chunk.computed_crc()
+                    ),
+                )
+                .chunk(index)
+                .at(end - 4);
+                diagnostics.push(d.clone());
+                if d.is_error() {
+                    return Err(Box::new(d));
+                }
+            }
+            let is_iend = chunk.chunk_type().bytes() == *b"IEND";
+            seen_iend |= is_iend;
+
+            chunks.push(chunk);
+            offsets.push(position);
+            position = end;
+            if is_iend && options.stop_at_iend {
+                break;
+            }
+        }
+
+        Self::check_ordering(&chunks, &offsets, options.complete, diagnostics);
+        Self::check_palettes(&chunks, &offsets, diagnostics);
+        Self::check_transparency(&chunks, &offsets, diagnostics);
+        Self::check_extensions(&chunks, &offsets, diagnostics);
+        if options.strict_spec {
+            Self::check_spec(&chunks, &offsets, diagnostics);
+        }
+        if !trailing.is_empty() {
+            diagnostics.push(
+                Diagnostic::warning(
+                    DiagnosticKind::TrailingData,
+                    format!("{} bytes of trailing data after IEND", trailing.len()),
+                )
+                .at(bytes.len() - trailing.len()),
+            );
+        }
+
+        Ok(Self {
+            signature,
+            chunks,
+            trailing,
+        })
+    }
+
+    /// Reports chunk placements that violate the ordering rules of the PNG spec, as errors
+    /// when `complete`, which also reports IHDR, IDAT or IEND missing.
+    fn check_ordering(
+        chunks: &[Chunk],
+        offsets: &[usize],
+        complete: bool,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let types: Vec<[u8; 4]> = chunks.iter().map(|c| c.chunk_type().bytes()).collect();
+        let ordering = |index: usize, message: String| {
+            Diagnostic::recoverable(!complete, DiagnosticKind::Ordering, message)
+                .chunk(index)
+                .at(offsets[index])
+        };
+        let missing = |chunk_type: &str| {
+            Diagnostic::error(
+                DiagnosticKind::MissingChunk,
+                format!("There is no {} chunk", chunk_type),
+            )
+        };
+        let end = chunks
+            .last()
+            .map_or(8, |c| offsets[chunks.len() - 1] + 12 + c.data().len());
+        // Apple CgBI files put their own chunk in front of IHDR
+        let header = usize::from(types.first() == Some(b"CgBI"));
+        if complete && !types.contains(b"IHDR") {
+            diagnostics.push(missing("IHDR").at(offsets.get(header).copied().unwrap_or(8)));
+        } else if types.len() > header && types[header] != *b"IHDR" {
+            diagnostics.push(ordering(header, "First chunk is not IHDR".to_string()));
+        }
+        if complete && !types.contains(b"IDAT") {
+            diagnostics.push(missing("IDAT"));
+        }
+        if complete && !types.contains(b"IEND") {
+            diagnostics.push(missing("IEND").at(end));
+        }
+        let first_idat = types.iter().position(|t| t == b"IDAT");
+        let iend = types.iter().position(|t| t == b"IEND");
+        for (index, ctype) in types.iter().enumerate() {
+            let name = chunks[index].chunk_type();
+            if index != header && ctype == b"IHDR" {
+                diagnostics.push(ordering(index, "IHDR is not the first chunk".to_string()));
+            }
+            if ctype == b"PLTE" && first_idat.is_some_and(|i| i < index) {
+                diagnostics.push(ordering(index, "PLTE appears after IDAT".to_string()));
+            }
+            if ctype == b"IDAT"
+                && index > 0
+                && first_idat != Some(index)
+                && types[index - 1] != *b"IDAT"
+            {
+                diagnostics.push(ordering(
+                    index,
+                    "IDAT chunks are not consecutive".to_string(),
+                ));
+            }
+            if iend.is_some_and(|i| i < index) {
+                diagnostics.push(ordering(
+                    index,
+                    format!("{} chunk appears after IEND", name),
+                ));
+            }
+        }
+    }
+
+    /// Reports sPLT and hIST chunks that can't be read, hIST not matching PLTE, and sPLT names
+    /// used twice.
+    fn check_palettes(chunks: &[Chunk], offsets: &[usize], diagnostics: &mut Vec<Diagnostic>) {
+        let plte = chunks.iter().find(|c| c.chunk_type().bytes() == *b"PLTE");
+        let mut names: Vec<String> = vec![];
+        for (index, chunk) in chunks.iter().enumerate() {
+            let mut report = |kind: DiagnosticKind, message: String| {
+                diagnostics.push(
+                    Diagnostic::warning(kind, message)
+                        .chunk(index)
+                        .at(offsets[index]),
+                );
+            };
+            match &chunk.chunk_type().bytes() {
+                b"sPLT" => match SuggestedPalette::parse(chunk.data()) {
+                    Ok(palette) if names.contains(&palette.name) => report(
+                        DiagnosticKind::DuplicatePaletteName,
+                        format!("More than one sPLT is named \"{}\"", palette.name),
+                    ),
+                    Ok(palette) => names.push(palette.name),
+                    Err(e) => report(DiagnosticKind::BadPalette, e.to_string()),
+                },
+                b"hIST" => match (Histogram::parse(chunk.data()), plte) {
+                    (Err(e), _) => report(DiagnosticKind::BadPalette, e.to_string()),
+                    (Ok(_), None) => report(
+                        DiagnosticKind::HistogramMismatch,
+                        "hIST appears without a PLTE to count".to_string(),
+                    ),
+                    (Ok(histogram), Some(plte))
+                        if histogram.frequencies.len() * 3 != plte.data().len() =>
+                    {
+                        report(
+                            DiagnosticKind::HistogramMismatch,
+                            format!(
+                                "hIST has {} entries but PLTE has {}",
+                                histogram.frequencies.len(),
+                                plte.data().len() / 3
+                            ),
+                        )
+                    }
+                    (Ok(_), Some(_)) => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Reports tRNS and bKGD chunks that can't be read for the color type in IHDR, and tRNS
+    /// giving more alphas than PLTE has entries. Without a readable IHDR there is nothing to
+    /// read them against.
+    fn check_transparency(chunks: &[Chunk], offsets: &[usize], diagnostics: &mut Vec<Diagnostic>) {
+        let Some(Ok(ihdr)) = chunks
+            .iter()
+            .find(|c| c.chunk_type().bytes() == *b"IHDR")
+            .map(|c| Ihdr::parse(c.data()))
+        else {
+            return;
+        };
+        let plte = chunks.iter().find(|c| c.chunk_type().bytes() == *b"PLTE");
+        for (index, chunk) in chunks.iter().enumerate() {
+            let mut report = |kind: DiagnosticKind, message: String| {
+                diagnostics.push(
+                    Diagnostic::warning(kind, message)
+                        .chunk(index)
+                        .at(offsets[index]),
+                );
+            };
+            match &chunk.chunk_type().bytes() {
+                b"tRNS" => match (Transparency::parse(chunk.data(), ihdr.color_type), plte) {
+                    (Err(e), _) => report(DiagnosticKind::BadTransparency, e.to_string()),
+                    (Ok(Transparency::PaletteAlpha(alpha)), plte)
+                        if alpha.len() > plte.map_or(0, |p| p.data().len() / 3) =>
+                    {
+                        report(
+                            DiagnosticKind::BadTransparency,
+                            format!(
+                                "tRNS has {} alpha entries but PLTE has {}",
+                                alpha.len(),
+                                plte.map_or(0, |p| p.data().len() / 3)
+                            ),
+                        )
+                    }
+                    (Ok(_), _) => {}
+                },
+                b"bKGD" => {
+                    if let Err(e) = Background::parse(chunk.data(), ihdr.color_type) {
+                        report(DiagnosticKind::BadBackground, e.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reports oFFs, sCAL and sTER chunks that can't be read.
+    fn check_extensions(chunks: &[Chunk], offsets: &[usize], diagnostics: &mut Vec<Diagnostic>) {
+        for (index, chunk) in chunks.iter().enumerate() {
+            let parsed = match &chunk.chunk_type().bytes() {
+                b"oFFs" => Offset::parse(chunk.data()).map(drop),
+                b"sCAL" => Scale::parse(chunk.data()).map(drop),
+                b"sTER" => Stereo::parse(chunk.data()).map(drop),
+                _ => continue,
+            };
+            if let Err(e) = parsed {
+                diagnostics.push(
+                    Diagnostic::warning(DiagnosticKind::BadExtension, e.to_string())
+                        .chunk(index)
+                        .at(offsets[index]),
+                );
+            }
+        }
+    }
+
+    /// The image header in the IHDR chunk, if there is one.
+    pub fn ihdr(&self) -> Option<crate::Result<Ihdr>> {
+        self.chunk_by_type("IHDR").map(|c| Ihdr::parse(c.data()))
+    }
+
+    /// The tRNS chunk, read for the color type in IHDR, if there is one.
+    pub fn transparency(&self) -> Option<crate::Result<Transparency>> {
+        let trns = self.chunk_by_type("tRNS")?;
+        Some(self.color_read(|color_type| Transparency::parse(trns.data(), color_type)))
+    }
+
+    /// The bKGD chunk, read for the color type in IHDR, if there is one.
+    pub fn background(&self) -> Option<crate::Result<Background>> {
+        let bkgd = self.chunk_by_type("bKGD")?;
+        Some(self.color_read(|color_type| Background::parse(bkgd.data(), color_type)))
+    }
+
+    /// The image's position from the oFFs chunk, if there is one.
+    pub fn offset(&self) -> Option<crate::Result<Offset>> {
+        self.chunk_by_type("oFFs").map(|c| Offset::parse(c.data()))
+    }
+
+    /// The physical size of a pixel from the sCAL chunk, if there is one.
+    pub fn scale(&self) -> Option<crate::Result<Scale>> {
+        self.chunk_by_type("sCAL").map(|c| Scale::parse(c.data()))
+    }
+
+    /// The stereo layout from the sTER chunk, if there is one.
+    pub fn stereo(&self) -> Option<crate::Result<Stereo>> {
+        self.chunk_by_type("sTER").map(|c| Stereo::parse(c.data()))
+    }
+
+    /// Runs `read` with the color type from IHDR, failing if there is no IHDR to take it from.
+    fn color_read<T>(&self, read: impl FnOnce(ColorType) -> crate::Result<T>) -> crate::Result<T> {
+        let ihdr = self.ihdr().ok_or_else(|| {
+            NotFoundError::chunk(
+                "IHDR",
+                None,
+                "There is no IHDR to give the color type".to_string(),
+            )
+        })??;
+        read(ihdr.color_type)
+    }
+
+    /// The suggested palettes in sPLT chunks, in order, each parsed or why it couldn't be.
+    pub fn suggested_palettes(&self) -> Vec<crate::Result<SuggestedPalette>> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == *b"sPLT")
+            .map(|c| SuggestedPalette::parse(c.data()))
+            .collect()
+    }
+
+    /// The keywords and text of the tEXt and zTXt chunks, in order, each read or why it
+    /// couldn't be.
+    pub fn texts(&self) -> Vec<crate::Result<TextChunk>> {
+        self.chunks
+            .iter()
+            .filter(|c| matches!(&c.chunk_type().bytes(), b"tEXt" | b"zTXt"))
+            .map(TextChunk::read)
+            .collect()
+    }
+
+    /// The palette histogram in the hIST chunk, if there is one.
+    pub fn histogram(&self) -> Option<crate::Result<Histogram>> {
+        self.chunk_by_type("hIST")
+            .map(|c| Histogram::parse(c.data()))
+    }
+
+    /// Reports, as errors, what `ParseOptions::strict_spec` asks for.
+    fn check_spec(chunks: &[Chunk], offsets: &[usize], diagnostics: &mut Vec<Diagnostic>) {
+        let types: Vec<[u8; 4]> = chunks.iter().map(|c| c.chunk_type().bytes()).collect();
+        let first_plte = types.iter().position(|t| t == b"PLTE");
+        let first_idat = types.iter().position(|t| t == b"IDAT");
+        for (index, chunk) in chunks.iter().enumerate() {
+            let mut report = |kind: DiagnosticKind, message: String| {
+                diagnostics.push(
+                    Diagnostic::error(kind, message)
+                        .chunk(index)
+                        .at(offsets[index]),
+                );
+            };
+            let (name, ctype) = (chunk.chunk_type(), &types[index]);
+            let after = |first: Option<usize>| first.is_some_and(|i| i < index);
+            if name.is_critical() && !KNOWN_CRITICAL.contains(&ctype) {
+                match name.is_public() {
+                    true => report(
+                        DiagnosticKind::UnknownCritical,
+                        format!("{} is critical but not defined by the PNG spec", name),
+                    ),
+                    false => report(
+                        DiagnosticKind::PrivateCritical,
+                        format!("{} is a private chunk marked critical", name),
+                    ),
+                }
+            }
+            if BEFORE_PLTE.contains(&ctype) && (after(first_plte) || after(first_idat)) {
+                report(
+                    DiagnosticKind::AncillaryOrdering,
+                    format!("{} must come before PLTE and IDAT", name),
+                );
+            }
+            if AFTER_PLTE.contains(&ctype)
+                && (first_plte.is_some_and(|i| i > index) || after(first_idat))
+            {
+                report(
+                    DiagnosticKind::AncillaryOrdering,
+                    format!("{} must come after PLTE and before IDAT", name),
+                );
+            }
+            if BEFORE_IDAT.contains(&ctype) && after(first_idat) {
+                report(
+                    DiagnosticKind::AncillaryOrdering,
+                    format!("{} must come before IDAT", name),
+                );
+            }
+            if ctype == b"IEND" && !chunk.data().is_empty() {
+                report(
+                    DiagnosticKind::IendData,
+                    format!(
+                        "IEND holds {} bytes of data, it must be empty",
+                        chunk.length()
+                    ),
+                );
+            }
+        }
     }
 
     /// Appends a chunk to the end of this `Png` file's `Chunk` list.
@@ -47,16 +671,117 @@ impl Png {
         self.chunks.push(chunk);
     }
 
+    /// Adds a chunk where `placement` says, returning its index. Chunks placed one after the
+    /// other the same way keep their order. Without IEND or IDAT to place it by, the chunk
+    /// goes at the end.
+    pub fn place_chunk(&mut self, chunk: Chunk, placement: Placement) -> usize {
+        let position = |chunk_type: &[u8; 4]| {
+            self.chunks
+                .iter()
+                .position(|c| c.chunk_type().bytes() == *chunk_type)
+        };
+        let index = match placement {
+            Placement::BeforeIend => position(b"IEND"),
+            Placement::BeforeIdat => position(b"IDAT").or_else(|| position(b"IEND")),
+            Placement::End => None,
+        }
+        .unwrap_or(self.chunks.len());
+        self.chunks.insert(index, chunk);
+        index
+    }
+
+    /// Inserts a chunk at position `index` of this `Png` file's `Chunk` list, failing if
+    /// `index` is greater than the number of chunks.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> crate::Result<()> {
+        if index > self.chunks.len() {
+            return Err(self.no_position(index));
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Removes every `Chunk` with the specified `chunk_type`, returning them in the order they
+    /// appeared. A `chunk_type` that isn't a valid type matches nothing.
+    pub fn remove_all_chunks(&mut self, chunk_type: &str) -> Vec<Chunk> {
+        let Ok(ctype) = ChunkType::from_str(chunk_type) else {
+            return vec![];
+        };
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|c| *c.chunk_type() == ctype);
+        self.chunks = kept;
+        removed
+    }
+
     /// Searches for a `Chunk` with the specified `chunk_type` and removes the first
-    /// matching `Chunk` from this `Png` list of chunks.
-    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<Chunk, ()> {
-        let ctype = ChunkType::from_str(chunk_type).unwrap();
-        for (idx, chunk) in self.chunks.clone().iter().enumerate() {
-            if *chunk.chunk_type() == ctype {
-                return Ok(self.chunks.remove(idx));
-            }
+    /// matching `Chunk` from this `Png` list of chunks, failing if there is none or if
+    /// `chunk_type` isn't a valid type.
+    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> crate::Result<Chunk> {
+        let ctype = ChunkType::from_str(chunk_type)?;
+        match self.chunks.iter().position(|c| *c.chunk_type() == ctype) {
+            Some(idx) => Ok(self.chunks.remove(idx)),
+            None => Err(NotFoundError::chunk(
+                chunk_type,
+                None,
+                format!("No {} chunk found", chunk_type),
+            )),
         }
-        Err(())
+    }
+
+    /// Removes and returns the chunk at position `index` of this `Png` file's `Chunk` list,
+    /// failing if there is no chunk there.
+    pub fn remove_chunk(&mut self, index: usize) -> crate::Result<Chunk> {
+        if index >= self.chunks.len() {
+            return Err(self.no_position(index));
+        }
+        Ok(self.chunks.remove(index))
+    }
+
+    fn no_position(&self, index: usize) -> crate::Error {
+        NotFoundError::boxed(format!(
+            "there is no chunk position {}, the png has {} chunks",
+            index,
+            self.chunks.len()
+        ))
+    }
+
+    /// The key-value store kept in this `Png`, see `KvStore`.
+    pub fn kv(&mut self) -> crate::Result<KvStore<'_>> {
+        KvStore::open(self)
+    }
+
+    /// Reads the message stored in the chunks of `chunk_type` as a stream, decrypting it with
+    /// `key` if it is encrypted, see `PayloadReader`.
+    pub fn payload_reader(
+        &self,
+        chunk_type: &ChunkType,
+        key: Option<&KeySource>,
+    ) -> crate::Result<PayloadReader<'_>> {
+        PayloadReader::new(self, chunk_type, key)
+    }
+
+    /// Stores the message written to the returned writer in chunks of `chunk_type`, once it is
+    /// finished, see `PayloadWriter`.
+    pub fn payload_writer(
+        &mut self,
+        chunk_type: ChunkType,
+        options: PayloadOptions,
+    ) -> PayloadWriter<'_> {
+        PayloadWriter::new(self, chunk_type, options)
+    }
+
+    /// Whether the PNG spec or one of its registered extensions defines `chunk_type`.
+    pub fn is_known_type(chunk_type: &ChunkType) -> bool {
+        let ctype = &chunk_type.bytes();
+        [
+            &KNOWN_CRITICAL[..],
+            &BEFORE_PLTE,
+            &AFTER_PLTE,
+            &BEFORE_IDAT,
+            &ANYWHERE,
+        ]
+        .iter()
+        .any(|types| types.contains(&ctype))
     }
 
     /// The header of this PNG.
@@ -70,7 +795,7 @@ impl Png {
     }
 
     fn find_chunk(&self, chunk_type: &str) -> Result<usize, ()> {
-        let ctype = ChunkType::from_str(chunk_type).unwrap();
+        let ctype = ChunkType::from_str(chunk_type).map_err(|_| ())?;
         for (idx, chunk) in self.chunks.iter().enumerate() {
             if *chunk.chunk_type() == ctype {
                 return Ok(idx);
@@ -79,7 +804,8 @@ impl Png {
         Err(())
     }
     /// Searches for a `Chunk` with the specified `chunk_type` and returns the first
-    /// matching `Chunk` from this `Png`.
+    /// matching `Chunk` from this `Png`, or `None` if there is none or `chunk_type` isn't a
+    /// valid type.
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         if let Ok(idx) = self.find_chunk(chunk_type) {
             return self.chunks.get(idx);
@@ -87,48 +813,171 @@ impl Png {
         None
     }
 
+    /// Every `Chunk` with the specified `chunk_type`, in file order. A `chunk_type` that isn't
+    /// a valid type matches nothing.
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+        let Ok(ctype) = ChunkType::from_str(chunk_type) else {
+            return vec![];
+        };
+        self.chunks
+            .iter()
+            .filter(|c| *c.chunk_type() == ctype)
+            .collect()
+    }
+
+    /// The indices of the chunks whose stored CRC doesn't match their type and data.
+    pub fn verify_crcs(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.has_valid_crc())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// The chunks an editor may carry over to a re-encoded image without understanding them:
+    /// the ancillary ones with the safe-to-copy bit set.
+    pub fn copyable_chunks(&self) -> Vec<&Chunk> {
+        self.chunks
+            .iter()
+            .filter(|c| !c.chunk_type().is_critical() && c.chunk_type().is_safe_to_copy())
+            .collect()
+    }
+
+    /// Calls `visitor` with the index of every chunk, in order, until it returns
+    /// `ControlFlow::Break`.
+    pub fn walk(
+        &self,
+        mut visitor: impl FnMut(usize, &Chunk) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            visitor(idx, chunk)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Like `walk`, but lets `visitor` modify chunks. Edits go through `Chunk`'s own
+    /// mutators, so lengths and CRCs stay consistent.
+    pub fn walk_mut(
+        &mut self,
+        mut visitor: impl FnMut(usize, &mut Chunk) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        for (idx, chunk) in self.chunks.iter_mut().enumerate() {
+            visitor(idx, chunk)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Calls `transform` with a copy of the data of every chunk `filter` picks, storing the data
+    /// back wherever `transform` returns `true` to say it changed. Lengths and CRCs are updated
+    /// to match, and if `transform` fails on any chunk, no chunk is changed. The indices of the
+    /// changed chunks, in order.
+    pub fn transform_chunks(
+        &mut self,
+        mut filter: impl FnMut(&Chunk) -> bool,
+        mut transform: impl FnMut(&mut Vec<u8>) -> crate::Result<bool>,
+    ) -> crate::Result<Vec<usize>> {
+        let mut changed = vec![];
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            if !filter(chunk) {
+                continue;
+            }
+            let mut data = chunk.data().to_vec();
+            if transform(&mut data)? {
+                changed.push((idx, data));
+            }
+        }
+        Ok(changed
+            .into_iter()
+            .map(|(idx, data)| {
+                self.chunks[idx].set_data(data);
+                idx
+            })
+            .collect())
+    }
+
+    /// Whether this is an Apple CgBI-optimized file, which starts with a CgBI chunk before
+    /// IHDR and stores image data that standard decoders can't read.
+    pub fn is_cgbi(&self) -> bool {
+        self.chunks
+            .first()
+            .is_some_and(|c| c.chunk_type().bytes() == *b"CgBI")
+    }
+
+    /// Compares the image content of two `Png`s: only critical chunks are considered, in
+    /// order, with the data of all IDAT chunks concatenated so that differently split image
+    /// data still compares equal. Ancillary chunks are ignored entirely.
+    pub fn content_equals(&self, other: &Png) -> bool {
+        self.critical_content() == other.critical_content()
+    }
+
+    /// SHA-256 of the same content `content_equals` compares: each critical chunk's type,
+    /// big endian data length and data, with the IDAT data concatenated into one chunk.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for (chunk_type, data) in self.critical_content() {
+            hasher.update(chunk_type.bytes());
+            hasher.update((data.len() as u64).to_be_bytes());
+            hasher.update(&data);
+        }
+        hasher.finalize().into()
+    }
+
+    fn critical_content(&self) -> Vec<(ChunkType, Vec<u8>)> {
+        let mut content: Vec<(ChunkType, Vec<u8>)> = vec![];
+        let mut idat: Option<usize> = None;
+        for chunk in self.chunks.iter().filter(|c| c.chunk_type().is_critical()) {
+            if chunk.chunk_type().bytes() == *b"IDAT" {
+                if let Some(idx) = idat {
+                    content[idx].1.extend_from_slice(chunk.data());
+                    continue;
+                }
+                idat = Some(content.len());
+            }
+            content.push((*chunk.chunk_type(), chunk.data().to_vec()));
+        }
+        content
+    }
+
+    /// Bytes following IEND that were not part of any chunk when this `Png` was parsed.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+
     /// Returns this `Png` as a byte sequence.
-    /// These bytes will contain the header followed by the bytes of all of the chunks.
+    /// These bytes will contain the header followed by the bytes of all of the chunks and any
+    /// trailing data.
     pub fn as_bytes(&self) -> Vec<u8> {
+        let _timer = profile::scope(Phase::Serialize);
         let mut r: Vec<u8> = vec![];
         for i in &self.chunks {
             r.append(&mut i.as_bytes());
         }
-        [&self.signature, r.as_slice()].concat()
+        [&self.signature, r.as_slice(), self.trailing.as_slice()].concat()
     }
 }
 
 impl TryFrom<&[u8]> for Png {
-    type Error = &'static str;
+    type Error = crate::Error;
     fn try_from(bytes: &[u8]) -> Result<Png, Self::Error> {
-        let signature: [u8; 8] = bytes[0..8].try_into().unwrap();
-        if signature != Self::STANDARD_HEADER {
-            return Err("Invalid header passed");
-        }
-        let mut chunk_vec: Vec<Chunk> = vec![];
-        let mut position: usize = 8;
-        while position < bytes.len() {
-            if position + 4 > bytes.len() {
-                break;
-            }
-
-            let length_bytes: [u8; 4] = bytes[position..position + 4].try_into().unwrap();
-            let chunk_size = 4 + u32::from_be_bytes(length_bytes) + 4 + 4;
-
-            let end = (position + chunk_size as usize).min(bytes.len());
-            let chunk = &bytes[position..end];
-            let chunk_object: Chunk = Chunk::try_from(chunk).unwrap();
+        Self::parse_report(bytes).0
+    }
+}
 
-            chunk_vec.push(chunk_object);
+/// Reads and parses the file at a path. Unlike `from_file`, a file that doesn't parse fails
+/// with the parse error itself rather than an `io::Error` describing it.
+impl TryFrom<&Path> for Png {
+    type Error = crate::Error;
+    fn try_from(path: &Path) -> Result<Png, Self::Error> {
+        Self::try_from(&std::fs::read(path)?[..])
+    }
+}
 
-            // Move position forward by the chunk_size
-            position += chunk_size as usize;
-        }
-        Ok(Self {
-            signature,
-            chunks: chunk_vec,
-        })
-        //Err("failed")
+/// Reads and parses the file at a path given as a string, like `TryFrom<&Path>`.
+impl TryFrom<&str> for Png {
+    type Error = crate::Error;
+    fn try_from(path: &str) -> Result<Png, Self::Error> {
+        Self::try_from(Path::new(path))
     }
 }
 
@@ -146,8 +995,10 @@ mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
+    use crate::diagnostic::Severity;
+    use crate::error::Failure;
     use std::convert::TryFrom;
-    type Error = &'static str;
+    use std::str::FromStr;
 
     fn testing_chunks() -> Vec<Chunk> {
         vec![
@@ -162,9 +1013,7 @@ mod tests {
         Png::from_chunks(chunks)
     }
 
-    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, Error> {
-        use std::str::FromStr;
-
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> crate::Result<Chunk> {
         let chunk_type = ChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
@@ -193,12 +1042,45 @@ mod tests {
             .chain(chunk_bytes.iter())
             .copied()
             .collect();
-        let png = Png::try_from(bytes.as_ref());
+        let png = Png::try_from(&bytes[..]);
         println!("TEST BYTES: {:x?}", bytes);
 
         assert!(png.is_ok());
     }
 
+    /// Hands out at most 3 bytes per read, as a slow pipe or socket would.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut chunks = testing_chunks();
+        chunks.push(chunk_from_strings("IEND", "").unwrap());
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        bytes.extend_from_slice(b"appended");
+
+        let png = Png::from_reader(Trickle(&bytes)).unwrap();
+        assert_eq!(png, Png::try_from(&bytes[..]).unwrap());
+        assert_eq!(png.trailing_data(), b"appended");
+        assert_eq!(png.as_bytes(), bytes);
+
+        let without_iend = testing_png().as_bytes();
+        assert_eq!(
+            Png::from_reader(&without_iend[..]).unwrap().chunks(),
+            testing_chunks()
+        );
+        assert!(Png::from_reader(Trickle(&bytes[..bytes.len() / 2])).is_err());
+        assert!(Png::from_reader(&bytes[1..]).is_err());
+    }
+
     #[test]
     fn test_invalid_header() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -212,7 +1094,7 @@ mod tests {
             .copied()
             .collect();
 
-        let png = Png::try_from(bytes.as_ref());
+        let png = Png::try_from(&bytes[..]);
 
         assert!(png.is_err());
     }
@@ -234,7 +1116,7 @@ mod tests {
 
         chunk_bytes.append(&mut bad_chunk);
 
-        let png = Png::try_from(chunk_bytes.as_ref());
+        let png = Png::try_from(&chunk_bytes[..]);
 
         assert!(png.is_err());
     }
@@ -270,6 +1152,86 @@ mod tests {
         png.remove_first_chunk("TeSt").unwrap();
         let chunk = png.chunk_by_type("TeSt");
         assert!(chunk.is_none());
+        let err = png.remove_first_chunk("TeSt").unwrap_err();
+        assert_eq!(Failure::of(&err).kind(), "NotFound");
+        let err = png.remove_first_chunk("Te5t").unwrap_err();
+        assert_eq!(Failure::of(&err).kind(), "ChunkType");
+    }
+
+    #[test]
+    fn test_place_chunk() {
+        let types = |png: &Png| -> Vec<String> {
+            png.chunks()
+                .iter()
+                .map(|c| c.chunk_type().to_string())
+                .collect()
+        };
+        let mut png = Png::from_chunks(
+            ["IHDR", "IDAT", "IEND"]
+                .iter()
+                .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), vec![]))
+                .collect(),
+        );
+        assert_eq!(
+            png.place_chunk(
+                chunk_from_strings("ruSt", "1").unwrap(),
+                Placement::default()
+            ),
+            2
+        );
+        png.place_chunk(
+            chunk_from_strings("ruSt", "2").unwrap(),
+            Placement::BeforeIend,
+        );
+        png.place_chunk(
+            chunk_from_strings("tEXt", "a").unwrap(),
+            Placement::BeforeIdat,
+        );
+        png.place_chunk(chunk_from_strings("leGy", "x").unwrap(), Placement::End);
+        assert_eq!(
+            types(&png),
+            ["IHDR", "tEXt", "IDAT", "ruSt", "ruSt", "IEND", "leGy"]
+        );
+        assert_eq!(png.chunks()[3].data(), b"1");
+
+        let mut bare = Png::from_chunks(vec![]);
+        assert_eq!(
+            bare.place_chunk(
+                chunk_from_strings("ruSt", "1").unwrap(),
+                Placement::BeforeIdat
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "two").unwrap());
+        let data: Vec<&[u8]> = png
+            .chunks_by_type("TeSt")
+            .iter()
+            .map(|c| c.data())
+            .collect();
+        assert_eq!(data, [&b"one"[..], b"two"]);
+        assert!(png.chunks_by_type("NoNe").is_empty());
+        assert!(png.chunks_by_type("bad").is_empty());
+    }
+
+    #[test]
+    fn test_remove_all_chunks() {
+        let mut png = testing_png();
+        let count = png.chunks().len();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.insert_chunk(1, chunk_from_strings("TeSt", "two").unwrap())
+            .unwrap();
+        let removed = png.remove_all_chunks("TeSt");
+        let data: Vec<&[u8]> = removed.iter().map(|c| c.data()).collect();
+        assert_eq!(data, [&b"two"[..], b"one"]);
+        assert_eq!(png.chunks().len(), count);
+        assert!(png.remove_all_chunks("TeSt").is_empty());
+        assert!(png.remove_all_chunks("bad").is_empty());
     }
 
     #[test]
@@ -299,11 +1261,719 @@ mod tests {
             .copied()
             .collect();
 
-        let png: Png = TryFrom::try_from(bytes.as_ref()).unwrap();
+        let png = Png::try_from(&bytes[..]).unwrap();
 
         let _png_string = format!("{}", png);
     }
 
+    /// A png with a non-IHDR first chunk at offset 8, a bad CRC on the second chunk at
+    /// offset 36 and 8 bytes of trailing garbage at offset 52.
+    fn damaged_png_bytes() -> Vec<u8> {
+        let mut bad_crc = chunk_from_strings("miDl", "hello").unwrap().as_bytes();
+        let last = bad_crc.len() - 1;
+        bad_crc[last] ^= 0xff;
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]);
+        [
+            Png::STANDARD_HEADER.to_vec(),
+            chunk_from_strings("FrSt", "abc").unwrap().as_bytes(),
+            bad_crc,
+            iend.as_bytes(),
+            b"garbage!".to_vec(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_parse_report_lenient_diagnostics() {
+        let bytes = damaged_png_bytes();
+        let (png, diagnostics) = Png::parse_report_lenient(&bytes);
+        let kinds: Vec<(Severity, DiagnosticKind, Option<usize>, Option<usize>)> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.kind, d.chunk_index, d.offset))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (Severity::Warning, DiagnosticKind::BadCrc, Some(1), Some(36)),
+                (
+                    Severity::Warning,
+                    DiagnosticKind::Ordering,
+                    Some(0),
+                    Some(8)
+                ),
+                (
+                    Severity::Warning,
+                    DiagnosticKind::TrailingData,
+                    None,
+                    Some(52)
+                ),
+            ]
+        );
+        let png = png.unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.trailing_data(), b"garbage!");
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_strict_spec_diagnostics() {
+        let chunks = [
+            ("IHDR", "header"),
+            ("bKGD", "bg"),
+            ("PLTE", "palette"),
+            ("IDAT", "data"),
+            ("gAMA", "gamma"),
+            ("SNAP", "unknown"),
+            ("MiNE", "private"),
+            ("IEND", "junk"),
+        ];
+        let bytes = Png::from_chunks(
+            chunks
+                .iter()
+                .map(|(t, d)| chunk_from_strings(t, d).unwrap())
+                .collect(),
+        )
+        .as_bytes();
+        let spec_kinds = |strict_spec: bool| -> Vec<(DiagnosticKind, Option<usize>)> {
+            let options = ParseOptions {
+                strict_spec,
+                ..Default::default()
+            };
+            let (png, diagnostics) = Png::parse_report_with(&bytes, options);
+            assert!(png.is_ok());
+            diagnostics
+                .iter()
+                .inspect(|d| assert_eq!(d.is_error(), strict_spec))
+                .map(|d| (d.kind, d.chunk_index))
+                .collect()
+        };
+        assert_eq!(
+            spec_kinds(true),
+            vec![
+                (DiagnosticKind::AncillaryOrdering, Some(1)),
+                (DiagnosticKind::AncillaryOrdering, Some(4)),
+                (DiagnosticKind::UnknownCritical, Some(5)),
+                (DiagnosticKind::PrivateCritical, Some(6)),
+                (DiagnosticKind::IendData, Some(7)),
+            ]
+        );
+        assert!(spec_kinds(false).is_empty());
+    }
+
+    #[test]
+    fn test_palette_checks() {
+        use crate::palette::tests::splt;
+        let kinds = |extra: &[(&str, Vec<u8>)]| -> Vec<(DiagnosticKind, Option<usize>)> {
+            let mut chunks = vec![
+                Chunk::new(ChunkType::from_str("IHDR").unwrap(), [0; 13]),
+                Chunk::new(ChunkType::from_str("PLTE").unwrap(), [0; 9]),
+            ];
+            for (t, d) in extra {
+                chunks.push(Chunk::new(ChunkType::from_str(t).unwrap(), d.clone()));
+            }
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), "data"));
+            chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), []));
+            let (png, diagnostics) = Png::parse_report(&Png::from_chunks(chunks).as_bytes());
+            assert!(png.is_ok());
+            diagnostics
+                .iter()
+                .map(|d| (d.kind, d.chunk_index))
+                .collect()
+        };
+        let web = splt("web", 8, &[[1, 2, 3, 255, 9]]);
+
+        let valid = [
+            ("sPLT", web.clone()),
+            ("sPLT", splt("deep", 16, &[[1, 2, 3, 4, 5]])),
+            ("hIST", vec![0, 1, 0, 2, 0, 3]),
+        ];
+        assert!(kinds(&valid).is_empty());
+        let png = Png::from_chunks(
+            valid
+                .iter()
+                .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.clone()))
+                .collect(),
+        );
+        assert_eq!(png.suggested_palettes()[1].as_ref().unwrap().name, "deep");
+        assert_eq!(png.histogram().unwrap().unwrap().frequencies, [1, 2, 3]);
+
+        let cases = [
+            (
+                vec![("hIST", vec![0, 1, 0, 2])],
+                DiagnosticKind::HistogramMismatch,
+            ),
+            (vec![("hIST", vec![0, 1, 0])], DiagnosticKind::BadPalette),
+            (
+                vec![("sPLT", web.clone()), ("sPLT", web.clone())],
+                DiagnosticKind::DuplicatePaletteName,
+            ),
+            (
+                vec![("sPLT", splt("web", 8, &[[1, 2, 3, 4, 5]])[..10].to_vec())],
+                DiagnosticKind::BadPalette,
+            ),
+            (
+                vec![("sPLT", splt(&"x".repeat(80), 8, &[]))],
+                DiagnosticKind::BadPalette,
+            ),
+        ];
+        for (extra, kind) in cases {
+            let index = extra.len() + 1;
+            assert_eq!(kinds(&extra), [(kind, Some(index))], "{:?}", extra);
+        }
+
+        let without_plte = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), [0; 13]),
+            Chunk::new(ChunkType::from_str("hIST").unwrap(), [0, 1]),
+        ]);
+        let (_, diagnostics) = Png::parse_report(&without_plte.as_bytes());
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::HistogramMismatch);
+    }
+
+    #[test]
+    fn test_transparency_checks() {
+        let png = |color_type: u8, extra: &[(&str, &[u8])]| {
+            let mut ihdr = [0; 13];
+            (ihdr[8], ihdr[9]) = (8, color_type);
+            let mut chunks = vec![
+                Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr),
+                Chunk::new(ChunkType::from_str("PLTE").unwrap(), [0; 6]),
+            ];
+            for (t, d) in extra {
+                chunks.push(Chunk::new(ChunkType::from_str(t).unwrap(), *d));
+            }
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), "data"));
+            chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), []));
+            Png::from_chunks(chunks)
+        };
+        let kinds = |png: Png| -> Vec<(DiagnosticKind, Option<usize>)> {
+            let (_, diagnostics) = Png::parse_report(&png.as_bytes());
+            diagnostics
+                .iter()
+                .map(|d| (d.kind, d.chunk_index))
+                .collect()
+        };
+
+        let indexed = png(3, &[("tRNS", &[0, 128]), ("bKGD", &[1])]);
+        assert_eq!(
+            indexed.transparency().unwrap().unwrap(),
+            Transparency::PaletteAlpha(vec![0, 128])
+        );
+        assert_eq!(
+            indexed.background().unwrap().unwrap(),
+            Background::PaletteIndex(1)
+        );
+        assert!(kinds(indexed).is_empty());
+        let rgb = png(
+            2,
+            &[("tRNS", &[0, 1, 0, 2, 0, 3]), ("bKGD", &[0, 9, 0, 8, 0, 7])],
+        );
+        assert_eq!(rgb.background().unwrap().unwrap(), Background::Rgb(9, 8, 7));
+        assert!(kinds(rgb).is_empty());
+        assert!(png(0, &[]).transparency().is_none());
+
+        let cases = [
+            (
+                png(3, &[("tRNS", &[0, 0, 0])]),
+                DiagnosticKind::BadTransparency,
+            ),
+            (
+                png(6, &[("tRNS", &[0, 0])]),
+                DiagnosticKind::BadTransparency,
+            ),
+            (
+                png(4, &[("tRNS", &[0, 0])]),
+                DiagnosticKind::BadTransparency,
+            ),
+            (
+                png(0, &[("tRNS", &[0, 0, 0])]),
+                DiagnosticKind::BadTransparency,
+            ),
+            (png(2, &[("bKGD", &[0, 0])]), DiagnosticKind::BadBackground),
+            (png(3, &[("bKGD", &[0, 0])]), DiagnosticKind::BadBackground),
+        ];
+        for (png, kind) in cases {
+            assert_eq!(kinds(png), [(kind, Some(2))]);
+        }
+
+        let headless = Png::from_chunks(vec![Chunk::new(
+            ChunkType::from_str("tRNS").unwrap(),
+            [0, 0],
+        )]);
+        assert!(headless.transparency().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_extension_checks() {
+        let png = |extra: &[(&str, &[u8])]| {
+            let mut chunks = vec![Chunk::new(ChunkType::from_str("IHDR").unwrap(), [0; 13])];
+            for (t, d) in extra {
+                chunks.push(Chunk::new(ChunkType::from_str(t).unwrap(), *d));
+            }
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), "data"));
+            chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), []));
+            Png::from_chunks(chunks)
+        };
+        let kinds = |png: &Png, strict_spec: bool| -> Vec<DiagnosticKind> {
+            let options = ParseOptions {
+                strict_spec,
+                ..ParseOptions::default()
+            };
+            let (_, diagnostics) = Png::parse_report_with(&png.as_bytes(), options);
+            diagnostics.iter().map(|d| d.kind).collect()
+        };
+
+        let valid = png(&[
+            ("oFFs", &[0, 0, 0, 1, 255, 255, 255, 254, 0]),
+            ("sCAL", b"\x011e-3\x002e-3"),
+            ("sTER", &[1]),
+        ]);
+        assert!(kinds(&valid, true).is_empty());
+        assert_eq!(valid.offset().unwrap().unwrap().y, -2);
+        assert_eq!(valid.scale().unwrap().unwrap().height, 2e-3);
+        assert_eq!(valid.stereo().unwrap().unwrap(), Stereo::DivergingFused);
+
+        for (ctype, data) in [
+            ("oFFs", &[0; 8][..]),
+            ("sCAL", b"\x011e-3 2e-3"),
+            ("sCAL", b"\x011,5\x002"),
+            ("sTER", &[2]),
+        ] {
+            assert_eq!(
+                kinds(&png(&[(ctype, data)]), false),
+                [DiagnosticKind::BadExtension]
+            );
+        }
+
+        let mut late = valid.chunks().to_vec();
+        late.swap(3, 4);
+        assert_eq!(
+            kinds(&Png::from_chunks(late), true),
+            [DiagnosticKind::AncillaryOrdering]
+        );
+    }
+
+    #[test]
+    fn test_parse_report_strict_fails_on_bad_crc() {
+        let (png, diagnostics) = Png::parse_report(&damaged_png_bytes());
+        assert!(png.is_err());
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::BadCrc);
+        assert_eq!(diagnostics[0].offset, Some(36));
+    }
+
+    #[test]
+    fn test_parse_report_truncated_chunk() {
+        let mut bytes = testing_png().as_bytes();
+        bytes.truncate(bytes.len() - 3);
+        let (png, diagnostics) = Png::parse_report(&bytes);
+        assert!(png.is_err());
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::LengthMismatch);
+        assert_eq!(diagnostics[0].chunk_index, Some(2));
+
+        let (png, _) = Png::parse_report_lenient(&bytes);
+        assert_eq!(png.unwrap().chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_complete_reports_missing_chunks() {
+        let options = ParseOptions {
+            lenient: true,
+            complete: true,
+            ..Default::default()
+        };
+        let kinds = |bytes: &[u8]| -> Vec<(Severity, DiagnosticKind, Option<usize>)> {
+            let (png, diagnostics) = Png::parse_report_with(bytes, options);
+            assert!(png.is_ok());
+            diagnostics
+                .iter()
+                .map(|d| (d.severity, d.kind, d.offset))
+                .collect()
+        };
+        assert_eq!(
+            kinds(&Png::STANDARD_HEADER),
+            [
+                (Severity::Error, DiagnosticKind::MissingChunk, Some(8)),
+                (Severity::Error, DiagnosticKind::MissingChunk, None),
+                (Severity::Error, DiagnosticKind::MissingChunk, Some(8)),
+            ]
+        );
+
+        let chunks = |types: &[&str]| -> Vec<u8> {
+            Png::from_chunks(
+                types
+                    .iter()
+                    .map(|t| chunk_from_strings(t, "").unwrap())
+                    .collect(),
+            )
+            .as_bytes()
+        };
+        assert_eq!(
+            kinds(&chunks(&["IHDR", "IDAT"])),
+            [(Severity::Error, DiagnosticKind::MissingChunk, Some(32))]
+        );
+        assert_eq!(
+            kinds(&chunks(&["IDAT", "IHDR", "IEND", "tEXt"])),
+            [
+                (Severity::Error, DiagnosticKind::Ordering, Some(8)),
+                (Severity::Error, DiagnosticKind::Ordering, Some(20)),
+                (Severity::Error, DiagnosticKind::Ordering, Some(44)),
+            ]
+        );
+        assert!(kinds(&chunks(&["IHDR", "IDAT", "IEND"])).is_empty());
+    }
+
+    #[test]
+    fn test_parse_report_chunks_after_iend_are_kept() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let (parsed, diagnostics) = Png::parse_report(&png.as_bytes());
+        assert_eq!(parsed.unwrap().chunks().len(), 5);
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.kind == DiagnosticKind::Ordering && d.chunk_index == Some(4)));
+    }
+
+    #[test]
+    fn test_walk_stops_early() {
+        let png = testing_png();
+        let mut visited = vec![];
+        let flow = png.walk(|idx, chunk| {
+            visited.push(idx);
+            if chunk.chunk_type().to_string() == "miDl" {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert!(flow.is_break());
+        assert_eq!(visited, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_walk_mut_persists_edits() {
+        let mut png = testing_png();
+        let flow = png.walk_mut(|_, chunk| {
+            if chunk.chunk_type().to_string() == "miDl" {
+                chunk.set_data(b"edited".to_vec());
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
+        });
+        assert!(flow.is_break());
+        let chunk = png.chunk_by_type("miDl").unwrap();
+        assert_eq!(chunk.data(), b"edited");
+        assert!(chunk.has_valid_crc());
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed, png);
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_types_are_send_and_sync() {
+        assert_send_sync::<Png>();
+        assert_send_sync::<Chunk>();
+        assert_send_sync::<ChunkType>();
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let mut png = testing_png();
+        // Chunks long enough for their data to be kept on the heap
+        for i in 0..64u8 {
+            let chunk_type = ChunkType::from_bytes_unchecked([b'r', b'u', b'S', b'a' + i % 25]);
+            png.append_chunk(Chunk::new(chunk_type, vec![i; 100 + i as usize]));
+        }
+        let png = &png;
+        let bytes = png.as_bytes();
+        std::thread::scope(|s| {
+            let workers: Vec<_> = ["FrSt", "miDl", "LASt", "ruSa", "ruSy"]
+                .iter()
+                .map(|&chunk_type| {
+                    s.spawn(move || {
+                        for _ in 0..50 {
+                            let chunk = png.chunk_by_type(chunk_type).unwrap();
+                            assert!(chunk.has_valid_crc());
+                            assert_eq!(chunk.crc(), chunk.computed_crc());
+                            assert!(png.verify_crcs().is_empty());
+                        }
+                        png.as_bytes()
+                    })
+                })
+                .collect();
+            for worker in workers {
+                assert_eq!(worker.join().unwrap(), bytes);
+            }
+        });
+    }
+
+    #[test]
+    fn test_transform_chunks() {
+        let mut png = testing_png();
+        let changed = png
+            .transform_chunks(
+                |c| c.chunk_type().is_critical(),
+                |data| {
+                    let before = data.len();
+                    data.retain(|&b| b != b' ');
+                    Ok(data.len() != before)
+                },
+            )
+            .unwrap();
+        assert_eq!(changed, [0, 2]);
+        let last = png.chunk_by_type("LASt").unwrap();
+        assert_eq!(last.data(), b"Iamthelastchunk");
+        assert_eq!(last.length(), 15);
+        assert!(last.has_valid_crc());
+        assert_eq!(png.chunks()[1].data(), b"I am another chunk");
+
+        // A failure on the second chunk leaves the first alone too
+        let before = png.clone();
+        let mut calls = 0;
+        let error = png.transform_chunks(
+            |_| true,
+            |data| {
+                calls += 1;
+                data.clear();
+                match calls {
+                    2 => Err("no".into()),
+                    _ => Ok(true),
+                }
+            },
+        );
+        assert!(error.is_err());
+        assert_eq!(png, before);
+    }
+
+    /// A CgBI file whose IDAT chunk has a byteswapped CRC, as produced by Xcode.
+    fn cgbi_png_bytes() -> Vec<u8> {
+        let mut idat = chunk_from_strings("IDAT", "rawdeflate").unwrap().as_bytes();
+        let crc_start = idat.len() - 4;
+        idat[crc_start..].reverse();
+        [
+            Png::STANDARD_HEADER.to_vec(),
+            Chunk::new(
+                ChunkType::from_str("CgBI").unwrap(),
+                vec![0x50, 0, 0x20, 0x02],
+            )
+            .as_bytes(),
+            chunk_from_strings("IHDR", "header").unwrap().as_bytes(),
+            idat,
+            chunk_from_strings("IEND", "").unwrap().as_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_parse_cgbi() {
+        let (png, diagnostics) = Png::parse_report(&cgbi_png_bytes());
+        let png = png.unwrap();
+        assert!(png.is_cgbi());
+        assert_eq!(png.chunks().len(), 4);
+        let kinds: Vec<DiagnosticKind> = diagnostics.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![DiagnosticKind::AppleCgbi, DiagnosticKind::BadCrc]
+        );
+        assert!(diagnostics.iter().all(|d| !d.is_error()));
+        assert!(!testing_png().is_cgbi());
+    }
+
+    #[test]
+    fn test_print_cgbi() {
+        let png = Png::try_from(&cgbi_png_bytes()[..]).unwrap();
+        let printed = format!("{}", png);
+        for ctype in ["CgBI", "IHDR", "IDAT", "IEND"] {
+            assert!(printed.contains(&format!("Type: {}", ctype)));
+        }
+    }
+
+    #[test]
+    fn test_cgbi_encode_roundtrip_preserves_structure() {
+        let original = cgbi_png_bytes();
+        let mut png = Png::try_from(&original[..]).unwrap();
+        let inserted = chunk_from_strings("ruSt", "payload").unwrap();
+        png.append_chunk(inserted.clone());
+        let bytes = png.as_bytes();
+        assert_eq!(bytes, [original, inserted.as_bytes()].concat());
+        let reparsed = Png::try_from(&bytes[..]).unwrap();
+        assert!(reparsed.is_cgbi());
+        assert_eq!(reparsed.chunk_by_type("ruSt").unwrap().data(), b"payload");
+    }
+
+    /// Deterministic filler bytes standing in for the surrounding file.
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_from_bytes_at_embedded_png() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let blob = [noise(300, 1), PNG_FILE.to_vec(), noise(500, 2)].concat();
+
+        assert_eq!(Png::find_signatures(&blob), vec![300]);
+        let embedded = Png::from_bytes_at(&blob, 300).unwrap();
+        assert_eq!(embedded, png);
+        assert_eq!(embedded.chunk_by_type("RuSt").unwrap().data(), b"hey");
+        assert!(embedded.trailing_data().is_empty());
+    }
+
+    #[test]
+    fn test_from_bytes_at_bad_offsets() {
+        let blob = [noise(30, 3), PNG_FILE.to_vec()].concat();
+        assert!(Png::from_bytes_at(&blob, 29).is_err());
+        assert!(Png::from_bytes_at(&blob, blob.len() + 1).is_err());
+    }
+
+    #[test]
+    fn test_image_ranges() {
+        let first = image_png(&["first"], "a").as_bytes();
+        let second = image_png(&["second"], "b").as_bytes();
+        let bytes = [first.clone(), second.clone()].concat();
+        let ranges = Png::image_ranges(&bytes).unwrap();
+        assert_eq!(ranges, vec![0..first.len(), first.len()..bytes.len()]);
+
+        assert_eq!(Png::image_ranges(&first).unwrap(), vec![0..first.len()]);
+        assert!(Png::image_ranges(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_trailing_offset() {
+        let png = image_png(&["pixels"], "a").as_bytes();
+        assert_eq!(Png::trailing_offset(&png).unwrap(), png.len());
+
+        let polyglot = [png.clone(), b"PK\x03\x04zipdata".to_vec()].concat();
+        assert_eq!(Png::trailing_offset(&polyglot).unwrap(), png.len());
+
+        let two = [png.clone(), png.clone(), b"junk".to_vec()].concat();
+        assert_eq!(Png::trailing_offset(&two).unwrap(), 2 * png.len());
+    }
+
+    fn image_png(idat: &[&str], text: &str) -> Png {
+        let mut chunks = vec![chunk_from_strings("IHDR", "header").unwrap()];
+        chunks.push(chunk_from_strings("tEXt", text).unwrap());
+        for data in idat {
+            chunks.push(chunk_from_strings("IDAT", data).unwrap());
+        }
+        chunks.push(chunk_from_strings("IEND", "").unwrap());
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_png_eq_is_order_sensitive() {
+        assert_eq!(testing_png(), testing_png());
+
+        let mut chunks = testing_chunks();
+        chunks.swap(0, 1);
+        assert_ne!(testing_png(), Png::from_chunks(chunks));
+    }
+
+    #[test]
+    fn test_content_equals_ignores_idat_split() {
+        let a = image_png(&["pixeldata"], "a");
+        let b = image_png(&["pix", "el", "data"], "a");
+        assert_ne!(a, b);
+        assert!(a.content_equals(&b));
+    }
+
+    #[test]
+    fn test_content_equals_ignores_ancillary_chunks() {
+        let a = image_png(&["pixeldata"], "Comment\0one");
+        let b = image_png(&["pixeldata"], "Comment\0two");
+        assert!(a.content_equals(&b));
+
+        let mut c = image_png(&["pixeldata"], "Comment\0one");
+        c.append_chunk(chunk_from_strings("ruSt", "payload").unwrap());
+        assert!(a.content_equals(&c));
+    }
+
+    #[test]
+    fn test_content_differs_on_critical_data() {
+        let a = image_png(&["pixeldata"], "a");
+        let b = image_png(&["pixeldatb"], "a");
+        assert!(!a.content_equals(&b));
+
+        let mut c = image_png(&["pixeldata"], "a");
+        c.append_chunk(chunk_from_strings("PLTE", "palette").unwrap());
+        assert!(!a.content_equals(&c));
+    }
+
+    #[test]
+    fn test_copyable_chunks() {
+        let mut png = image_png(&["pixeldata"], "Comment\0one");
+        png.append_chunk(chunk_from_strings("ruSt", "payload").unwrap());
+        png.append_chunk(chunk_from_strings("prIV", "unsafe").unwrap());
+        let types: Vec<String> = png
+            .copyable_chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["tEXt", "ruSt"]);
+    }
+
+    #[test]
+    fn test_content_hash_follows_content_equals() {
+        let a = image_png(&["pixeldata"], "Comment\0one");
+        let b = image_png(&["pix", "eldata"], "Comment\0two");
+        assert_eq!(a.content_hash(), b.content_hash());
+        let c = image_png(&["pixeldatb"], "Comment\0one");
+        assert_ne!(a.content_hash(), c.content_hash());
+    }
+
+    /// Reads chunks the obvious way, one field at a time, for checking `Png::parse` against.
+    fn reference_chunks(bytes: &[u8]) -> Vec<([u8; 4], Vec<u8>, u32)> {
+        let mut chunks = vec![];
+        let mut rest = &bytes[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let chunk_type: [u8; 4] = rest[4..8].try_into().unwrap();
+            let data = rest[8..8 + length].to_vec();
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+            chunks.push((chunk_type, data, crc));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_parse_matches_reference() {
+        let many: Vec<Chunk> = (0..5_000u32)
+            .map(|i| {
+                Chunk::new(
+                    ChunkType::from_str("ruSt").unwrap(),
+                    i.to_be_bytes().to_vec(),
+                )
+            })
+            .collect();
+        let large = vec![Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<u8>>(),
+        )];
+        for bytes in [
+            PNG_FILE.to_vec(),
+            Png::from_chunks(many).as_bytes(),
+            Png::from_chunks(large).as_bytes(),
+        ] {
+            let png = Png::try_from(&bytes[..]).unwrap();
+            let parsed: Vec<([u8; 4], Vec<u8>, u32)> = png
+                .chunks()
+                .iter()
+                .map(|c| (c.chunk_type().bytes(), c.data().to_vec(), c.crc()))
+                .collect();
+            assert_eq!(parsed, reference_chunks(&bytes));
+            assert_eq!(png.as_bytes(), bytes);
+            assert!(png.verify_crcs().is_empty());
+        }
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
@@ -0,0 +1,125 @@
+//! Wall-clock time spent in each phase of a command, for `--profile`. Phases are timed with
+//! `scope`, which does nothing but load a flag until `enable` is called, so the timers can
+//! stay in hot paths.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Total time and number of timed scopes for each phase, indexed by `Phase as usize`
+static TOTALS: Mutex<[(Duration, u32); Phase::ALL.len()]> =
+    Mutex::new([(Duration::ZERO, 0); Phase::ALL.len()]);
+
+/// A part of a command worth timing on its own. Parse includes the CRC checks it makes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Reading the input file
+    Read,
+    /// Splitting the bytes into chunks
+    Parse,
+    /// Checking chunk CRCs
+    Crc,
+    /// Compressing, encrypting and error-correcting messages, and undoing it
+    Codec,
+    /// Turning chunks back into bytes
+    Serialize,
+    /// Writing the output file
+    Write,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Read,
+        Phase::Parse,
+        Phase::Crc,
+        Phase::Codec,
+        Phase::Serialize,
+        Phase::Write,
+    ];
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Read => "read",
+            Phase::Parse => "parse",
+            Phase::Crc => "crc",
+            Phase::Codec => "codec",
+            Phase::Serialize => "serialize",
+            Phase::Write => "write",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The time spent in one phase over a whole command.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    pub micros: u64,
+    /// How many times the phase was entered
+    pub count: u32,
+}
+
+/// Starts timing from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Adds the time until it is dropped to its phase. Holds nothing when profiling is off.
+#[must_use = "the phase is timed until the timer is dropped"]
+pub struct Timer(Option<(Phase, Instant)>);
+
+pub fn scope(phase: Phase) -> Timer {
+    Timer(is_enabled().then(|| (phase, Instant::now())))
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((phase, start)) = self.0 {
+            let mut totals = TOTALS.lock().unwrap_or_else(|e| e.into_inner());
+            let (total, count) = &mut totals[phase as usize];
+            *total += start.elapsed();
+            *count += 1;
+        }
+    }
+}
+
+/// The phases timed so far, in the order of `Phase::ALL`, leaving the totals at zero.
+pub fn take() -> Vec<PhaseTiming> {
+    let mut totals = TOTALS.lock().unwrap_or_else(|e| e.into_inner());
+    let timings = Phase::ALL
+        .iter()
+        .zip(totals.iter())
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(&phase, &(total, count))| PhaseTiming {
+            phase,
+            micros: total.as_micros() as u64,
+            count,
+        })
+        .collect();
+    *totals = [(Duration::ZERO, 0); Phase::ALL.len()];
+    timings
+}
+
+/// The breakdown `--profile` prints on stderr, a line per phase.
+pub fn render(timings: &[PhaseTiming]) -> String {
+    let mut out = String::from("profile:\n");
+    for timing in timings {
+        out += &format!(
+            "  {:<10} {:>10.3} ms  ({}x)\n",
+            timing.phase.to_string(),
+            timing.micros as f64 / 1000.0,
+            timing.count
+        );
+    }
+    out
+}
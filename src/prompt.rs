@@ -0,0 +1,108 @@
+use std::io;
+
+use crate::error::UsageError;
+use crate::secret::SecretBytes;
+
+/// Asks the user for secrets. The terminal implementation is swapped for scripted answers in
+/// tests.
+pub trait Prompt {
+    /// Shows `prompt` and reads a line without echoing it. Fails if there is nobody to ask.
+    fn secret(&mut self, prompt: &str) -> io::Result<SecretBytes>;
+}
+
+/// Prompts on the controlling terminal with echo disabled, so stdout and stdin stay free for
+/// piped payloads and png bytes.
+pub struct TerminalPrompt;
+
+impl Prompt for TerminalPrompt {
+    fn secret(&mut self, prompt: &str) -> io::Result<SecretBytes> {
+        rpassword::prompt_password(prompt).map(SecretBytes::from)
+    }
+}
+
+/// Asks for a passphrase, and with `confirm` asks again and checks both entries match.
+pub fn passphrase(prompt: &mut dyn Prompt, confirm: bool) -> crate::Result<SecretBytes> {
+    let no_terminal = |e: io::Error| {
+        UsageError::boxed(format!(
+            "Can't prompt for a passphrase ({}), pass --passphrase or --key-file instead",
+            e
+        ))
+    };
+    let first = prompt.secret("Passphrase: ").map_err(no_terminal)?;
+    if first.is_empty() {
+        return Err(UsageError::boxed(
+            "The passphrase must not be empty".to_string(),
+        ));
+    }
+    if confirm && prompt.secret("Confirm passphrase: ").map_err(no_terminal)? != first {
+        return Err(UsageError::boxed(
+            "The passphrases do not match".to_string(),
+        ));
+    }
+    Ok(first)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Answers prompts from a script, failing like a missing terminal once it runs out.
+    pub(crate) struct ScriptedPrompt {
+        pub(crate) answers: VecDeque<String>,
+        pub(crate) asked: Vec<String>,
+    }
+
+    impl ScriptedPrompt {
+        pub(crate) fn new(answers: &[&str]) -> Self {
+            Self {
+                answers: answers.iter().map(|a| a.to_string()).collect(),
+                asked: vec![],
+            }
+        }
+    }
+
+    impl Prompt for ScriptedPrompt {
+        fn secret(&mut self, prompt: &str) -> io::Result<SecretBytes> {
+            self.asked.push(prompt.to_string());
+            self.answers
+                .pop_front()
+                .map(SecretBytes::from)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no terminal"))
+        }
+    }
+
+    #[test]
+    fn test_confirmed_passphrase() {
+        let mut prompt = ScriptedPrompt::new(&["hunter2", "hunter2"]);
+        assert_eq!(passphrase(&mut prompt, true).unwrap().expose(), b"hunter2");
+        assert_eq!(prompt.asked, ["Passphrase: ", "Confirm passphrase: "]);
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let mut prompt = ScriptedPrompt::new(&["hunter2", "hunter3"]);
+        let err = passphrase(&mut prompt, true).unwrap_err();
+        assert!(err.to_string().contains("do not match"));
+    }
+
+    #[test]
+    fn test_single_prompt_without_confirm() {
+        let mut prompt = ScriptedPrompt::new(&["hunter2"]);
+        assert_eq!(passphrase(&mut prompt, false).unwrap().expose(), b"hunter2");
+        assert_eq!(prompt.asked, ["Passphrase: "]);
+    }
+
+    #[test]
+    fn test_no_terminal() {
+        let mut prompt = ScriptedPrompt::new(&[]);
+        let err = passphrase(&mut prompt, true).unwrap_err();
+        assert!(err.to_string().contains("--key-file"));
+    }
+
+    #[test]
+    fn test_empty_passphrase() {
+        let mut prompt = ScriptedPrompt::new(&["", ""]);
+        assert!(passphrase(&mut prompt, true).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Python bindings, built by maturin into the `pngme` extension module with the `python`
+//! feature. Each function wraps the library the way the matching command does, working on png
+//! bytes rather than files, and errors become the exceptions below, picked by exit code.
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compress::{self, Codec};
+use crate::crypto::{self, Entropy, KeySource};
+use crate::envelope::{self, Envelope};
+use crate::error::{ExitCode, Failure, UsageError};
+use crate::png::{Placement, Png};
+
+create_exception!(pngme, PngmeError, PyException, "Any error pngme reports.");
+create_exception!(
+    pngme,
+    NotPngError,
+    PngmeError,
+    "The bytes aren't a png or can't be parsed."
+);
+create_exception!(
+    pngme,
+    IntegrityError,
+    PngmeError,
+    "A CRC, checksum, key or other integrity check failed."
+);
+
+/// The exception for `error`, carrying its message.
+pub fn exception(error: &crate::Error) -> PyErr {
+    let failure = Failure::of(error);
+    let message = failure.error().to_string();
+    match ExitCode::of(&failure) {
+        ExitCode::NotPng => NotPngError::new_err(message),
+        ExitCode::Integrity => IntegrityError::new_err(message),
+        _ => PngmeError::new_err(message),
+    }
+}
+
+fn chunk_type(arg: &str) -> PyResult<ChunkType> {
+    ChunkType::from_str(arg).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// `png` with `message` stored in a new chunk of `chunk_type` just before IEND, compressed
+/// and encrypted with `passphrase` if asked.
+pub fn encode_message(
+    png: &[u8],
+    chunk_type: ChunkType,
+    message: &[u8],
+    compress: bool,
+    passphrase: Option<&str>,
+) -> crate::Result<Vec<u8>> {
+    let mut png = Png::try_from(png)?;
+    let (message, codec) = match compress {
+        true => {
+            let (compressed, stats) = compress::compress(message, Codec::Deflate, 0)?;
+            (compressed, Some(stats.codec))
+        }
+        false => (message.to_vec(), None),
+    };
+    let mut envelope = match passphrase {
+        Some(passphrase) => {
+            let source = KeySource::Passphrase(passphrase.into());
+            let (cipher, ciphertext) = crypto::encrypt(&source, &message, &mut Entropy::Os)?;
+            Envelope::new(ciphertext).with_cipher(cipher)
+        }
+        None => Envelope::new(message),
+    };
+    if let Some(codec) = codec {
+        envelope = envelope.with_codec(codec);
+    }
+    png.place_chunk(
+        Chunk::new(chunk_type, envelope.as_bytes()),
+        Placement::BeforeIend,
+    );
+    Ok(png.as_bytes())
+}
+
+/// The message in the chunks of `chunk_type`, or in those of the first chunk holding a pngme
+/// envelope when no type is given, or `None` if there are no such chunks.
+pub fn decode_message(
+    png: &[u8],
+    chunk_type: Option<ChunkType>,
+    passphrase: Option<&str>,
+) -> crate::Result<Option<Vec<u8>>> {
+    let png = Png::try_from(png)?;
+    let chunk_type = chunk_type.or_else(|| {
+        png.chunks()
+            .iter()
+            .find(|c| !c.chunk_type().is_critical() && Envelope::is_envelope(c.data()))
+            .map(|c| *c.chunk_type())
+    });
+    let Some(chunk_type) = chunk_type else {
+        return Ok(None);
+    };
+    let chunks: Vec<&Chunk> = png
+        .chunks()
+        .iter()
+        .filter(|c| *c.chunk_type() == chunk_type)
+        .collect();
+    let envelope = match chunks.as_slice() {
+        [] => return Ok(None),
+        [chunk] if !Envelope::is_envelope(chunk.data()) => return Ok(Some(chunk.data().to_vec())),
+        _ => {
+            let copies: Vec<crate::Result<Envelope>> = chunks
+                .iter()
+                .map(|c| {
+                    Envelope::from_bytes(c.data())?.ok_or_else(|| "not a pngme envelope".into())
+                })
+                .collect();
+            envelope::recover(&copies)?.envelope
+        }
+    };
+    let message = match (envelope.cipher, passphrase) {
+        (Some(cipher), Some(passphrase)) => crypto::decrypt(
+            &KeySource::Passphrase(passphrase.into()),
+            &cipher,
+            &envelope.payload,
+        )?,
+        (Some(_), None) => {
+            return Err(UsageError::boxed(
+                "The message is encrypted, pass a passphrase".to_string(),
+            ))
+        }
+        (None, _) => envelope.payload.clone(),
+    };
+    Ok(Some(envelope.unpack(message)?))
+}
+
+/// Stores `message` in a new chunk of `chunk_type` and returns the new png.
+#[pyfunction]
+#[pyo3(signature = (png_bytes, chunk_type, message, *, compress=false, passphrase=None))]
+fn encode<'py>(
+    py: Python<'py>,
+    png_bytes: &[u8],
+    chunk_type: &str,
+    message: &[u8],
+    compress: bool,
+    passphrase: Option<&str>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let chunk_type = self::chunk_type(chunk_type)?;
+    let png = encode_message(png_bytes, chunk_type, message, compress, passphrase)
+        .map_err(|e| exception(&e))?;
+    Ok(PyBytes::new(py, &png))
+}
+
+/// The message in chunks of `chunk_type`, or in the first pngme chunk, or None.
+#[pyfunction]
+#[pyo3(signature = (png_bytes, chunk_type=None, *, passphrase=None))]
+fn decode<'py>(
+    py: Python<'py>,
+    png_bytes: &[u8],
+    chunk_type: Option<&str>,
+    passphrase: Option<&str>,
+) -> PyResult<Option<Bound<'py, PyBytes>>> {
+    let chunk_type = chunk_type.map(self::chunk_type).transpose()?;
+    let message = decode_message(png_bytes, chunk_type, passphrase).map_err(|e| exception(&e))?;
+    Ok(message.map(|message| PyBytes::new(py, &message)))
+}
+
+/// A dict for each chunk, with the fields `pngme print --json` lists.
+#[pyfunction]
+fn chunks<'py>(py: Python<'py>, png_bytes: &[u8]) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let png = Png::try_from(png_bytes).map_err(|e| exception(&e))?;
+    png.chunks()
+        .iter()
+        .enumerate()
+        .map(|(index, c)| {
+            let dict = PyDict::new(py);
+            dict.set_item("index", index)?;
+            dict.set_item("chunk_type", c.chunk_type().to_string())?;
+            dict.set_item("critical", c.chunk_type().is_critical())?;
+            dict.set_item("public", c.chunk_type().is_public())?;
+            dict.set_item("safe_to_copy", c.chunk_type().is_safe_to_copy())?;
+            dict.set_item("length", c.length())?;
+            dict.set_item("crc", c.crc())?;
+            dict.set_item("crc_ok", c.has_valid_crc())?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// The problems `pngme check` would report, as messages. Empty for a clean png.
+#[pyfunction]
+fn check(png_bytes: &[u8]) -> Vec<String> {
+    let (_, diagnostics) = Png::parse_report_lenient(png_bytes);
+    diagnostics.iter().map(|d| d.to_string()).collect()
+}
+
+#[pymodule]
+fn pngme(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(encode, m)?)?;
+    m.add_function(wrap_pyfunction!(decode, m)?)?;
+    m.add_function(wrap_pyfunction!(chunks, m)?)?;
+    m.add_function(wrap_pyfunction!(check, m)?)?;
+    m.add("PngmeError", m.py().get_type::<PngmeError>())?;
+    m.add("NotPngError", m.py().get_type::<NotPngError>())?;
+    m.add("IntegrityError", m.py().get_type::<IntegrityError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png() -> Vec<u8> {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d);
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        let encoded = encode_message(&png(), rust, b"hello", true, Some("hunter2")).unwrap();
+        let types: Vec<String> = Png::try_from(&encoded[..])
+            .unwrap()
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+
+        let decoded = decode_message(&encoded, None, Some("hunter2")).unwrap();
+        assert_eq!(decoded.as_deref(), Some(&b"hello"[..]));
+        assert!(decode_message(&encoded, Some(rust), None).is_err());
+        assert!(decode_message(&encoded, Some(rust), Some("wrong")).is_err());
+        assert_eq!(decode_message(&png(), None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_errors_map_to_exceptions() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let not_png = exception(&decode_message(b"GIF89a", None, None).unwrap_err());
+            assert!(not_png.is_instance_of::<NotPngError>(py));
+            assert!(not_png.is_instance_of::<PngmeError>(py));
+
+            let rust = ChunkType::from_str("ruSt").unwrap();
+            let encoded = encode_message(&png(), rust, b"hello", false, Some("right")).unwrap();
+            let wrong = decode_message(&encoded, None, Some("wrong")).unwrap_err();
+            let wrong = exception(&wrong);
+            assert!(wrong.is_instance_of::<IntegrityError>(py));
+            assert!(wrong
+                .value(py)
+                .to_string()
+                .contains("wrong key or passphrase"));
+
+            let plain = exception(&decode_message(&encoded, None, None).unwrap_err());
+            assert!(!plain.is_instance_of::<IntegrityError>(py));
+            assert!(plain.is_instance_of::<PngmeError>(py));
+
+            assert!(chunk_type("ru!t")
+                .unwrap_err()
+                .is_instance_of::<PyValueError>(py));
+        });
+    }
+}
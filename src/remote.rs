@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::UsageError;
+
+/// How much `fetch` downloads by default before giving up, 100 MiB.
+pub const DEFAULT_MAX_DOWNLOAD: u64 = 100 * 1024 * 1024;
+
+/// The cap given with `--max-download`, set once before the command runs.
+static MAX_DOWNLOAD: AtomicU64 = AtomicU64::new(DEFAULT_MAX_DOWNLOAD);
+
+/// A png couldn't be downloaded.
+#[derive(Debug)]
+pub struct RemoteError {
+    reason: String,
+}
+impl RemoteError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Download error: {}", self.reason)
+    }
+}
+impl Error for RemoteError {}
+
+/// Whether a file argument names an http or https URL rather than a local path.
+pub fn is_url(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+pub fn set_max_download(bytes: u64) {
+    MAX_DOWNLOAD.store(bytes, Ordering::Relaxed);
+}
+
+/// Fails if `path` is a URL, as there is no writing back to one.
+pub fn local_output(path: &str) -> crate::Result<()> {
+    match is_url(path) {
+        true => Err(UsageError::boxed(format!(
+            "Can't write to {}, pass --out-path with a local file to save the result",
+            path
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Downloads the png at `url` into memory, up to the `--max-download` cap.
+#[cfg(feature = "http")]
+pub fn fetch(url: &str) -> crate::Result<Vec<u8>> {
+    fetch_with_limit(url, MAX_DOWNLOAD.load(Ordering::Relaxed))
+}
+
+#[cfg(not(feature = "http"))]
+pub fn fetch(url: &str) -> crate::Result<Vec<u8>> {
+    Err(RemoteError::boxed(format!(
+        "can't download {}, pngme was built without the http feature",
+        url
+    )))
+}
+
+/// Only a 200 response is taken, and only if it is served as a png or as untyped bytes.
+#[cfg(feature = "http")]
+fn fetch_with_limit(url: &str, limit: u64) -> crate::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let response = match ureq::get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, response)) => {
+            return Err(RemoteError::boxed(format!(
+                "{} answered {} {}",
+                url,
+                code,
+                response.status_text()
+            )))
+        }
+        Err(e) => return Err(RemoteError::boxed(e.to_string())),
+    };
+    if response.status() != 200 {
+        return Err(RemoteError::boxed(format!(
+            "{} answered {} {}",
+            url,
+            response.status(),
+            response.status_text()
+        )));
+    }
+    if let Some(content_type) = response.header("Content-Type") {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if !["image/png", "image/apng", "application/octet-stream"]
+            .iter()
+            .any(|accepted| mime.eq_ignore_ascii_case(accepted))
+        {
+            return Err(RemoteError::boxed(format!(
+                "{} is served as {}, not as a png",
+                url, mime
+            )));
+        }
+    }
+    let too_large = || {
+        RemoteError::boxed(format!(
+            "{} is larger than the --max-download limit of {} bytes",
+            url, limit
+        ))
+    };
+    let declared = response
+        .header("Content-Length")
+        .and_then(|l| l.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return Err(too_large());
+    }
+    let mut bytes = vec![];
+    response
+        .into_reader()
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(too_large());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_detection() {
+        assert!(is_url("https://example.com/img.png"));
+        assert!(is_url("HTTP://example.com/img.png"));
+        assert!(!is_url("img.png"));
+        assert!(!is_url("./http://img.png"));
+        assert!(local_output("out.png").is_ok());
+        assert!(local_output("https://example.com/img.png").is_err());
+    }
+
+    #[cfg(feature = "http")]
+    mod http {
+        use super::super::*;
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        /// Serves a single request with `status`, `content_type` and `body`, returning the URL
+        /// to fetch it from.
+        fn serve(status: &str, content_type: &'static str, body: Vec<u8>) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/img.png", listener.local_addr().unwrap());
+            let status = status.to_string();
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    content_type,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&body);
+            });
+            url
+        }
+
+        fn fixture() -> Vec<u8> {
+            let chunk =
+                |t: &str, d: &[u8]| crate::chunk::Chunk::new(t.parse().unwrap(), d.to_vec());
+            crate::png::Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("IEND", b"")])
+                .as_bytes()
+        }
+
+        #[test]
+        fn test_fetches_a_png() {
+            let url = serve("200 OK", "image/png", fixture());
+            let bytes = fetch_with_limit(&url, DEFAULT_MAX_DOWNLOAD).unwrap();
+            assert_eq!(bytes, fixture());
+            assert!(crate::png::Png::try_from(&bytes[..]).is_ok());
+        }
+
+        #[test]
+        fn test_rejects_oversized_responses() {
+            let url = serve("200 OK", "image/png", vec![0; 4096]);
+            let err = fetch_with_limit(&url, 1024).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("--max-download limit of 1024 bytes"));
+        }
+
+        #[test]
+        fn test_rejects_errors_and_other_content() {
+            let url = serve("404 Not Found", "image/png", vec![]);
+            let err = fetch_with_limit(&url, DEFAULT_MAX_DOWNLOAD).unwrap_err();
+            assert!(err.to_string().contains("404 Not Found"), "{}", err);
+
+            let url = serve("200 OK", "text/html; charset=utf-8", b"<html>".to_vec());
+            let err = fetch_with_limit(&url, DEFAULT_MAX_DOWNLOAD).unwrap_err();
+            assert!(err.to_string().contains("served as text/html"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Rebuilding a png whose chunks have bad CRCs or length fields, for `pngme repair`. Each chunk
+//! is read as declared when that checks out; otherwise the length is found again from where
+//! the stored CRC matches, or from where the next intact chunk starts, and the CRC recomputed.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+use crate::chunk::{Chunk, CRC_PNG};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// The file can't be repaired, or not without dropping chunks it wasn't allowed to.
+#[derive(Debug)]
+pub struct RepairError {
+    reason: String,
+}
+impl RepairError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for RepairError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Can't repair: {}", self.reason)
+    }
+}
+impl Error for RepairError {}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixKind {
+    /// The signature was damaged but IHDR follows where it should
+    Signature,
+    /// The stored CRC didn't match the chunk and was recomputed
+    Crc,
+    /// The length field was wrong, and the CRC too if it didn't match the corrected data
+    Length,
+    /// The chunk couldn't be told apart from what follows it and was left out
+    Dropped,
+    /// There was no IEND, so one was added
+    AddedIend,
+}
+
+/// One change made by `repair`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Fix {
+    pub kind: FixKind,
+    /// Offset of the damaged part in the original file
+    pub offset: usize,
+    pub chunk_type: Option<String>,
+    pub detail: String,
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.offset)?;
+        if let Some(chunk_type) = &self.chunk_type {
+            write!(f, " {}", chunk_type)?;
+        }
+        write!(f, ": {}", self.detail)
+    }
+}
+
+/// The repaired file and what was changed to get it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Repaired {
+    pub bytes: Vec<u8>,
+    pub fixes: Vec<Fix>,
+}
+
+/// How a chunk read at some position turned out.
+enum Read {
+    Intact(Chunk, usize),
+    Fixed(Chunk, usize, Fix),
+    Unrecoverable(String),
+}
+
+/// Repairs the png in `bytes`. A chunk that can't be recovered fails the repair unless
+/// `drop_unrecoverable` is given, in which case it is left out along with everything up to
+/// the next intact chunk.
+pub fn repair(bytes: &[u8], drop_unrecoverable: bool) -> crate::Result<Repaired> {
+    let mut fixes = vec![];
+    if bytes.len() < 8 {
+        return Err(RepairError::boxed(format!(
+            "the file is only {} bytes",
+            bytes.len()
+        )));
+    }
+    if bytes[..8] != Png::STANDARD_HEADER {
+        if bytes.get(12..16) != Some(b"IHDR") {
+            return Err(RepairError::boxed(
+                "the file has no png signature and no IHDR after where it would be".to_string(),
+            ));
+        }
+        fixes.push(Fix {
+            kind: FixKind::Signature,
+            offset: 0,
+            chunk_type: None,
+            detail: "restored the png signature".to_string(),
+        });
+    }
+
+    let mut chunks: Vec<Chunk> = vec![];
+    let mut trailing: &[u8] = &[];
+    let mut position = 8;
+    while position < bytes.len() {
+        if chunks
+            .last()
+            .is_some_and(|c| c.chunk_type().bytes() == *b"IEND")
+        {
+            trailing = &bytes[position..];
+            break;
+        }
+        match read_at(bytes, position) {
+            Read::Intact(chunk, end) => {
+                chunks.push(chunk);
+                position = end;
+            }
+            Read::Fixed(chunk, end, fix) => {
+                chunks.push(chunk);
+                fixes.push(fix);
+                position = end;
+            }
+            Read::Unrecoverable(reason) if !drop_unrecoverable => {
+                return Err(RepairError::boxed(format!(
+                    "the chunk at {:#x} {}. Use --drop-unrecoverable to leave it out",
+                    position, reason
+                )));
+            }
+            Read::Unrecoverable(reason) => {
+                let next = resync(bytes, position + 1);
+                fixes.push(Fix {
+                    kind: FixKind::Dropped,
+                    offset: position,
+                    chunk_type: None,
+                    detail: format!("dropped {} bytes: the chunk {}", next - position, reason),
+                });
+                position = next;
+            }
+        }
+    }
+    if !chunks.iter().any(|c| c.chunk_type().bytes() == *b"IEND") {
+        fixes.push(Fix {
+            kind: FixKind::AddedIend,
+            offset: position,
+            chunk_type: Some("IEND".to_string()),
+            detail: "added the missing IEND".to_string(),
+        });
+        chunks.push(Chunk::new(
+            ChunkType::from_bytes_unchecked(*b"IEND"),
+            vec![],
+        ));
+    }
+
+    let mut out = Png::STANDARD_HEADER.to_vec();
+    for chunk in &chunks {
+        out.extend(chunk.as_bytes());
+    }
+    out.extend(trailing);
+    Ok(Repaired { bytes: out, fixes })
+}
+
+/// The chunk type at `position`, if the four bytes are letters as they must be.
+fn type_at(bytes: &[u8], position: usize) -> Option<[u8; 4]> {
+    let code: [u8; 4] = bytes.get(position + 4..position + 8)?.try_into().ok()?;
+    code.iter()
+        .all(|&b| ChunkType::is_valid_byte(b))
+        .then_some(code)
+}
+
+/// The length field at `position`.
+fn declared_at(bytes: &[u8], position: usize) -> usize {
+    u32::from_be_bytes(bytes[position..position + 4].try_into().unwrap()) as usize
+}
+
+/// Whether a chunk could start at `position`: its type is letters and its declared data fits
+/// in the file. The end of the file counts too, as where the last chunk ends.
+fn plausible_at(bytes: &[u8], position: usize) -> bool {
+    position == bytes.len()
+        || (type_at(bytes, position).is_some()
+            && declared_at(bytes, position)
+                .checked_add(position + 12)
+                .is_some_and(|end| end <= bytes.len()))
+}
+
+/// Where the chunk at `position` ends, if it is exactly as declared. The CRC is checked on
+/// the file's own bytes, so nothing is copied for a chunk that turns out not to be intact.
+fn intact_end(bytes: &[u8], position: usize) -> Option<usize> {
+    if !plausible_at(bytes, position) || position == bytes.len() {
+        return None;
+    }
+    let end = position + 12 + declared_at(bytes, position);
+    let stored = u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap());
+    (CRC_PNG.checksum(&bytes[position + 4..end - 4]) == stored).then_some(end)
+}
+
+/// The chunk at `position` and where it ends, if it is exactly as declared.
+fn read_intact(bytes: &[u8], position: usize) -> Option<(Chunk, usize)> {
+    let end = intact_end(bytes, position)?;
+    let chunk = Chunk::from_bytes_unverified(&bytes[position..end]).ok()?;
+    Some((chunk, end))
+}
+
+/// Where the first intact chunk at or after `from` starts, or the end of the file if there is
+/// none, for picking up again after a chunk that has to be dropped.
+///
+/// Only offsets holding a letter type, with another chunk, the end of the file or trailing
+/// data after an IEND where the declared length leads, get their CRC checked, so a long
+/// stretch of garbage is skipped over without checksumming its bytes again at every offset.
+fn resync(bytes: &[u8], from: usize) -> usize {
+    (from..bytes.len())
+        .find(|&at| {
+            let leads_on =
+                |end: usize| type_at(bytes, at) == Some(*b"IEND") || plausible_at(bytes, end);
+            plausible_at(bytes, at)
+                && leads_on(at + 12 + declared_at(bytes, at))
+                && intact_end(bytes, at).is_some()
+        })
+        .unwrap_or(bytes.len())
+}
+
+fn read_at(bytes: &[u8], position: usize) -> Read {
+    let remaining = bytes.len() - position;
+    if remaining < 12 {
+        return Read::Unrecoverable(format!("is cut short, {} bytes are left", remaining));
+    }
+    let Some(code) = type_at(bytes, position) else {
+        return Read::Unrecoverable("has a type that isn't four letters".to_string());
+    };
+    if let Some((chunk, end)) = read_intact(bytes, position) {
+        return Read::Intact(chunk, end);
+    }
+    let chunk_type = ChunkType::from_bytes_unchecked(code);
+    let declared = declared_at(bytes, position);
+    let data_start = position + 8;
+    let fixed = |length: usize, kind: FixKind, detail: String| {
+        let end = data_start + length + 4;
+        let chunk = Chunk::new(chunk_type, &bytes[data_start..data_start + length]);
+        let fix = Fix {
+            kind,
+            offset: position,
+            chunk_type: Some(chunk_type.to_string()),
+            detail,
+        };
+        Read::Fixed(chunk, end, fix)
+    };
+
+    // An intact chunk, or the end of the file, straight after the declared data shows only
+    // the CRC is wrong
+    let end = declared
+        .checked_add(position + 12)
+        .filter(|&end| end <= bytes.len());
+    let stored_crc = |end: usize| u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap());
+    if let Some(end) = end
+        .filter(|&end| end == bytes.len() || code == *b"IEND" || read_intact(bytes, end).is_some())
+    {
+        return fixed(
+            declared,
+            FixKind::Crc,
+            format!(
+                "recomputed the CRC, which was stored as {:08x}",
+                stored_crc(end)
+            ),
+        );
+    }
+
+    // A stored CRC that matches some shorter or longer run of data gives away the length
+    let mut digest = CRC_PNG.digest();
+    digest.update(&code);
+    for length in 0..=remaining - 12 {
+        let stored = &bytes[data_start + length..data_start + length + 4];
+        if u32::from_be_bytes(stored.try_into().unwrap()) == digest.clone().finalize() {
+            return fixed(
+                length,
+                FixKind::Length,
+                format!("length field said {}, the CRC matches {}", declared, length),
+            );
+        }
+        digest.update(&bytes[data_start + length..data_start + length + 1]);
+    }
+
+    // Failing that, the length is taken to be right if what follows could be a chunk
+    if let Some(end) = end.filter(|&end| plausible_at(bytes, end)) {
+        return fixed(
+            declared,
+            FixKind::Crc,
+            format!(
+                "recomputed the CRC, which was stored as {:08x}",
+                stored_crc(end)
+            ),
+        );
+    }
+    match (data_start + 4..=bytes.len())
+        .find(|&end| end == bytes.len() || read_intact(bytes, end).is_some())
+    {
+        Some(end) => fixed(
+            end - data_start - 4,
+            FixKind::Length,
+            format!(
+                "length field said {}, the next chunk starts after {}, CRC recomputed",
+                declared,
+                end - data_start - 4
+            ),
+        ),
+        None => Read::Unrecoverable("has no length that leads to another chunk".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Vec<u8> {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()).as_bytes()
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        [&Png::STANDARD_HEADER[..], &chunks.concat()].concat()
+    }
+
+    fn intact() -> Vec<u8> {
+        png(&[
+            chunk("IHDR", b"header"),
+            chunk("tEXt", b"Title\0hello"),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    fn kinds(repaired: &Repaired) -> Vec<FixKind> {
+        repaired.fixes.iter().map(|f| f.kind).collect()
+    }
+
+    #[test]
+    fn test_intact_file_is_unchanged() {
+        let repaired = repair(&intact(), false).unwrap();
+        assert_eq!(repaired.bytes, intact());
+        assert!(repaired.fixes.is_empty());
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut damaged = intact();
+        let text_crc = 8 + 18 + 8 + 11;
+        damaged[text_crc] ^= 0xff;
+        let repaired = repair(&damaged, false).unwrap();
+        assert_eq!(repaired.bytes, intact());
+        assert_eq!(kinds(&repaired), [FixKind::Crc]);
+        assert_eq!(repaired.fixes[0].offset, 8 + 18);
+        assert!(repaired.fixes[0]
+            .to_string()
+            .starts_with("0x1a tEXt: recomputed"));
+    }
+
+    #[test]
+    fn test_bad_length() {
+        for length in [4u32, 30, 0xffff_ffff] {
+            let mut damaged = intact();
+            damaged[8 + 18..8 + 22].copy_from_slice(&length.to_be_bytes());
+            let repaired = repair(&damaged, false).unwrap();
+            assert_eq!(repaired.bytes, intact(), "{}", length);
+            assert_eq!(kinds(&repaired), [FixKind::Length]);
+        }
+    }
+
+    #[test]
+    fn test_bad_length_and_crc() {
+        let mut damaged = intact();
+        damaged[8 + 18..8 + 22].copy_from_slice(&3u32.to_be_bytes());
+        damaged[8 + 18 + 8 + 11] ^= 0xff;
+        let repaired = repair(&damaged, false).unwrap();
+        assert_eq!(repaired.bytes, intact());
+        assert_eq!(kinds(&repaired), [FixKind::Length]);
+    }
+
+    #[test]
+    fn test_unrecoverable_chunks_are_only_dropped_when_asked() {
+        let mut damaged = intact();
+        // The tEXt type is no longer letters, so nothing tells where the chunk ends
+        damaged[8 + 18 + 4] = b'1';
+        assert!(repair(&damaged, false)
+            .unwrap_err()
+            .to_string()
+            .contains("--drop-unrecoverable"));
+        let repaired = repair(&damaged, true).unwrap();
+        assert_eq!(kinds(&repaired), [FixKind::Dropped]);
+        let expected = png(&[
+            chunk("IHDR", b"header"),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ]);
+        assert_eq!(repaired.bytes, expected);
+    }
+
+    #[test]
+    fn test_dropping_resyncs_past_garbage() {
+        // Every twelve bytes a letter type with a length that fits, but nothing where that
+        // length leads, which is all that keeps each from having its 64KiB checksummed
+        let garbage = b"\x01\x02\x03\x04\0\0\xff\xffzzzz".repeat(20_000);
+        let damaged = png(&[
+            chunk("IHDR", b"header"),
+            garbage,
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ]);
+        let repaired = repair(&damaged, true).unwrap();
+        assert_eq!(kinds(&repaired), [FixKind::Dropped]);
+        let expected = png(&[
+            chunk("IHDR", b"header"),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ]);
+        assert_eq!(repaired.bytes, expected);
+    }
+
+    #[test]
+    fn test_truncated_file_gets_an_iend() {
+        let damaged = &intact()[..8 + 18 + 23 + 5];
+        let repaired = repair(damaged, true).unwrap();
+        assert_eq!(kinds(&repaired), [FixKind::Dropped, FixKind::AddedIend]);
+        let expected = png(&[
+            chunk("IHDR", b"header"),
+            chunk("tEXt", b"Title\0hello"),
+            chunk("IEND", b""),
+        ]);
+        assert_eq!(repaired.bytes, expected);
+    }
+
+    #[test]
+    fn test_signature_and_trailing_data() {
+        let mut damaged = [intact(), b"appended".to_vec()].concat();
+        damaged[1] = b'p';
+        let repaired = repair(&damaged, false).unwrap();
+        assert_eq!(kinds(&repaired), [FixKind::Signature]);
+        assert_eq!(repaired.bytes, [intact(), b"appended".to_vec()].concat());
+        assert!(repair(b"not a png at all", false).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! Find and replace on chunk data for `pngme rewrite`, written the way sed writes it:
+//! `s/find/replace/flags`. Matching works on bytes, so chunks that aren't UTF-8 can be edited
+//! as well as text.
+
+use regex::bytes::{NoExpand, Regex, RegexBuilder};
+use std::error::Error;
+use std::fmt;
+
+/// A substitution expression can't be parsed.
+#[derive(Debug)]
+pub struct SubstitutionError {
+    reason: String,
+}
+impl SubstitutionError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for SubstitutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad substitution: {}", self.reason)
+    }
+}
+impl Error for SubstitutionError {}
+
+/// A parsed `s/find/replace/flags` expression. Any character other than a letter, digit or
+/// backslash can stand in for `/`, and is written `\/` inside the fields. The flags are `g`, to
+/// replace every match rather than the first in each chunk, and `i`, to ignore ASCII case.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    pattern: Regex,
+    replacement: Vec<u8>,
+    /// Whether the replacement is used as is, rather than expanding `$1` and `${name}`
+    literal: bool,
+    global: bool,
+}
+
+impl Substitution {
+    /// Parses `expression`. With `literal` the find field is matched exactly, and `$` in the
+    /// replacement has no special meaning; otherwise the find field is a regular expression in
+    /// which `.` matches any byte, and the replacement can refer to its groups.
+    pub fn parse(expression: &str, literal: bool) -> Result<Self, Box<SubstitutionError>> {
+        let mut chars = expression
+            .strip_prefix('s')
+            .ok_or_else(|| {
+                SubstitutionError::boxed(format!(
+                    "\"{}\" must start with s, as in s/find/replace/",
+                    expression
+                ))
+            })?
+            .chars();
+        let delimiter = match chars.next() {
+            Some(c) if !(c.is_alphanumeric() || c == '\\' || c.is_whitespace()) => c,
+            _ => {
+                return Err(SubstitutionError::boxed(
+                    "s must be followed by a delimiter such as /".to_string(),
+                ))
+            }
+        };
+        let mut fields = vec![String::new()];
+        while let Some(c) = chars.next() {
+            let field = fields.last_mut().unwrap();
+            match c {
+                '\\' => match chars.next() {
+                    Some(next) if next == delimiter => field.push(next),
+                    Some(next) => {
+                        field.push('\\');
+                        field.push(next);
+                    }
+                    None => field.push('\\'),
+                },
+                c if c == delimiter => fields.push(String::new()),
+                c => field.push(c),
+            }
+        }
+        let [find, replace, flags] = <[String; 3]>::try_from(fields).map_err(|fields| {
+            SubstitutionError::boxed(match fields.len() {
+                n if n < 3 => format!("missing the closing {}", delimiter),
+                _ => format!("too many {} delimiters", delimiter),
+            })
+        })?;
+        if find.is_empty() {
+            return Err(SubstitutionError::boxed(
+                "there is nothing to find".to_string(),
+            ));
+        }
+        let (mut global, mut ignore_case) = (false, false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                other => {
+                    return Err(SubstitutionError::boxed(format!(
+                        "unknown flag {}, use g or i",
+                        other
+                    )))
+                }
+            }
+        }
+        let find = match literal {
+            true => regex::escape(&find),
+            false => find,
+        };
+        let pattern = RegexBuilder::new(&find)
+            .unicode(false)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| SubstitutionError::boxed(e.to_string()))?;
+        Ok(Self {
+            pattern,
+            replacement: replace.into_bytes(),
+            literal,
+            global,
+        })
+    }
+
+    /// Replaces the first match in `data`, or every match with the `g` flag. Whether `data`
+    /// changed: a match replaced by the same bytes doesn't count.
+    pub fn apply(&self, data: &mut Vec<u8>) -> bool {
+        let limit = match self.global {
+            true => 0,
+            false => 1,
+        };
+        let replaced = match self.literal {
+            true => self
+                .pattern
+                .replacen(data, limit, NoExpand(&self.replacement)),
+            false => self.pattern.replacen(data, limit, &self.replacement[..]),
+        }
+        .into_owned();
+        let changed = replaced != *data;
+        *data = replaced;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(expression: &str, literal: bool, data: &[u8]) -> (Vec<u8>, bool) {
+        let mut data = data.to_vec();
+        let changed = Substitution::parse(expression, literal)
+            .unwrap()
+            .apply(&mut data);
+        (data, changed)
+    }
+
+    #[test]
+    fn test_substitution() {
+        let text = b"Copyright\0(c) 2023 Example, 2023";
+        assert_eq!(
+            apply("s/2023/2024/", true, text).0,
+            b"Copyright\0(c) 2024 Example, 2023"
+        );
+        assert_eq!(
+            apply("s/2023/2024/g", true, text).0,
+            b"Copyright\0(c) 2024 Example, 2024"
+        );
+        assert_eq!(
+            apply(r"s|\(c\) (\d+)|(c) $1-2024|", false, text).0,
+            b"Copyright\0(c) 2023-2024 Example, 2023"
+        );
+        // `$` is kept as is in literal mode, and `.` matches bytes that aren't UTF-8
+        assert_eq!(apply("s/./$1/", true, b"a.b").0, b"a$1b");
+        assert_eq!(apply("s/a.b/x/", false, b"a\xffb\xfe").0, b"x\xfe");
+        assert_eq!(apply(r"s/\//-/g", true, b"1/2/3").0, b"1-2-3");
+        assert_eq!(apply("s/©/(c)/", false, "© 2023".as_bytes()).0, b"(c) 2023");
+        assert!(apply("s/EXAMPLE/x/i", false, text).1);
+        assert_eq!(apply("s/2023/2023/", false, text), (text.to_vec(), false));
+        assert_eq!(apply("s/1999/2000/", false, text), (text.to_vec(), false));
+    }
+
+    #[test]
+    fn test_bad_substitutions() {
+        let cases = [
+            ("y/a/b/", "must start with s"),
+            ("sa", "delimiter"),
+            ("s/a/b", "missing the closing /"),
+            ("s/a/b/c/", "too many / delimiters"),
+            ("s//b/", "nothing to find"),
+            ("s/a/b/x", "unknown flag x"),
+            ("s/(/b/", "unclosed group"),
+        ];
+        for (expression, expected) in cases {
+            let error = Substitution::parse(expression, false)
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::envelope::Envelope;
+use crate::expiry;
+use crate::output;
+use crate::png::Png;
+
+/// Ancillary chunks bigger than this are flagged, as text and metadata rarely come close.
+pub const LARGE_ANCILLARY: usize = 64 * 1024;
+
+/// What scanning one png file turned up.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FileFindings {
+    pub file: String,
+    pub chunks: usize,
+    /// Types of the private chunks in the file, in the order they appear
+    pub private_chunks: Vec<String>,
+    /// Types of the public chunks that neither the spec nor its extensions define
+    pub unknown_chunks: Vec<String>,
+    /// Types of the ancillary chunks holding more than `LARGE_ANCILLARY` bytes
+    pub large_ancillary_chunks: Vec<String>,
+    /// Bytes appended after the last IEND
+    pub trailing_bytes: usize,
+    /// Data in private chunks plus trailing bytes
+    pub hidden_payload_bytes: usize,
+    /// Problems found while parsing, which didn't stop the file from being read
+    pub problems: Vec<String>,
+    /// Types of the chunks holding a message past the expiry it was stored with
+    pub expired_payloads: Vec<String>,
+    /// Types of the chunks holding a message that no longer matches its own checksum
+    pub damaged_payloads: Vec<String>,
+    #[serde(skip)]
+    chunk_types: Vec<String>,
+}
+
+/// A file that couldn't be scanned.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    pub file: String,
+    pub error: String,
+}
+
+/// Aggregate findings across every file under a directory.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CorpusReport {
+    pub files_scanned: usize,
+    pub png_files: usize,
+    /// Files skipped because they don't start with a png signature
+    pub not_png: usize,
+    /// How many chunks of each type were seen in total
+    pub chunk_types: BTreeMap<String, usize>,
+    pub files_with_private_chunks: Vec<String>,
+    pub files_with_unknown_chunks: Vec<String>,
+    pub files_with_large_ancillary_chunks: Vec<String>,
+    pub files_with_trailing_data: Vec<String>,
+    pub files_with_expired_payloads: Vec<String>,
+    pub files_with_damaged_payloads: Vec<String>,
+    pub hidden_payload_bytes: usize,
+    pub failures: Vec<Failure>,
+    pub files: Vec<FileFindings>,
+}
+
+/// Scans `root`, which is a single file or a directory whose files are scanned (and with
+/// `recursive`, those of its subdirectories too). Files are read in parallel, but the report
+/// lists them in path order so runs over the same tree give the same output.
+pub fn scan(root: &Path, recursive: bool) -> CorpusReport {
+    scan_all(&[root.to_path_buf()], recursive)
+}
+
+/// Scans every one of `roots` as `scan` does, in one report. With a single root files are
+/// named relative to it; with several, by their full path so they can't be confused.
+pub fn scan_all(roots: &[PathBuf], recursive: bool) -> CorpusReport {
+    let mut report = CorpusReport::default();
+    let mut files = vec![];
+    for root in roots {
+        collect_files(root, recursive, &mut files, &mut report.failures);
+    }
+    files.sort();
+    files.dedup();
+
+    let name = |path: &Path| match roots {
+        [root] => match path.strip_prefix(root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+            _ => path.display().to_string(),
+        },
+        _ => path.display().to_string(),
+    };
+    report.files_scanned = files.len();
+    for (path, result) in files.iter().zip(scan_files(&files)) {
+        match result {
+            Ok(Some(mut findings)) => {
+                findings.file = name(path);
+                report.add(findings);
+            }
+            Ok(None) => report.not_png += 1,
+            Err(error) => report.failures.push(Failure {
+                file: name(path),
+                error,
+            }),
+        }
+    }
+    report.failures.sort_by(|a, b| a.file.cmp(&b.file));
+    report
+}
+
+impl CorpusReport {
+    fn add(&mut self, findings: FileFindings) {
+        self.png_files += 1;
+        for chunk_type in &findings.chunk_types {
+            *self.chunk_types.entry(chunk_type.clone()).or_default() += 1;
+        }
+        if !findings.private_chunks.is_empty() {
+            self.files_with_private_chunks.push(findings.file.clone());
+        }
+        if !findings.unknown_chunks.is_empty() {
+            self.files_with_unknown_chunks.push(findings.file.clone());
+        }
+        if !findings.large_ancillary_chunks.is_empty() {
+            self.files_with_large_ancillary_chunks
+                .push(findings.file.clone());
+        }
+        if findings.trailing_bytes > 0 {
+            self.files_with_trailing_data.push(findings.file.clone());
+        }
+        if !findings.expired_payloads.is_empty() {
+            self.files_with_expired_payloads.push(findings.file.clone());
+        }
+        if !findings.damaged_payloads.is_empty() {
+            self.files_with_damaged_payloads.push(findings.file.clone());
+        }
+        self.hidden_payload_bytes += findings.hidden_payload_bytes;
+        self.files.push(findings);
+    }
+}
+
+impl std::fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Scanned {} file(s): {} png, {} with private chunks, {} with trailing data, \
+             {} of hidden payload, {} failure(s)",
+            self.files_scanned,
+            self.png_files,
+            self.files_with_private_chunks.len(),
+            self.files_with_trailing_data.len(),
+            output::size(self.hidden_payload_bytes),
+            self.failures.len()
+        )
+    }
+}
+
+fn collect_files(
+    dir: &Path,
+    recursive: bool,
+    files: &mut Vec<PathBuf>,
+    failures: &mut Vec<Failure>,
+) {
+    if !dir.is_dir() {
+        files.push(dir.to_path_buf());
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            failures.push(Failure {
+                file: dir.display().to_string(),
+                error: e.to_string(),
+            });
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(t) if t.is_dir() && recursive => collect_files(&path, recursive, files, failures),
+            Ok(t) if t.is_file() => files.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Scans `files` on as many threads as there are cores, returning the results in input order.
+fn scan_files(files: &[PathBuf]) -> Vec<Result<Option<FileFindings>, String>> {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers.min(files.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = vec![];
+                    while let Some(path) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        // One malformed file must not take the whole audit down with it
+                        let result = panic::catch_unwind(|| scan_file(path))
+                            .unwrap_or_else(|_| Err("pngme crashed reading this file".into()));
+                        done.push((path, result));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_default())
+            .collect()
+    });
+    results.sort_by_key(|(path, _)| *path);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The findings for a single file, or `None` if it isn't a png.
+fn scan_file(path: &Path) -> Result<Option<FileFindings>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Ok(None);
+    }
+    let (png, diagnostics) = Png::parse_report_lenient(&bytes);
+    let png = png.map_err(|e| e.to_string())?;
+    let trailing_bytes = Png::trailing_offset(&bytes).map_or(0, |end| bytes.len() - end);
+    let private: Vec<_> = png
+        .chunks()
+        .iter()
+        .filter(|c| !c.chunk_type().is_public())
+        .collect();
+    let unknown_chunks = png
+        .chunks()
+        .iter()
+        .map(|c| c.chunk_type())
+        .filter(|t| t.is_public() && !Png::is_known_type(t))
+        .map(ToString::to_string)
+        .collect();
+    let large_ancillary_chunks = png
+        .chunks()
+        .iter()
+        .filter(|c| !c.chunk_type().is_critical() && c.data().len() > LARGE_ANCILLARY)
+        .map(|c| c.chunk_type().to_string())
+        .collect();
+    let now = expiry::now();
+    let mut expired_payloads: Vec<String> = vec![];
+    let mut damaged_payloads: Vec<String> = vec![];
+    for chunk in &private {
+        let ctype = chunk.chunk_type().to_string();
+        let envelope = Envelope::from_bytes(chunk.data());
+        let expired = envelope
+            .as_ref()
+            .is_ok_and(|e| e.as_ref().is_some_and(|e| e.is_expired(now)));
+        if expired && !expired_payloads.contains(&ctype) {
+            expired_payloads.push(ctype.clone());
+        }
+        // Error correction that fails is as much a sign of damage as a checksum that doesn't match
+        let damaged = match envelope {
+            Ok(envelope) => envelope.is_some_and(|e| !e.is_intact()),
+            Err(_) => Envelope::is_envelope(chunk.data()),
+        };
+        if damaged && !damaged_payloads.contains(&ctype) {
+            damaged_payloads.push(ctype);
+        }
+    }
+    Ok(Some(FileFindings {
+        file: String::new(),
+        chunks: png.chunks().len(),
+        private_chunks: private.iter().map(|c| c.chunk_type().to_string()).collect(),
+        unknown_chunks,
+        large_ancillary_chunks,
+        trailing_bytes,
+        hidden_payload_bytes: trailing_bytes
+            + private.iter().map(|c| c.data().len()).sum::<usize>(),
+        problems: diagnostics.iter().map(|d| d.to_string()).collect(),
+        expired_payloads,
+        damaged_payloads,
+        chunk_types: png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::envelope::Envelope;
+    use std::str::FromStr;
+
+    fn png_with(extra: &[(&str, &[u8])]) -> Vec<u8> {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        let mut chunks = vec![chunk("IHDR", b"header"), chunk("IDAT", b"pixels")];
+        chunks.extend(extra.iter().map(|(t, d)| chunk(t, d)));
+        chunks.push(chunk("IEND", &[]));
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    /// A tree with a clean image, two with payloads in nested directories, a text file and a
+    /// png with a garbled chunk type.
+    fn synthetic_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("assets/icons")).unwrap();
+        fs::write(root.join("clean.png"), png_with(&[])).unwrap();
+        fs::write(root.join("notes.txt"), "not an image").unwrap();
+        fs::write(
+            root.join("assets/build.png"),
+            png_with(&[("ruSt", b"build 1234")]),
+        )
+        .unwrap();
+        let mut appended = png_with(&[("xqJy", b"key"), ("tEXt", b"Comment\0hi")]);
+        appended.extend_from_slice(b"appended!");
+        fs::write(root.join("assets/icons/appended.png"), appended).unwrap();
+        let broken = [&Png::STANDARD_HEADER[..], &[0, 0, 0, 0], b"1!2@", &[0; 4]].concat();
+        fs::write(root.join("assets/icons/broken.png"), broken).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_recursive_corpus_counts() {
+        let dir = synthetic_tree();
+        let report = scan(dir.path(), true);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["files_scanned"], 5);
+        assert_eq!(json["png_files"], 3);
+        assert_eq!(json["not_png"], 1);
+        assert_eq!(json["chunk_types"]["IHDR"], 3);
+        assert_eq!(json["chunk_types"]["ruSt"], 1);
+        assert_eq!(json["chunk_types"]["tEXt"], 1);
+        assert_eq!(
+            json["files_with_private_chunks"],
+            serde_json::json!(["assets/build.png", "assets/icons/appended.png"])
+        );
+        assert_eq!(
+            json["files_with_trailing_data"],
+            serde_json::json!(["assets/icons/appended.png"])
+        );
+        assert_eq!(json["hidden_payload_bytes"], 10 + 3 + 9);
+        assert_eq!(json["failures"][0]["file"], "assets/icons/broken.png");
+        assert_eq!(json["failures"].as_array().unwrap().len(), 1);
+        let files: Vec<_> = report.files.iter().map(|f| f.file.as_str()).collect();
+        assert_eq!(
+            files,
+            ["assets/build.png", "assets/icons/appended.png", "clean.png"]
+        );
+    }
+
+    #[test]
+    fn test_extension_chunks_are_not_suspicious() {
+        let dir = tempfile::tempdir().unwrap();
+        let offset = [&[0, 0, 0, 5, 0, 0, 0, 9][..], &[0]].concat();
+        let extensions = png_with(&[
+            ("oFFs", &offset),
+            ("sCAL", b"\x010.001\x000.001"),
+            ("sTER", &[0]),
+        ]);
+        fs::write(dir.path().join("scanned.png"), extensions).unwrap();
+        fs::write(
+            dir.path().join("bad-scale.png"),
+            png_with(&[("sCAL", b"\x01one\x00two")]),
+        )
+        .unwrap();
+        let report = scan(dir.path(), false);
+        assert!(report.files_with_private_chunks.is_empty());
+        assert_eq!(report.hidden_payload_bytes, 0);
+        assert_eq!(report.files[1].file, "scanned.png");
+        assert!(report.files[1].problems.is_empty());
+        assert!(report.files[0].problems[0].contains("sCAL width \"one\""));
+    }
+
+    #[test]
+    fn test_output_is_deterministic() {
+        let dir = synthetic_tree();
+        let first = serde_json::to_string(&scan(dir.path(), true)).unwrap();
+        for _ in 0..5 {
+            assert_eq!(
+                serde_json::to_string(&scan(dir.path(), true)).unwrap(),
+                first
+            );
+        }
+    }
+
+    #[test]
+    fn test_without_recursion() {
+        let dir = synthetic_tree();
+        let report = scan(dir.path(), false);
+        assert_eq!(report.files_scanned, 2);
+        assert_eq!(report.png_files, 1);
+        assert!(report.failures.is_empty());
+
+        let single = scan(&dir.path().join("assets/build.png"), false);
+        assert_eq!(single.files[0].private_chunks, ["ruSt"]);
+    }
+
+    #[test]
+    fn test_unknown_and_large_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let comment = [&b"Comment\0"[..], &vec![b'x'; LARGE_ANCILLARY]].concat();
+        fs::write(
+            dir.path().join("odd.png"),
+            png_with(&[("sEEk", b"hidden"), ("tEXt", &comment), ("tIME", &[0; 7])]),
+        )
+        .unwrap();
+        fs::write(dir.path().join("plain.png"), png_with(&[("tEXt", b"a\0b")])).unwrap();
+
+        let report = scan(dir.path(), false);
+        assert_eq!(report.files_with_unknown_chunks, ["odd.png"]);
+        assert_eq!(report.files_with_large_ancillary_chunks, ["odd.png"]);
+        assert_eq!(report.files[0].unknown_chunks, ["sEEk"]);
+        assert_eq!(report.files[0].large_ancillary_chunks, ["tEXt"]);
+        assert!(report.files[1].unknown_chunks.is_empty());
+        assert!(report.files[1].large_ancillary_chunks.is_empty());
+    }
+
+    #[test]
+    fn test_several_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b"] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("shot.png"), png_with(&[])).unwrap();
+        }
+        let roots = [
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("a"),
+        ];
+        let report = scan_all(&roots, false);
+        assert_eq!(report.files_scanned, 2);
+        let names: Vec<&str> = report.files.iter().map(|f| f.file.as_str()).collect();
+        let full = |name: &str| dir.path().join(name).join("shot.png").display().to_string();
+        assert_eq!(names, [full("a"), full("b")]);
+    }
+
+    #[test]
+    fn test_empty_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("marker.png");
+        let empty = Envelope::new(vec![]).as_bytes();
+        fs::write(&path, png_with(&[("ruSt", &[]), ("ruSu", &empty)])).unwrap();
+
+        let report = scan(&path, false);
+        assert!(report.failures.is_empty());
+        assert_eq!(report.files[0].private_chunks, ["ruSt", "ruSu"]);
+        assert!(report.files[0].damaged_payloads.is_empty());
+        assert_eq!(report.hidden_payload_bytes, empty.len());
+    }
+
+    #[test]
+    fn test_damaged_payload() {
+        let mut damaged = Envelope::new(b"archival".to_vec()).as_bytes();
+        *damaged.last_mut().unwrap() ^= 0xff;
+        let intact = Envelope::new(b"archival".to_vec()).as_bytes();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, png_with(&[("ruSt", &damaged), ("ruSu", &intact)])).unwrap();
+
+        let report = scan(&path, false);
+        assert_eq!(report.files[0].damaged_payloads, ["ruSt"]);
+        assert_eq!(
+            report.files_with_damaged_payloads,
+            [path.display().to_string()]
+        );
+    }
+}
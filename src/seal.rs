@@ -0,0 +1,144 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Placement, Png};
+use crate::secret::SecretBytes;
+
+/// Private, ancillary and unsafe to copy, so editors that change the image drop the seal.
+pub const SEAL_CHUNK: &str = "seAL";
+const SEAL_VERSION: u8 = 1;
+const HASH_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The image has no valid seal, or was changed after it was sealed.
+#[derive(Debug)]
+pub struct SealError {
+    reason: String,
+}
+impl SealError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Attestation failed: {}", self.reason)
+    }
+}
+impl Error for SealError {}
+
+fn mac(key: &SecretBytes, hash: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.expose()).expect("HMAC takes keys of any size");
+    mac.update(hash);
+    mac
+}
+
+/// Stores the image's content hash, authenticated with `key`, in a seal chunk before IEND,
+/// replacing any seal already there.
+pub fn seal(png: &mut Png, key: &SecretBytes) -> crate::Result<()> {
+    while png.remove_first_chunk(SEAL_CHUNK).is_ok() {}
+    let hash = png.content_hash();
+    let mut data = vec![SEAL_VERSION];
+    data.extend_from_slice(&hash);
+    data.extend(mac(key, &hash).finalize().into_bytes());
+    let chunk = Chunk::new(ChunkType::from_str(SEAL_CHUNK)?, data);
+    png.place_chunk(chunk, Placement::BeforeIend);
+    Ok(())
+}
+
+/// Checks the seal was made with `key` and that the critical chunks haven't changed since.
+pub fn attest(png: &Png, key: &SecretBytes) -> crate::Result<()> {
+    let data = png
+        .chunk_by_type(SEAL_CHUNK)
+        .ok_or_else(|| SealError::boxed("the image is not sealed".to_string()))?
+        .data();
+    let (hash, tag) = match data.split_first() {
+        Some((&SEAL_VERSION, rest)) if rest.len() == 2 * HASH_LEN => rest.split_at(HASH_LEN),
+        _ => return Err(SealError::boxed("the seal chunk is malformed".to_string())),
+    };
+    mac(key, hash).verify_slice(tag).map_err(|_| {
+        SealError::boxed("the seal was made with a different key, or has been altered".to_string())
+    })?;
+    if png.content_hash() != hash {
+        return Err(SealError::boxed(
+            "the image data was modified after it was sealed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed() -> (Png, SecretBytes) {
+        let mut png = crate::lsb::tests::generated_png(8, 8, 2, 3);
+        let key = SecretBytes::from(vec![3; 32]);
+        seal(&mut png, &key).unwrap();
+        (png, key)
+    }
+
+    #[test]
+    fn test_seal_then_attest() {
+        let (png, key) = sealed();
+        attest(&png, &key).unwrap();
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        attest(&reparsed, &key).unwrap();
+        let types: Vec<_> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types[types.len() - 2..], ["seAL", "IEND"]);
+    }
+
+    #[test]
+    fn test_modified_image_fails() {
+        let (png, key) = sealed();
+        let chunks = png
+            .chunks()
+            .iter()
+            .map(|c| match c.chunk_type().bytes() == *b"IDAT" {
+                true => Chunk::new(
+                    *c.chunk_type(),
+                    c.data().iter().rev().copied().collect::<Vec<u8>>(),
+                ),
+                false => c.clone(),
+            })
+            .collect();
+        let tampered = Png::from_chunks(chunks);
+        let err = attest(&tampered, &key).unwrap_err();
+        assert!(err.to_string().contains("modified after it was sealed"));
+    }
+
+    #[test]
+    fn test_wrong_key_fails() {
+        let (png, _) = sealed();
+        let err = attest(&png, &SecretBytes::from(vec![4; 32])).unwrap_err();
+        assert!(err.to_string().contains("different key"));
+        let unsealed = crate::lsb::tests::generated_png(8, 8, 2, 3);
+        assert!(attest(&unsealed, &vec![3; 32].into()).is_err());
+    }
+
+    #[test]
+    fn test_resealing_replaces_the_seal() {
+        let (mut png, key) = sealed();
+        let other = SecretBytes::from(vec![9; 32]);
+        seal(&mut png, &other).unwrap();
+        let seals = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == SEAL_CHUNK)
+            .count();
+        assert_eq!(seals, 1);
+        attest(&png, &other).unwrap();
+        assert!(attest(&png, &key).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Passphrases and key material. The buffer is wiped when dropped, and formatting never shows
+/// the contents, so printing arguments or errors can't leak a secret.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    /// The secret itself, for handing to the KDF or cipher.
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Mutable access for filling in derived key material.
+    pub fn expose_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<String> for SecretBytes {
+    /// Takes over the string's buffer without copying it.
+    fn from(string: String) -> Self {
+        Self(string.into_bytes())
+    }
+}
+
+impl From<&str> for SecretBytes {
+    fn from(string: &str) -> Self {
+        Self(string.as_bytes().to_vec())
+    }
+}
+
+impl FromStr for SecretBytes {
+    type Err = Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl Zeroize for SecretBytes {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SecretBytes {}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([redacted])")
+    }
+}
+
+impl fmt::Display for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl SecretBytes {
+        /// The whole allocation, including bytes past the current length.
+        fn allocation(&self) -> &[u8] {
+            // SAFETY: the buffer is allocated for `capacity` bytes, all of which were
+            // initialized when the secret was created or zeroed since.
+            unsafe { std::slice::from_raw_parts(self.0.as_ptr(), self.0.capacity()) }
+        }
+    }
+
+    #[test]
+    fn test_formatting_is_redacted() {
+        let secret = SecretBytes::from("hunter2");
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+        assert!(!format!("{}", secret).contains("hunter2"));
+        assert!(!format!("{:?}", Some(secret)).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_zeroize_wipes_the_whole_buffer() {
+        // Drop runs exactly this before the buffer is freed
+        let mut secret = SecretBytes::from("hunter2");
+        assert_eq!(secret.allocation(), b"hunter2");
+        secret.zeroize();
+        assert!(secret.is_empty());
+        assert!(secret.allocation().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_parses_from_arguments() {
+        let secret: SecretBytes = "hunter2".parse().unwrap();
+        assert_eq!(secret.expose(), b"hunter2");
+    }
+
+    fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+
+    #[test]
+    fn test_is_zeroize_on_drop() {
+        assert_zeroize_on_drop::<SecretBytes>();
+    }
+}
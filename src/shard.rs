@@ -0,0 +1,348 @@
+//! Payloads too big for one carrier image, split across several files. Each file gets a chunk
+//! holding one shard: an envelope with the metadata of the whole payload, its part of the bytes
+//! and which part of how many it is, so the shards can be put back together in any order.
+
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::chunk::Chunk;
+use crate::ecc;
+use crate::envelope::{Envelope, EnvelopeError, ShardInfo};
+
+const CHECKSUM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Bytes of length, type and CRC around the data of every chunk.
+const CHUNK_OVERHEAD: usize = 12;
+
+/// A payload can't be split into shards, or its shards can't be put back together.
+#[derive(Debug)]
+pub struct ShardError {
+    reason: String,
+    missing: Vec<u16>,
+    damaged: bool,
+}
+impl ShardError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            missing: vec![],
+            damaged: false,
+        })
+    }
+
+    fn damaged(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            missing: vec![],
+            damaged: true,
+        })
+    }
+
+    /// The shards no carrier holds, counting from 0.
+    pub fn missing(&self) -> &[u16] {
+        &self.missing
+    }
+
+    /// Whether the shards were found but disagree with each other or their checksums.
+    pub fn is_damaged(&self) -> bool {
+        self.damaged
+    }
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad sharded payload: {}", self.reason)
+    }
+}
+impl Error for ShardError {}
+
+/// Splits the payload of `envelope` into as few shards as it takes for the chunk holding each
+/// to be at most `budget` bytes. Every shard keeps the rest of the envelope, so each can be
+/// read with the same key and settings, and carries a checksum of its own part.
+pub fn split(mut envelope: Envelope, budget: usize) -> crate::Result<Vec<Envelope>> {
+    let payload = std::mem::take(&mut envelope.payload);
+    let id = CHECKSUM.checksum(&payload);
+    envelope.copy = None;
+    envelope.checksum = Some(id);
+    envelope.shard = Some(ShardInfo {
+        index: 0,
+        total: 0,
+        id,
+    });
+    let overhead = CHUNK_OVERHEAD + envelope.as_bytes().len();
+    let room = budget.saturating_sub(overhead);
+    // Error correction adds its parity bytes to every block of the payload
+    let capacity = match envelope.ecc.map(usize::from) {
+        Some(parity) => {
+            room / ecc::BLOCK_LEN * (ecc::BLOCK_LEN - parity)
+                + (room % ecc::BLOCK_LEN).saturating_sub(parity)
+        }
+        None => room,
+    };
+    if capacity == 0 {
+        return Err(ShardError::boxed(format!(
+            "shards of at most {} bytes leave no room for the payload, the chunk and envelope \
+             around it take {}",
+            budget, overhead
+        )));
+    }
+    let total = payload.len().div_ceil(capacity).max(1);
+    let total = u16::try_from(total).map_err(|_| {
+        ShardError::boxed(format!(
+            "the payload would take {} shards of at most {} bytes, more than the {} supported",
+            total,
+            budget,
+            u16::MAX
+        ))
+    })?;
+    Ok((0..total)
+        .map(|index| {
+            let start = index as usize * capacity;
+            let part = payload[start..(start + capacity).min(payload.len())].to_vec();
+            let mut shard = envelope.clone().with_shard(index, total, id);
+            shard.checksum = Some(CHECKSUM.checksum(&part));
+            shard.payload = part;
+            shard
+        })
+        .collect())
+}
+
+/// Puts the payload back together from `shards`, each paired with the file it was read from,
+/// in whatever order they were found. Every shard must be there, once, and belong to the same
+/// payload. The result is the envelope the payload was split from.
+pub fn join(shards: Vec<(String, Envelope)>) -> crate::Result<Envelope> {
+    let intact: Vec<bool> = shards.iter().map(|(_, e)| e.is_intact()).collect();
+    let (info, order) = arrange(&shards, &intact)?;
+    let mut envelope = shards[order[0]].1.clone();
+    envelope.payload = order
+        .iter()
+        .flat_map(|&idx| shards[idx].1.payload.iter().copied())
+        .collect();
+    envelope.corrections = order.iter().map(|&idx| shards[idx].1.corrections).sum();
+    if CHECKSUM.checksum(&envelope.payload) != info.id {
+        return Err(ShardError::damaged(
+            "the reassembled payload doesn't match its checksum".to_string(),
+        ));
+    }
+    envelope.checksum = Some(info.id);
+    envelope.shard = None;
+    Ok(envelope)
+}
+
+/// Checks that `shards` hold every part of one payload, `intact` telling which match their
+/// own checksum, and returns the position in `shards` of each part in order. A shard found
+/// twice is fine as long as both copies hold the same bytes.
+fn arrange(
+    shards: &[(String, Envelope)],
+    intact: &[bool],
+) -> crate::Result<(ShardInfo, Vec<usize>)> {
+    let (first, info) = shards
+        .iter()
+        .find_map(|(path, e)| e.shard.map(|info| (path, info)))
+        .ok_or_else(|| ShardError::boxed("no shards were found".to_string()))?;
+    let mut parts: Vec<Option<usize>> = vec![None; info.total as usize];
+    for (position, (path, envelope)) in shards.iter().enumerate() {
+        let Some(shard) = envelope.shard else {
+            return Err(ShardError::boxed(format!("{} holds no shard", path)));
+        };
+        if shard.id != info.id || shard.total != info.total {
+            return Err(ShardError::damaged(format!(
+                "{} holds a shard of another payload than {}",
+                path, first
+            )));
+        }
+        let slot = parts.get_mut(shard.index as usize).ok_or_else(|| {
+            ShardError::damaged(format!(
+                "{} holds shard {} of only {}",
+                path,
+                shard.index + 1,
+                shard.total
+            ))
+        })?;
+        if !intact[position] {
+            return Err(ShardError::damaged(format!(
+                "shard {} of {} in {} doesn't match its checksum",
+                shard.index + 1,
+                shard.total,
+                path
+            )));
+        }
+        match slot {
+            Some(other) if shards[*other].1.checksum != envelope.checksum => {
+                return Err(ShardError::damaged(format!(
+                    "there are two different shards numbered {}, one in {}",
+                    shard.index + 1,
+                    path
+                )))
+            }
+            _ => *slot = Some(position),
+        }
+    }
+    let missing: Vec<u16> = (0..info.total)
+        .filter(|&index| parts[index as usize].is_none())
+        .collect();
+    if !missing.is_empty() {
+        let numbers: Vec<String> = missing.iter().map(|i| (i + 1).to_string()).collect();
+        return Err(Box::new(ShardError {
+            reason: format!(
+                "missing shard{} {} of {}",
+                if missing.len() == 1 { "" } else { "s" },
+                numbers.join(", "),
+                info.total
+            ),
+            missing,
+            damaged: false,
+        }));
+    }
+    Ok((info, parts.into_iter().flatten().collect()))
+}
+
+/// Reads the payload split over `chunks` of one png, as `join` would put it back together,
+/// holding only the shard being read in memory. The shards are checked against each other and
+/// their own checksums before anything is read, and the payload against its checksum once the
+/// last shard has been read.
+pub struct ShardReader<'a> {
+    chunks: Vec<&'a Chunk>,
+    next: usize,
+    part: io::Cursor<Vec<u8>>,
+    digest: Option<Digest<'static, u32>>,
+    envelope: Envelope,
+}
+
+impl<'a> ShardReader<'a> {
+    pub fn new(chunks: &[&'a Chunk]) -> crate::Result<Self> {
+        let mut shards = vec![];
+        let mut intact = vec![];
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let mut envelope = Envelope::from_bytes(chunk.data())?.ok_or_else(|| {
+                EnvelopeError::boxed(format!("chunk {} is not a pngme envelope", idx + 1))
+            })?;
+            intact.push(envelope.is_intact());
+            envelope.payload = vec![];
+            shards.push((format!("chunk {}", idx + 1), envelope));
+        }
+        let (info, order) = arrange(&shards, &intact)?;
+        let mut envelope = shards.swap_remove(order[0]).1;
+        envelope.checksum = Some(info.id);
+        envelope.shard = None;
+        Ok(Self {
+            chunks: order.iter().map(|&idx| chunks[idx]).collect(),
+            next: 0,
+            part: io::Cursor::new(vec![]),
+            digest: Some(CHECKSUM.digest()),
+            envelope,
+        })
+    }
+
+    /// The envelope the payload was split from, without the payload.
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+}
+
+impl Read for ShardReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.part.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            let Some(chunk) = self.chunks.get(self.next) else {
+                let sum = self.digest.take().map(|digest| digest.finalize());
+                if sum.is_some_and(|sum| Some(sum) != self.envelope.checksum) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        ShardError::damaged(
+                            "the reassembled payload doesn't match its checksum".to_string(),
+                        ),
+                    ));
+                }
+                return Ok(0);
+            };
+            self.next += 1;
+            let payload = Envelope::from_bytes(chunk.data())
+                .ok()
+                .flatten()
+                .map(|e| e.payload)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "a shard changed while read")
+                })?;
+            if let Some(digest) = &mut self.digest {
+                digest.update(&payload);
+            }
+            self.part = io::Cursor::new(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(shards: Vec<Envelope>) -> Vec<(String, Envelope)> {
+        shards
+            .into_iter()
+            .enumerate()
+            .map(|(i, shard)| (format!("{}.png", i), shard))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let envelope = Envelope::new(payload.clone()).with_label("big");
+        let shards = split(envelope.clone(), 400).unwrap();
+        assert_eq!(shards.len(), 3);
+        for shard in &shards {
+            assert!(CHUNK_OVERHEAD + shard.as_bytes().len() <= 400);
+            assert_eq!(shard.label.as_deref(), Some("big"));
+        }
+
+        let mut shuffled = shards.clone();
+        shuffled.rotate_left(2);
+        assert_eq!(join(named(shuffled)).unwrap(), envelope);
+
+        let with_ecc = Envelope::new(payload).with_ecc(32);
+        let shards = split(with_ecc.clone(), 600).unwrap();
+        assert!(shards
+            .iter()
+            .all(|shard| CHUNK_OVERHEAD + shard.as_bytes().len() <= 600));
+        let read: Vec<Envelope> = shards
+            .iter()
+            .map(|s| Envelope::from_bytes(&s.as_bytes()).unwrap().unwrap())
+            .collect();
+        assert_eq!(join(named(read)).unwrap(), with_ecc);
+
+        let error = split(Envelope::new(b"x".to_vec()), 20).unwrap_err();
+        assert!(error.to_string().contains("leave no room"), "{}", error);
+    }
+
+    #[test]
+    fn test_join_failures() {
+        let payload = vec![7; 100];
+        let shards = split(Envelope::new(payload.clone()), 60).unwrap();
+        let total = shards.len();
+
+        let mut missing = named(shards.clone());
+        missing.remove(1);
+        let error = join(missing).unwrap_err();
+        let shard_error = error.downcast_ref::<ShardError>().unwrap();
+        assert_eq!(shard_error.missing(), [1]);
+        assert!(error
+            .to_string()
+            .contains(&format!("missing shard 2 of {}", total)));
+
+        let mut damaged = named(shards.clone());
+        damaged[0].1.payload[0] ^= 1;
+        assert!(join(damaged).unwrap_err().to_string().contains("checksum"));
+
+        let mut mixed = named(shards);
+        mixed[0].1 = split(Envelope::new(vec![8; 100]), 60).unwrap().remove(0);
+        assert!(join(mixed)
+            .unwrap_err()
+            .to_string()
+            .contains("another payload"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Ed25519 signatures over stored payloads, so that whoever holds the public key can tell a
+//! message was stored by the holder of the signing key and hasn't been changed since, and
+//! HMAC-SHA256 tags that tell the same to whoever shares a symmetric key.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::crypto;
+use crate::secret::SecretBytes;
+
+/// Length of a signing key seed and of a public key.
+pub const KEY_LEN: usize = 32;
+/// Length of an Ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+/// Length of an HMAC-SHA256 tag.
+pub const MAC_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A key couldn't be read, or a payload isn't signed by the key it was checked against.
+#[derive(Debug)]
+pub struct SigningError {
+    reason: String,
+}
+impl SigningError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for SigningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signature error: {}", self.reason)
+    }
+}
+impl Error for SigningError {}
+
+/// A new signing key seed and the public key that goes with it.
+pub fn generate() -> crate::Result<(SecretBytes, [u8; KEY_LEN])> {
+    let seed: [u8; KEY_LEN] = crypto::random_bytes()?;
+    let public = SigningKey::from_bytes(&seed).verifying_key().to_bytes();
+    Ok((SecretBytes::from(seed.to_vec()), public))
+}
+
+/// Reads a signing key seed, as written by `pngme keygen --signing`.
+pub fn read_signing_key(path: &Path) -> crate::Result<SigningKey> {
+    let seed = crypto::read_key_file(path)?;
+    let seed: &[u8; KEY_LEN] = seed.expose().try_into()?;
+    Ok(SigningKey::from_bytes(seed))
+}
+
+/// Reads a public key, as written next to the signing key by `pngme keygen --signing`.
+pub fn read_verifying_key(path: &Path) -> crate::Result<VerifyingKey> {
+    let bytes = fs::read(path)?;
+    let bytes: [u8; KEY_LEN] = bytes.as_slice().try_into().map_err(|_| {
+        SigningError::boxed(format!(
+            "public key file {} is {} bytes, expected {}",
+            path.display(),
+            bytes.len(),
+            KEY_LEN
+        ))
+    })?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| {
+        SigningError::boxed(format!(
+            "{} doesn't hold an Ed25519 public key",
+            path.display()
+        ))
+        .into()
+    })
+}
+
+/// A detached signature over `payload`.
+pub fn sign(key: &SigningKey, payload: &[u8]) -> [u8; SIGNATURE_LEN] {
+    key.sign(payload).to_bytes()
+}
+
+/// Fails unless `signature` is present and signs `payload` with `key`.
+pub fn verify(
+    key: &VerifyingKey,
+    payload: &[u8],
+    signature: Option<&[u8; SIGNATURE_LEN]>,
+) -> crate::Result<()> {
+    let signature =
+        signature.ok_or_else(|| SigningError::boxed("the message isn't signed".to_string()))?;
+    key.verify_strict(payload, &Signature::from_bytes(signature))
+        .map_err(|_| {
+            SigningError::boxed(
+                "the signature doesn't match: the message was changed after it was signed, or \
+                 signed with another key"
+                    .to_string(),
+            )
+            .into()
+        })
+}
+
+fn hmac(key: &SecretBytes, payload: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.expose()).expect("HMAC takes keys of any size");
+    mac.update(payload);
+    mac
+}
+
+/// An HMAC-SHA256 tag over `payload`.
+pub fn tag(key: &SecretBytes, payload: &[u8]) -> [u8; MAC_LEN] {
+    hmac(key, payload).finalize().into_bytes().into()
+}
+
+/// Fails unless `tag` is present and was computed over `payload` with `key`.
+pub fn check_tag(
+    key: &SecretBytes,
+    payload: &[u8],
+    tag: Option<&[u8; MAC_LEN]>,
+) -> crate::Result<()> {
+    let tag =
+        tag.ok_or_else(|| SigningError::boxed("the message has no HMAC tag to check".to_string()))?;
+    hmac(key, payload).verify_slice(tag).map_err(|_| {
+        SigningError::boxed(
+            "the HMAC tag doesn't match: the message was changed after it was written, or \
+             written with another key"
+                .to_string(),
+        )
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let (seed, public) = generate().unwrap();
+        fs::write(dir.path().join("key"), seed.expose()).unwrap();
+        fs::write(dir.path().join("key.pub"), public).unwrap();
+        let signing = read_signing_key(&dir.path().join("key")).unwrap();
+        let verifying = read_verifying_key(&dir.path().join("key.pub")).unwrap();
+
+        let signature = sign(&signing, b"payload");
+        assert!(verify(&verifying, b"payload", Some(&signature)).is_ok());
+        assert!(verify(&verifying, b"payloaD", Some(&signature)).is_err());
+        assert!(verify(&verifying, b"payload", None).is_err());
+
+        let (_, other) = generate().unwrap();
+        let other = VerifyingKey::from_bytes(&other).unwrap();
+        assert!(verify(&other, b"payload", Some(&signature)).is_err());
+
+        fs::write(dir.path().join("short.pub"), [0; 31]).unwrap();
+        assert!(read_verifying_key(&dir.path().join("short.pub")).is_err());
+    }
+
+    #[test]
+    fn test_tag() {
+        let key = SecretBytes::from(vec![1; KEY_LEN]);
+        let tagged = tag(&key, b"payload");
+        assert!(check_tag(&key, b"payload", Some(&tagged)).is_ok());
+        assert!(check_tag(&key, b"payloaD", Some(&tagged)).is_err());
+        assert!(check_tag(&key, b"payload", None).is_err());
+        let other = SecretBytes::from(vec![2; KEY_LEN]);
+        let err = check_tag(&other, b"payload", Some(&tagged)).unwrap_err();
+        assert!(
+            err.to_string().contains("HMAC tag doesn't match"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,153 @@
+/// What a payload probably is, going by its first bytes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Kind {
+    pub name: &'static str,
+    pub mime: &'static str,
+    /// Extension for a file holding the payload, without the dot
+    pub extension: &'static str,
+}
+
+/// A payload is of `kind` if it holds `magic` at `offset`.
+struct Signature {
+    offset: usize,
+    magic: &'static [u8],
+    kind: Kind,
+}
+
+const fn signature(
+    magic: &'static [u8],
+    name: &'static str,
+    mime: &'static str,
+    extension: &'static str,
+) -> Signature {
+    Signature {
+        offset: 0,
+        magic,
+        kind: Kind {
+            name,
+            mime,
+            extension,
+        },
+    }
+}
+
+/// Checked in order, so a longer magic has to come before any shorter one it starts with.
+const SIGNATURES: &[Signature] = &[
+    signature(b"\x89PNG\r\n\x1a\n", "PNG image", "image/png", "png"),
+    signature(b"\xff\xd8\xff", "JPEG image", "image/jpeg", "jpg"),
+    signature(b"GIF87a", "GIF image", "image/gif", "gif"),
+    signature(b"GIF89a", "GIF image", "image/gif", "gif"),
+    Signature {
+        offset: 8,
+        magic: b"WEBP",
+        kind: Kind {
+            name: "WebP image",
+            mime: "image/webp",
+            extension: "webp",
+        },
+    },
+    signature(b"%PDF-", "PDF document", "application/pdf", "pdf"),
+    signature(b"PK\x03\x04", "ZIP archive", "application/zip", "zip"),
+    signature(b"PK\x05\x06", "ZIP archive", "application/zip", "zip"),
+    signature(b"\x1f\x8b", "gzip data", "application/gzip", "gz"),
+    signature(b"BZh", "bzip2 data", "application/x-bzip2", "bz2"),
+    signature(b"\xfd7zXZ\x00", "xz data", "application/x-xz", "xz"),
+    signature(b"\x28\xb5\x2f\xfd", "zstd data", "application/zstd", "zst"),
+    signature(
+        b"7z\xbc\xaf\x27\x1c",
+        "7-Zip archive",
+        "application/x-7z-compressed",
+        "7z",
+    ),
+    signature(
+        b"\x7fELF",
+        "ELF executable",
+        "application/x-executable",
+        "elf",
+    ),
+    signature(
+        b"MZ",
+        "PE executable",
+        "application/vnd.microsoft.portable-executable",
+        "exe",
+    ),
+    signature(b"pgAR", "pngme archive", "application/octet-stream", "pgar"),
+];
+
+const JSON: Kind = Kind {
+    name: "JSON",
+    mime: "application/json",
+    extension: "json",
+};
+const TEXT: Kind = Kind {
+    name: "UTF-8 text",
+    mime: "text/plain",
+    extension: "txt",
+};
+const BINARY: Kind = Kind {
+    name: "binary data",
+    mime: "application/octet-stream",
+    extension: "bin",
+};
+
+/// Guesses what `data` is: a known signature first, then JSON or text if it is UTF-8 without
+/// control characters other than whitespace, and binary data otherwise.
+pub fn sniff(data: &[u8]) -> Kind {
+    let known = SIGNATURES.iter().find(|s| {
+        data.get(s.offset..s.offset + s.magic.len()) == Some(s.magic)
+            // WebP is a RIFF container, whose magic comes before the size
+            && (s.offset == 0 || data.starts_with(b"RIFF"))
+    });
+    if let Some(signature) = known {
+        return signature.kind;
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) if text.chars().all(|c| !c.is_control() || c.is_whitespace()) => {
+            let trimmed = text.trim_start();
+            let structured = trimmed.starts_with('{') || trimmed.starts_with('[');
+            match structured && serde_json::from_str::<serde_json::Value>(text).is_ok() {
+                true => JSON,
+                false => TEXT,
+            }
+        }
+        _ => BINARY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signatures() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "png"),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", "jpg"),
+            (b"GIF89a\x01\0", "gif"),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", "webp"),
+            (b"%PDF-1.7\n", "pdf"),
+            (b"PK\x03\x04\x14\0", "zip"),
+            (b"PK\x05\x06", "zip"),
+            (b"\x1f\x8b\x08\0", "gz"),
+            (b"BZh91AY", "bz2"),
+            (b"\xfd7zXZ\0\0", "xz"),
+            (b"\x28\xb5\x2f\xfd\x04", "zst"),
+            (b"7z\xbc\xaf\x27\x1c\0\x04", "7z"),
+            (b"\x7fELF\x02\x01\x01", "elf"),
+            (b"MZ\x90\0", "exe"),
+            (b"pgAR\x01", "pgar"),
+            (b"{\"version\": 1}", "json"),
+            (b" [1, 2]\n", "json"),
+            (b"hello\tworld\n", "txt"),
+            (b"{not json", "txt"),
+            (b"", "txt"),
+            (b"RIFF\x24\0\0\0WAVEfmt ", "bin"),
+            (b"\0\x01\x02\x03 unknown blob", "bin"),
+            (b"\xc3\x28 invalid UTF-8", "bin"),
+        ];
+        for (data, extension) in cases {
+            assert_eq!(sniff(data).extension, *extension, "{:?}", data);
+        }
+        assert_eq!(sniff(b"%PDF-1.4").mime, "application/pdf");
+    }
+}
@@ -0,0 +1,305 @@
+use crate::cancel::Cancel;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::ControlFlow;
+
+/// Something went wrong while reading chunks from a stream.
+#[derive(Debug)]
+pub struct ChunkStreamError {
+    reason: String,
+    bad_crc: bool,
+}
+impl ChunkStreamError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            bad_crc: false,
+        })
+    }
+
+    fn bad_crc(reason: String) -> Box<Self> {
+        Box::new(Self {
+            reason,
+            bad_crc: true,
+        })
+    }
+
+    /// Whether a chunk's stored CRC didn't match it, rather than the stream being malformed.
+    pub fn is_bad_crc(&self) -> bool {
+        self.bad_crc
+    }
+}
+
+impl fmt::Display for ChunkStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad PNG stream: {}", self.reason)
+    }
+}
+impl Error for ChunkStreamError {}
+
+/// Reads the chunks of a PNG one at a time from any `Read`, so only the chunk currently
+/// being looked at has to be held in memory.
+pub struct ChunkStream<R: Read> {
+    reader: R,
+    done: bool,
+    stop_at_iend: bool,
+    cancel: Option<Cancel>,
+}
+
+#[allow(dead_code)]
+impl<R: Read> ChunkStream<R> {
+    /// Reads and checks the PNG signature, leaving `reader` positioned at the first chunk.
+    pub fn new(mut reader: R) -> crate::Result<Self> {
+        let mut signature = [0u8; 8];
+        reader.read_exact(&mut signature)?;
+        if signature != Png::STANDARD_HEADER {
+            return Err(ChunkStreamError::boxed("Invalid header passed".to_string()));
+        }
+        Ok(Self {
+            reader,
+            done: false,
+            stop_at_iend: false,
+            cancel: None,
+        })
+    }
+
+    /// Reads chunks from a `reader` positioned just past a signature that was already checked.
+    pub fn after_signature(reader: R) -> Self {
+        Self {
+            reader,
+            done: false,
+            stop_at_iend: false,
+            cancel: None,
+        }
+    }
+
+    /// Returns the underlying reader, mid-stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Makes the stream end after IEND instead of reading until EOF, for PNGs embedded in
+    /// other data.
+    pub fn stop_at_iend(mut self) -> Self {
+        self.stop_at_iend = true;
+        self
+    }
+
+    /// Makes the stream fail with `CancelledError` before reading the next chunk once `cancel`
+    /// is cancelled.
+    pub fn cancellable(mut self, cancel: Cancel) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Calls `visitor` with the index of every chunk in the stream, in order, until it returns
+    /// `ControlFlow::Break`. Nothing past the chunk that caused the break is read.
+    pub fn walk(
+        &mut self,
+        mut visitor: impl FnMut(usize, &Chunk) -> ControlFlow<()>,
+    ) -> crate::Result<ControlFlow<()>> {
+        for (index, chunk) in self.by_ref().enumerate() {
+            if visitor(index, &chunk?).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_chunk(&mut self) -> crate::Result<Option<Chunk>> {
+        if let Some(cancel) = &self.cancel {
+            cancel.check()?;
+        }
+        let mut header = [0u8; 8];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(ChunkStreamError::boxed(
+                        "Stream ended inside a chunk header".to_string(),
+                    ))
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let chunktype = ChunkType::try_from([header[4], header[5], header[6], header[7]])?;
+
+        let mut data = vec![];
+        (&mut self.reader).take(length).read_to_end(&mut data)?;
+        let mut crc = [0u8; 4];
+        if data.len() as u64 != length || self.reader.read_exact(&mut crc).is_err() {
+            return Err(ChunkStreamError::boxed(format!(
+                "Stream ended inside {} chunk",
+                chunktype
+            )));
+        }
+
+        let chunk = Chunk::from_parts(chunktype, data, u32::from_be_bytes(crc));
+        if !chunk.has_valid_crc() {
+            return Err(ChunkStreamError::bad_crc(format!(
+                "Bad CRC on {} chunk (stored {:08x}, expected {:08x})",
+                chunk.chunk_type(),
+                chunk.crc(),
+                chunk.computed_crc()
+            )));
+        }
+        Ok(Some(chunk))
+    }
+}
+
+impl<R: Read> Iterator for ChunkStream<R> {
+    type Item = crate::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.read_chunk().transpose();
+        self.done = match &next {
+            Some(Ok(chunk)) => self.stop_at_iend && chunk.chunk_type().bytes() == *b"IEND",
+            _ => true,
+        };
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Counts how many bytes have been pulled from the inner reader.
+    struct CountingReader<'a> {
+        inner: &'a [u8],
+        read: usize,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(
+            ["FrSt", "miDl", "LASt"]
+                .iter()
+                .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), t.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_stream_reads_all_chunks() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        let chunks: Vec<Chunk> = ChunkStream::new(&bytes[..])
+            .unwrap()
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(chunks, png.chunks());
+    }
+
+    #[test]
+    fn test_stream_rejects_bad_header() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[0] = 0;
+        assert!(ChunkStream::new(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_stream_reports_truncation() {
+        let bytes = testing_png().as_bytes();
+        let mut stream = ChunkStream::new(&bytes[..bytes.len() - 2]).unwrap();
+        assert!(stream.next().unwrap().is_ok());
+        assert!(stream.next().unwrap().is_ok());
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_stops_at_iend() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+        let bytes = [png.as_bytes(), b"not a chunk".to_vec()].concat();
+        let chunks: Vec<Chunk> = ChunkStream::new(&bytes[..])
+            .unwrap()
+            .stop_at_iend()
+            .collect::<crate::Result<_>>()
+            .unwrap();
+        assert_eq!(chunks.len(), 4);
+    }
+
+    #[test]
+    fn test_walk_early_exit_stops_reading() {
+        let bytes = testing_png().as_bytes();
+        let mut reader = CountingReader {
+            inner: &bytes,
+            read: 0,
+        };
+        let mut visited = vec![];
+        let flow = ChunkStream::new(&mut reader)
+            .unwrap()
+            .walk(|idx, chunk| {
+                visited.push(idx);
+                if chunk.chunk_type().to_string() == "FrSt" {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert!(flow.is_break());
+        assert_eq!(visited, vec![0]);
+        // Only the signature and the first chunk were consumed
+        assert_eq!(reader.read, 8 + 12 + 4);
+    }
+
+    #[test]
+    fn test_walk_visits_everything_without_break() {
+        let bytes = testing_png().as_bytes();
+        let mut count = 0;
+        let flow = ChunkStream::new(&bytes[..])
+            .unwrap()
+            .walk(|_, _| {
+                count += 1;
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert!(flow.is_continue());
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_cancel_mid_walk_stops_reading() {
+        let bytes = testing_png().as_bytes();
+        let cancel = Cancel::new();
+        let mut visited = vec![];
+        let error = ChunkStream::new(&bytes[..])
+            .unwrap()
+            .cancellable(cancel.clone())
+            .walk(|idx, _| {
+                visited.push(idx);
+                cancel.cancel();
+                ControlFlow::Continue(())
+            })
+            .unwrap_err();
+        assert!(error.is::<crate::cancel::CancelledError>());
+        assert_eq!(visited, vec![0]);
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::chunk::Chunk;
+use crate::digest::FileDigest;
+use crate::document;
+use crate::output;
+use crate::png::Png;
+
+/// What a command that rewrites a png changed about the file.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MutationSummary {
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+    pub delta: i64,
+    pub chunks_added: usize,
+    pub chunks_removed: usize,
+    pub chunks_modified: usize,
+    /// The digest of the written file, with --print-hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<FileDigest>,
+}
+
+impl MutationSummary {
+    /// Compares two versions of a file. Chunks of the same type with the same data are matched
+    /// up first; of the rest, as many as appear in both versions count as modified, and the
+    /// remainder as added or removed.
+    pub fn between(before: &[u8], after: &[u8]) -> Self {
+        let chunks = |bytes: &[u8]| {
+            Png::parse_report_lenient(bytes)
+                .0
+                .map_or(vec![], |png| png.chunks().to_vec())
+        };
+        let (old_chunks, mut new_chunks) = (by_type(chunks(before)), by_type(chunks(after)));
+        let mut summary = Self {
+            bytes_before: before.len(),
+            bytes_after: after.len(),
+            delta: after.len() as i64 - before.len() as i64,
+            ..Default::default()
+        };
+        for (chunk_type, old) in old_chunks {
+            let mut new = new_chunks.remove(&chunk_type).unwrap_or_default();
+            let mut unmatched = 0;
+            for chunk in old {
+                match new.iter().position(|c| c.data() == chunk.data()) {
+                    Some(index) => {
+                        new.swap_remove(index);
+                    }
+                    None => unmatched += 1,
+                }
+            }
+            let modified = unmatched.min(new.len());
+            summary.chunks_modified += modified;
+            summary.chunks_removed += unmatched - modified;
+            summary.chunks_added += new.len() - modified;
+        }
+        summary.chunks_added += new_chunks.values().map(Vec::len).sum::<usize>();
+        summary
+    }
+
+    /// Prints the summary as one line on stderr, or with `json` hands it over as the command's
+    /// result. The digest of the written file goes to stdout on a line of its own, as sha256sum
+    /// prints it, unless the file itself went there.
+    pub fn render(&self, json: bool) -> crate::Result<()> {
+        if json {
+            document::emit(self)?;
+        } else {
+            eprintln!("{}", self);
+            if let Some(digest) = &self.digest {
+                output::say(digest);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn by_type(chunks: Vec<Chunk>) -> HashMap<[u8; 4], Vec<Chunk>> {
+    let mut map: HashMap<[u8; 4], Vec<Chunk>> = HashMap::new();
+    for chunk in chunks {
+        map.entry(chunk.chunk_type().bytes())
+            .or_default()
+            .push(chunk);
+    }
+    map
+}
+
+impl fmt::Display for MutationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} ({}), {} chunk(s) added, {} removed, {} modified",
+            output::size(self.bytes_before),
+            output::size(self.bytes_after),
+            output::size_change(self.delta),
+            self.chunks_added,
+            self.chunks_removed,
+            self.chunks_modified
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn png(chunks: &[(&str, &[u8])]) -> Vec<u8> {
+        let chunks = chunks
+            .iter()
+            .map(|(t, d)| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec()))
+            .collect();
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_chunk_changes() {
+        let before = png(&[
+            ("IHDR", b"h"),
+            ("IDAT", b"a"),
+            ("IDAT", b"b"),
+            ("tEXt", b"t"),
+        ]);
+        let after = png(&[
+            ("IHDR", b"h"),
+            ("IDAT", b"a"),
+            ("IDAT", b"c"),
+            ("ruSt", b"new"),
+        ]);
+        let summary = MutationSummary::between(&before, &after);
+        assert_eq!(
+            summary,
+            MutationSummary {
+                bytes_before: before.len(),
+                bytes_after: after.len(),
+                delta: 2,
+                chunks_added: 1,
+                chunks_removed: 1,
+                chunks_modified: 1,
+                digest: None,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            format!(
+                "{} bytes -> {} bytes (+2 bytes), 1 chunk(s) added, 1 removed, 1 modified",
+                before.len(),
+                after.len()
+            )
+        );
+    }
+
+    #[test]
+    fn test_removing_one_of_several() {
+        let before = png(&[("IHDR", b"h"), ("ruSt", b"first"), ("ruSt", b"second")]);
+        let after = png(&[("IHDR", b"h"), ("ruSt", b"second")]);
+        let summary = MutationSummary::between(&before, &after);
+        assert_eq!(summary.chunks_removed, 1);
+        assert_eq!(summary.chunks_modified, 0);
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let bytes = png(&[("IHDR", b"h"), ("IEND", b"")]);
+        let summary = MutationSummary::between(&bytes, &bytes);
+        assert_eq!(summary.delta, 0);
+        assert_eq!(
+            (
+                summary.chunks_added,
+                summary.chunks_removed,
+                summary.chunks_modified
+            ),
+            (0, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fmt;
+
+/// A template can't be parsed.
+#[derive(Debug)]
+pub struct TemplateError {
+    reason: String,
+}
+impl TemplateError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad template: {}", self.reason)
+    }
+}
+impl Error for TemplateError {}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+/// A line of text with `{field}` placeholders, parsed once and then filled in for every record.
+/// `\t`, `\n` and `\\` are escapes, and `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parses `text`, accepting only placeholders named in `fields`.
+    pub fn parse(text: &str, fields: &[&str]) -> Result<Self, Box<TemplateError>> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => literal.push(match chars.next() {
+                    Some('t') => '\t',
+                    Some('n') => '\n',
+                    Some('\\') => '\\',
+                    Some(other) => {
+                        return Err(TemplateError::boxed(format!(
+                            "unknown escape \\{}, use \\t, \\n or \\\\",
+                            other
+                        )))
+                    }
+                    None => {
+                        return Err(TemplateError::boxed(
+                            "the template ends in a lone \\".to_string(),
+                        ))
+                    }
+                }),
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(TemplateError::boxed(format!(
+                            "{{{} isn't closed with }}",
+                            rest
+                        )));
+                    };
+                    let name = &rest[..end];
+                    if !fields.contains(&name) {
+                        return Err(TemplateError::boxed(format!(
+                            "unknown field {{{}}}, valid fields are {}",
+                            name,
+                            fields.join(", ")
+                        )));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(name.to_string()));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(TemplateError::boxed(
+                        "a lone } has to be written as }}".to_string(),
+                    ))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// The template with every placeholder replaced by what `value` gives for its field.
+    pub fn render(&self, mut value: impl FnMut(&str) -> String) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(name) => value(name),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &[&str] = &["index", "type"];
+
+    #[test]
+    fn test_renders_fields_and_escapes() {
+        let template = Template::parse("{index}\\t{type}\\n{{{index}}} \\\\", FIELDS).unwrap();
+        let rendered = template.render(|field| match field {
+            "index" => "3".to_string(),
+            _ => "ruSt".to_string(),
+        });
+        assert_eq!(rendered, "3\truSt\n{3} \\");
+        assert_eq!(
+            Template::parse("", FIELDS)
+                .unwrap()
+                .render(|_| unreachable!()),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_templates() {
+        let err = Template::parse("{index} {size}", FIELDS).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unknown field {size}, valid fields are index, type"),
+            "{}",
+            err
+        );
+        for bad in ["{index", "index}", "\\q", "trailing \\"] {
+            assert!(Template::parse(bad, FIELDS).is_err(), "{}", bad);
+        }
+    }
+}
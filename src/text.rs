@@ -0,0 +1,285 @@
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+use crate::capacity::PRACTICAL_CHUNK_LEN;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compress;
+
+/// The type of the chunks holding uncompressed Latin-1 text.
+pub const TEXT_CHUNK: &str = "tEXt";
+/// The type of the chunks holding Latin-1 text compressed with zlib.
+pub const COMPRESSED_TEXT_CHUNK: &str = "zTXt";
+/// Keywords are at most this many bytes, as the PNG spec allows.
+pub const MAX_KEYWORD: usize = 79;
+/// The most the text of a zTXt chunk is inflated to, the same limit libpng applies.
+pub const MAX_INFLATED_TEXT: usize = PRACTICAL_CHUNK_LEN;
+
+/// A keyword or text doesn't follow the rules the PNG spec sets for tEXt and zTXt chunks.
+#[derive(Debug)]
+pub struct TextError {
+    reason: String,
+}
+impl TextError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bad text chunk: {}", self.reason)
+    }
+}
+impl Error for TextError {}
+
+/// A keyword and its text, as stored in a tEXt chunk: the keyword, a null byte and the text,
+/// both in Latin-1. A zTXt chunk holds the same with a compression method byte after the null
+/// and the text compressed. Viewers and other tools show these as the image's metadata.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+    /// Whether the text is stored compressed, in a zTXt chunk
+    pub compressed: bool,
+}
+
+impl TextChunk {
+    /// A text chunk for `keyword`, which must be 1 to 79 printable Latin-1 characters without
+    /// leading, trailing or consecutive spaces. `text` can be any Latin-1 except null.
+    pub fn new(keyword: &str, text: &str) -> crate::Result<Self> {
+        check_keyword(&latin1(keyword, "keyword")?)?;
+        let encoded = latin1(text, "text")?;
+        if encoded.contains(&0) {
+            return Err(TextError::boxed(
+                "the text contains a null byte".to_string(),
+            ));
+        }
+        Ok(Self {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+            compressed: false,
+        })
+    }
+
+    /// The same keyword and text, to be stored compressed in a zTXt chunk.
+    pub fn compressed(self) -> Self {
+        Self {
+            compressed: true,
+            ..self
+        }
+    }
+
+    /// Reads a tEXt or zTXt chunk.
+    pub fn read(chunk: &Chunk) -> crate::Result<Self> {
+        match &chunk.chunk_type().bytes() {
+            b"tEXt" => Self::parse(chunk.data()),
+            b"zTXt" => Self::parse_compressed(chunk.data()),
+            _ => Err(TextError::boxed(format!(
+                "{} isn't a tEXt or zTXt chunk",
+                chunk.chunk_type()
+            ))),
+        }
+    }
+
+    /// Reads the data of a tEXt chunk.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let (keyword, text) = split_keyword(data)?;
+        Self::decoded(keyword, text, false)
+    }
+
+    /// Reads the data of a zTXt chunk, inflating the text.
+    pub fn parse_compressed(data: &[u8]) -> crate::Result<Self> {
+        let (keyword, rest) = split_keyword(data)?;
+        let Some((&method, compressed)) = rest.split_first() else {
+            return Err(TextError::boxed(
+                "there is no compression method after the keyword".to_string(),
+            ));
+        };
+        if method != 0 {
+            return Err(TextError::boxed(format!(
+                "compression method {} isn't 0, the only one defined",
+                method
+            )));
+        }
+        let text = compress::read_limited(ZlibDecoder::new(compressed), MAX_INFLATED_TEXT)
+            .map_err(|e| TextError::boxed(format!("the text can't be inflated: {}", e)))?;
+        Self::decoded(keyword, &text, true)
+    }
+
+    fn decoded(keyword: &[u8], text: &[u8], compressed: bool) -> crate::Result<Self> {
+        check_keyword(keyword)?;
+        if text.contains(&0) {
+            return Err(TextError::boxed(
+                "the text contains a null byte".to_string(),
+            ));
+        }
+        // Latin-1 is the first 256 code points, so each byte is the char of the same value
+        let decode = |bytes: &[u8]| bytes.iter().map(|&b| char::from(b)).collect();
+        Ok(Self {
+            keyword: decode(keyword),
+            text: decode(text),
+            compressed,
+        })
+    }
+
+    /// The tEXt chunk holding this keyword and text, or the zTXt chunk if it is compressed.
+    pub fn to_chunk(&self) -> crate::Result<Chunk> {
+        let keyword = latin1(&self.keyword, "keyword")?;
+        let text = latin1(&self.text, "text")?;
+        let (chunk_type, data) = match self.compressed {
+            false => (TEXT_CHUNK, [keyword, vec![0], text].concat()),
+            true => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+                encoder.write_all(&text)?;
+                let text = encoder.finish()?;
+                (COMPRESSED_TEXT_CHUNK, [keyword, vec![0, 0], text].concat())
+            }
+        };
+        Ok(Chunk::new(
+            ChunkType::from_str(chunk_type).expect("tEXt and zTXt are valid chunk types"),
+            data,
+        ))
+    }
+}
+
+impl fmt::Display for TextChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.keyword, self.text)
+    }
+}
+
+/// A line with the keyword and text of a tEXt or zTXt chunk, inflated if need be, or why it
+/// can't be read. `None` for other chunks.
+pub fn describe(chunk_type: &[u8; 4], data: &[u8]) -> Option<String> {
+    let described = match chunk_type {
+        b"tEXt" => TextChunk::parse(data),
+        b"zTXt" => TextChunk::parse_compressed(data),
+        _ => return None,
+    };
+    Some(described.map_or_else(|e| e.to_string(), |t| t.to_string()))
+}
+
+/// The keyword of a tEXt or zTXt chunk and the bytes after its null separator.
+fn split_keyword(data: &[u8]) -> crate::Result<(&[u8], &[u8])> {
+    let separator = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| TextError::boxed("there is no null byte after the keyword".to_string()))?;
+    Ok((&data[..separator], &data[separator + 1..]))
+}
+
+/// `value` in Latin-1, failing on characters it can't hold.
+fn latin1(value: &str, what: &str) -> crate::Result<Vec<u8>> {
+    value
+        .chars()
+        .map(|c| {
+            u8::try_from(c).map_err(|_| {
+                TextError::boxed(format!("the {} has '{}', which isn't Latin-1", what, c)).into()
+            })
+        })
+        .collect()
+}
+
+fn check_keyword(keyword: &[u8]) -> crate::Result<()> {
+    if keyword.is_empty() || keyword.len() > MAX_KEYWORD {
+        return Err(TextError::boxed(format!(
+            "keywords are 1 to {} characters, not {}",
+            MAX_KEYWORD,
+            keyword.len()
+        )));
+    }
+    if let Some(&b) = keyword
+        .iter()
+        .find(|&&b| !matches!(b, 32..=126 | 161..=255))
+    {
+        return Err(TextError::boxed(format!(
+            "the keyword has byte {:#04x}, which isn't printable Latin-1",
+            b
+        )));
+    }
+    if keyword.starts_with(b" ")
+        || keyword.ends_with(b" ")
+        || keyword.windows(2).any(|w| w == b"  ")
+    {
+        return Err(TextError::boxed(
+            "the keyword has leading, trailing or consecutive spaces".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = TextChunk::new("Author", "Zoë\nsecond line").unwrap();
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Author\0Zo\xeb\nsecond line");
+        assert_eq!(TextChunk::read(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let description = "a long description, ".repeat(50);
+        let text = TextChunk::new("Description", &description)
+            .unwrap()
+            .compressed();
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().starts_with(b"Description\0\0"));
+        assert!(chunk.data().len() < description.len());
+        assert_eq!(TextChunk::read(&chunk).unwrap(), text);
+
+        assert_eq!(
+            describe(b"zTXt", chunk.data()).unwrap(),
+            format!("Description: {:?}", description)
+        );
+        assert!(describe(b"ruSt", chunk.data()).is_none());
+
+        assert!(TextChunk::parse_compressed(b"Title\0").is_err());
+        assert!(TextChunk::parse_compressed(b"Title\0\x01x").is_err());
+        assert!(TextChunk::parse_compressed(b"Title\0\0not zlib").is_err());
+
+        // A few kilobytes that would inflate to more than libpng reads are refused
+        let bomb = TextChunk::new("Comment", &"a".repeat(MAX_INFLATED_TEXT + 1))
+            .unwrap()
+            .compressed()
+            .to_chunk()
+            .unwrap();
+        assert!(bomb.data().len() < 100_000);
+        let err = TextChunk::read(&bomb).unwrap_err();
+        assert!(err.to_string().contains("inflates to more than"), "{}", err);
+    }
+
+    #[test]
+    fn test_keyword_rules() {
+        assert!(TextChunk::new("Creation Time", "").is_ok());
+        assert!(TextChunk::new(&"k".repeat(79), "").is_ok());
+        for keyword in ["", " Title", "Title ", "Two  spaces", "tab\there", "日本"] {
+            assert!(TextChunk::new(keyword, "x").is_err(), "{:?}", keyword);
+        }
+        assert!(TextChunk::new(&"k".repeat(80), "").is_err());
+        assert!(TextChunk::new("Title", "snow ☃").is_err());
+        assert!(TextChunk::new("Title", "a\0b").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(TextChunk::parse(b"no separator").is_err());
+        assert!(TextChunk::parse(b"\0text").is_err());
+        assert!(TextChunk::parse(b"Title\0a\0b").is_err());
+        let parsed = TextChunk::parse(b"Title\0caf\xe9").unwrap();
+        assert_eq!(parsed.text, "café");
+    }
+}
@@ -0,0 +1,305 @@
+//! What's wrong with a png that won't open, for `pngme triage`: what the lenient parser finds,
+//! most serious first, and a verdict on whether enough of the file survives to salvage.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::png::Png;
+
+/// How much a finding matters to getting the image back. Findings sort in this order.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Nothing can be read past it
+    Fatal,
+    /// Part of the file is lost or can't be trusted
+    Damage,
+    /// Worth knowing, but viewers cope with it
+    Notice,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Priority::Fatal => "fatal",
+            Priority::Damage => "damage",
+            Priority::Notice => "notice",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+    pub priority: Priority,
+    /// Byte offset into the file where the problem was found, if known
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Healthy,
+    Recoverable,
+    Fatal,
+}
+
+/// Everything triage found in one file.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TriageReport {
+    pub bytes: usize,
+    /// Chunks read before parsing stopped
+    pub chunks: usize,
+    /// Offset of the first byte no chunk could be read from, when the file ends mid-chunk or
+    /// turns to garbage before IEND
+    pub truncated_at: Option<usize>,
+    pub findings: Vec<Finding>,
+    pub verdict: Verdict,
+    /// The verdict and its reason in a line, such as "fatal: truncated before first IDAT"
+    pub summary: String,
+}
+
+impl Finding {
+    fn new(priority: Priority, diagnostic: &Diagnostic, message: String) -> Self {
+        Self {
+            priority,
+            offset: diagnostic.offset,
+            message,
+        }
+    }
+}
+
+/// Triage of the png in `bytes`.
+pub fn triage(bytes: &[u8]) -> TriageReport {
+    let (parsed, mut diagnostics) = Png::parse_report_lenient(bytes);
+    let mut truncated_at = None;
+    let png = match parsed {
+        Ok(png) => Some(png),
+        // A chunk type that isn't letters stops even the lenient parser, so read up to it
+        Err(_) => match diagnostics
+            .iter()
+            .find(|d| d.kind == DiagnosticKind::BadChunkType)
+            .cloned()
+        {
+            Some(bad_type) => {
+                let start = bad_type.offset.unwrap_or(8).saturating_sub(4);
+                truncated_at = Some(start);
+                let (png, before) = Png::parse_report_lenient(&bytes[..start]);
+                diagnostics = before;
+                diagnostics.push(bad_type);
+                png.ok()
+            }
+            None => None,
+        },
+    };
+
+    let mut findings = vec![];
+    let truncated = diagnostics
+        .iter()
+        .any(|d| d.kind == DiagnosticKind::LengthMismatch);
+    for d in &diagnostics {
+        let finding = match d.kind {
+            DiagnosticKind::BadSignature => Finding::new(
+                Priority::Fatal,
+                d,
+                "The file doesn't start with the png signature".to_string(),
+            ),
+            DiagnosticKind::LengthMismatch => {
+                truncated_at = d.offset;
+                Finding::new(
+                    Priority::Damage,
+                    d,
+                    format!("The file ends in the middle of a chunk: {}", d.message),
+                )
+            }
+            DiagnosticKind::BadChunkType => Finding::new(
+                Priority::Damage,
+                d,
+                format!("Nothing past this can be read: {}", d.message),
+            ),
+            DiagnosticKind::BadCrc => Finding::new(Priority::Damage, d, d.message.clone()),
+            // What's left after a truncated chunk is what was truncated, not data after IEND
+            DiagnosticKind::TrailingData if truncated => continue,
+            DiagnosticKind::TrailingData => Finding::new(
+                Priority::Notice,
+                d,
+                format!("{}, `pngme strip` removes them", d.message),
+            ),
+            // Where IHDR is gets a finding of its own below
+            DiagnosticKind::Ordering if d.message.contains("IHDR") => continue,
+            _ => Finding::new(Priority::Notice, d, d.message.clone()),
+        };
+        findings.push(finding);
+    }
+
+    let chunks = png.as_ref().map_or(0, |png| png.chunks().len());
+    let types: Vec<[u8; 4]> = png.as_ref().map_or(vec![], |png| {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().bytes())
+            .collect()
+    });
+    let header = usize::from(types.first() == Some(b"CgBI"));
+    let ihdr = types.iter().position(|t| t == b"IHDR");
+    let idats = types.iter().filter(|&t| t == b"IDAT").count();
+    let mut structure = |priority: Priority, message: String| {
+        findings.push(Finding {
+            priority,
+            offset: None,
+            message,
+        })
+    };
+    match ihdr {
+        Some(index) if index != header => structure(
+            Priority::Damage,
+            format!("IHDR is chunk {} when it must be the first", index),
+        ),
+        None if png.is_some() => structure(Priority::Fatal, "There is no IHDR chunk".to_string()),
+        _ => {}
+    }
+    if png.is_some() && truncated_at.is_none() && types.last() != Some(b"IEND") {
+        structure(Priority::Damage, "The file ends without IEND".to_string());
+    }
+    findings.sort_by_key(|f| (f.priority, f.offset));
+
+    let damage = findings
+        .iter()
+        .filter(|f| f.priority == Priority::Damage)
+        .count();
+    let (verdict, summary) = if png.is_none() {
+        (
+            Verdict::Fatal,
+            "fatal: not a png, the signature is missing".to_string(),
+        )
+    } else if ihdr.is_none() {
+        (Verdict::Fatal, "fatal: no IHDR chunk survives".to_string())
+    } else if idats == 0 && truncated_at.is_some() {
+        (
+            Verdict::Fatal,
+            "fatal: truncated before first IDAT".to_string(),
+        )
+    } else if idats == 0 {
+        (
+            Verdict::Fatal,
+            "fatal: there is no IDAT holding image data".to_string(),
+        )
+    } else if damage > 0 {
+        (
+            Verdict::Recoverable,
+            format!(
+                "recoverable: {} damaged part(s), but IHDR and {} IDAT chunk(s) survive to \
+                 salvage",
+                damage, idats
+            ),
+        )
+    } else {
+        let notices = findings.len();
+        let summary = match notices {
+            0 => "healthy".to_string(),
+            n => format!("healthy, with {} notice(s)", n),
+        };
+        (Verdict::Healthy, summary)
+    };
+    TriageReport {
+        bytes: bytes.len(),
+        chunks,
+        truncated_at,
+        findings,
+        verdict,
+        summary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn png() -> Vec<u8> {
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d);
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0fine"),
+            chunk("IDAT", b"pixels"),
+            chunk("IDAT", b"more pixels"),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    fn priorities(report: &TriageReport) -> Vec<Priority> {
+        report.findings.iter().map(|f| f.priority).collect()
+    }
+
+    #[test]
+    fn test_healthy() {
+        let report = triage(&png());
+        assert_eq!(report.verdict, Verdict::Healthy);
+        assert_eq!(report.summary, "healthy");
+        assert!(report.findings.is_empty());
+        assert_eq!(report.chunks, 5);
+
+        let appended = [png(), b"appended".to_vec()].concat();
+        let report = triage(&appended);
+        assert_eq!(report.verdict, Verdict::Healthy);
+        assert_eq!(report.summary, "healthy, with 1 notice(s)");
+        assert!(report.findings[0].message.contains("pngme strip"));
+    }
+
+    #[test]
+    fn test_bit_rot() {
+        let mut bytes = png();
+        // A flipped bit in the first IDAT's data, which its CRC no longer matches
+        let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap();
+        bytes[idat + 6] ^= 0x10;
+        let report = triage(&bytes);
+        assert_eq!(report.verdict, Verdict::Recoverable);
+        assert_eq!(priorities(&report), [Priority::Damage]);
+        assert_eq!(report.findings[0].offset, Some(idat + 4 + 6));
+        assert!(report.findings[0].message.contains("Bad CRC on IDAT"));
+        assert!(report.summary.starts_with("recoverable: 1 damaged part(s)"));
+        assert_eq!(report.truncated_at, None);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = png();
+        let iend = bytes.len() - 12;
+        let report = triage(&bytes[..iend - 5]);
+        assert_eq!(report.verdict, Verdict::Recoverable);
+        assert_eq!(report.truncated_at, Some(iend - 23));
+        assert!(report.findings[0].message.contains("ends in the middle"));
+        // Being truncated explains the missing IEND, which isn't reported again
+        assert_eq!(report.findings.len(), 1);
+
+        let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap();
+        let report = triage(&bytes[..idat + 2]);
+        assert_eq!(report.verdict, Verdict::Fatal);
+        assert_eq!(report.summary, "fatal: truncated before first IDAT");
+    }
+
+    #[test]
+    fn test_fatal() {
+        let report = triage(b"GIF89a and so on");
+        assert_eq!(report.verdict, Verdict::Fatal);
+        assert_eq!(priorities(&report), [Priority::Fatal]);
+
+        // The reserved bit set in the second chunk's type, which no chunk can have
+        let mut bytes = png();
+        bytes[39] = b'x';
+        let report = triage(&bytes);
+        assert_eq!(report.truncated_at, Some(33));
+        assert_eq!(report.chunks, 1);
+        assert_eq!(report.summary, "fatal: truncated before first IDAT");
+
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d);
+        let headless = Png::from_chunks(vec![chunk("IDAT", b"pixels"), chunk("IEND", &[])]);
+        let report = triage(&headless.as_bytes());
+        assert_eq!(report.summary, "fatal: no IHDR chunk survives");
+        assert_eq!(priorities(&report), [Priority::Fatal]);
+    }
+}
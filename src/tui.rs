@@ -0,0 +1,388 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chunk_type::Category;
+use crate::lock;
+use crate::png::Png;
+
+/// Bytes shown per line of the hex view.
+const HEX_WIDTH: usize = 16;
+
+/// One line of the chunk list.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Row {
+    pub chunk_type: String,
+    pub size: usize,
+    pub crc_ok: bool,
+    pub category: Category,
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:>8} {} {}",
+            self.chunk_type,
+            self.size,
+            if self.crc_ok { "crc ok " } else { "BAD CRC" },
+            self.category
+        )
+    }
+}
+
+/// What a key press asks the browser to do.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Action {
+    Up,
+    Down,
+    Delete,
+    Export,
+    Save,
+    Confirm,
+    Cancel,
+    Quit,
+}
+
+/// The state of the browser, kept apart from the terminal so it can be driven by tests. Every
+/// change to the png goes through `Png`'s own methods.
+pub struct Browser {
+    path: PathBuf,
+    png: Png,
+    selected: usize,
+    modified: bool,
+    confirming_save: bool,
+    quit: bool,
+    status: String,
+}
+
+impl Browser {
+    /// Reads the png at `path`. Chunks with bad CRCs are kept and flagged rather than refused.
+    pub fn open(path: &Path) -> crate::Result<Self> {
+        let png = Png::parse_report_lenient(&fs::read(path)?).0?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            png,
+            selected: 0,
+            modified: false,
+            confirming_save: false,
+            quit: false,
+            status: "↑/↓ select, d delete, e export, s save, q quit".to_string(),
+        })
+    }
+
+    pub fn rows(&self) -> Vec<Row> {
+        self.png
+            .chunks()
+            .iter()
+            .map(|chunk| Row {
+                chunk_type: chunk.chunk_type().to_string(),
+                size: chunk.data().len(),
+                crc_ok: chunk.has_valid_crc(),
+                category: chunk.chunk_type().category(),
+            })
+            .collect()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// The data of the selected chunk as lines of offset, hex and ASCII.
+    pub fn detail(&self) -> Vec<String> {
+        let Some(chunk) = self.png.chunks().get(self.selected) else {
+            return vec![];
+        };
+        chunk
+            .data()
+            .chunks(HEX_WIDTH)
+            .enumerate()
+            .map(|(line, bytes)| {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                let ascii: String = bytes
+                    .iter()
+                    .map(|&b| match b {
+                        0x20..=0x7e => b as char,
+                        _ => '.',
+                    })
+                    .collect();
+                format!(
+                    "{:08x}  {:<width$}  |{}|",
+                    line * HEX_WIDTH,
+                    hex.join(" "),
+                    ascii,
+                    width = HEX_WIDTH * 3 - 1
+                )
+            })
+            .collect()
+    }
+
+    pub fn apply(&mut self, action: Action) -> crate::Result<()> {
+        if self.confirming_save {
+            self.confirming_save = false;
+            match action {
+                Action::Confirm => self.save()?,
+                _ => self.status = "Not saved".to_string(),
+            }
+            return Ok(());
+        }
+        let last = self.png.chunks().len().saturating_sub(1);
+        match action {
+            Action::Up => self.selected = self.selected.saturating_sub(1),
+            Action::Down => self.selected = (self.selected + 1).min(last),
+            Action::Delete => self.delete(),
+            Action::Export => self.export()?,
+            Action::Save if self.modified => {
+                self.confirming_save = true;
+                self.status = format!("Write the changes to {}? (y/n)", self.path.display());
+            }
+            Action::Save => self.status = "Nothing to save".to_string(),
+            Action::Quit => self.quit = true,
+            Action::Confirm | Action::Cancel => {}
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self) {
+        let Some(chunk) = self.png.chunks().get(self.selected) else {
+            return;
+        };
+        if chunk.chunk_type().is_critical() {
+            self.status = format!(
+                "{} is critical to the image and can't be deleted",
+                chunk.chunk_type()
+            );
+            return;
+        }
+        let Ok(removed) = self.png.remove_chunk(self.selected) else {
+            return;
+        };
+        self.selected = self.selected.min(self.png.chunks().len().saturating_sub(1));
+        self.modified = true;
+        self.status = format!(
+            "Deleted {} ({} bytes)",
+            removed.chunk_type(),
+            removed.length()
+        );
+    }
+
+    /// Writes the selected chunk's data next to the png, named after its position and type.
+    fn export(&mut self) -> crate::Result<()> {
+        let Some(chunk) = self.png.chunks().get(self.selected) else {
+            return Ok(());
+        };
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.{}.bin", self.selected, chunk.chunk_type()));
+        let out = self.path.with_file_name(name);
+        fs::write(&out, chunk.data())?;
+        self.status = format!("Exported {} bytes to {}", chunk.data().len(), out.display());
+        Ok(())
+    }
+
+    fn save(&mut self) -> crate::Result<()> {
+        let _lock = lock::acquire(&self.path, None)?;
+        fs::write(&self.path, self.png.as_bytes())?;
+        self.modified = false;
+        self.status = format!("Saved {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// Browses the png at `path` in the terminal until the user quits.
+pub fn run(path: &Path) -> crate::Result<()> {
+    let mut browser = Browser::open(path)?;
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut browser);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, browser: &mut Browser) -> crate::Result<()> {
+    while !browser.should_quit() {
+        terminal.draw(|frame| draw(frame, browser))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let action = match key.code {
+            KeyCode::Up | KeyCode::Char('k') => Action::Up,
+            KeyCode::Down | KeyCode::Char('j') => Action::Down,
+            KeyCode::Char('d') | KeyCode::Delete => Action::Delete,
+            KeyCode::Char('e') => Action::Export,
+            KeyCode::Char('s') => Action::Save,
+            KeyCode::Char('y') => Action::Confirm,
+            KeyCode::Char('n') | KeyCode::Esc => Action::Cancel,
+            KeyCode::Char('q') => Action::Quit,
+            _ => continue,
+        };
+        // A failed export or save is shown instead of tearing down the whole browser
+        if let Err(e) = browser.apply(action) {
+            browser.status = e.to_string();
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, browser: &Browser) {
+    let [main, status] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+    let [list, detail] =
+        Layout::horizontal([Constraint::Length(42), Constraint::Min(1)]).areas(main);
+
+    let title = match browser.is_modified() {
+        true => format!("{} (modified)", browser.path.display()),
+        false => browser.path.display().to_string(),
+    };
+    let items: Vec<ListItem> = browser
+        .rows()
+        .iter()
+        .map(|row| ListItem::new(row.to_string()))
+        .collect();
+    let mut state = ListState::default().with_selected(Some(browser.selected()));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+        list,
+        &mut state,
+    );
+    frame.render_widget(
+        Paragraph::new(browser.detail().join("\n"))
+            .block(Block::default().borders(Borders::ALL).title("data")),
+        detail,
+    );
+    frame.render_widget(Paragraph::new(browser.status()), status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn browser() -> (tempfile::TempDir, Browser) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let chunk = |t: &str, d: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), d.to_vec());
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", b"header"),
+            chunk("tEXt", b"Comment\0hi"),
+            chunk("ruSt", b"hidden message, longer than a line"),
+            chunk("IEND", b""),
+        ]);
+        fs::write(&path, png.as_bytes()).unwrap();
+        let browser = Browser::open(&path).unwrap();
+        (dir, browser)
+    }
+
+    #[test]
+    fn test_rows() {
+        let (_dir, browser) = browser();
+        let rows = browser.rows();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[2],
+            Row {
+                chunk_type: "ruSt".to_string(),
+                size: 34,
+                crc_ok: true,
+                category: Category::Private,
+            }
+        );
+        assert_eq!(rows[0].category, Category::Critical);
+        assert_eq!(rows[1].category, Category::Ancillary);
+        assert_eq!(rows[2].to_string(), "ruSt       34 crc ok  private");
+    }
+
+    #[test]
+    fn test_navigation_stays_in_bounds() {
+        let (_dir, mut browser) = browser();
+        browser.apply(Action::Up).unwrap();
+        assert_eq!(browser.selected(), 0);
+        for _ in 0..10 {
+            browser.apply(Action::Down).unwrap();
+        }
+        assert_eq!(browser.selected(), 3);
+    }
+
+    #[test]
+    fn test_hex_view() {
+        let (_dir, mut browser) = browser();
+        browser.apply(Action::Down).unwrap();
+        browser.apply(Action::Down).unwrap();
+        let detail = browser.detail();
+        assert_eq!(detail.len(), 3);
+        assert_eq!(
+            detail[0],
+            "00000000  68 69 64 64 65 6e 20 6d 65 73 73 61 67 65 2c 20  |hidden message, |"
+        );
+        assert!(detail[2].starts_with("00000020  6e 65"));
+        assert!(detail[2].ends_with("|ne|"));
+    }
+
+    #[test]
+    fn test_critical_chunks_are_kept() {
+        let (_dir, mut browser) = browser();
+        browser.apply(Action::Delete).unwrap();
+        assert_eq!(browser.rows().len(), 4);
+        assert!(!browser.is_modified());
+        assert!(browser.status().contains("critical"));
+    }
+
+    #[test]
+    fn test_save_after_delete() {
+        let (dir, mut browser) = browser();
+        let path = dir.path().join("image.png");
+        browser.apply(Action::Down).unwrap();
+        browser.apply(Action::Down).unwrap();
+        browser.apply(Action::Delete).unwrap();
+        assert!(browser.is_modified());
+        assert_eq!(browser.selected(), 2);
+
+        // Saving waits for confirmation, and declining leaves the file alone
+        let original = fs::read(&path).unwrap();
+        browser.apply(Action::Save).unwrap();
+        browser.apply(Action::Cancel).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), original);
+
+        browser.apply(Action::Save).unwrap();
+        browser.apply(Action::Confirm).unwrap();
+        assert!(!browser.is_modified());
+        let saved = Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        let types: Vec<String> = saved
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_export() {
+        let (dir, mut browser) = browser();
+        browser.apply(Action::Down).unwrap();
+        browser.apply(Action::Export).unwrap();
+        let exported = fs::read(dir.path().join("image.png.1.tEXt.bin")).unwrap();
+        assert_eq!(exported, b"Comment\0hi");
+    }
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::fmt;
+
+/// The image no longer decodes with a real PNG decoder.
+#[derive(Debug)]
+pub struct ImageVerifyError {
+    reason: String,
+}
+impl ImageVerifyError {
+    fn boxed(reason: String) -> Box<Self> {
+        Box::new(Self { reason })
+    }
+}
+
+impl fmt::Display for ImageVerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Image verification failed: {}", self.reason)
+    }
+}
+impl Error for ImageVerifyError {}
+
+/// Fully decodes `bytes` with the `png` crate, expanding palettes like a viewer would, and
+/// returns the decoder's error if the image can't be displayed.
+#[cfg(feature = "image-verify")]
+pub fn verify_image(bytes: &[u8]) -> crate::Result<()> {
+    let mut decoder = ::png::Decoder::new(std::io::Cursor::new(bytes));
+    decoder.set_transformations(::png::Transformations::EXPAND);
+    let decode = || -> Result<(), ::png::DecodingError> {
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        reader.next_frame(&mut buf)?;
+        Ok(())
+    };
+    decode().map_err(|e| ImageVerifyError::boxed(e.to_string()) as crate::Error)
+}
+
+#[cfg(not(feature = "image-verify"))]
+pub fn verify_image(_bytes: &[u8]) -> crate::Result<()> {
+    Err(ImageVerifyError::boxed(
+        "pngme was built without the image-verify feature".to_string(),
+    ))
+}
+
+#[cfg(all(test, feature = "image-verify"))]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encodes a 2x2 palette image with the `png` crate.
+    pub(crate) fn palette_png() -> Vec<u8> {
+        let mut out = vec![];
+        let mut encoder = ::png::Encoder::new(&mut out, 2, 2);
+        encoder.set_color(::png::ColorType::Indexed);
+        encoder.set_depth(::png::BitDepth::Eight);
+        encoder.set_palette(vec![255, 0, 0, 0, 0, 255]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 1, 1, 0]).unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn test_valid_image_verifies() {
+        assert!(verify_image(&palette_png()).is_ok());
+    }
+
+    #[test]
+    fn test_garbage_fails_verification() {
+        let mut bytes = palette_png();
+        bytes.truncate(40);
+        assert!(verify_image(&bytes).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use pngme::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::str::FromStr;
+
+/// Counts the allocations made on each thread, so tests running alongside don't interfere.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.set(ALLOCATIONS.get() + 1);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.get();
+    let result = f();
+    (result, ALLOCATIONS.get() - before)
+}
+
+/// An animation-like file of 5,000 chunks holding a few bytes each, like fcTL and tIME.
+fn many_small_chunks() -> Vec<u8> {
+    let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+    let mut chunks = vec![chunk("IHDR", vec![0; 13])];
+    for i in 0..2_500u32 {
+        chunks.push(chunk("fcTL", [i.to_be_bytes(); 6].concat()));
+        chunks.push(chunk("tIME", vec![7, 234, 1, 2, 3, 4, i as u8]));
+    }
+    chunks.push(chunk("IEND", vec![]));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+#[test]
+fn small_chunks_parse_without_allocating_each() {
+    let bytes = many_small_chunks();
+    let (png, count) = allocations(|| Png::try_from(&bytes[..]).unwrap());
+    let chunks = png.chunks().len();
+    println!("{} allocations to parse {} chunks", count, chunks);
+    assert!(
+        count < chunks / 20,
+        "{} allocations for {} chunks",
+        count,
+        chunks
+    );
+    assert_eq!(png.as_bytes(), bytes);
+}
@@ -0,0 +1,66 @@
+use pngme::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+fn rust() -> ChunkType {
+    ChunkType::from_str("ruSt").unwrap()
+}
+
+/// Writes a png holding a ruSt chunk to `dir` and returns its path.
+fn png_file(dir: &Path) -> PathBuf {
+    let path = dir.join("image.png");
+    let png = Png::from_chunks(vec![
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), [0; 13]),
+        Chunk::new(rust(), "message"),
+        Chunk::new(ChunkType::from_str("IEND").unwrap(), []),
+    ]);
+    fs::write(&path, png.as_bytes()).unwrap();
+    path
+}
+
+#[test]
+fn paths_as_str_string_and_pathbuf() {
+    let dir = tempfile::tempdir().unwrap();
+    let path: PathBuf = png_file(dir.path());
+    let as_str: &str = path.to_str().unwrap();
+    let as_string: String = as_str.to_string();
+
+    let parsed = [
+        Png::from_file(as_str).unwrap(),
+        Png::from_file(&as_string).unwrap(),
+        Png::from_file(as_string).unwrap(),
+        Png::from_file(&path).unwrap(),
+        Png::try_from(as_str).unwrap(),
+        Png::try_from(path.as_path()).unwrap(),
+    ];
+    for png in parsed {
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"message");
+    }
+
+    let missing = Png::try_from(dir.path().join("missing.png").as_path()).unwrap_err();
+    assert!(matches!(Failure::of(&missing), Failure::FileNotFound(_)));
+}
+
+#[test]
+fn data_as_slices_arrays_and_vecs() {
+    let bytes: Vec<u8> = b"payload".to_vec();
+    let slice: &[u8] = &bytes;
+    let chunks = [
+        Chunk::new(rust(), slice),
+        Chunk::new(rust(), b"payload"),
+        Chunk::new(rust(), "payload"),
+        Chunk::new(rust(), bytes.clone()),
+    ];
+    for chunk in &chunks {
+        assert_eq!(chunk.data(), b"payload");
+        assert_eq!(chunk, &chunks[0]);
+    }
+
+    let large = vec![7; 4096];
+    let pointer = large.as_ptr();
+    let envelope = Envelope::new(large);
+    // An owned payload is moved in rather than copied
+    assert_eq!(envelope.payload.as_ptr(), pointer);
+    assert_eq!(Envelope::new(slice).payload, Envelope::new(bytes).payload);
+}
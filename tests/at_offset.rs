@@ -0,0 +1,76 @@
+mod common;
+
+use common::{chunk, pngme, write_png};
+use std::path::Path;
+
+/// Writes a png to `image.png` in `dir` and returns its path. IHDR starts at 0x08, IDAT at
+/// 0x1a with its data from 0x22 to 0x30, IEND at 0x34, and 4 bytes of trailing data at 0x40.
+fn png(dir: &Path) -> String {
+    write_png(
+        &dir.join("image.png"),
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"IDAT", b"pixels of data"),
+            chunk(b"IEND", b""),
+            b"tail".to_vec(),
+        ],
+    )
+}
+
+#[test]
+fn attributes_each_region() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let expected = [
+        ("5", "0x5\tsignature\t-\t-\t5\t5"),
+        ("0x1b", "0x1b\tlength\t1\tIDAT\t1\t1"),
+        ("0x1e", "0x1e\ttype\t1\tIDAT\t4\t0"),
+        ("0x25", "0x25\tdata\t1\tIDAT\t11\t3"),
+        ("0x33", "0x33\tcrc\t1\tIDAT\t25\t3"),
+        ("0x38", "0x38\ttype\t2\tIEND\t4\t0"),
+        ("0x43", "0x43\ttrailing\t-\t-\t3\t3"),
+    ];
+    for (offset, row) in expected {
+        let output = pngme(&["at-offset", "-f", file, offset, "--format", "plain"]);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("{}\n", row)
+        );
+    }
+}
+
+#[test]
+fn offset_past_the_end_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let output = pngme(&["at-offset", "-f", file, "0x44"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("past the end of the file (68 bytes)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn context_dump_marks_boundaries() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let output = pngme(&["at-offset", "-f", file, "0x1c", "--context", "4"]);
+    let dumped: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    assert_eq!(
+        dumped,
+        [
+            "00000018 d8 e8 .. IHDR chunk 0 crc",
+            "> 0000001a 00 00 00 0e .... IDAT chunk 1 length",
+            "0000001e 49 44 41 IDA IDAT chunk 1 type",
+        ]
+    );
+}
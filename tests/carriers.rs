@@ -0,0 +1,101 @@
+mod common;
+
+use common::{chunk, pngme, write_png};
+use std::fs;
+use std::path::Path;
+
+fn png(path: &Path) {
+    write_png(
+        path,
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"IDAT", b"pixels"),
+            chunk(b"IEND", b""),
+        ],
+    );
+}
+
+#[test]
+fn shards_are_reassembled_in_any_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let assets = dir.path().join("assets");
+    fs::create_dir(&assets).unwrap();
+    for name in ["a.png", "b.png", "c.png"] {
+        png(&assets.join(name));
+    }
+    let payload: Vec<u8> = (0..2500u32).map(|i| (i * 31 % 251) as u8).collect();
+    let big = dir.path().join("big.bin");
+    fs::write(&big, &payload).unwrap();
+    let carriers = assets.join("*.png");
+    let carriers = carriers.to_str().unwrap();
+
+    let output = pngme(&[
+        "--json",
+        "encode",
+        "--carriers",
+        carriers,
+        "--payload-file",
+        big.to_str().unwrap(),
+        "--max-per-file",
+        "1KiB",
+        "-c",
+        "ruSt",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let placed = document["result"].as_array().unwrap();
+    assert_eq!(placed.len(), 3);
+    for (index, shard) in placed.iter().enumerate() {
+        assert_eq!(shard["index"], index);
+        assert_eq!(shard["total"], 3);
+        assert!(shard["delta"].as_i64().unwrap() <= 1024);
+    }
+
+    // Swap the names around, so that sorting by name no longer gives the shards in order
+    fs::rename(assets.join("a.png"), assets.join("tmp.png")).unwrap();
+    fs::rename(assets.join("c.png"), assets.join("a.png")).unwrap();
+    fs::rename(assets.join("tmp.png"), assets.join("c.png")).unwrap();
+    let out = dir.path().join("out.bin");
+    let output = pngme(&[
+        "decode",
+        "--carriers",
+        carriers,
+        "--output",
+        out.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(fs::read(&out).unwrap(), payload);
+
+    // b.png held the second shard, and kept its name
+    fs::remove_file(assets.join("b.png")).unwrap();
+    let output = pngme(&["decode", "--carriers", carriers, "-c", "ruSt"]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing shard 2 of 3"), "{}", stderr);
+}
+
+#[test]
+fn too_few_carriers_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    png(&dir.path().join("only.png"));
+    let carriers = dir.path().join("*.png");
+    let output = pngme(&[
+        "encode",
+        "--carriers",
+        carriers.to_str().unwrap(),
+        "-m",
+        &"x".repeat(300),
+        "--max-per-file",
+        "100",
+        "-c",
+        "ruSt",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("only 1 file(s) match"), "{}", stderr);
+    // Nothing is written when the message doesn't fit
+    assert_eq!(
+        fs::read(dir.path().join("only.png")).unwrap().len(),
+        8 + 18 + 18 + 12
+    );
+}
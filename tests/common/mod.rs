@@ -0,0 +1,73 @@
+//! Fixtures shared by the integration tests: raw chunk and png builders, and ways to run the
+//! pngme binary. Each test file uses only some of them.
+#![allow(dead_code)]
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+pub const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+pub fn crc(chunk_type: &[u8; 4], data: &[u8]) -> u32 {
+    CRC_PNG.checksum(&[&chunk_type[..], data].concat())
+}
+
+/// A chunk storing `stored` as its CRC, right or not.
+pub fn chunk_with_crc(chunk_type: &[u8; 4], data: &[u8], stored: u32) -> Vec<u8> {
+    [
+        &(data.len() as u32).to_be_bytes()[..],
+        chunk_type,
+        data,
+        &stored.to_be_bytes(),
+    ]
+    .concat()
+}
+
+pub fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    chunk_with_crc(chunk_type, data, crc(chunk_type, data))
+}
+
+/// The signature followed by `chunks`, each already serialized.
+pub fn png_bytes(chunks: &[Vec<u8>]) -> Vec<u8> {
+    [&SIGNATURE[..], &chunks.concat()].concat()
+}
+
+/// Writes a png made of `chunks` to `path`, returning the path as a string.
+pub fn write_png(path: &Path, chunks: &[Vec<u8>]) -> String {
+    fs::write(path, png_bytes(chunks)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// A pngme command with `args`, ready for more configuration.
+pub fn command(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pngme"));
+    command.args(args);
+    command
+}
+
+pub fn pngme(args: &[&str]) -> Output {
+    command(args).output().unwrap()
+}
+
+/// Runs pngme, asserting it succeeded, and returns what it printed on stdout.
+pub fn stdout(args: &[&str]) -> String {
+    let output = pngme(args);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs pngme with `input` piped to stdin.
+pub fn piped(args: &[&str], input: &[u8]) -> Output {
+    let mut child = command(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{chunk, command, write_png};
+use std::fs;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+/// Encodes the same message into a fresh copy of the same png with `extra` arguments,
+/// returning the written bytes.
+fn encode_copy(dir: &Path, name: &str, extra: &[&str]) -> Vec<u8> {
+    let path = dir.join(name);
+    let file = write_png(
+        &path,
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"IDAT", b"pixels"),
+            chunk(b"IEND", b""),
+        ],
+    );
+    let output = pngme(&[&["encode", "-f", &file, "-c", "ruSt", "-m", "hello"], extra].concat());
+    assert!(output.status.success(), "{:?}", output);
+    fs::read(&path).unwrap()
+}
+
+fn pngme(args: &[&str]) -> Output {
+    command(args)
+        .env_remove("PNGME_PASSPHRASE")
+        .env_remove("PNGME_KEY_HEX")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn plain_encode_is_deterministic_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let extra = [
+        "--compress",
+        "--ecc",
+        "8",
+        "--expires",
+        "2030-01-31T12:00:00Z",
+    ];
+    assert_eq!(
+        encode_copy(dir.path(), "a.png", &extra),
+        encode_copy(dir.path(), "b.png", &extra)
+    );
+}
+
+#[test]
+fn encrypted_encode_is_deterministic_with_the_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key.bin");
+    fs::write(&key, [7; 32]).unwrap();
+    let key = key.to_str().unwrap();
+    let extra = |seed| {
+        [
+            "--encrypt",
+            "--key-file",
+            key,
+            "--pad-block",
+            "64",
+            "--deterministic",
+            "--deterministic-seed",
+            seed,
+        ]
+    };
+    let first = encode_copy(dir.path(), "a.png", &extra("build 42"));
+    assert_eq!(first, encode_copy(dir.path(), "b.png", &extra("build 42")));
+    assert_ne!(first, encode_copy(dir.path(), "c.png", &extra("build 43")));
+    // Without the flag every run draws a fresh nonce
+    let random = encode_copy(dir.path(), "d.png", &["--encrypt", "--key-file", key]);
+    assert_ne!(
+        random,
+        encode_copy(dir.path(), "e.png", &["--encrypt", "--key-file", key])
+    );
+}
+
+#[test]
+fn deterministic_refuses_what_it_cannot_repeat() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("image.png");
+    fs::write(&file, b"unused").unwrap();
+    let file = file.to_str().unwrap();
+    for extra in [
+        &["--audit"][..],
+        &["--expires", "30d"],
+        &["--encrypt", "--passphrase", "hunter2"],
+    ] {
+        let args = [
+            &[
+                "encode",
+                "-f",
+                file,
+                "-c",
+                "ruSt",
+                "-m",
+                "hi",
+                "--deterministic",
+            ][..],
+            extra,
+        ]
+        .concat();
+        let output = pngme(&args);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Can't encode with --deterministic"),
+            "{}",
+            stderr
+        );
+    }
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{chunk, command, write_png};
+use std::fs;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+fn png(path: &Path) {
+    write_png(
+        path,
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"IDAT", b"pixels"),
+            chunk(b"IEND", b""),
+        ],
+    );
+}
+
+/// Runs pngme with only the given secret variables set, and no stdin.
+fn pngme(args: &[&str], env: &[(&str, &str)]) -> Output {
+    let mut command = command(args);
+    command
+        .env_remove("PNGME_PASSPHRASE")
+        .env_remove("PNGME_KEY_HEX")
+        .stdin(Stdio::null());
+    for (key, value) in env {
+        command.env(key, value);
+    }
+    command.output().unwrap()
+}
+
+fn output_text(output: &Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+fn encode(file: &str, extra: &[&str], env: &[(&str, &str)]) -> Output {
+    let args = [
+        &[
+            "encode",
+            "-f",
+            file,
+            "-c",
+            "ruSt",
+            "-m",
+            "classified",
+            "--encrypt",
+        ][..],
+        extra,
+    ]
+    .concat();
+    pngme(&args, env)
+}
+
+fn decode(file: &str, extra: &[&str], env: &[(&str, &str)]) -> Output {
+    let args = [
+        &["decode", "-f", file, "-c", "ruSt", "--decrypt"][..],
+        extra,
+    ]
+    .concat();
+    pngme(&args, env)
+}
+
+#[test]
+fn passphrase_from_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    let file = path.to_str().unwrap();
+    png(&path);
+
+    let env = [("PNGME_PASSPHRASE", "env-secret-value")];
+    let output = encode(file, &[], &env);
+    assert!(output.status.success(), "{}", output_text(&output));
+    let text = output_text(&output);
+    assert!(text.contains("PNGME_PASSPHRASE"));
+    assert!(!text.contains("env-secret-value"));
+
+    let output = decode(file, &[], &env);
+    assert!(output.status.success(), "{}", output_text(&output));
+    assert!(output_text(&output).contains("classified"));
+    assert!(!output_text(&output).contains("env-secret-value"));
+
+    let output = decode(file, &["--passphrase", "env-secret-value"], &[]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn flag_beats_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    let file = path.to_str().unwrap();
+    png(&path);
+
+    let env = [("PNGME_PASSPHRASE", "from-env")];
+    let output = encode(file, &["--passphrase", "from-flag"], &env);
+    assert!(output.status.success(), "{}", output_text(&output));
+    assert!(!output_text(&output).contains("PNGME_PASSPHRASE"));
+
+    assert!(!decode(file, &[], &env).status.success());
+    assert!(decode(file, &["--passphrase", "from-flag"], &env)
+        .status
+        .success());
+}
+
+#[test]
+fn passphrase_variable_beats_key_variable() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    let file = path.to_str().unwrap();
+    png(&path);
+
+    let hex = "ab".repeat(32);
+    let both = [("PNGME_PASSPHRASE", "from-env"), ("PNGME_KEY_HEX", &hex)];
+    assert!(encode(file, &[], &both).status.success());
+    assert!(!decode(file, &[], &[("PNGME_KEY_HEX", &hex)])
+        .status
+        .success());
+    assert!(decode(file, &[], &[("PNGME_PASSPHRASE", "from-env")])
+        .status
+        .success());
+}
+
+#[test]
+fn hex_key_from_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    let file = path.to_str().unwrap();
+    let key_file = dir.path().join("key.bin");
+    png(&path);
+    fs::write(&key_file, [0xab; 32]).unwrap();
+
+    let hex = "ab".repeat(32);
+    let output = encode(file, &[], &[("PNGME_KEY_HEX", &hex)]);
+    assert!(output.status.success(), "{}", output_text(&output));
+    assert!(output_text(&output).contains("PNGME_KEY_HEX"));
+    assert!(!output_text(&output).contains(&hex));
+
+    let output = decode(file, &["--key-file", key_file.to_str().unwrap()], &[]);
+    assert!(output.status.success(), "{}", output_text(&output));
+
+    let bad = "not-a-hex-key-value";
+    let output = decode(file, &[], &[("PNGME_KEY_HEX", bad)]);
+    assert!(!output.status.success());
+    assert!(!output_text(&output).contains(bad));
+}
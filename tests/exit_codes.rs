@@ -0,0 +1,133 @@
+mod common;
+
+use common::{chunk, write_png};
+use std::fs;
+use std::path::Path;
+
+/// Writes a minimal png to `image.png` in `dir`, returning its path.
+fn png(dir: &Path) -> String {
+    write_png(
+        &dir.join("image.png"),
+        &[chunk(b"IHDR", b"header"), chunk(b"IEND", b"")],
+    )
+}
+
+fn pngme(args: &[&str]) -> i32 {
+    common::pngme(args).status.code().unwrap()
+}
+
+#[test]
+fn success() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    assert_eq!(pngme(&["encode", "-f", file, "-c", "ruSt", "-m", "hi"]), 0);
+    assert_eq!(
+        pngme(&["decode", "-f", file, "-c", "ruSt", "--expect", "hi"]),
+        0
+    );
+    assert_eq!(pngme(&["--help"]), 0);
+}
+
+#[test]
+fn usage_error() {
+    assert_eq!(pngme(&["decode", "--no-such-flag"]), 1);
+    assert_eq!(pngme(&["no-such-command"]), 1);
+}
+
+#[test]
+fn unreadable_file() {
+    assert_eq!(pngme(&["print", "-f", "/definitely/not/here.png"]), 2);
+}
+
+#[test]
+fn not_a_png() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.txt");
+    fs::write(&path, "just some text").unwrap();
+    assert_eq!(pngme(&["print", "-f", path.to_str().unwrap()]), 3);
+}
+
+#[test]
+fn not_found() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    assert_eq!(pngme(&["decode", "-f", file, "-c", "ruSt"]), 4);
+    assert_eq!(pngme(&["remove", "-f", file, "-c", "ruSt"]), 4);
+}
+
+#[test]
+fn integrity_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    assert_eq!(pngme(&["encode", "-f", file, "-c", "ruSt", "-m", "hi"]), 0);
+    assert_eq!(
+        pngme(&["decode", "-f", file, "-c", "ruSt", "--expect", "bye"]),
+        5
+    );
+
+    let mut bytes = fs::read(file).unwrap();
+    // The message sits just before the 12 bytes of IEND; flip the last byte of its CRC
+    let crc = bytes.len() - 13;
+    bytes[crc] ^= 0xff;
+    fs::write(file, bytes).unwrap();
+    assert_eq!(pngme(&["decode", "-f", file, "-c", "ruSt"]), 5);
+}
+
+#[test]
+fn check_fails_on_problems_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checked.png");
+    let file = path.to_str().unwrap();
+    let check = |chunks: &[Vec<u8>], trailing: &[u8]| {
+        let signature = [137, 80, 78, 71, 13, 10, 26, 10];
+        fs::write(&path, [&signature[..], &chunks.concat(), trailing].concat()).unwrap();
+        pngme(&["check", "-f", file])
+    };
+    let (ihdr, idat, iend) = (
+        chunk(b"IHDR", b"header"),
+        chunk(b"IDAT", b"pixels"),
+        chunk(b"IEND", b""),
+    );
+    assert_eq!(check(&[ihdr.clone(), idat.clone(), iend.clone()], b""), 0);
+    // Data after IEND is only a warning
+    assert_eq!(
+        check(&[ihdr.clone(), idat.clone(), iend.clone()], b"appended"),
+        0
+    );
+    assert_eq!(check(&[ihdr.clone(), idat.clone()], b""), 3);
+    let mut damaged = idat.clone();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    assert_eq!(check(&[ihdr, damaged, iend], b""), 5);
+}
+
+#[test]
+fn image_index_out_of_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    assert_eq!(pngme(&["print", "-f", file, "--image-index", "0"]), 0);
+    assert_eq!(pngme(&["print", "-f", file, "--image-index", "3"]), 4);
+}
+
+#[test]
+fn refused_without_force() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key.bin");
+    let key = key.to_str().unwrap();
+    assert_eq!(pngme(&["keygen", "--symmetric", "-o", key]), 0);
+    let first = fs::read(key).unwrap();
+    assert_eq!(pngme(&["keygen", "--symmetric", "-o", key]), 6);
+    assert_eq!(fs::read(key).unwrap(), first);
+    assert_eq!(pngme(&["keygen", "--symmetric", "-o", key, "--force"]), 0);
+    assert_ne!(fs::read(key).unwrap(), first);
+}
+
+#[test]
+fn errors_are_readable() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let output = common::pngme(&["decode", "-f", file, "-c", "ruSt"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Error: "), "{stderr}");
+    assert!(!stderr.contains("NotFoundError {"), "{stderr}");
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{chunk, pngme, write_png};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+/// Writes a minimal png to `name` in `dir` with a message expiring at `expires`, returning
+/// its path.
+fn stamped(dir: &Path, name: &str, expires: &str) -> String {
+    let file = write_png(
+        &dir.join(name),
+        &[chunk(b"IHDR", b"header"), chunk(b"IEND", b"")],
+    );
+    let encoded = pngme(&[
+        "encode",
+        "-f",
+        &file,
+        "-c",
+        "ruSt",
+        "-m",
+        "token",
+        "--expires",
+        expires,
+    ]);
+    assert!(encoded.status.success(), "{:?}", encoded);
+    file
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn expired_message_warns() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &stamped(dir.path(), "old.png", "2001-02-03T04:05:06Z");
+    let decoded = pngme(&["decode", "-f", file, "-c", "ruSt", "--expect", "token"]);
+    assert_eq!(decoded.status.code(), Some(0));
+    assert!(stderr(&decoded).contains("WARNING: the message expired at 2001-02-03T04:05:06Z"));
+
+    let strict = pngme(&["decode", "-f", file, "-c", "ruSt", "--strict-expiry"]);
+    assert_eq!(strict.status.code(), Some(5));
+    assert!(stderr(&strict).contains("expired at 2001-02-03T04:05:06Z"));
+
+    let scanned = pngme(&["scan", "-f", dir.path().to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&scanned.stdout);
+    assert!(
+        stdout.contains("old.png: expired message(s) in ruSt"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn future_expiry_is_quiet() {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, expires) in [
+        ("far.png", "2999-12-31T23:59:59+00:00"),
+        ("soon.png", "30d"),
+    ] {
+        let file = &stamped(dir.path(), name, expires);
+        let strict = pngme(&["decode", "-f", file, "-c", "ruSt", "--strict-expiry"]);
+        assert_eq!(strict.status.code(), Some(0));
+        assert!(!stderr(&strict).contains("expired"));
+    }
+    let scanned = pngme(&["scan", "-f", dir.path().to_str().unwrap()]);
+    assert!(!String::from_utf8_lossy(&scanned.stdout).contains("expired"));
+}
+
+#[test]
+fn non_utc_times_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    fs::write(&path, b"unused").unwrap();
+    let encoded = pngme(&[
+        "encode",
+        "-f",
+        path.to_str().unwrap(),
+        "-c",
+        "ruSt",
+        "-m",
+        "token",
+        "--expires",
+        "2030-01-01T00:00:00+02:00",
+    ]);
+    assert_eq!(encoded.status.code(), Some(1));
+    assert!(stderr(&encoded).contains("isn't in UTC"));
+}
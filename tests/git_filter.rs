@@ -0,0 +1,50 @@
+mod common;
+
+use common::{chunk, piped, png_bytes, pngme};
+use std::fs;
+
+fn png() -> Vec<u8> {
+    png_bytes(&[
+        chunk(b"IHDR", b"header"),
+        chunk(b"tEXt", b"Comment\0hi"),
+        chunk(b"IEND", b""),
+    ])
+}
+
+/// Runs pngme with `input` piped to stdin, returning its stdout.
+fn filter(args: &[&str], input: &[u8]) -> Vec<u8> {
+    let output = piped(args, input);
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn clean_strips_stamped_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    let file = path.to_str().unwrap();
+    fs::write(&path, png()).unwrap();
+    let status = pngme(&["encode", "-f", file, "--label", "build", "-m", "1234"]).status;
+    assert!(status.success());
+    let stamped = fs::read(&path).unwrap();
+    assert_ne!(stamped, png());
+
+    let cleaned = filter(&["git-filter", "clean"], &stamped);
+    assert_eq!(cleaned, png());
+    assert_eq!(filter(&["git-filter", "clean"], &cleaned), cleaned);
+    assert_eq!(filter(&["git-filter", "smudge"], &stamped), stamped);
+}
+
+#[test]
+fn untouched_files_pass_through_exactly() {
+    let inputs = [
+        png(),
+        [png(), b"trailing".to_vec()].concat(),
+        b"not a png".to_vec(),
+        vec![],
+    ];
+    for input in inputs {
+        assert_eq!(filter(&["git-filter", "clean"], &input), input);
+        assert_eq!(filter(&["git-filter", "smudge"], &input), input);
+    }
+}
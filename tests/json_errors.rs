@@ -0,0 +1,92 @@
+mod common;
+
+use common::{chunk, command};
+use serde_json::Value;
+use std::fs;
+
+/// Runs pngme with `--json-errors`, returning the exit code and the JSON object on stderr.
+fn failing(args: &[&str]) -> (i32, Value) {
+    let output = command(args).arg("--json-errors").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let mut lines = stderr.lines();
+    let report = serde_json::from_str(lines.next().unwrap()).unwrap();
+    assert_eq!(
+        lines.next(),
+        None,
+        "more than one line on stderr: {}",
+        stderr
+    );
+    (output.status.code().unwrap(), report)
+}
+
+#[test]
+fn missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("missing.png");
+    let path = path.to_str().unwrap();
+    let (code, report) = failing(&["remove", "-f", path, "-c", "ruSt"]);
+    assert_eq!(report["kind"], "FileNotFound");
+    assert_eq!(report["file"], path);
+    assert!(report["message"].as_str().unwrap().contains("No such file"));
+    assert_eq!(report["exit_code"], code);
+}
+
+#[test]
+fn bad_crc() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bad.png");
+    let mut idat = chunk(b"IDAT", b"pixels");
+    let last = idat.len() - 1;
+    idat[last] ^= 0xff;
+    let png = [
+        &[137, 80, 78, 71, 13, 10, 26, 10][..],
+        &chunk(b"IHDR", b"header"),
+        &idat,
+        &chunk(b"IEND", b""),
+    ]
+    .concat();
+    fs::write(&path, png).unwrap();
+    let path = path.to_str().unwrap();
+
+    let (code, report) = failing(&["remove", "-f", path, "-c", "ruSt"]);
+    assert_eq!(report["kind"], "Parse");
+    assert_eq!(report["chunk_index"], 1);
+    // Where the stored CRC of the damaged chunk sits
+    assert_eq!(report["offset"], 8 + 18 + 8 + 6);
+    assert!(report["message"].as_str().unwrap().contains("CRC"));
+    assert_eq!(report["exit_code"], code);
+}
+
+#[test]
+fn usage() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.png");
+    let png = [
+        &[137, 80, 78, 71, 13, 10, 26, 10][..],
+        &chunk(b"IHDR", b"header"),
+        &chunk(b"IEND", b""),
+    ]
+    .concat();
+    fs::write(&path, png).unwrap();
+    let path = path.to_str().unwrap();
+
+    for args in [
+        &["encode", "-f", path, "-m", "hi"][..],
+        &["decode", "-f", path, path, "-c", "ruSt", "--all"][..],
+    ] {
+        let (code, report) = failing(args);
+        assert_eq!(report["kind"], "Usage", "{:?}", args);
+        assert_eq!((code, report["exit_code"].as_i64()), (1, Some(1)));
+    }
+}
+
+#[test]
+fn success_is_unaffected() {
+    let output = command(&["keygen", "--symmetric", "--json-errors", "-o"])
+        .arg(tempfile::tempdir().unwrap().path().join("key"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
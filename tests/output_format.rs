@@ -0,0 +1,156 @@
+mod common;
+
+use common::{chunk, command, stdout, write_png};
+use std::fs;
+use std::path::Path;
+
+/// Writes a png holding the message "hello" in a ruSt chunk to `dir`, returning its path.
+fn png(dir: &Path) -> String {
+    write_png(
+        &dir.join("image.png"),
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"ruSt", b"hello"),
+            chunk(b"IEND", b""),
+        ],
+    )
+}
+
+#[test]
+fn piped_output_is_plain() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let printed = stdout(&["print", "-f", file]);
+    let lines: Vec<Vec<&str>> = printed.lines().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0][..3], ["0", "IHDR", "6"]);
+    assert_eq!(lines[1][..3], ["1", "ruSt", "5"]);
+    assert_eq!(lines[2][..3], ["2", "IEND", "0"]);
+    assert!(!printed.contains('│') && !printed.contains("Print:"));
+
+    assert_eq!(stdout(&["decode", "-f", file, "-c", "ruSt"]), "hello");
+}
+
+#[test]
+fn forced_pretty_output_is_decorated() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let printed = stdout(&["print", "-f", file, "--format", "pretty"]);
+    assert!(!printed.contains("PrintArgs"));
+    assert!(printed.contains(": 3 chunk(s)"));
+    assert!(printed.contains("│ index │ type │ length │ crc      │"));
+    assert!(printed.contains("│ 1     │ ruSt │ 5      │"));
+    assert!(printed.trim_end().ends_with('┘'));
+
+    let decoded = stdout(&["decode", "-f", file, "-c", "ruSt", "--format", "pretty"]);
+    assert!(decoded.ends_with("\"hello\"\n"));
+}
+
+#[test]
+fn pretty_output_keeps_secrets_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let output = command(&[
+        "encode",
+        "-f",
+        file,
+        "-c",
+        "ruSu",
+        "-m",
+        "the launch code",
+        "--encrypt",
+        "--format",
+        "pretty",
+    ])
+    .env("PNGME_PASSPHRASE", "hunter2")
+    .output()
+    .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let printed = [output.stdout, output.stderr].concat();
+    let printed = String::from_utf8_lossy(&printed);
+    assert!(!printed.contains("EncodeArgs"), "{}", printed);
+    assert!(!printed.contains("the launch code"), "{}", printed);
+    assert!(!printed.contains("hunter2"), "{}", printed);
+}
+
+#[test]
+fn forced_json_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let printed: serde_json::Value =
+        serde_json::from_str(&stdout(&["print", "-f", file, "--format", "json"])).unwrap();
+    assert_eq!(printed["command"], "print");
+    assert_eq!(printed["result"][1]["chunk_type"], "ruSt");
+    assert_eq!(printed["result"][1]["length"], 5);
+    assert_eq!(printed["result"][1]["offset"], 8 + 18);
+    assert_eq!(printed["result"][1]["data_base64"], "aGVsbG8=");
+
+    let decoded: serde_json::Value =
+        serde_json::from_str(&stdout(&["decode", "-f", file, "-c", "ruSt", "--json"])).unwrap();
+    assert_eq!(decoded["result"]["message"], "hello");
+    assert_eq!(decoded["result"]["message_base64"], "aGVsbG8=");
+
+    let flag: serde_json::Value =
+        serde_json::from_str(&stdout(&["print", "-f", file, "--json"])).unwrap();
+    assert_eq!(flag, printed);
+
+    let capacity: serde_json::Value =
+        serde_json::from_str(&stdout(&["capacity", "-f", file, "--json"])).unwrap();
+    assert_eq!(capacity["result"][0]["strategy"], "chunk");
+    assert_eq!(capacity["result"][0]["limit"], 2147483647);
+    // The test image has no pixels to hide anything in
+    assert_eq!(capacity["result"][4]["strategy"], "lsb");
+    assert!(capacity["result"][4]["limit"].is_null());
+}
+
+#[test]
+fn sizes_are_readable_unless_bytes_is_given() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let message = "x".repeat(2000);
+    let stderr = |extra: &[&str]| {
+        let output = command(&["encode", "-f", file, "-c", "ruSu", "-m", &message])
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let readable = stderr(&[]);
+    assert!(
+        readable.contains("(+2.0 KiB (2,012 bytes))"),
+        "{}",
+        readable
+    );
+    let exact = stderr(&["--bytes"]);
+    assert!(exact.contains("(+2012 bytes)"), "{}", exact);
+    assert!(
+        !exact.contains("KiB") && !exact.contains("2,012"),
+        "{}",
+        exact
+    );
+}
+
+#[test]
+fn decode_output_writes_the_raw_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let blob: Vec<u8> = (0..=255u8).chain([0xc3, 0x28, 0x00]).collect();
+    let input = dir.path().join("blob.bin");
+    fs::write(&input, &blob).unwrap();
+    let input = input.to_str().unwrap();
+    stdout(&["encode", "-f", file, "-c", "ruSu", "--input-file", input]);
+
+    let out = dir.path().join("out.bin");
+    let printed = stdout(&[
+        "decode",
+        "-f",
+        file,
+        "-c",
+        "ruSu",
+        "--output",
+        out.to_str().unwrap(),
+    ]);
+    assert_eq!(fs::read(&out).unwrap(), blob);
+    assert!(printed.starts_with("Wrote 259 bytes to"), "{}", printed);
+}
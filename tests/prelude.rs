@@ -0,0 +1,51 @@
+use pngme::prelude::*;
+use std::str::FromStr;
+
+#[test]
+fn roundtrip_through_the_prelude() -> Result<()> {
+    let mut png = Png::from_chunks(vec![
+        Chunk::new(ChunkType::from_str("IHDR")?, vec![0; 13]),
+        Chunk::new(ChunkType::from_str("IDAT")?, b"pixels".to_vec()),
+        Chunk::new(ChunkType::from_str("IEND")?, vec![]),
+    ]);
+    let envelope = Envelope::new(b"hello from an embedder".to_vec()).with_ecc(8);
+    png.insert_chunk(
+        2,
+        Chunk::new(ChunkType::from_str("ruSt")?, envelope.as_bytes()),
+    )?;
+
+    let bytes = png.as_bytes();
+    let read = Png::try_from(&bytes[..])?;
+    let chunk = read.chunk_by_type("ruSt").expect("the chunk was added");
+    let decoded = Envelope::from_bytes(chunk.data())?.expect("the chunk holds an envelope");
+    assert_eq!(decoded.payload, b"hello from an embedder");
+    assert!(decoded.is_intact());
+
+    let unsupported = Envelope::from_bytes(b"pgME\x09").expect_err("version 9 isn't supported");
+    assert!(matches!(Failure::of(&unsupported), Failure::Envelope(_)));
+    assert_eq!(
+        ExitCode::of(&Failure::of(&unsupported)),
+        ExitCode::Integrity
+    );
+    Ok(())
+}
+
+#[test]
+fn bad_input_is_an_error_not_a_panic() {
+    assert!(ChunkType::from_str("ruStX").is_err());
+    assert!(ChunkType::from_str("").is_err());
+    assert!(Png::try_from(&b""[..]).is_err());
+    assert!(Png::try_from(&b"\x89PNG\r\n\x1a\n\0\0\0\xffIDAT"[..]).is_err());
+    assert!(Chunk::try_from(&b"\0\0\0\0IE"[..]).is_err());
+    assert!(Envelope::from_bytes(b"pgME").is_err());
+
+    let mut png = Png::from_chunks(vec![Chunk::new(ChunkType::from_str("IEND").unwrap(), [])]);
+    assert!(png.chunk_by_type("too long").is_none());
+    assert!(png.remove_first_chunk("IE").is_err());
+    let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), []);
+    assert!(png.insert_chunk(2, chunk.clone()).is_err());
+    assert!(png.remove_chunk(1).is_err());
+    assert!(png.insert_chunk(1, chunk).is_ok());
+    assert!(png.remove_chunk(1).is_ok());
+    assert_eq!(png.chunks().len(), 1);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{chunk, chunk_with_crc, crc, pngme, write_png};
+use std::fs;
+use std::path::Path;
+
+/// Writes a png whose tEXt and IDAT chunks have corrupted CRCs to `damaged.png` in `dir`,
+/// returning its path.
+fn damaged_png(dir: &Path) -> String {
+    write_png(
+        &dir.join("damaged.png"),
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk_with_crc(b"tEXt", b"Comment\0hi", 0xdeadbeef),
+            chunk_with_crc(b"IDAT", b"pixels", 0x12345678),
+            chunk(b"IEND", b""),
+        ],
+    )
+}
+
+#[test]
+fn print_marks_damaged_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &damaged_png(dir.path());
+    let output = pngme(&["print", "-f", file, "--format", "plain"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let marker = |chunk_type: &str| {
+        stdout
+            .lines()
+            .find(|line| line.contains(chunk_type))
+            .unwrap()
+            .trim_end()
+            .to_string()
+    };
+    assert!(marker("IHDR").contains("\tok\t"), "{}", stdout);
+    assert!(marker("IEND").contains("\tok\t"), "{}", stdout);
+    assert!(
+        marker("tEXt").contains(&format!(
+            "\tBAD (stored deadbeef, want {:08x})",
+            crc(b"tEXt", b"Comment\0hi")
+        )),
+        "{}",
+        stdout
+    );
+    assert!(
+        marker("IDAT").contains(&format!(
+            "\tBAD (stored 12345678, want {:08x})",
+            crc(b"IDAT", b"pixels")
+        )),
+        "{}",
+        stdout
+    );
+
+    let output = pngme(&["print", "-f", file, "--format", "pretty"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("2 of 4 chunks have invalid CRCs"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn check_crcs_sets_the_exit_code() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &damaged_png(dir.path());
+    let output = pngme(&["print", "-f", file, "--check-crcs"]);
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("2 of 4 chunks have invalid CRCs"),
+        "{}",
+        stderr
+    );
+
+    let intact = dir.path().join("intact.png");
+    let bytes = [
+        &[137, 80, 78, 71, 13, 10, 26, 10][..],
+        &chunk(b"IHDR", b"header"),
+        &chunk(b"IEND", b""),
+    ]
+    .concat();
+    fs::write(&intact, bytes).unwrap();
+    let output = pngme(&["print", "-f", intact.to_str().unwrap(), "--check-crcs"]);
+    assert!(output.status.success(), "{:?}", output);
+}
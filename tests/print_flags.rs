@@ -0,0 +1,79 @@
+mod common;
+
+use common::{chunk, stdout, write_png};
+use std::path::Path;
+
+/// Writes a png mixing standard and private chunks to `image.png` in `dir`, returning its
+/// path.
+fn png(dir: &Path) -> String {
+    write_png(
+        &dir.join("image.png"),
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"tEXt", b"Comment\0hi"),
+            chunk(b"ruSt", b"hidden"),
+            chunk(b"prIV", b"unsafe"),
+            chunk(b"IDAT", b"pixels"),
+            chunk(b"IEND", b""),
+        ],
+    )
+}
+
+#[test]
+fn print_shows_flags() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let printed = stdout(&["print", "-f", file, "--format", "plain"]);
+    let columns: Vec<String> = printed
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            [fields[0], fields[1], fields[2], fields[5]].join("|")
+        })
+        .collect();
+    assert_eq!(
+        columns,
+        [
+            "0|IHDR|6|crit pub unsafe",
+            "1|tEXt|10|anc pub copy",
+            "2|ruSt|6|anc priv copy",
+            "3|prIV|6|anc priv unsafe",
+            "4|IDAT|6|crit pub unsafe",
+            "5|IEND|0|crit pub unsafe",
+        ]
+    );
+}
+
+#[test]
+fn print_json_has_flag_booleans() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let printed: serde_json::Value =
+        serde_json::from_str(&stdout(&["print", "-f", file, "--format", "json"])).unwrap();
+    let flags: Vec<(String, bool, bool, bool, bool)> = printed["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            (
+                c["chunk_type"].as_str().unwrap().to_string(),
+                c["critical"].as_bool().unwrap(),
+                c["public"].as_bool().unwrap(),
+                c["reserved_bit_valid"].as_bool().unwrap(),
+                c["safe_to_copy"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    let expected = [
+        ("IHDR", true, true, true, false),
+        ("tEXt", false, true, true, true),
+        ("ruSt", false, false, true, true),
+        ("prIV", false, false, true, false),
+        ("IDAT", true, true, true, false),
+        ("IEND", true, true, true, false),
+    ];
+    assert_eq!(
+        flags,
+        expected.map(|(t, c, p, r, s)| (t.to_string(), c, p, r, s))
+    );
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{chunk, stdout, write_png};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+/// Writes a minimal png with a tEXt chunk to `image.png` in `dir`, returning its path.
+fn png(dir: &Path) -> String {
+    write_png(
+        &dir.join("image.png"),
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"tEXt", b"Comment\0hi"),
+            chunk(b"IEND", b""),
+        ],
+    )
+}
+
+fn sha256(path: &str) -> String {
+    Sha256::digest(fs::read(path).unwrap())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[test]
+fn encode_prints_sha256sum_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let printed = stdout(&[
+        "encode",
+        "-f",
+        file,
+        "-c",
+        "ruSt",
+        "-m",
+        "hi",
+        "--print-hash",
+    ]);
+    assert_eq!(printed, format!("{}  {}\n", sha256(file), file));
+}
+
+#[test]
+fn remove_prints_blake3() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    stdout(&["encode", "-f", file, "-c", "ruSt", "-m", "hi"]);
+    let printed = stdout(&["remove", "-f", file, "-c", "ruSt", "--print-hash", "blake3"]);
+    let expected = blake3::hash(&fs::read(file).unwrap()).to_hex().to_string();
+    assert_eq!(
+        printed.lines().last().unwrap(),
+        format!("{}  {}", expected, file)
+    );
+}
+
+#[test]
+fn strip_puts_the_digest_in_the_json_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let out = dir.path().join("stripped.png");
+    let out = out.to_str().unwrap();
+    let printed = stdout(&[
+        "strip",
+        "-f",
+        file,
+        "-o",
+        out,
+        "--json",
+        "--print-hash",
+        "sha256",
+    ]);
+    let document: serde_json::Value = serde_json::from_str(&printed).unwrap();
+    let summary = &document["result"];
+    assert_eq!(summary["digest"]["algorithm"], "sha256");
+    assert_eq!(summary["digest"]["hex"], sha256(out));
+    assert_eq!(summary["digest"]["path"], out);
+    assert_ne!(sha256(out), sha256(file));
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{chunk, command, write_png};
+use std::path::Path;
+use std::process::Output;
+
+/// Every phase an encode with --compress goes through.
+const PHASES: [&str; 6] = ["read", "parse", "crc", "codec", "serialize", "write"];
+
+fn png(dir: &Path) -> String {
+    write_png(
+        &dir.join("image.png"),
+        &[
+            chunk(b"IHDR", b"header"),
+            chunk(b"IDAT", b"pixels"),
+            chunk(b"IEND", b""),
+        ],
+    )
+}
+
+fn encode(file: &str, flags: &[&str]) -> Output {
+    let output = command(flags)
+        .args([
+            "encode",
+            "-f",
+            file,
+            "-c",
+            "ruSt",
+            "-m",
+            "hello hello hello",
+        ])
+        .arg("--compress")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
+
+#[test]
+fn profile_prints_every_phase() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let stderr = String::from_utf8(encode(file, &["--profile"]).stderr).unwrap();
+    let lines: Vec<&str> = stderr
+        .lines()
+        .skip_while(|line| *line != "profile:")
+        .skip(1)
+        .collect();
+    for phase in PHASES {
+        let line = lines
+            .iter()
+            .find(|line| line.split_whitespace().next() == Some(phase))
+            .unwrap_or_else(|| panic!("no {} in {:?}", phase, stderr));
+        let millis: f64 = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+        assert!(millis >= 0.0, "{}", line);
+    }
+}
+
+#[test]
+fn profile_goes_into_the_json_document() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    let output = encode(file, &["--json", "--profile"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("profile:"));
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let phases: Vec<&str> = document["profile"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|timing| {
+            assert!(timing["micros"].is_u64(), "{}", timing);
+            timing["phase"].as_str().unwrap()
+        })
+        .collect();
+    assert_eq!(phases, PHASES);
+}
+
+#[test]
+fn no_profile_without_the_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = &png(dir.path());
+    assert!(!String::from_utf8_lossy(&encode(file, &[]).stderr).contains("profile:"));
+    let output = encode(file, &["--json"]);
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(document.get("profile").is_none());
+}
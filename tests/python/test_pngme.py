@@ -0,0 +1,49 @@
+"""Exercises the Python bindings. Build them first with `maturin develop`, then run pytest."""
+
+import struct
+import zlib
+
+import pytest
+
+import pngme
+
+
+def chunk(chunk_type: bytes, data: bytes) -> bytes:
+    crc = zlib.crc32(chunk_type + data)
+    return struct.pack(">I", len(data)) + chunk_type + data + struct.pack(">I", crc)
+
+
+PNG = (
+    b"\x89PNG\r\n\x1a\n"
+    + chunk(b"IHDR", bytes(13))
+    + chunk(b"IDAT", b"pixels")
+    + chunk(b"IEND", b"")
+)
+
+
+def test_roundtrip():
+    encoded = pngme.encode(PNG, "ruSt", b"hello", compress=True, passphrase="hunter2")
+    assert pngme.decode(encoded, passphrase="hunter2") == b"hello"
+    assert pngme.decode(encoded, "ruSt", passphrase="hunter2") == b"hello"
+    assert pngme.decode(PNG) is None
+
+
+def test_chunks_and_check():
+    encoded = pngme.encode(PNG, "ruSt", b"hello")
+    listed = pngme.chunks(encoded)
+    assert [c["chunk_type"] for c in listed] == ["IHDR", "IDAT", "ruSt", "IEND"]
+    assert all(c["crc_ok"] for c in listed)
+    assert pngme.check(encoded) == []
+    assert pngme.check(encoded + b"tail") != []
+
+
+def test_errors():
+    with pytest.raises(pngme.NotPngError):
+        pngme.decode(b"GIF89a")
+    encoded = pngme.encode(PNG, "ruSt", b"hello", passphrase="right")
+    with pytest.raises(pngme.IntegrityError, match="wrong key or passphrase"):
+        pngme.decode(encoded, passphrase="wrong")
+    with pytest.raises(pngme.PngmeError):
+        pngme.decode(encoded)
+    with pytest.raises(ValueError):
+        pngme.encode(PNG, "ru!t", b"hello")
@@ -0,0 +1,108 @@
+//! Every file in `tests/regressions/` is an input that once made a parser panic, hang or
+//! give a wrong answer. Each one is fed to every parser, which must return, with an error or
+//! not, within `TIMEOUT`.
+//!
+//! To add a case, point `add_regression` at the input. Inputs that still fail are shrunk to
+//! the smallest one that fails the same way before they are copied in:
+//!
+//! ```text
+//! PNGME_REGRESSION=crash.png cargo test --test regressions add_regression -- --ignored
+//! ```
+
+use pngme::prelude::*;
+use pngme::stream::ChunkStream;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn corpus() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/regressions")
+}
+
+/// Runs `input` through every parser on another thread. Returns what went wrong: a panic or
+/// running past `TIMEOUT`, which leaves the thread behind.
+fn failure(input: &[u8]) -> Option<String> {
+    let input = input.to_vec();
+    let (done, result) = mpsc::channel();
+    thread::spawn(move || {
+        let outcome = panic::catch_unwind(|| {
+            let _ = Png::try_from(&input[..]);
+            let _ = Png::parse_report_lenient(&input);
+            let _ = Chunk::try_from(&input[..]);
+            let _ = input.get(8..).map(Chunk::try_from);
+            let _ = ChunkStream::new(&input[..]).map(|stream| stream.count());
+        });
+        let _ = done.send(outcome.is_err());
+    });
+    match result.recv_timeout(TIMEOUT) {
+        Ok(false) => None,
+        Ok(true) => Some("panicked".to_string()),
+        Err(_) => Some(format!("took longer than {:?}", TIMEOUT)),
+    }
+}
+
+#[test]
+fn corpus_inputs_fail_cleanly() {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(corpus())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+    let failures: Vec<String> = inputs
+        .iter()
+        .filter_map(|path| {
+            let failure = failure(&fs::read(path).unwrap())?;
+            Some(format!("{}: {}", path.display(), failure))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{:#?}", failures);
+}
+
+/// Removes ever smaller runs of bytes from `input` for as long as it keeps failing the way
+/// it did to begin with.
+fn minimize(mut input: Vec<u8>, how: &str) -> Vec<u8> {
+    let mut run = input.len() / 2;
+    while run > 0 {
+        let mut start = 0;
+        while start < input.len() {
+            let end = (start + run).min(input.len());
+            let shorter = [&input[..start], &input[end..]].concat();
+            match failure(&shorter).as_deref() == Some(how) {
+                true => input = shorter,
+                false => start += run,
+            }
+        }
+        run /= 2;
+    }
+    input
+}
+
+#[test]
+#[ignore = "adds the input named by PNGME_REGRESSION to the corpus"]
+fn add_regression() {
+    let source = std::env::var("PNGME_REGRESSION").expect("PNGME_REGRESSION names the input");
+    let input = fs::read(&source).unwrap();
+    let input = match failure(&input) {
+        Some(how) => {
+            let minimized = minimize(input, &how);
+            println!("{} ({} bytes once minimized)", how, minimized.len());
+            minimized
+        }
+        None => input,
+    };
+    let name = Path::new(&source).file_stem().unwrap().to_string_lossy();
+    let path = corpus().join(format!("{}.bin", name));
+    assert!(
+        !path.exists(),
+        "{} is already in the corpus",
+        path.display()
+    );
+    fs::write(&path, input).unwrap();
+    println!("added {}", path.display());
+}
@@ -0,0 +1,14 @@
+use std::process::Command;
+
+#[test]
+fn selftest_passes() {
+    let output = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .arg("selftest")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("pass  encrypted with an ephemeral key: decode"));
+    assert!(!stdout.contains("FAIL") && !stdout.contains("skip"));
+    assert!(stdout.ends_with("All stages passed\n"));
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{chunk, piped, png_bytes};
+
+fn png() -> Vec<u8> {
+    png_bytes(&[chunk(b"IHDR", b"header"), chunk(b"IEND", b"")])
+}
+
+#[test]
+fn encode_from_stdin_to_stdout() {
+    let encoded = piped(
+        &[
+            "encode", "-f", "-", "-c", "ruSt", "-m", "hi", "-o", "-", "--format", "pretty",
+        ],
+        &png(),
+    );
+    assert!(encoded.status.success());
+    // Only the png goes to stdout, with the summary and echoed arguments on stderr
+    assert!(encoded.stdout.starts_with(&png()[..8]));
+    assert!(!String::from_utf8_lossy(&encoded.stderr).is_empty());
+
+    let decoded = piped(
+        &["decode", "-f", "-", "-c", "ruSt", "--expect", "hi"],
+        &encoded.stdout,
+    );
+    assert!(decoded.status.success());
+
+    // Without --out-path the png goes back where it came from
+    let removed = piped(&["remove", "-f", "-", "-c", "ruSt"], &encoded.stdout);
+    assert!(removed.status.success());
+    assert_eq!(removed.stdout, png());
+}
+
+#[test]
+fn stdout_is_refused_with_json() {
+    let output = piped(&["--json", "strip", "-f", "-", "-o", "-"], &png());
+    assert!(!output.status.success());
+    assert!(!output.stdout.starts_with(&png()[..8]));
+}
+
+#[test]
+fn batch_encode_reads_the_message_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths: Vec<String> = ["a.png", "b.png"]
+        .iter()
+        .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+        .collect();
+    for path in &paths {
+        std::fs::write(path, png()).unwrap();
+    }
+    let encoded = piped(
+        &[
+            "encode",
+            "-c",
+            "ruSt",
+            "--message-file",
+            "-",
+            &paths[0],
+            &paths[1],
+        ],
+        b"secret",
+    );
+    assert!(encoded.status.success());
+    // Every file gets the message, not just the first to read stdin
+    for path in &paths {
+        let decoded = piped(
+            &["decode", "-f", path, "-c", "ruSt", "--expect", "secret"],
+            b"",
+        );
+        assert!(decoded.status.success(), "{}", path);
+    }
+}